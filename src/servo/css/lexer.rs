@@ -1,7 +1,12 @@
-//! Code to lex and tokenize css files
+/*!
+Tokenizes CSS, following CSS Syntax Level 3, Section 4.
+
+Errors never stop the tokenizer: as in browsers, malformed input
+becomes `BadString`, `BadUrl` or `Delim` tokens, and it is up to the
+parser to discard the constructs that contain them.
+*/
 
 use option::is_none;
-use str::from_bytes;
 use vec::push;
 
 use pipes::{Port, Chan};
@@ -9,223 +14,602 @@ use pipes::{Port, Chan};
 use html::lexer_util::*;
 
 use std::net::url::Url;
-use resource::resource_task::{ResourceTask, ProgressMsg, Load};
+use resource::resource_task::{ResourceTask, ProgressMsg, Load, Payload, Done};
+
+pub enum Token {
+    Ident(~str),
+    Function(~str),
+    AtKeyword(~str),
+    // the flag is set if the name is also a valid identifier, as in an id selector
+    Hash(~str, bool),
+    String(~str),
+    BadString,
+    Url(~str),
+    BadUrl,
+    Delim(char),
+    Number(NumericValue),
+    Percentage(NumericValue),
+    Dimension(NumericValue, ~str),
+    Whitespace,
+    CDO,
+    CDC,
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+    Eof
+}
 
-enum ParserState {
-    CssElement,
-    CssRelation,
-    CssDescription,
-    CssAttribute
+pub struct NumericValue {
+    // the number as written, so that values can be serialized back unchanged
+    representation: ~str,
+    value: float,
+    // Some if the number was written as an integer
+    int_value: Option<int>
 }
 
+impl NumericValue : cmp::Eq {
+    pure fn eq(other: &NumericValue) -> bool {
+        self.representation == other.representation
+    }
+    pure fn ne(other: &NumericValue) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl Token : cmp::Eq {
+    pure fn eq(other: &Token) -> bool {
+        match (copy self, copy *other) {
+          (Ident(a), Ident(b)) | (Function(a), Function(b)) | (AtKeyword(a), AtKeyword(b))
+          | (String(a), String(b)) | (Url(a), Url(b)) => a == b,
+          (Hash(a, id_a), Hash(b, id_b)) => a == b && id_a == id_b,
+          (Delim(a), Delim(b)) => a == b,
+          (Number(a), Number(b)) | (Percentage(a), Percentage(b)) => a == b,
+          (Dimension(a, unit_a), Dimension(b, unit_b)) => a == b && unit_a == unit_b,
+          (BadString, BadString) | (BadUrl, BadUrl) | (Whitespace, Whitespace)
+          | (CDO, CDO) | (CDC, CDC) | (Colon, Colon) | (Semicolon, Semicolon)
+          | (Comma, Comma) | (OpenSquare, OpenSquare) | (CloseSquare, CloseSquare)
+          | (OpenParen, OpenParen) | (CloseParen, CloseParen) | (OpenCurly, OpenCurly)
+          | (CloseCurly, CloseCurly) | (Eof, Eof) => true,
+          (_, _) => false
+        }
+    }
+    pure fn ne(other: &Token) -> bool {
+        return !self.eq(other);
+    }
+}
+
+/// Where a token starts in a style sheet. Lines and columns count from 1.
+pub struct SourcePosition {
+    line: uint,
+    column: uint
+}
+
+/// Stands for the end of the input in lookahead; real NULs are replaced while reading.
+const EOF_CHAR: char = '\x00';
+const REPLACEMENT_CHAR: char = '\uFFFD';
+
 type CssLexer = {
     input_state: InputState,
-    mut parser_state: ParserState
+    // code points that were read ahead but not consumed yet, in order
+    mut lookahead: ~[(char, SourcePosition)],
+    // where the next code point to be decoded starts
+    mut position: SourcePosition,
+    // the code point consumed last, which unget_char puts back
+    mut last: (char, SourcePosition)
 };
 
-pub enum Token {
-    StartDescription,
-    EndDescription,
-    Descendant,
-    Child,
-    Sibling,
-    Comma,
-    Element(~str),
-    Attr(css::values::Attr), 
-    Description(~str, ~str),
-    Eof
+pure fn is_digit(c: char) -> bool { c >= '0' && c <= '9' }
+
+pure fn is_hex_digit(c: char) -> bool {
+    is_digit(c) || (c >= 'a' && c <= 'f') || (c >= 'A' && c <= 'F')
+}
+
+pure fn is_css_whitespace(c: char) -> bool { c == ' ' || c == '\n' || c == '\t' }
+
+pure fn is_name_start(c: char) -> bool {
+    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_' || c as uint >= 0x80
+}
+
+pure fn is_name(c: char) -> bool { is_name_start(c) || is_digit(c) || c == '-' }
+
+pure fn is_non_printable(c: char) -> bool {
+    (c as uint <= 0x08) || c == '\x0B' || (c as uint >= 0x0E && c as uint <= 0x1F)
+        || c == '\x7F'
+}
+
+/// Section 4.3.8: whether two code points are a valid escape.
+pure fn is_valid_escape(c1: char, c2: char) -> bool {
+    c1 == '\\' && c2 != '\n' && c2 != EOF_CHAR
+}
+
+/// Section 4.3.9: whether three code points would start an identifier.
+pure fn starts_identifier(c1: char, c2: char, c3: char) -> bool {
+    match c1 {
+      '-' => is_name_start(c2) || c2 == '-' || is_valid_escape(c2, c3),
+      '\\' => is_valid_escape(c1, c2),
+      _ => is_name_start(c1)
+    }
+}
+
+/// Section 4.3.10: whether three code points would start a number.
+pure fn starts_number(c1: char, c2: char, c3: char) -> bool {
+    match c1 {
+      '+' | '-' => is_digit(c2) || (c2 == '.' && is_digit(c3)),
+      '.' => is_digit(c2),
+      _ => is_digit(c1)
+    }
 }
 
 trait CssLexerMethods {
-    fn parse_css() -> Token;
-    fn parse_css_relation(c : u8) -> Token;
-    fn parse_css_element(c : u8) -> Token;
-    fn parse_css_attribute(c : u8) -> Token;
-    fn parse_css_description(c: u8) -> Token;
+    fn token_position() -> SourcePosition;
+    fn read_ahead();
+    fn next_char() -> char;
+    fn unget_char(c: char);
+    fn peek_chars(n: uint) -> ~[char];
+    fn consume_token() -> Token;
+    fn consume_comments();
+    fn consume_whitespace();
+    fn consume_escape() -> char;
+    fn consume_name() -> ~str;
+    fn consume_number() -> NumericValue;
+    fn consume_numeric() -> Token;
+    fn consume_ident_like() -> Token;
+    fn consume_string(ending: char) -> Token;
+    fn consume_url() -> Token;
+    fn consume_bad_url_remnants();
+    fn consume_digits(repr: &mut ~str);
+    fn decode_char() -> char;
 }
 
 impl CssLexer : CssLexerMethods {
-    fn parse_css() -> Token {
-        let mut ch: u8;
-        match self.input_state.get() {
-            CoeChar(c) => ch = c,
-            CoeEof => { return Eof; }
-        }
-
-        let token = match self.parser_state {
-          CssDescription => self.parse_css_description(ch),
-          CssAttribute => self.parse_css_attribute(ch),
-          CssElement => self.parse_css_element(ch),
-          CssRelation => self.parse_css_relation(ch)
+    /** Decodes the next code point from the UTF-8 input, or EOF_CHAR. */
+    fn decode_char() -> char {
+        let first = match self.input_state.get() {
+          CoeChar(b) => b,
+          CoeEof => return EOF_CHAR
         };
 
-        #debug["token=%?", token];
-        return move token;
-    }
+        let (width, mut code) = if first < 0x80u8 {
+            (1u, first as uint)
+        } else if first & 0xE0u8 == 0xC0u8 {
+            (2u, (first & 0x1Fu8) as uint)
+        } else if first & 0xF0u8 == 0xE0u8 {
+            (3u, (first & 0x0Fu8) as uint)
+        } else if first & 0xF8u8 == 0xF0u8 {
+            (4u, (first & 0x07u8) as uint)
+        } else {
+            return REPLACEMENT_CHAR;
+        };
 
-    fn parse_css_relation(c : u8) -> Token {
-        self.parser_state = CssElement;
+        for uint::range(1u, width) |_i| {
+            match self.input_state.get() {
+              CoeChar(b) if b & 0xC0u8 == 0x80u8 => code = code << 6 | (b & 0x3Fu8) as uint,
+              CoeChar(b) => { self.input_state.unget(b); return REPLACEMENT_CHAR; }
+              CoeEof => return REPLACEMENT_CHAR
+            }
+        }
 
-        let token = match c {
-          '{' as u8 => { self.parser_state = CssDescription; StartDescription }
-          '>' as u8 => { Child }
-          '+' as u8 => { Sibling }
-          ',' as u8 => { Comma }
-          _ => { self.input_state.unget(c); Descendant }
-        };
+        if code > 0x10FFFFu || (code >= 0xD800u && code <= 0xDFFFu) {
+            REPLACEMENT_CHAR
+        } else {
+            code as char
+        }
+    }
 
-        self.input_state.eat_whitespace();
-        
-        return move token;
+    /** Where the next code point to be consumed starts. */
+    fn token_position() -> SourcePosition {
+        if self.lookahead.len() > 0 {
+            let (_, position) = self.lookahead[0];
+            position
+        } else {
+            self.position
+        }
     }
 
-    fn parse_css_element(c : u8) -> Token {
-        assert is_none(&self.input_state.lookahead);
+    /**
+    Decodes one more code point into the lookahead, preprocessing it
+    as in Section 3.3 and noting where it starts.
+    */
+    fn read_ahead() {
+        let position = self.position;
+        let c = match self.decode_char() {
+          '\r' => {
+            // CRLF counts as a single newline
+            match self.input_state.get() {
+              CoeChar(b) if b == '\n' as u8 => {}
+              CoeChar(b) => self.input_state.unget(b),
+              CoeEof => {}
+            }
+            '\n'
+          }
+          '\x0C' => '\n',
+          c if c == EOF_CHAR && !self.input_state.eof => REPLACEMENT_CHAR,
+          c => c
+        };
 
-        /* Check for special attributes with an implied element,
-        or a wildcard which is not a alphabet character.*/
-        if c == '.' as u8 || c == '#' as u8 {
-            self.parser_state = CssAttribute;
-            self.input_state.unget(c);
-            return Element(~"*");
-        } else if c == '*' as u8 {
-            self.parser_state = CssAttribute;
-            return Element(~"*");
+        if c == '\n' {
+            self.position = SourcePosition { line: position.line + 1u, column: 1u };
+        } else if c != EOF_CHAR {
+            self.position = SourcePosition { column: position.column + 1u, .. position };
         }
+        push(&mut self.lookahead, (c, position));
+    }
 
-        self.input_state.unget(c);
-        let element = self.input_state.parse_ident();
+    /** Consumes the next code point, or returns EOF_CHAR at the end of the input. */
+    fn next_char() -> char {
+        if self.lookahead.len() == 0 {
+            self.read_ahead();
+        }
+        self.last = vec::shift(&mut self.lookahead);
+        let (c, _) = self.last;
+        c
+    }
 
-        self.parser_state = CssAttribute;
-        
-        return move Element(move element);
+    /** Puts back the code point consumed last. */
+    fn unget_char(c: char) {
+        let (last, _) = self.last;
+        assert c == last;
+        vec::unshift(&mut self.lookahead, self.last);
     }
 
-    fn parse_css_attribute(c : u8) -> Token {
-        let mut ch = c;
-        
-        /* If we've reached the end of this list of attributes,
-        look for the relation to the next element.*/
-        if c.is_whitespace() {
-            self.parser_state = CssRelation;
-            self.input_state.eat_whitespace();
+    /** Looks at the next `n` code points without consuming them. */
+    fn peek_chars(n: uint) -> ~[char] {
+        while self.lookahead.len() < n {
+            self.read_ahead();
+        }
+        vec::from_fn(n, |i| { let (c, _) = self.lookahead[i]; c })
+    }
 
-            match self.input_state.get() {
-              CoeChar(c) => { ch = c }
-              CoeEof => { fail ~"File ended before description of style" }
-            }
+    /** Section 4.3.1: consumes a token. */
+    fn consume_token() -> Token {
+        self.consume_comments();
 
-            return self.parse_css_relation(ch);
+        let c = self.next_char();
+        if c == EOF_CHAR {
+            return Eof;
         }
-        
-        match ch {
-          '.' as u8 => return Attr(css::values::Includes(~"class", self.input_state.parse_ident())),
-          '#' as u8 => return Attr(css::values::Includes(~"id", self.input_state.parse_ident())),
-          '[' as u8 => {
-            let attr_name = self.input_state.parse_ident();
-            
-            match self.input_state.get() {
-              CoeChar(c) => { ch = c; }
-              CoeEof => { fail ~"File ended before description finished"; }
+        match c {
+          ' ' | '\n' | '\t' => { self.consume_whitespace(); Whitespace }
+          '"' | '\'' => self.consume_string(c),
+          '#' => {
+            let next = self.peek_chars(2);
+            if is_name(next[0]) || is_valid_escape(next[0], next[1]) {
+                let next = self.peek_chars(3);
+                let is_id = starts_identifier(next[0], next[1], next[2]);
+                Hash(self.consume_name(), is_id)
+            } else {
+                Delim(c)
             }
-
-            if ch == ']' as u8 {
-                return Attr(css::values::Exists(move attr_name));
-            } else if ch == '=' as u8 {
-                let attr_val = self.input_state.parse_ident();
-                self.input_state.expect(']' as u8);
-                return Attr(css::values::Exact(move attr_name, move attr_val));
-            } else if ch == '~' as u8 {
-                self.input_state.expect('=' as u8);
-                let attr_val = self.input_state.parse_ident();
-                self.input_state.expect(']' as u8);
-                return Attr(css::values::Includes(move attr_name, move attr_val));
-            } else if ch == '|' as u8 {
-                self.input_state.expect('=' as u8);
-                let attr_val = self.input_state.parse_ident();
-                self.input_state.expect(']' as u8);
-                return Attr(css::values::StartsWith(move attr_name, move attr_val));
+          }
+          '(' => OpenParen,
+          ')' => CloseParen,
+          '[' => OpenSquare,
+          ']' => CloseSquare,
+          '{' => OpenCurly,
+          '}' => CloseCurly,
+          ',' => Comma,
+          ':' => Colon,
+          ';' => Semicolon,
+          '+' | '.' => {
+            let next = self.peek_chars(2);
+            if starts_number(c, next[0], next[1]) {
+                self.unget_char(c);
+                self.consume_numeric()
+            } else {
+                Delim(c)
             }
-            
-            fail #fmt("Unexpected symbol %c in attribute", ch as char);
           }
-          _ => { fail #fmt("Unexpected symbol %c in attribute", ch as char); }
+          '-' => {
+            let next = self.peek_chars(2);
+            if starts_number(c, next[0], next[1]) {
+                self.unget_char(c);
+                self.consume_numeric()
+            } else if next[0] == '-' && next[1] == '>' {
+                self.next_char();
+                self.next_char();
+                CDC
+            } else if starts_identifier(c, next[0], next[1]) {
+                self.unget_char(c);
+                self.consume_ident_like()
+            } else {
+                Delim(c)
+            }
+          }
+          '<' => {
+            let next = self.peek_chars(3);
+            if next[0] == '!' && next[1] == '-' && next[2] == '-' {
+                for uint::range(0u, 3u) |_i| { self.next_char(); }
+                CDO
+            } else {
+                Delim(c)
+            }
+          }
+          '@' => {
+            let next = self.peek_chars(3);
+            if starts_identifier(next[0], next[1], next[2]) {
+                AtKeyword(self.consume_name())
+            } else {
+                Delim(c)
+            }
+          }
+          '\\' => {
+            let next = self.peek_chars(1);
+            if is_valid_escape(c, next[0]) {
+                self.unget_char(c);
+                self.consume_ident_like()
+            } else {
+                #debug["CSS lexer: invalid escape"];
+                Delim(c)
+            }
+          }
+          c if is_digit(c) => {
+            self.unget_char(c);
+            self.consume_numeric()
+          }
+          c if is_name_start(c) => {
+            self.unget_char(c);
+            self.consume_ident_like()
+          }
+          c => Delim(c)
         }
     }
 
-    fn parse_css_description(c: u8) -> Token {
-        let mut ch = c;
+    /** Section 4.3.2: consumes any comments, up to the next token. */
+    fn consume_comments() {
+        loop {
+            let next = self.peek_chars(2);
+            if next[0] != '/' || next[1] != '*' {
+                return;
+            }
+            self.next_char();
+            self.next_char();
+
+            loop {
+                match self.next_char() {
+                  c if c == EOF_CHAR => { #debug["CSS lexer: unterminated comment"]; return; }
+                  '*' => {
+                    if self.peek_chars(1)[0] == '/' {
+                        self.next_char();
+                        break;
+                    }
+                  }
+                  _ => {}
+                }
+            }
+        }
+    }
 
-        if ch == '}' as u8 {
-            self.parser_state = CssElement;
-            self.input_state.eat_whitespace();
-            return EndDescription;
-        } else if ch.is_whitespace() {
-            self.input_state.eat_whitespace();
+    fn consume_whitespace() {
+        loop {
+            let c = self.next_char();
+            if !is_css_whitespace(c) {
+                self.unget_char(c);
+                return;
+            }
+        }
+    }
 
-            match self.input_state.get() {
-              CoeChar(c) => { ch = c }
-              CoeEof => { fail ~"Reached end of file in CSS description" }
+    /** Section 4.3.7: consumes an escaped code point, after the backslash. */
+    fn consume_escape() -> char {
+        let c = self.next_char();
+        if c == EOF_CHAR {
+            return REPLACEMENT_CHAR;
+        }
+        if !is_hex_digit(c) {
+            return c;
+        }
+
+        let mut code = char::to_digit(c, 16u).get();
+        for uint::range(1u, 6u) |_i| {
+            let c = self.next_char();
+            if is_hex_digit(c) {
+                code = code * 16u + char::to_digit(c, 16u).get();
+            } else {
+                self.unget_char(c);
+                break;
             }
         }
-        
-        let mut desc_name = ~[];
-        
-        // Get the name of the descriptor
+        // a single whitespace character ends the escape
+        let c = self.next_char();
+        if !is_css_whitespace(c) {
+            self.unget_char(c);
+        }
+
+        if code == 0u || code > 0x10FFFFu || (code >= 0xD800u && code <= 0xDFFFu) {
+            REPLACEMENT_CHAR
+        } else {
+            code as char
+        }
+    }
+
+    /** Section 4.3.11: consumes a name. The caller checks that one starts here. */
+    fn consume_name() -> ~str {
+        let mut name = ~"";
         loop {
-            if ch.is_whitespace() {
-                self.input_state.eat_whitespace();
-            } else if ch == ':' as u8 {
-                if desc_name.len() == 0u {
-                    fail ~"Expected descriptor name";
-                } else {
-                    break;
-                }
+            let c = self.next_char();
+            if is_name(c) {
+                str::push_char(&mut name, c);
+            } else if is_valid_escape(c, self.peek_chars(1)[0]) {
+                str::push_char(&mut name, self.consume_escape());
             } else {
-                push(&mut desc_name, ch);
+                self.unget_char(c);
+                return move name;
             }
+        }
+    }
 
-            match self.input_state.get() {
-              CoeChar(c) => { ch = c }
-              CoeEof => { fail ~"Reached end of file in CSS description" }
-            }
+    /** Section 4.3.12: consumes a number. The caller checks that one starts here. */
+    fn consume_number() -> NumericValue {
+        let mut repr = ~"";
+        let mut is_integer = true;
+
+        let c = self.next_char();
+        if c == '+' || c == '-' {
+            str::push_char(&mut repr, c);
+        } else {
+            self.unget_char(c);
+        }
+        self.consume_digits(&mut repr);
+
+        let next = self.peek_chars(2);
+        if next[0] == '.' && is_digit(next[1]) {
+            is_integer = false;
+            str::push_char(&mut repr, self.next_char());
+            self.consume_digits(&mut repr);
+        }
+
+        let next = self.peek_chars(3);
+        if (next[0] == 'e' || next[0] == 'E')
+            && (is_digit(next[1]) || ((next[1] == '+' || next[1] == '-') && is_digit(next[2]))) {
+            is_integer = false;
+            str::push_char(&mut repr, self.next_char());
+            str::push_char(&mut repr, self.next_char());
+            self.consume_digits(&mut repr);
+        }
+
+        // float::from_str doesn't take a leading '+'
+        let unsigned = if repr.starts_with("+") { repr.substr(1, repr.len() - 1) }
+                       else { copy repr };
+        let value = float::from_str(unsigned).get_default(0.0);
+        NumericValue {
+            int_value: if is_integer { int::from_str(unsigned) } else { None },
+            value: value,
+            representation: move repr
+        }
+    }
+
+    /** Section 4.3.3: consumes a number, percentage or dimension. */
+    fn consume_numeric() -> Token {
+        let number = self.consume_number();
+        let next = self.peek_chars(3);
+        if starts_identifier(next[0], next[1], next[2]) {
+            Dimension(move number, self.consume_name())
+        } else if next[0] == '%' {
+            self.next_char();
+            Percentage(move number)
+        } else {
+            Number(move number)
         }
+    }
 
-        self.input_state.eat_whitespace();
-        let mut desc_val = ~[];
+    /** Section 4.3.4: consumes an identifier, function or url. */
+    fn consume_ident_like() -> Token {
+        let name = self.consume_name();
+        if self.peek_chars(1)[0] != '(' {
+            return Ident(move name);
+        }
+        self.next_char();
+
+        if str::to_lower(name) == ~"url" {
+            // a quoted url is an ordinary function taking a string
+            let next = self.peek_chars(2);
+            let quote = if is_css_whitespace(next[0]) { next[1] } else { next[0] };
+            if quote != '"' && quote != '\'' {
+                return self.consume_url();
+            }
+        }
+        Function(move name)
+    }
 
-        // Get the value of the descriptor
+    /** Section 4.3.5: consumes a string, after the opening quote. */
+    fn consume_string(ending: char) -> Token {
+        let mut value = ~"";
         loop {
-            match self.input_state.get() {
-              CoeChar(c) => { ch = c }
-              CoeEof => { fail ~"Reached end of file in CSS description" }
+            let c = self.next_char();
+            match c {
+              c if c == EOF_CHAR => {
+                #debug["CSS lexer: unterminated string"];
+                return String(move value);
+              }
+              '\n' => {
+                #debug["CSS lexer: newline in string"];
+                self.unget_char(c);
+                return BadString;
+              }
+              '\\' => {
+                match self.peek_chars(1)[0] {
+                  // an escaped newline continues the string
+                  '\n' => { self.next_char(); }
+                  c if c == EOF_CHAR => {}
+                  _ => str::push_char(&mut value, self.consume_escape())
+                }
+              }
+              c if c == ending => return String(move value),
+              c => str::push_char(&mut value, c)
             }
+        }
+    }
 
-            if ch.is_whitespace() {
-                self.input_state.eat_whitespace();
-            } else if ch == '}' as u8 {
-                if desc_val.len() == 0u {
-                    fail ~"Expected descriptor value";
-                } else {
-                    self.input_state.unget('}' as u8);
-                    break;
+    /** Section 4.3.6: consumes an unquoted url, after 'url('. */
+    fn consume_url() -> Token {
+        let mut value = ~"";
+        self.consume_whitespace();
+        loop {
+            let c = self.next_char();
+            match c {
+              ')' => return Url(move value),
+              c if c == EOF_CHAR => {
+                #debug["CSS lexer: unterminated url"];
+                return Url(move value);
+              }
+              ' ' | '\n' | '\t' => {
+                self.consume_whitespace();
+                let c = self.next_char();
+                if c == ')' || c == EOF_CHAR {
+                    return Url(move value);
                 }
-            } else if ch == ';' as u8 {
-                if desc_val.len() == 0u {
-                    fail ~"Expected descriptor value";
+                self.consume_bad_url_remnants();
+                return BadUrl;
+              }
+              '"' | '\'' | '(' => { self.consume_bad_url_remnants(); return BadUrl; }
+              '\\' => {
+                if is_valid_escape(c, self.peek_chars(1)[0]) {
+                    str::push_char(&mut value, self.consume_escape());
                 } else {
-                    break;
+                    self.consume_bad_url_remnants();
+                    return BadUrl;
                 }
-            } else {
-                push(&mut desc_val, ch);
+              }
+              c if is_non_printable(c) => { self.consume_bad_url_remnants(); return BadUrl; }
+              c => str::push_char(&mut value, c)
+            }
+        }
+    }
+
+    /** Section 4.3.14: skips the rest of a bad url, so that tokenizing can carry on. */
+    fn consume_bad_url_remnants() {
+        loop {
+            let c = self.next_char();
+            match c {
+              ')' => return,
+              c if c == EOF_CHAR => return,
+              '\\' if is_valid_escape(c, self.peek_chars(1)[0]) => { self.consume_escape(); }
+              _ => {}
             }
         }
+    }
 
-        return Description(from_bytes(desc_name), from_bytes(desc_val));
+    fn consume_digits(repr: &mut ~str) {
+        loop {
+            let c = self.next_char();
+            if is_digit(c) {
+                str::push_char(repr, c);
+            } else {
+                self.unget_char(c);
+                return;
+            }
+        }
     }
 }
 
-fn parser(input_port: comm::Port<ProgressMsg>, state : ParserState) -> CssLexer {
+fn lexer(input_port: comm::Port<ProgressMsg>) -> CssLexer {
+    let start = SourcePosition { line: 1u, column: 1u };
     return {
            input_state: {
                mut lookahead: None,
@@ -233,31 +617,42 @@ fn parser(input_port: comm::Port<ProgressMsg>, state : ParserState) -> CssLexer
                input_port: input_port,
                mut eof: false
            },
-           mut parser_state: state
+           mut lookahead: ~[],
+           mut position: start,
+           mut last: (EOF_CHAR, start)
     };
 }
 
-fn lex_css_from_bytes(input_port: comm::Port<ProgressMsg>, result_chan : &Chan<Token>) {
-    let lexer = parser(input_port, CssElement);
+fn lex_css_from_bytes(input_port: comm::Port<ProgressMsg>,
+                      result_chan : &Chan<(Token, SourcePosition)>) {
+    let lexer = lexer(input_port);
 
     loop {
-        let token = lexer.parse_css();
+        lexer.consume_comments();
+        let position = lexer.token_position();
+        let token = lexer.consume_token();
+        #debug["token=%? at %?", token, position];
         let should_break = match token { Eof => true, _ => false };
 
-        result_chan.send(move token);
+        result_chan.send((move token, position));
 
-        if should_break { 
+        if should_break {
             break;
         }
     }
 }
 
-fn spawn_css_lexer_from_string(content : ~str) -> pipes::Port<Token> {
+fn spawn_css_lexer_from_string(content : ~str) -> pipes::Port<(Token, SourcePosition)> {
+    spawn_css_lexer_from_bytes(str::to_bytes(content))
+}
+
+/** Lexes a style sheet that was already loaded. */
+pub fn spawn_css_lexer_from_bytes(content : ~[u8]) -> pipes::Port<(Token, SourcePosition)> {
     let (result_chan, result_port) = pipes::stream();
 
     do task::spawn |move result_chan, move content| {
         let input_port = comm::Port();
-        input_port.send(Payload(str::to_bytes(content)));
+        input_port.send(Payload(move content));
         input_port.send(Done(Ok(())));
 
         lex_css_from_bytes(input_port, &result_chan);
@@ -267,11 +662,11 @@ fn spawn_css_lexer_from_string(content : ~str) -> pipes::Port<Token> {
 }
 
 #[allow(non_implicitly_copyable_typarams)]
-pub fn spawn_css_lexer_task(url: Url, resource_task: ResourceTask) -> pipes::Port<Token> {
+pub fn spawn_css_lexer_task(url: Url, resource_task: ResourceTask)
+    -> pipes::Port<(Token, SourcePosition)> {
     let (result_chan, result_port) = pipes::stream();
 
     do task::spawn |move result_chan, move url| {
-        assert url.path.ends_with(".css");
         let input_port = Port();
         // TODO: change copy to move once the compiler permits it
         resource_task.send(Load(copy url, input_port.chan()));
@@ -281,3 +676,86 @@ pub fn spawn_css_lexer_task(url: Url, resource_task: ResourceTask) -> pipes::Por
 
     return move result_port;
 }
+
+#[cfg(test)]
+mod test {
+    fn lex(input: ~str) -> ~[Token] {
+        let port = spawn_css_lexer_from_string(move input);
+        let mut tokens = ~[];
+        loop {
+            match port.recv() {
+              (Eof, _) => return move tokens,
+              (tok, _) => push(&mut tokens, move tok)
+            }
+        }
+    }
+
+    fn num(repr: ~str) -> NumericValue {
+        NumericValue { representation: move repr, value: 0.0, int_value: None }
+    }
+
+    #[test]
+    fn should_lex_rule() {
+        let tokens = lex(~"p#id.cls > a:hover { color: red; }");
+        assert tokens == ~[Ident(~"p"), Hash(~"id", true), Delim('.'), Ident(~"cls"), Whitespace,
+                           Delim('>'), Whitespace, Ident(~"a"), Colon, Ident(~"hover"),
+                           Whitespace, OpenCurly, Whitespace, Ident(~"color"), Colon, Whitespace,
+                           Ident(~"red"), Semicolon, Whitespace, CloseCurly];
+    }
+
+    #[test]
+    fn should_lex_numbers() {
+        let tokens = lex(~"12px -1.5em 50% +3 1e2 #123");
+        assert tokens == ~[Dimension(num(~"12"), ~"px"), Whitespace,
+                           Dimension(num(~"-1.5"), ~"em"), Whitespace, Percentage(num(~"50")),
+                           Whitespace, Number(num(~"+3")), Whitespace, Number(num(~"1e2")),
+                           Whitespace, Hash(~"123", false)];
+
+        match tokens[0] {
+          Dimension(ref n, _) => assert n.value == 12.0 && n.int_value == Some(12),
+          _ => fail
+        }
+        match tokens[2] {
+          Dimension(ref n, _) => assert n.value == -1.5 && n.int_value.is_none(),
+          _ => fail
+        }
+        match tokens[8] {
+          Number(ref n) => assert n.value == 100.0,
+          _ => fail
+        }
+    }
+
+    #[test]
+    fn should_skip_comments_and_lex_strings() {
+        let tokens = lex(~"/* comment */@import \"a\\\"b\" url( foo.css );");
+        assert tokens == ~[AtKeyword(~"import"), Whitespace, String(~"a\"b"), Whitespace,
+                           Url(~"foo.css"), Semicolon];
+    }
+
+    #[test]
+    fn should_recover_from_bad_strings_and_urls() {
+        let tokens = lex(~"'abc\nurl(a b) x");
+        assert tokens == ~[BadString, Whitespace, BadUrl, Whitespace, Ident(~"x")];
+    }
+
+    #[test]
+    fn should_lex_escapes_and_utf8() {
+        let tokens = lex(~"\\41 b café <!-- -->");
+        assert tokens == ~[Ident(~"Ab"), Whitespace, Ident(~"café"), Whitespace, CDO,
+                           Whitespace, CDC];
+    }
+
+    #[test]
+    fn should_track_positions() {
+        let port = spawn_css_lexer_from_string(~"a {\r\n  /* x */ b: c }");
+        let mut positions = ~[];
+        loop {
+            match port.recv() {
+              (Eof, _) => break,
+              (Whitespace, _) => {}
+              (_, position) => push(&mut positions, (position.line, position.column))
+            }
+        }
+        assert positions == ~[(1u, 1u), (1u, 3u), (2u, 11u), (2u, 12u), (2u, 14u), (2u, 16u)];
+    }
+}
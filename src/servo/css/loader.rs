@@ -0,0 +1,188 @@
+/*!
+Loads author style sheets together with the style sheets they import.
+The rules of an imported style sheet come before those of the style
+sheet importing it, in the order of the '@import' rules, and only apply
+to the media the '@import' rule lists (CSS 2.1 Section 6.3). An
+imported style sheet that fails to load, or that is not text, is
+dropped like any other invalid construct.
+*/
+
+use css::lexer::{SourcePosition, Token, spawn_css_lexer_from_bytes, spawn_css_lexer_from_string,
+                 spawn_css_lexer_task};
+use css::parser::{Diagnostic, parse_stylesheet_with_imports};
+use css::values::{MediaQueryList, Rule, Stylesheet};
+use resource::resource_task::{Done, Load, Payload, ResourceTask};
+use std::net::url::Url;
+use util::url::make_url;
+
+/** Loads the style sheet at `url` and the style sheets it imports. */
+pub fn load_stylesheet(url: Url, resource_task: ResourceTask) -> Stylesheet {
+    let stream = spawn_css_lexer_task(copy url, resource_task);
+    let url_str = url.to_str();
+    parse_with_imports(move stream, &url, url_str, resource_task, ~[copy url_str])
+}
+
+/**
+Parses the text of a <style> element, loading the style sheets it
+imports relative to the URL of its document.
+*/
+pub fn load_stylesheet_from_string(text: ~str, document_url: Url,
+                                   resource_task: ResourceTask) -> Stylesheet {
+    let stream = spawn_css_lexer_from_string(move text);
+    parse_with_imports(move stream, &document_url, ~"<style>", resource_task, ~[])
+}
+
+/**
+Parses a style sheet, putting the rules of the style sheets it imports
+before its own. `importers` holds the URL of this style sheet and those
+of the style sheets that import it, directly or not. Importing one of
+them again would be a cycle, so such an '@import' rule is dropped.
+*/
+fn parse_with_imports(stream: pipes::Port<(Token, SourcePosition)>, base_url: &Url,
+                      name: &str, resource_task: ResourceTask,
+                      importers: ~[~str]) -> Stylesheet {
+    let (imports, rules, diagnostics) = parse_stylesheet_with_imports(move stream);
+    for diagnostics.each |diagnostic| {
+        report(name, diagnostic);
+    }
+
+    let mut result = ~[];
+    for imports.each |import| {
+        let url = make_url(copy import.url, Some(copy *base_url));
+        let url_str = url.to_str();
+        if importers.contains(&url_str) {
+            report(name, &Diagnostic {
+                message: #fmt["Ignored @import of '%s', which imports this style sheet", url_str],
+                position: import.position
+            });
+            loop;
+        }
+
+        let bytes = match fetch_stylesheet(&url, resource_task) {
+          Ok(move bytes) => move bytes,
+          Err(move reason) => {
+            report(name, &Diagnostic {
+                message: #fmt["Ignored @import of '%s', which %s", url_str, reason],
+                position: import.position
+            });
+            loop;
+          }
+        };
+        let stream = spawn_css_lexer_from_bytes(move bytes);
+        let imported = parse_with_imports(move stream, &url, url_str, resource_task,
+                                          vec::append_one(copy importers, copy url_str));
+        for imported.each |rule| {
+            vec::push(&mut result, with_import_media(*rule, &import.media));
+        }
+    }
+    vec::push_all_move(&mut result, move rules);
+    return move result;
+}
+
+fn report(name: &str, diagnostic: &Diagnostic) {
+    #warn("%s:%u:%u: %s", name, diagnostic.position.line, diagnostic.position.column,
+          diagnostic.message);
+}
+
+/**
+Loads the whole of a style sheet, or says why it can't be used: it
+failed to load, or it holds binary data, which no style sheet does
+(the binary data bytes of MIME Sniffing Section 7.1, among the first
+512 bytes).
+*/
+fn fetch_stylesheet(url: &Url, resource_task: ResourceTask) -> Result<~[u8], ~str> {
+    let progress_port = comm::Port();
+    resource_task.send(Load(copy *url, progress_port.chan()));
+    let mut bytes = ~[];
+    loop {
+        match progress_port.recv() {
+          Payload(move data) => vec::push_all_move(&mut bytes, move data),
+          Done(Ok(())) => { break; }
+          Done(Err(())) => { return Err(~"failed to load"); }
+        }
+    }
+
+    for uint::range(0u, uint::min(bytes.len(), 512u)) |i| {
+        let b = bytes[i];
+        if b <= 0x08u8 || b == 0x0Bu8 || (b >= 0x0Eu8 && b <= 0x1Au8)
+            || (b >= 0x1Cu8 && b <= 0x1Fu8) {
+            return Err(~"is not a style sheet");
+        }
+    }
+    Ok(move bytes)
+}
+
+/** Makes an imported rule apply only to the media of the '@import' rule. */
+fn with_import_media(rule: &Rule, media: &MediaQueryList) -> ~Rule {
+    let (selectors, decls, important_decls, rule_media) = copy *rule;
+    // an empty list means all media, so there is nothing to add
+    let rule_media = if media.is_empty() { move rule_media } else { ~[copy *media] + rule_media };
+    return ~(move selectors, move decls, move important_decls, move rule_media);
+}
+
+#[cfg(test)]
+mod test {
+    use css::values::Element;
+    use resource::resource_task::{Exit, ProgressMsg, create_resource_task_with_loaders};
+    use std::net::url;
+
+    // Serves style sheets from a fixed set, by path.
+    fn test_resource_task() -> ResourceTask {
+        let loader_factory = fn~(url: Url, progress_chan: comm::Chan<ProgressMsg>) {
+            let text = match copy url.path {
+              ~"/a.css" => ~"@import 'sub/b.css' print; @import url(c.css); a {}",
+              ~"/sub/b.css" => ~"@import url(\"../a.css\"); @import url(b.css); b {}",
+              ~"/c.css" => ~"@charset \"utf-8\"; c {} @import url(b.css);",
+              ~"/d.css" => ~"@import 'missing.css'; @import 'e.php?v=2'; @import 'f'; "
+                           + ~"@import 'image.png'; d {}",
+              ~"/e.php" => ~"e {}",
+              ~"/f" => ~"f {}",
+              ~"/image.png" => ~"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR",
+              _ => {
+                progress_chan.send(Done(Err(())));
+                return;
+              }
+            };
+            progress_chan.send(Payload(str::to_bytes(text)));
+            progress_chan.send(Done(Ok(())));
+        };
+        create_resource_task_with_loaders(~[(~"test", move loader_factory)])
+    }
+
+    #[test]
+    fn should_splice_imported_rules_and_skip_cycles() {
+        let resource_task = test_resource_task();
+        let sheet = load_stylesheet(url::from_str(~"test://host/a.css").get(), resource_task);
+        resource_task.send(Exit);
+
+        // b.css imports a.css and itself, which are both cycles, and the
+        // @import in c.css comes after a rule, so it is ignored.
+        let tags = do sheet.map |rule| {
+            let (selectors, _, _, media) = copy **rule;
+            let tag = match *selectors[0] {
+              Element(ref tag, _) => copy *tag,
+              _ => fail
+            };
+            (move tag, media.len())
+        };
+        assert tags == ~[(~"b", 1u), (~"c", 0u), (~"a", 0u)];
+    }
+
+    #[test]
+    fn should_import_any_url_and_drop_failed_imports() {
+        let resource_task = test_resource_task();
+        let sheet = load_stylesheet(url::from_str(~"test://host/d.css").get(), resource_task);
+        resource_task.send(Exit);
+
+        // The missing style sheet and the image are dropped; the others
+        // are imported whatever their URLs look like.
+        let tags = do sheet.map |rule| {
+            let (selectors, _, _, _) = copy **rule;
+            match *selectors[0] {
+              Element(ref tag, _) => copy *tag,
+              _ => fail
+            }
+        };
+        assert tags == ~[~"e", ~"f", ~"d"];
+    }
+}
@@ -0,0 +1,131 @@
+/*!
+Evaluates media queries (Media Queries Level 3) against the device that
+the document is laid out for.
+*/
+
+use au = gfx::geometry;
+use css::resolve::apply::{LengthContext, MEDIUM_FONT_SIZE_PX, length_to_au};
+use css::values::*;
+use geom::size::Size2D;
+use gfx::geometry::Au;
+
+/** What media queries are tested against. */
+pub struct Device {
+    media_type: MediaType,
+    // the size of the viewport
+    width: Au,
+    height: Au,
+    // device pixels per 'px'
+    resolution: float
+}
+
+/** A screen showing a viewport of the given size. */
+pub fn Device(width: Au, height: Au) -> Device {
+    Device {
+        media_type: MediaScreen,
+        width: width,
+        height: height,
+        resolution: 1.0
+    }
+}
+
+impl Device : cmp::Eq {
+    pure fn eq(other: &Device) -> bool {
+        let same_type = match (self.media_type, other.media_type) {
+          (MediaAll, MediaAll) | (MediaScreen, MediaScreen) | (MediaPrint, MediaPrint)
+          | (MediaUnknown, MediaUnknown) => true,
+          _ => false
+        };
+        same_type && self.width == other.width && self.height == other.height
+            && self.resolution == other.resolution
+    }
+    pure fn ne(other: &Device) -> bool {
+        !self.eq(other)
+    }
+}
+
+/** Compares a device value with the value of a feature with the given range. */
+pure fn in_range<T: Copy Eq Ord>(range: MediaRange, device_value: T, value: T) -> bool {
+    match range {
+      MediaMin => device_value >= value,
+      MediaMax => device_value <= value,
+      MediaExact => device_value == value
+    }
+}
+
+impl Device {
+    pure fn matches_type(media_type: MediaType) -> bool {
+        match (media_type, self.media_type) {
+          (MediaAll, _) => true,
+          (MediaScreen, MediaScreen) | (MediaPrint, MediaPrint) => true,
+          _ => false
+        }
+    }
+
+    pure fn matches_feature(feature: &MediaFeature) -> bool {
+        // 'em' and 'rem' in media queries are relative to the initial font size
+        let lengths = LengthContext {
+            font_size: au::from_px(MEDIUM_FONT_SIZE_PX),
+            root_font_size: au::from_px(MEDIUM_FONT_SIZE_PX),
+            viewport: Size2D(self.width, self.height)
+        };
+        match *feature {
+          MediaWidth(range, len) => in_range(range, self.width, length_to_au(len, &lengths)),
+          MediaHeight(range, len) => in_range(range, self.height, length_to_au(len, &lengths)),
+          // a square viewport is portrait
+          MediaPortrait(portrait) => (self.height >= self.width) == portrait,
+          MediaResolution(range, dppx) => in_range(range, self.resolution, dppx)
+        }
+    }
+
+    pure fn matches_query(query: &MediaQuery) -> bool {
+        let matches = self.matches_type(query.media_type)
+            && query.features.all(|feature| self.matches_feature(feature));
+        matches != query.negated
+    }
+
+    /** Checks a media query list. An empty list matches everything. */
+    pure fn matches_query_list(queries: &MediaQueryList) -> bool {
+        queries.is_empty() || queries.any(|query| self.matches_query(query))
+    }
+
+    /** Checks whether every media query list a rule is nested in matches. */
+    pure fn matches_rule(rule: &Rule) -> bool {
+        let (_, _, _, ref media) = *rule;
+        media.all(|queries| self.matches_query_list(queries))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    fn query(negated: bool, media_type: MediaType, features: ~[MediaFeature]) -> MediaQuery {
+        MediaQuery { negated: negated, media_type: media_type, features: move features }
+    }
+
+    #[test]
+    fn should_match_viewport_size() {
+        let device = Device(au::from_px(800), au::from_px(600));
+        assert device.matches_query(&query(false, MediaScreen,
+                                           ~[MediaWidth(MediaMin, Px(800.0))]));
+        assert !device.matches_query(&query(false, MediaScreen,
+                                            ~[MediaWidth(MediaMin, Px(801.0))]));
+        assert device.matches_query(&query(false, MediaAll, ~[MediaHeight(MediaMax, Em(40.0))]));
+        assert !device.matches_query(&query(false, MediaAll, ~[MediaPortrait(true)]));
+        assert device.matches_query(&query(false, MediaAll,
+                                           ~[MediaResolution(MediaExact, 1.0)]));
+    }
+
+    #[test]
+    fn should_match_media_types() {
+        let device = Device(au::from_px(800), au::from_px(600));
+        assert device.matches_query(&query(false, MediaAll, ~[]));
+        assert !device.matches_query(&query(false, MediaPrint, ~[]));
+        assert device.matches_query(&query(true, MediaPrint, ~[]));
+        assert !device.matches_query(&query(false, MediaUnknown, ~[]));
+
+        // a list matches if any query does, and an empty list always does
+        assert device.matches_query_list(&~[query(false, MediaPrint, ~[]),
+                                            query(false, MediaScreen, ~[])]);
+        assert device.matches_query_list(&~[]);
+    }
+}
@@ -1,226 +1,1096 @@
 /**
 Constructs a list of css style rules from a token stream
-*/
 
-// TODO: fail according to the css spec instead of failing when things
-// are not as expected
+Errors are handled as in CSS 2.1 Section 4.2: an invalid declaration
+is skipped up to the next ';', and a rule with invalid selectors is
+skipped along with its description, but the rest of the style sheet
+is kept. Everything dropped is reported as a `Diagnostic`.
+*/
 
 use css::values::*;
 // Disambiguate parsed Selector, Rule values from tokens
 use css = css::values;
 use tok = lexer;
-use lexer::Token;
+use lexer::{Token, SourcePosition};
 use comm::recv;
+use core::dvec::DVec;
 use option::{map, is_none};
 use vec::push;
 use parser_util::*;
 use util::color::parsing::parse_color;
-use vec::push;
 
-type TokenReader = {stream : pipes::Port<Token>, mut lookahead : Option<Token>};
+/** An '@import' rule: the URL of a style sheet, as written, and the media it applies to. */
+pub struct Import {
+    url: ~str,
+    media: MediaQueryList,
+    // where the rule starts in the importing style sheet
+    position: SourcePosition
+}
+
+/** A construct that was dropped from a style sheet, and where it started. */
+pub struct Diagnostic {
+    message: ~str,
+    position: SourcePosition
+}
+
+type TokenReader = {
+    stream : pipes::Port<(Token, SourcePosition)>,
+    mut lookahead : Option<(Token, SourcePosition)>,
+    // where the token returned last by `get` starts
+    mut position : SourcePosition,
+    // the '@import' rules at the start of the style sheet
+    imports : DVec<Import>,
+    diagnostics : DVec<Diagnostic>
+};
 
 trait TokenReaderMethods {
     fn get() -> Token;
     fn unget(+tok : Token);
+    fn skip_whitespace() -> bool;
+    fn error_at(position: SourcePosition, +message: ~str);
+    fn error(+message: ~str);
 }
 
 impl TokenReader : TokenReaderMethods {
     fn get() -> Token {
-        match copy self.lookahead {
-          Some(tok) => { self.lookahead = None; copy tok }
+        let (tok, position) = match copy self.lookahead {
+          Some(lookahead) => { self.lookahead = None; lookahead }
           None => { self.stream.recv() }
-        }
+        };
+        self.position = position;
+        move tok
     }
 
     fn unget(tok : Token) {
         assert is_none(&self.lookahead);
-        self.lookahead = Some(move tok);
+        self.lookahead = Some((move tok, self.position));
+    }
+
+    /** Skips whitespace tokens, returning whether there were any. */
+    fn skip_whitespace() -> bool {
+        let mut skipped = false;
+        loop {
+            match self.get() {
+              tok::Whitespace => { skipped = true; }
+              token => { self.unget(move token); return skipped; }
+            }
+        }
+    }
+
+    fn error_at(position: SourcePosition, message: ~str) {
+        #debug["CSS parse error at %u:%u: %s", position.line, position.column, message];
+        self.diagnostics.push(Diagnostic { message: move message, position: position });
+    }
+
+    /** Reports an error at the token returned last by `get`. */
+    fn error(message: ~str) {
+        self.error_at(self.position, move message);
     }
 }
 
+/*
+The parsing methods return None for something invalid, after reporting
+it and putting back the token at fault. Their callers then skip to the
+end of the enclosing construct.
+*/
 trait ParserMethods {
     fn parse_element() -> Option<~css::Selector>;
+    fn parse_attribute() -> Option<css::Attr>;
+    fn parse_pseudo_class() -> Option<css::PseudoClass>;
+    fn parse_arguments() -> ~[Token];
     fn parse_selector() -> Option<~[~css::Selector]>;
-    fn parse_description() -> Option<~[StyleDeclaration]>;
-    fn parse_rule() -> Option<~css::Rule>;
+    fn parse_value() -> ~[Token];
+    fn parse_description() -> (~[StyleDeclaration], ~[StyleDeclaration]);
+    fn parse_rule(media: &[MediaQueryList]) -> Option<~css::Rule>;
+    fn parse_rule_list(media: &[MediaQueryList], nested: bool) -> ~[~css::Rule];
+    fn parse_media_rule(media: &[MediaQueryList]) -> ~[~css::Rule];
+    fn parse_media_prelude(position: SourcePosition, prelude: &[Token]) -> MediaQueryList;
+    fn parse_import_rule();
+    fn skip_block();
+    fn skip_rule();
+    fn skip_at_rule();
 }
 
 impl TokenReader : ParserMethods {
     fn parse_element() -> Option<~css::Selector> {
-        // Get the current element type
-         let elmt_name = match self.get() {
-           lexer::Element(tag) => { copy tag }
-           lexer::Eof => { return None; }
-           _ => { fail ~"Expected an element" }
-         };
-
-         let mut attr_list = ~[];
-
-         // Get the attributes associated with that element
-         loop {
-             let token = self.get();
-             match token {
-               lexer::Attr(attr) => { push(&mut attr_list, copy attr); }
-               tok::StartDescription | tok::Descendant | tok::Child | tok::Sibling | tok::Comma => {
-                 self.unget(move token); 
-                 break;
-               }
-               tok::Eof => { return None; }
-               tok::Element(_) => fail ~"Unexpected second element without relation to first element",
-               tok::EndDescription => fail ~"Unexpected '}'",
-               tok::Description(_, _) => fail ~"Unexpected description"
-             }
-         }
-        return Some(~css::Element(move elmt_name, move attr_list));
+        // Get the current element type, if there is one
+        let elmt_name = match self.get() {
+          tok::Ident(tag) => Some(str::to_lower(tag)),
+          tok::Delim('*') => Some(~"*"),
+          token => { self.unget(move token); None }
+        };
+
+        let mut attr_list = ~[];
+
+        // Get the attributes associated with that element
+        loop {
+            match self.get() {
+              tok::Hash(name, true) => { push(&mut attr_list, css::Id(move name)); }
+              tok::Delim('.') => {
+                match self.get() {
+                  tok::Ident(name) => { push(&mut attr_list, css::Class(move name)); }
+                  token => {
+                    self.error(#fmt["Expected a class name, found %?", token]);
+                    self.unget(move token);
+                    return None;
+                  }
+                }
+              }
+              tok::OpenSquare => {
+                match self.parse_attribute() {
+                  Some(attr) => { push(&mut attr_list, move attr); }
+                  None => { return None; }
+                }
+              }
+              tok::Colon => {
+                match self.parse_pseudo_class() {
+                  Some(pc) => { push(&mut attr_list, css::Pseudo(move pc)); }
+                  None => { return None; }
+                }
+              }
+              token => { self.unget(move token); break; }
+            }
+        }
+
+        if elmt_name.is_none() && attr_list.len() == 0 {
+            let token = self.get();
+            self.error(#fmt["Expected a selector, found %?", token]);
+            self.unget(move token);
+            return None;
+        }
+        return Some(~css::Element(elmt_name.get_default(~"*"), move attr_list));
+    }
+
+    /** Parses an attribute selector, after its opening '['. */
+    fn parse_attribute() -> Option<css::Attr> {
+        self.skip_whitespace();
+        let attr_name = match self.get() {
+          tok::Ident(name) => str::to_lower(name),
+          token => {
+            self.error(#fmt["Expected an attribute name, found %?", token]);
+            self.unget(move token);
+            return None;
+          }
+        };
+
+        self.skip_whitespace();
+        let op = match self.get() {
+          tok::CloseSquare => { return Some(css::Exists(move attr_name)); }
+          tok::Delim('=') => '=',
+          tok::Delim(c) if c == '~' || c == '|' || c == '^' || c == '$' || c == '*' => {
+            match self.get() {
+              tok::Delim('=') => c,
+              token => {
+                self.error(#fmt["Expected '=' in attribute selector, found %?", token]);
+                self.unget(move token);
+                return None;
+              }
+            }
+          }
+          token => {
+            self.error(#fmt["Unexpected %? in attribute selector", token]);
+            self.unget(move token);
+            return None;
+          }
+        };
+
+        self.skip_whitespace();
+        let attr_val = match self.get() {
+          tok::Ident(val) | tok::String(val) => val,
+          token => {
+            self.error(#fmt["Expected an attribute value, found %?", token]);
+            self.unget(move token);
+            return None;
+          }
+        };
+
+        self.skip_whitespace();
+        let ignore_case = match self.get() {
+          tok::Ident(flag) if str::to_lower(flag) == ~"i" => {
+            self.skip_whitespace();
+            true
+          }
+          token => { self.unget(move token); false }
+        };
+
+        match self.get() {
+          tok::CloseSquare => {}
+          token => {
+            self.error(#fmt["Expected ']', found %?", token]);
+            self.unget(move token);
+            return None;
+          }
+        }
+
+        let attr = match op {
+          '=' => css::Exact(move attr_name, move attr_val),
+          '~' => css::Includes(move attr_name, move attr_val),
+          '|' => css::DashMatch(move attr_name, move attr_val),
+          '^' => css::Prefix(move attr_name, move attr_val),
+          '$' => css::Suffix(move attr_name, move attr_val),
+          _ => css::Substring(move attr_name, move attr_val)
+        };
+        return Some(if ignore_case { css::CaseInsensitive(~move attr) } else { move attr });
     }
 
+    /** Parses a pseudo-class, after its ':'. */
+    fn parse_pseudo_class() -> Option<css::PseudoClass> {
+        match self.get() {
+          tok::Ident(name) => {
+            let pc = match str::to_lower(name) {
+              ~"first-child" => css::FirstChild,
+              ~"last-child" => css::LastChild,
+              ~"root" => css::Root,
+              ~"empty" => css::Empty,
+              ~"link" => css::Link,
+              ~"hover" => css::Hover,
+              ~"focus" => css::Focus,
+              _ => {
+                self.error(#fmt["Unsupported pseudo-class ':%s'", name]);
+                self.unget(tok::Ident(move name));
+                return None;
+              }
+            };
+            Some(move pc)
+          }
+          tok::Function(name) => {
+            match str::to_lower(name) {
+              ~"nth-child" => {
+                let position = self.position;
+                let args = self.parse_arguments();
+                match serialize_value(args).chain(|s| parse_nth(s)) {
+                  Some((a, b)) => Some(css::NthChild(a, b)),
+                  None => {
+                    self.error_at(position, ~"Invalid argument to ':nth-child()'");
+                    None
+                  }
+                }
+              }
+              ~"not" => {
+                self.skip_whitespace();
+                let sel = match self.parse_element() {
+                  Some(sel) => sel,
+                  None => { return None; }
+                };
+                self.skip_whitespace();
+                match self.get() {
+                  tok::CloseParen => Some(css::Not(move sel)),
+                  token => {
+                    self.error(#fmt["Expected ')' after the argument of ':not(', found %?",
+                                    token]);
+                    self.unget(move token);
+                    None
+                  }
+                }
+              }
+              _ => {
+                self.error(#fmt["Unsupported pseudo-class ':%s()'", name]);
+                self.parse_arguments();
+                None
+              }
+            }
+          }
+          token => {
+            self.error(#fmt["Expected a pseudo-class name, found %?", token]);
+            self.unget(move token);
+            None
+          }
+        }
+    }
+
+    /**
+    Collects the tokens of a function's arguments, after its opening
+    '(', up to and including the matching ')'.
+    */
+    fn parse_arguments() -> ~[Token] {
+        let mut args = ~[];
+        let mut depth = 0u;
+        loop {
+            let token = self.get();
+            match token {
+              tok::CloseParen if depth == 0u => { return move args; }
+              tok::Eof => { self.unget(move token); return move args; }
+              tok::Function(_) | tok::OpenParen => { depth += 1u; }
+              tok::CloseParen => { depth -= 1u; }
+              _ => {}
+            }
+            push(&mut args, move token);
+        }
+    }
+
+    /** Parses the selectors of a rule, up to and including the '{' of its description. */
     fn parse_selector() -> Option<~[~css::Selector]> {
         let mut sel_list = ~[];
 
         // Collect all the selectors that this rule applies to
         loop {
-            let mut cur_sel;
-
-            match self.parse_element() {
-              Some(elmt) => { cur_sel = copy elmt; }
-              None => { return None; } // we hit an eof in the middle of a rule
-            }
+            self.skip_whitespace();
+            let mut cur_sel = match self.parse_element() {
+              Some(elmt) => elmt,
+              None => { return None; }
+            };
 
             loop {
-                let tok = self.get();
+                // whitespace on its own is the descendant combinator
+                let saw_whitespace = self.skip_whitespace();
+                let token = self.get();
                 let built_sel <- cur_sel;
-                
-                match tok {
-                  tok::Descendant => {
-                    match self.parse_element() {
-                      Some(elmt) => { 
-                        let new_sel = copy elmt;
-                        cur_sel <- ~css::Descendant(move built_sel, move new_sel)
-                      }
-                      None => { return None; }
-                    }
-                  }
-                  tok::Child => {
-                    match self.parse_element() {
-                      Some(elmt) => { 
-                        let new_sel = copy elmt;
-                        cur_sel <- ~css::Child(move built_sel, move new_sel)
-                      }
-                      None => { return None; }
-                    }
-                  }
-                  tok::Sibling => {
-                    match self.parse_element() {
-                      Some(elmt) => { 
-                        let new_sel = copy elmt;
-                        cur_sel <- ~css::Sibling(move built_sel, move new_sel)
-                      }
+
+                match token {
+                  tok::Delim('>') | tok::Delim('+') | tok::Delim('~') => {
+                    self.skip_whitespace();
+                    let new_sel = match self.parse_element() {
+                      Some(elmt) => elmt,
                       None => { return None; }
-                    }
+                    };
+                    cur_sel <- match token {
+                      tok::Delim('>') => ~css::Child(move built_sel, move new_sel),
+                      tok::Delim('+') => ~css::Sibling(move built_sel, move new_sel),
+                      _ => ~css::GeneralSibling(move built_sel, move new_sel)
+                    };
                   }
-                  tok::StartDescription => {
+                  tok::OpenCurly => {
                     push(&mut sel_list, move built_sel);
-                    self.unget(tok::StartDescription);
-                    break;
+                    return Some(move sel_list);
                   }
                   tok::Comma => {
                     push(&mut sel_list, move built_sel);
-                    self.unget(tok::Comma);
                     break;
                   }
-                  tok::Attr(_) | tok::EndDescription | tok::Element(_) | tok::Description(_, _) => {
-                    fail #fmt["Unexpected token %? in elements", tok];
+                  tok::Eof => {
+                    self.error(~"Unexpected end of style sheet in selector");
+                    self.unget(move token);
+                    return None;
+                  }
+                  _ if saw_whitespace => {
+                    self.unget(move token);
+                    let new_sel = match self.parse_element() {
+                      Some(elmt) => elmt,
+                      None => { return None; }
+                    };
+                    cur_sel <- ~css::Descendant(move built_sel, move new_sel);
+                  }
+                  _ => {
+                    self.error(#fmt["Unexpected %? in selector", token]);
+                    self.unget(move token);
+                    return None;
                   }
-                  tok::Eof => { return None; }
                 }
             }
+        }
+    }
 
-            // check if we should break out of the nesting loop as well
-            // TODO: fix this when rust gets labelled loops
-            let tok = self.get();
-            match tok {
-              tok::StartDescription => { break; }
-              tok::Comma => { }
-              _ => { self.unget(move tok); }
+    /**
+    Collects the tokens of a declaration's value, up to the ';' that
+    ends it (which is consumed) or the '}' that ends the description
+    (which is not). Blocks and functions in the value are kept whole.
+    */
+    fn parse_value() -> ~[Token] {
+        let mut value = ~[];
+        let mut depth = 0u;
+        loop {
+            let token = self.get();
+            match token {
+              tok::Semicolon if depth == 0u => { return move value; }
+              tok::CloseCurly if depth == 0u => {
+                self.unget(move token);
+                return move value;
+              }
+              tok::Eof => { self.unget(move token); return move value; }
+              tok::Function(_) | tok::OpenParen | tok::OpenSquare | tok::OpenCurly => {
+                depth += 1u;
+              }
+              tok::CloseParen | tok::CloseSquare | tok::CloseCurly if depth > 0u => {
+                depth -= 1u;
+              }
+              _ => {}
             }
+            push(&mut value, move token);
         }
-        
-        return Some(move sel_list);
     }
 
-    fn parse_description() -> Option<~[StyleDeclaration]> {
+    /**
+    Parses the declarations of a rule, after its '{', up to and
+    including its '}'. Invalid declarations are dropped. The values of
+    custom properties, and of declarations that use 'var()', are kept
+    as tokens until the custom properties are computed.
+    */
+    fn parse_description() -> (~[StyleDeclaration], ~[StyleDeclaration]) {
         let mut desc_list : ~[StyleDeclaration]= ~[];
-        
+        let mut important_list : ~[StyleDeclaration]= ~[];
+
         // Get the description to be applied to the selector
         loop {
-            let tok = self.get();
-            match tok {
-              tok::EndDescription => { break; }
-              tok::Description(prop, val) => {
-                let desc : Option<StyleDeclaration> = match prop {
-                  // TODO: have color parsing return a ParseResult instead of a real value
-                  ~"background-color" => parse_color(val).map(|res| BackgroundColor(Specified(BgColor(*res)))),
-                  ~"color" => parse_color(val).map(|res| Color(Specified(TextColor(*res)))),
-                  ~"display" => parse_display_type(val).extract(|res| Display(res)),
-                  ~"font-size" => parse_font_size(val).extract(|res| FontSize(res)),
-                  ~"height" => parse_box_sizing(val).extract(|res| Height(res)),
-                  ~"width" => parse_box_sizing(val).extract(|res| Width(res)),
-                    ~"border-width" => parse_length(val).map(|res| BorderWidth(Specified(*res))),
-                    ~"border-color" => parse_color(val).map(|res| BorderColor(Specified(BdrColor(*res)))),
-                    ~"position" => parse_position(val).extract(|res| Position(res)),
-                    ~"top" => parse_length(val).map(|res| Top(Specified(*res))),
-                    ~"right" => parse_length(val).map(|res| Right(Specified(*res))),
-                    ~"bottom" => parse_length(val).map(|res| Bottom(Specified(*res))),
-                    ~"left" => parse_length(val).map(|res| Left(Specified(*res))),
-                  _ => { #debug["Recieved unknown style property '%s'", val]; None }
+            match self.get() {
+              tok::CloseCurly => { break; }
+              tok::Eof => {
+                // the rule is closed by the end of the style sheet
+                self.unget(tok::Eof);
+                break;
+              }
+              tok::Whitespace | tok::Semicolon => {}
+              tok::Ident(prop) => {
+                let position = self.position;
+                self.skip_whitespace();
+                match self.get() {
+                  tok::Colon => {}
+                  token => {
+                    self.error(#fmt["Expected ':' after property '%s', found %?", prop, token]);
+                    self.unget(move token);
+                    self.parse_value();
+                    loop;
+                  }
+                }
+
+                let (value, important) = split_importance(self.parse_value());
+                let list = if important { &mut important_list } else { &mut desc_list };
+                // custom property names are case-sensitive
+                if prop.starts_with("--") {
+                    let value = match value.position(|t| *t != tok::Whitespace) {
+                      Some(start) => vec::slice(value, start, value.len()),
+                      None => ~[]
+                    };
+                    vec::push(list, CustomProperty(prop, move value));
+                    loop;
+                }
+                let prop = str::to_lower(prop);
+                if references_variables(value) {
+                    vec::push(list, VariableDeclaration(prop, move value));
+                    loop;
+                }
+                let val = match serialize_value(value) {
+                  Some(val) => val,
+                  None => {
+                    self.error_at(position, #fmt["Invalid value for property '%s'", prop]);
+                    loop;
+                  }
                 };
-                match desc {
-                  Some(d) => push(&mut desc_list, d),
-                  None => { #debug["Couldn't parse value '%s' for property '%s'", val, prop] }
+                match parse_declaration(copy prop, val) {
+                  Ok(decls) => vec::push_all_move(list, move decls),
+                  Err(message) => self.error_at(position, move message)
                 }
               }
-              tok::Eof => { return None; }
-              tok::StartDescription | tok::Descendant |  tok::Child | tok::Sibling 
-              | tok::Comma | tok::Element(_) | tok::Attr(_) => {
-                fail #fmt["Unexpected token %? in description", tok]; 
+              token => {
+                self.error(#fmt["Expected a declaration, found %?", token]);
+                self.unget(move token);
+                self.parse_value();
               }
             }
         }
-        
-        return Some(move desc_list);
+
+        return (move desc_list, move important_list);
     }
 
-    fn parse_rule() -> Option<~css::Rule> {
+    /**
+    Parses a rule, or returns None if its selectors are invalid, in
+    which case the whole rule is skipped. `media` holds the media query
+    lists of the '@media' rules around it.
+    */
+    fn parse_rule(media: &[MediaQueryList]) -> Option<~css::Rule> {
         // TODO: get rid of copies once match move works
         let sel_list = match self.parse_selector() {
           Some(list) => { copy list }
-          None => { return None; }
+          None => {
+            self.skip_rule();
+            return None;
+          }
         };
 
         #debug("sel_list: %?", sel_list);
-        
+
         // Get the description to be applied to the selector
-        let desc_list = match self.parse_description() {
-          Some(list) => { copy list }
-          None => { return None; }
-        };
+        let (desc_list, important_list) = self.parse_description();
 
         #debug("desc_list: %?", desc_list);
-        
-        return Some(~(move sel_list, move desc_list));
+        #debug("important_list: %?", important_list);
+
+        return Some(~(move sel_list, move desc_list, move important_list, vec::from_slice(media)));
+    }
+
+    /**
+    Parses rules and at-rules up to the end of the style sheet or, if
+    `nested`, up to and including the '}' that closes the block they
+    are in.
+    */
+    fn parse_rule_list(media: &[MediaQueryList], nested: bool) -> ~[~css::Rule] {
+        let mut rule_list = ~[];
+        // '@import' rules must come before all other rules
+        let mut imports_allowed = !nested;
+        loop {
+            match self.get() {
+              tok::Whitespace | tok::CDO | tok::CDC => {}
+              tok::Eof => { self.unget(tok::Eof); break; }
+              tok::CloseCurly if nested => { break; }
+              tok::CloseCurly => { self.error(~"Unexpected '}' outside of a block"); }
+              tok::AtKeyword(name) => {
+                match str::to_lower(name) {
+                  ~"import" if imports_allowed => { self.parse_import_rule(); }
+                  ~"import" => {
+                    self.error(~"Ignored @import rule after other rules");
+                    self.skip_at_rule();
+                  }
+                  ~"media" => {
+                    imports_allowed = false;
+                    vec::push_all_move(&mut rule_list, self.parse_media_rule(media));
+                  }
+                  // the encoding was dealt with when the bytes were decoded
+                  ~"charset" => { self.skip_at_rule(); }
+                  _ => {
+                    imports_allowed = false;
+                    self.error(#fmt["Ignored unsupported @%s rule", name]);
+                    self.skip_at_rule();
+                  }
+                }
+              }
+              token => {
+                imports_allowed = false;
+                self.unget(move token);
+                match self.parse_rule(media) {
+                  Some(rule) => { push(&mut rule_list, move rule); }
+                  None => {}
+                }
+              }
+            }
+        }
+        return move rule_list;
+    }
+
+    /**
+    Parses a '@media' rule, after its keyword, into the rules in its
+    block. Each of them carries the rule's media query list after those
+    in `media`.
+    */
+    fn parse_media_rule(media: &[MediaQueryList]) -> ~[~css::Rule] {
+        let position = self.position;
+        let mut prelude = ~[];
+        loop {
+            match self.get() {
+              tok::OpenCurly => { break; }
+              tok::Semicolon => {
+                self.error_at(position, ~"Expected a block after '@media'");
+                return ~[];
+              }
+              tok::Eof => {
+                self.error_at(position, ~"Expected a block after '@media'");
+                self.unget(tok::Eof);
+                return ~[];
+              }
+              token => { push(&mut prelude, move token); }
+            }
+        }
+
+        let queries = self.parse_media_prelude(position, prelude);
+        let media = vec::append_one(vec::from_slice(media), move queries);
+        return self.parse_rule_list(media, true);
+    }
+
+    /** Parses the media query list of an '@media' or '@import' rule that starts at `position`. */
+    fn parse_media_prelude(position: SourcePosition, prelude: &[Token]) -> MediaQueryList {
+        let queries = parse_media_query_list(prelude);
+        if queries.any(|query| query.is_none()) {
+            self.error_at(position, ~"Invalid media query, which never matches");
+        }
+        // an invalid query becomes 'not all' (Media Queries Section 3.1)
+        do queries.map |query| {
+            query.get_default(MediaQuery { negated: true, media_type: MediaAll, features: ~[] })
+        }
+    }
+
+    /**
+    Parses an '@import' rule, after its keyword: a URL, given as a url()
+    or a string, then an optional media query list and a ';'.
+    */
+    fn parse_import_rule() {
+        let position = self.position;
+        self.skip_whitespace();
+        let url = match self.get() {
+          tok::Url(url) | tok::String(url) => Some(url),
+          tok::Function(name) if str::to_lower(name) == ~"url" => {
+            let args = self.parse_arguments().filtered(|token| *token != tok::Whitespace);
+            match args.len() {
+              1u => match copy args[0] { tok::String(url) => Some(url), _ => None },
+              _ => None
+            }
+          }
+          token => { self.unget(move token); None }
+        };
+
+        let mut prelude = ~[];
+        loop {
+            match self.get() {
+              tok::Semicolon => { break; }
+              tok::Eof => { self.unget(tok::Eof); break; }
+              tok::OpenCurly => {
+                self.error_at(position, ~"Unexpected block in @import rule");
+                self.skip_block();
+                return;
+              }
+              token => { push(&mut prelude, move token); }
+            }
+        }
+
+        match url {
+          Some(url) => {
+            let media = self.parse_media_prelude(position, prelude);
+            self.imports.push(Import { url: move url, media: move media, position: position });
+          }
+          None => self.error_at(position, ~"Expected a URL after '@import'")
+        }
+    }
+
+    /** Skips the rest of a block or function, after its opening token. */
+    fn skip_block() {
+        let mut depth = 1u;
+        loop {
+            match self.get() {
+              tok::Function(_) | tok::OpenParen | tok::OpenSquare | tok::OpenCurly => {
+                depth += 1u;
+              }
+              tok::CloseParen | tok::CloseSquare | tok::CloseCurly => {
+                depth -= 1u;
+                if depth == 0u { return; }
+              }
+              tok::Eof => { self.unget(tok::Eof); return; }
+              _ => {}
+            }
+        }
+    }
+
+    /** Skips the rest of a rule: the rest of its selectors, and its description. */
+    fn skip_rule() {
+        loop {
+            match self.get() {
+              tok::OpenCurly => { self.skip_block(); return; }
+              tok::Function(_) | tok::OpenParen | tok::OpenSquare => { self.skip_block(); }
+              tok::Eof => { self.unget(tok::Eof); return; }
+              _ => {}
+            }
+        }
+    }
+
+    /** Skips an at-rule, after its keyword: up to a ';' or through its block. */
+    fn skip_at_rule() {
+        loop {
+            match self.get() {
+              tok::Semicolon => { return; }
+              tok::OpenCurly => { self.skip_block(); return; }
+              tok::Function(_) | tok::OpenParen | tok::OpenSquare => { self.skip_block(); }
+              tok::Eof => { self.unget(tok::Eof); return; }
+              _ => {}
+            }
+        }
+    }
+}
+
+/**
+Parses the value of a property into the declarations it stands for. A
+shorthand property like 'margin' or 'border' expands into declarations of
+each of the properties it sets. Fails with a message for unknown
+properties and invalid values.
+*/
+fn parse_declaration(prop: ~str, val: &str) -> Result<~[StyleDeclaration], ~str> {
+    let decls = match prop {
+      // TODO: have color parsing return a ParseResult instead of a real value
+      ~"background-color" => parse_color(val).map(|res| {
+          ~[BackgroundColor(Specified(BgColor(*res)))]
+      }),
+      ~"color" => parse_color(val).map(|res| ~[Color(Specified(TextColor(*res)))]),
+      ~"display" => parse_display_type(val).extract(|res| ~[Display(res)]),
+      ~"font-size" => parse_font_size(val).extract(|res| ~[FontSize(res)]),
+      ~"font-family" => parse_font_family(val).extract(|res| ~[FontFamily(res)]),
+      ~"font-weight" => parse_font_weight(val).extract(|res| ~[FontWeight(res)]),
+      ~"font-style" => parse_font_style(val).extract(|res| ~[FontStyle(res)]),
+      ~"font" => parse_font(val).map(|f| {
+          let (style, weight, size, families) = copy *f;
+          ~[FontStyle(style), FontWeight(weight), FontSize(size), FontFamily(move families)]
+      }),
+      ~"direction" => parse_direction(val).extract(|res| ~[Direction(res)]),
+      ~"white-space" => parse_white_space(val).extract(|res| ~[WhiteSpace(res)]),
+      ~"text-align" => parse_text_align(val).extract(|res| ~[TextAlign(res)]),
+      ~"text-indent" => parse_text_indent(val).extract(|res| ~[TextIndent(res)]),
+      ~"height" => parse_box_sizing(val).extract(|res| ~[Height(res)]),
+      ~"width" => parse_box_sizing(val).extract(|res| ~[Width(res)]),
+      ~"margin" => parse_box_sides(val, parse_margin).map(|v| {
+          ~[MarginTop(v[0]), MarginRight(v[1]), MarginBottom(v[2]), MarginLeft(v[3])]
+      }),
+      ~"margin-top" => parse_margin(val).extract(|res| ~[MarginTop(res)]),
+      ~"margin-right" => parse_margin(val).extract(|res| ~[MarginRight(res)]),
+      ~"margin-bottom" => parse_margin(val).extract(|res| ~[MarginBottom(res)]),
+      ~"margin-left" => parse_margin(val).extract(|res| ~[MarginLeft(res)]),
+      ~"padding" => parse_box_sides(val, parse_padding).map(|v| {
+          ~[PaddingTop(v[0]), PaddingRight(v[1]), PaddingBottom(v[2]), PaddingLeft(v[3])]
+      }),
+      ~"padding-top" => parse_padding(val).extract(|res| ~[PaddingTop(res)]),
+      ~"padding-right" => parse_padding(val).extract(|res| ~[PaddingRight(res)]),
+      ~"padding-bottom" => parse_padding(val).extract(|res| ~[PaddingBottom(res)]),
+      ~"padding-left" => parse_padding(val).extract(|res| ~[PaddingLeft(res)]),
+      ~"border" => parse_border(val).map(|b| {
+          let (width, style, color) = *b;
+          ~[BorderTopWidth(width), BorderRightWidth(width),
+            BorderBottomWidth(width), BorderLeftWidth(width),
+            BorderTopStyle(style), BorderRightStyle(style),
+            BorderBottomStyle(style), BorderLeftStyle(style),
+            BorderTopColor(color), BorderRightColor(color),
+            BorderBottomColor(color), BorderLeftColor(color)]
+      }),
+      ~"border-width" => parse_box_sides(val, parse_border_width).map(|v| {
+          ~[BorderTopWidth(v[0]), BorderRightWidth(v[1]),
+            BorderBottomWidth(v[2]), BorderLeftWidth(v[3])]
+      }),
+      ~"border-style" => parse_box_sides(val, parse_border_style).map(|v| {
+          ~[BorderTopStyle(v[0]), BorderRightStyle(v[1]),
+            BorderBottomStyle(v[2]), BorderLeftStyle(v[3])]
+      }),
+      ~"border-color" => parse_box_sides(val, parse_border_color).map(|v| {
+          ~[BorderTopColor(v[0]), BorderRightColor(v[1]),
+            BorderBottomColor(v[2]), BorderLeftColor(v[3])]
+      }),
+      ~"border-top" => parse_border(val).map(|b| {
+          let (width, style, color) = *b;
+          ~[BorderTopWidth(width), BorderTopStyle(style), BorderTopColor(color)]
+      }),
+      ~"border-top-width" => parse_border_width(val).extract(|res| ~[BorderTopWidth(res)]),
+      ~"border-top-style" => parse_border_style(val).extract(|res| ~[BorderTopStyle(res)]),
+      ~"border-top-color" => parse_border_color(val).extract(|res| ~[BorderTopColor(res)]),
+      ~"border-right" => parse_border(val).map(|b| {
+          let (width, style, color) = *b;
+          ~[BorderRightWidth(width), BorderRightStyle(style), BorderRightColor(color)]
+      }),
+      ~"border-right-width" => parse_border_width(val).extract(|res| ~[BorderRightWidth(res)]),
+      ~"border-right-style" => parse_border_style(val).extract(|res| ~[BorderRightStyle(res)]),
+      ~"border-right-color" => parse_border_color(val).extract(|res| ~[BorderRightColor(res)]),
+      ~"border-bottom" => parse_border(val).map(|b| {
+          let (width, style, color) = *b;
+          ~[BorderBottomWidth(width), BorderBottomStyle(style), BorderBottomColor(color)]
+      }),
+      ~"border-bottom-width" => parse_border_width(val).extract(|res| ~[BorderBottomWidth(res)]),
+      ~"border-bottom-style" => parse_border_style(val).extract(|res| ~[BorderBottomStyle(res)]),
+      ~"border-bottom-color" => parse_border_color(val).extract(|res| ~[BorderBottomColor(res)]),
+      ~"border-left" => parse_border(val).map(|b| {
+          let (width, style, color) = *b;
+          ~[BorderLeftWidth(width), BorderLeftStyle(style), BorderLeftColor(color)]
+      }),
+      ~"border-left-width" => parse_border_width(val).extract(|res| ~[BorderLeftWidth(res)]),
+      ~"border-left-style" => parse_border_style(val).extract(|res| ~[BorderLeftStyle(res)]),
+      ~"border-left-color" => parse_border_color(val).extract(|res| ~[BorderLeftColor(res)]),
+      ~"position" => parse_position(val).extract(|res| ~[Position(res)]),
+      ~"top" => parse_length(val).map(|res| ~[Top(Specified(*res))]),
+      ~"right" => parse_length(val).map(|res| ~[Right(Specified(*res))]),
+      ~"bottom" => parse_length(val).map(|res| ~[Bottom(Specified(*res))]),
+      ~"left" => parse_length(val).map(|res| ~[Left(Specified(*res))]),
+      _ => { return Err(#fmt["Unknown property '%s'", prop]); }
+    };
+
+    match decls {
+      Some(decls) => Ok(move decls),
+      None => Err(#fmt["Invalid value '%s' for property '%s'", val, prop])
+    }
+}
+
+/** Returns true if a declaration value refers to custom properties through 'var()'. */
+fn references_variables(value: &[Token]) -> bool {
+    do value.any |token| {
+        match *token {
+          tok::Function(ref name) => str::to_lower(*name) == ~"var",
+          _ => false
+        }
+    }
+}
+
+/**
+Returns the longhands that a declaration of a property sets: the ones
+a shorthand expands to, or else the property itself.
+*/
+fn longhand_names(prop: &str) -> ~[~str] {
+    let sides = [~"top", ~"right", ~"bottom", ~"left"];
+    let parts = [~"width", ~"style", ~"color"];
+    match prop.to_str() {
+      ~"margin" | ~"padding" => sides.map(|side| prop + "-" + *side),
+      ~"border-width" | ~"border-style" | ~"border-color" => {
+        sides.map(|side| ~"border-" + *side + prop.substr(6, prop.len() - 6))
+      }
+      ~"border" => vec::concat(sides.map(|side| parts.map(|part| {
+          ~"border-" + *side + "-" + *part
+      }))),
+      ~"border-top" | ~"border-right" | ~"border-bottom" | ~"border-left" => {
+        parts.map(|part| prop + "-" + *part)
+      }
+      ~"font" => ~[~"font-style", ~"font-weight", ~"font-size", ~"font-family"],
+      name => ~[move name]
     }
 }
 
-pub fn build_stylesheet(stream : pipes::Port<Token>) -> ~[~css::Rule] {
-    let mut rule_list = ~[];
-    let reader = {stream : move stream, mut lookahead : None};
+/**
+Splits a trailing '!important' off the tokens of a declaration value,
+returning the remaining tokens and whether it was there.
+*/
+fn split_importance(value: ~[Token]) -> (~[Token], bool) {
+    let mut value = move value;
+    while value.len() > 0 && value.last() == tok::Whitespace { vec::pop(&mut value); }
+    let len = value.len();
+    if len == 0 { return (move value, false); }
 
+    match copy value[len - 1] {
+      tok::Ident(ident) if str::to_lower(ident) == ~"important" => {
+        let mut i = len - 1;
+        while i > 0 && value[i - 1] == tok::Whitespace { i -= 1; }
+        if i > 0 && value[i - 1] == tok::Delim('!') {
+            (vec::slice(value, 0, i - 1), true)
+        } else {
+            (move value, false)
+        }
+      }
+      _ => (move value, false)
+    }
+}
+
+/**
+Turns the tokens of a declaration value back into text for the value
+parsers. Whitespace is kept as a single space, since it separates the
+parts of multi-valued properties ('margin: 1px 2px'), except around
+'(', ')' and ','. Returns None if the value contains a bad string or url.
+*/
+fn serialize_value(value: &[Token]) -> Option<~str> {
+    let mut result = ~"";
+    let mut pending_space = false;
+    for value.each |token| {
+        let text = match *token {
+          tok::Whitespace => { pending_space = true; loop; }
+          tok::BadString | tok::BadUrl => { return None; }
+          tok::Ident(ref s) => copy *s,
+          tok::Function(ref name) => *name + ~"(",
+          tok::AtKeyword(ref name) => ~"@" + *name,
+          tok::Hash(ref name, _) => ~"#" + *name,
+          tok::String(ref s) => ~"\"" + *s + ~"\"",
+          tok::Url(ref url) => ~"url(" + *url + ~")",
+          tok::Delim(c) => str::from_char(c),
+          tok::Number(ref n) => copy n.representation,
+          tok::Percentage(ref n) => n.representation + ~"%",
+          tok::Dimension(ref n, ref unit) => n.representation + *unit,
+          tok::CDO => ~"<!--",
+          tok::CDC => ~"-->",
+          tok::Colon => ~":",
+          tok::Semicolon => ~";",
+          tok::Comma => ~",",
+          tok::OpenSquare => ~"[",
+          tok::CloseSquare => ~"]",
+          tok::OpenParen => ~"(",
+          tok::CloseParen => ~")",
+          tok::OpenCurly => ~"{",
+          tok::CloseCurly => ~"}",
+          tok::Eof => ~""
+        };
+        if pending_space && result.len() > 0 && !result.ends_with("(")
+            && !result.ends_with(",") && text != ~")" && text != ~"," {
+            str::push_char(&mut result, ' ');
+        }
+        pending_space = false;
+        result += text;
+    }
+    return Some(move result);
+}
+
+/**
+Parses a comma-separated list of media queries, with None for each
+invalid query. An empty list is valid, and matches all media.
+*/
+fn parse_media_query_list(tokens: &[Token]) -> ~[Option<MediaQuery>] {
+    // whitespace only separates the parts of a query
+    let tokens = tokens.filtered(|token| *token != tok::Whitespace);
+    if tokens.is_empty() { return ~[]; }
+
+    let mut queries = ~[];
+    let mut start = 0u;
+    for uint::range(0u, tokens.len() + 1u) |i| {
+        if i == tokens.len() || tokens[i] == tok::Comma {
+            push(&mut queries, parse_media_query(vec::view(tokens, start, i)));
+            start = i + 1u;
+        }
+    }
+    return move queries;
+}
+
+/**
+Parses a media query, such as 'not screen and (min-width: 600px)',
+given without whitespace.
+*/
+fn parse_media_query(tokens: &[Token]) -> Option<MediaQuery> {
+    let mut i = 0u;
+    let mut negated = false;
+    let mut media_type = MediaAll;
+    let mut needs_type = false;
+
+    if i < tokens.len() {
+        match copy tokens[i] {
+          tok::Ident(word) if str::to_lower(word) == ~"not" => {
+            negated = true; needs_type = true; i += 1u;
+          }
+          // 'only' just hides the query from old user agents
+          tok::Ident(word) if str::to_lower(word) == ~"only" => { needs_type = true; i += 1u; }
+          _ => {}
+        }
+    }
+
+    let mut needs_and = false;
+    if i < tokens.len() {
+        match copy tokens[i] {
+          tok::Ident(name) => {
+            media_type = match str::to_lower(name) {
+              ~"all" => MediaAll,
+              ~"screen" => MediaScreen,
+              ~"print" => MediaPrint,
+              _ => MediaUnknown
+            };
+            needs_and = true;
+            i += 1u;
+          }
+          _ if needs_type => { return None; }
+          _ => {}
+        }
+    } else {
+        // an empty query, or 'not' or 'only' on its own
+        return None;
+    }
+
+    let mut features = ~[];
+    while i < tokens.len() {
+        if needs_and {
+            match copy tokens[i] {
+              tok::Ident(word) if str::to_lower(word) == ~"and" => { i += 1u; }
+              _ => { return None; }
+            }
+        }
+        // '(' name ':' value ')'
+        if i + 4u >= tokens.len() { return None; }
+        let feature = match (copy tokens[i], copy tokens[i + 1u], copy tokens[i + 2u],
+                             copy tokens[i + 4u]) {
+          (tok::OpenParen, tok::Ident(name), tok::Colon, tok::CloseParen) => {
+            parse_media_feature(str::to_lower(name), &tokens[i + 3u])
+          }
+          _ => None
+        };
+        match feature {
+          Some(feature) => push(&mut features, move feature),
+          None => { return None; }
+        }
+        i += 5u;
+        needs_and = true;
+    }
+
+    return Some(MediaQuery { negated: negated, media_type: media_type, features: move features });
+}
+
+/** Parses a media feature with the given name and value. */
+fn parse_media_feature(name: ~str, value: &Token) -> Option<MediaFeature> {
+    let (range, name) = if name.starts_with("min-") {
+        (MediaMin, name.substr(4u, name.len() - 4u))
+    } else if name.starts_with("max-") {
+        (MediaMax, name.substr(4u, name.len() - 4u))
+    } else {
+        (MediaExact, copy name)
+    };
+
+    let length = match *value {
+      tok::Dimension(ref n, ref unit) => parse_length(n.representation + *unit),
+      tok::Number(ref n) if n.value == 0.0 => Some(Px(0.0)),
+      _ => None
+    };
+    match (name, range) {
+      (~"width", _) => length.map(|len| MediaWidth(range, *len)),
+      (~"height", _) => length.map(|len| MediaHeight(range, *len)),
+      (~"orientation", MediaExact) => {
+        match *value {
+          tok::Ident(ref word) if str::to_lower(*word) == ~"portrait" => Some(MediaPortrait(true)),
+          tok::Ident(ref word) if str::to_lower(*word) == ~"landscape" => {
+            Some(MediaPortrait(false))
+          }
+          _ => None
+        }
+      }
+      (~"resolution", _) => {
+        // in dots per 'px'; there are 96 'px' in an inch
+        let dppx = match *value {
+          tok::Dimension(ref n, ref unit) => {
+            match str::to_lower(*unit) {
+              ~"dppx" | ~"x" => Some(n.value),
+              ~"dpi" => Some(n.value / 96.0),
+              ~"dpcm" => Some(n.value * 2.54 / 96.0),
+              _ => None
+            }
+          }
+          _ => None
+        };
+        dppx.map(|dppx| MediaResolution(range, *dppx))
+      }
+      _ => None
+    }
+}
+
+/**
+Parses a style sheet, returning its '@import' rules and its other rules
+together with the constructs that were dropped from it. It is up to the
+caller to load the imported style sheets; see `css::loader`.
+*/
+pub fn parse_stylesheet_with_imports(stream : pipes::Port<(Token, SourcePosition)>)
+    -> (~[Import], ~[~css::Rule], ~[Diagnostic]) {
+    let reader = {
+        stream : move stream,
+        mut lookahead : None,
+        mut position : SourcePosition { line: 1u, column: 1u },
+        imports : DVec(),
+        diagnostics : DVec()
+    };
+
+    let rule_list = reader.parse_rule_list(~[], false);
+    return (reader.imports.get(), move rule_list, reader.diagnostics.get());
+}
+
+/**
+Parses a style sheet that cannot import others, returning its rules
+together with the constructs that were dropped from it.
+*/
+pub fn parse_stylesheet(stream : pipes::Port<(Token, SourcePosition)>)
+    -> (~[~css::Rule], ~[Diagnostic]) {
+    let (imports, rule_list, diagnostics) = parse_stylesheet_with_imports(move stream);
+    if imports.is_empty() { return (move rule_list, move diagnostics); }
+
+    // The imports start the style sheet, so this keeps the diagnostics in order.
+    let ignored = do imports.map |import| {
+        Diagnostic {
+            message: #fmt["Ignored @import of '%s'", import.url],
+            position: import.position
+        }
+    };
+    return (move rule_list, ignored + diagnostics);
+}
+
+/**
+Parses the contents of a 'style' attribute, which is a description
+without the braces (CSSOM Section 6.7.1), into its normal and
+'!important' declarations.
+*/
+pub fn parse_style_attribute(stream : pipes::Port<(Token, SourcePosition)>)
+    -> ((~[StyleDeclaration], ~[StyleDeclaration]), ~[Diagnostic]) {
+    let reader = {
+        stream : move stream,
+        mut lookahead : None,
+        mut position : SourcePosition { line: 1u, column: 1u },
+        imports : DVec(),
+        diagnostics : DVec()
+    };
+
+    let decls = reader.parse_description();
+    // a stray '}' ends the description early; ignore what follows
     loop {
-        match reader.parse_rule() {
-          Some(rule) => { push(&mut rule_list, copy rule); }
-          None => { break; }
+        match reader.get() {
+          tok::Eof => { break; }
+          _ => {}
         }
     }
+    return (move decls, reader.diagnostics.get());
+}
 
+pub fn build_stylesheet(stream : pipes::Port<(Token, SourcePosition)>) -> ~[~css::Rule] {
+    let (rule_list, _) = parse_stylesheet(move stream);
     return move rule_list;
 }
@@ -4,29 +4,222 @@ use css::values::*;
 use str::{pop_char, from_chars};
 use float::from_str;
 use option::map;
+use util::color::parsing::parse_color;
 
 export parse_font_size;
+export parse_font_family;
+export parse_font_weight;
+export parse_font_style;
+export parse_font;
+export parse_direction;
+export parse_white_space;
+export parse_text_align;
+export parse_text_indent;
 export parse_size;
 export parse_box_sizing;
 export parse_display_type;
+export parse_position;
+export parse_length;
+export parse_margin;
+export parse_padding;
+export parse_border_width;
+export parse_border_style;
+export parse_border_color;
+export parse_box_sides;
+export parse_border;
+export parse_nth;
+export parse_calc;
+export split_components;
 
 
+/**
+Parses a length in any of the units of CSS Values Level 3, Section 5.
+Absolute units other than 'px' are normalized to 'pt', and a zero
+needs no unit.
+*/
 fn parse_length(str : &str) -> Option<Length> {
-    // TODO: use these once we stop lexing below
-    const PTS_PER_INCH: float = 72.0;
-    const CM_PER_INCH: float = 2.54;
-    const PX_PER_PT: float = 1.0 / 0.75;
+    const PT_PER_IN: float = 72.0;
+    const MM_PER_IN: float = 25.4;
 
-    match str {
-      s if s.ends_with("in") => from_str(str.substr(0, str.len() - 2)).map(|f| Px(1.0/0.75 * 72.0 * *f)),
-      s if s.ends_with("cm") => from_str(str.substr(0, str.len() - 2)).map(|f| Px(*f / 2.54 * 72.0 * 1.0/0.75)),
-      s if s.ends_with("mm") => from_str(str.substr(0, str.len() - 2)).map(|f| Px(*f * 0.1 / 2.54 * 72.0 * 1.0/0.75)),
-      s if s.ends_with("pt") => from_str(str.substr(0, str.len() - 2)).map(|f| Px(1.0/0.75 * *f)),
-      s if s.ends_with("pc") => from_str(str.substr(0, str.len() - 2)).map(|f| Px(1.0/0.75 * 12.0 * *f)),
-      s if s.ends_with("px") => from_str(str.substr(0, str.len() - 2)).map(|f| Px(*f)),
-      s if s.ends_with("em") => from_str(str.substr(0, str.len() - 2)).map(|f| Em(*f)),
-      s if s.ends_with("ex") => from_str(str.substr(0, str.len() - 2)).map(|f| Em(0.5 * *f)),
-      _ => None,
+    if is_calc(str) {
+        return match parse_calc(str) {
+          Some(calc) if calc.percent == 0.0 => Some(Calc(calc)),
+          _ => None
+        };
+    }
+
+    // the unit starts at the first letter, since numbers have no exponent here
+    let split = match str::find(str, |c| char::is_alphabetic(c)) {
+      Some(i) => i,
+      None => str.len()
+    };
+    let number = match from_str(str.substr(0, split)) {
+      Some(number) => number,
+      None => return None
+    };
+
+    match str::to_lower(str.substr(split, str.len() - split)) {
+      ~"" if number == 0.0 => Some(Px(0.0)),
+      ~"px" => Some(Px(number)),
+      ~"pt" => Some(Pt(number)),
+      ~"pc" => Some(Pt(12.0 * number)),
+      ~"in" => Some(Pt(PT_PER_IN * number)),
+      ~"cm" => Some(Pt(10.0 * number / MM_PER_IN * PT_PER_IN)),
+      ~"mm" => Some(Pt(number / MM_PER_IN * PT_PER_IN)),
+      ~"q" => Some(Pt(0.25 * number / MM_PER_IN * PT_PER_IN)),
+      ~"em" => Some(Em(number)),
+      ~"ex" => Some(Ex(number)),
+      ~"rem" => Some(Rem(number)),
+      ~"vw" => Some(Vw(number)),
+      ~"vh" => Some(Vh(number)),
+      ~"vmin" => Some(Vmin(number)),
+      ~"vmax" => Some(Vmax(number)),
+      _ => None
+    }
+}
+
+/**
+Splits a value into its space-separated components, keeping each
+function, such as 'calc(1em + 2px)', in one piece.
+*/
+fn split_components(str : &str) -> ~[~str] {
+    let mut components = ~[], current = ~"";
+    let mut depth = 0u;
+    for str::each_char(str) |c| {
+        match c {
+          ' ' if depth == 0u => {
+            if current.len() > 0u { vec::push(&mut components, move current); }
+            current = ~"";
+            loop;
+          }
+          '(' => depth += 1u,
+          ')' if depth > 0u => depth -= 1u,
+          _ => {}
+        }
+        str::push_char(&mut current, c);
+    }
+    if current.len() > 0u { vec::push(&mut components, move current); }
+    move components
+}
+
+fn is_calc(str : &str) -> bool {
+    str::to_lower(str).starts_with("calc(")
+}
+
+/** An operand of a 'calc()' expression, or the value of one of its parts. */
+enum CalcValue {
+    CalcNumber(float),
+    CalcSum(CalcLength)
+}
+
+/**
+Parses a 'calc()' expression (CSS Values Level 3, Section 8.1) into a
+sum of lengths in 'px', in each relative unit, and a percentage. As in
+the spec, '+' and '-' need whitespace around them, and '*' and '/' need
+a number on one side.
+*/
+fn parse_calc(str : &str) -> Option<CalcLength> {
+    if !is_calc(str) { return None; }
+    let chars = str::chars(str::to_lower(str));
+    // start at the '(' of 'calc(', which makes the whole value a parenthesized operand
+    let mut pos = 4u;
+    match parse_calc_operand(chars, &mut pos) {
+      Some(CalcSum(sum)) if pos == chars.len() => Some(sum),
+      _ => None
+    }
+}
+
+fn skip_calc_whitespace(chars : &[char], pos : &mut uint) -> bool {
+    let start = *pos;
+    while *pos < chars.len() && chars[*pos] == ' ' { *pos += 1u; }
+    *pos > start
+}
+
+fn parse_calc_sum(chars : &[char], pos : &mut uint) -> Option<CalcValue> {
+    let mut sum = match parse_calc_product(chars, pos) {
+      Some(v) => v,
+      None => return None
+    };
+    loop {
+        let start = *pos;
+        if !skip_calc_whitespace(chars, pos) || *pos + 1u >= chars.len()
+            || (chars[*pos] != '+' && chars[*pos] != '-') || chars[*pos + 1u] != ' ' {
+            *pos = start;
+            return Some(sum);
+        }
+        let sign = if chars[*pos] == '+' { 1.0 } else { -1.0 };
+        *pos += 1u;
+        skip_calc_whitespace(chars, pos);
+        sum = match (sum, parse_calc_product(chars, pos)) {
+          (CalcNumber(a), Some(CalcNumber(b))) => CalcNumber(a + sign * b),
+          (CalcSum(a), Some(CalcSum(b))) => CalcSum(a.add(&b.scale(sign))),
+          // numbers and lengths can't be added together
+          _ => return None
+        };
+    }
+}
+
+fn parse_calc_product(chars : &[char], pos : &mut uint) -> Option<CalcValue> {
+    let mut product = match parse_calc_operand(chars, pos) {
+      Some(v) => v,
+      None => return None
+    };
+    loop {
+        let start = *pos;
+        skip_calc_whitespace(chars, pos);
+        if *pos == chars.len() || (chars[*pos] != '*' && chars[*pos] != '/') {
+            *pos = start;
+            return Some(product);
+        }
+        let op = chars[*pos];
+        *pos += 1u;
+        skip_calc_whitespace(chars, pos);
+        product = match (op, product, parse_calc_operand(chars, pos)) {
+          ('*', CalcNumber(a), Some(CalcNumber(b))) => CalcNumber(a * b),
+          ('*', CalcNumber(a), Some(CalcSum(b))) | ('*', CalcSum(b), Some(CalcNumber(a))) => {
+            CalcSum(b.scale(a))
+          }
+          ('/', CalcNumber(a), Some(CalcNumber(b))) if b != 0.0 => CalcNumber(a / b),
+          ('/', CalcSum(a), Some(CalcNumber(b))) if b != 0.0 => CalcSum(a.scale(1.0 / b)),
+          _ => return None
+        };
+    }
+}
+
+/** Parses a number, a length, a percentage or a parenthesized sum. */
+fn parse_calc_operand(chars : &[char], pos : &mut uint) -> Option<CalcValue> {
+    if *pos == chars.len() { return None; }
+    if chars[*pos] == '(' || (chars.len() - *pos > 5u && chars[*pos] == 'c'
+                              && from_chars(vec::slice(chars, *pos, *pos + 5u)) == ~"calc(") {
+        while chars[*pos] != '(' { *pos += 1u; }
+        *pos += 1u;
+        skip_calc_whitespace(chars, pos);
+        let sum = parse_calc_sum(chars, pos);
+        skip_calc_whitespace(chars, pos);
+        if *pos == chars.len() || chars[*pos] != ')' { return None; }
+        *pos += 1u;
+        return sum;
+    }
+
+    let start = *pos;
+    if chars[*pos] == '+' || chars[*pos] == '-' { *pos += 1u; }
+    while *pos < chars.len() && (char::is_digit(chars[*pos]) || chars[*pos] == '.') {
+        *pos += 1u;
+    }
+    let number = from_chars(vec::slice(chars, start, *pos));
+    let unit_start = *pos;
+    while *pos < chars.len() && (char::is_alphabetic(chars[*pos]) || chars[*pos] == '%') {
+        *pos += 1u;
+    }
+    let unit = from_chars(vec::slice(chars, unit_start, *pos));
+
+    match unit {
+      ~"" => from_str(number).map(|n| CalcNumber(*n)),
+      ~"%" => from_str(number).map(|n| CalcSum(CalcLength { percent: *n, .. CalcLength() })),
+      _ => match parse_length(number + unit) {
+        Some(Calc(_)) | None => None,
+        Some(length) => Some(CalcSum(CalcLength().add_length(length)))
+      }
     }
 }
 
@@ -64,9 +257,223 @@ fn parse_relative_size(str: &str) -> ParseResult<RelativeSize> {
     }
 }
 
-fn parse_font_size(_str: &str) -> ParseResult<CSSFontSize> {
-    // TODO: complete me
-    Value(LengthSize(Px(14.0)))
+// Keywords, or a length or percentage that is not negative
+fn parse_font_size(str: &str) -> ParseResult<CSSFontSize> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"inherit" => CSSInherit,
+      s if s.ends_with("%") => match from_str(str.substr(0, str.len() - 1)) {
+        Some(f) if f >= 0.0 => Value(PercentSize(f)),
+        _ => Fail
+      },
+      _ => match parse_absolute_size(str) {
+        Value(size) => Value(AbsoluteSize(size)),
+        _ => match parse_relative_size(str) {
+          Value(size) => Value(RelativeSize(size)),
+          _ => match parse_length(str) {
+            Some(len) if !len.is_negative() => Value(LengthSize(len)),
+            _ => Fail
+          }
+        }
+      }
+    }
+}
+
+/**
+Parses a comma-separated list of font families (CSS Fonts Level 3,
+Section 3.1). A family is a quoted name, a generic family keyword, or
+a name written as one or more identifiers.
+*/
+fn parse_font_family(str: &str) -> ParseResult<~[CSSFontFamily]> {
+    if str == "inherit" { return CSSInherit; }
+
+    let mut families = ~[];
+    for split_font_families(str).each |family| {
+        match parse_family_name(str::trim(*family)) {
+          Some(move family) => vec::push(&mut families, move family),
+          None => return Fail
+        }
+    }
+    Value(move families)
+}
+
+// Splits a list of font families at the commas that aren't in a quoted name
+fn split_font_families(str: &str) -> ~[~str] {
+    let mut families = ~[], family = ~"";
+    let mut quote: Option<char> = None;
+    for str::each_char(str) |c| {
+        match quote {
+          Some(q) if c == q => quote = None,
+          Some(_) => {}
+          None if c == '"' || c == '\'' => quote = Some(c),
+          None if c == ',' => {
+            vec::push(&mut families, move family);
+            family = ~"";
+            loop;
+          }
+          None => {}
+        }
+        str::push_char(&mut family, c);
+    }
+    vec::push(&mut families, move family);
+    move families
+}
+
+fn parse_family_name(str: &str) -> Option<CSSFontFamily> {
+    let quoted = str.starts_with("\"") && str.ends_with("\"")
+        || str.starts_with("'") && str.ends_with("'");
+    if str.len() >= 2u && quoted {
+        return Some(FamilyName(str.substr(1u, str.len() - 2u)));
+    }
+
+    let words = str::words(str);
+    if words.is_empty() || !words.all(|word| is_identifier(*word)) { return None; }
+    if words.len() > 1u { return Some(FamilyName(str::connect(words, " "))); }
+    match str::to_lower(words[0]) {
+      ~"serif" => Some(GenericSerif),
+      ~"sans-serif" => Some(GenericSansSerif),
+      ~"cursive" => Some(GenericCursive),
+      ~"fantasy" => Some(GenericFantasy),
+      ~"monospace" => Some(GenericMonospace),
+      // these keywords can't be family names, even on their own
+      ~"inherit" | ~"initial" | ~"default" => None,
+      _ => Some(FamilyName(copy words[0]))
+    }
+}
+
+// Whether a word is a CSS identifier: it does not start with a digit
+pure fn is_identifier(str: &str) -> bool {
+    let start = if str.starts_with("-") { 1u } else { 0u };
+    str.len() > start && !char::is_digit(str[start] as char)
+        && str.all(|c| char::is_alphanumeric(c) || c == '-' || c == '_')
+}
+
+fn parse_font_weight(str: &str) -> ParseResult<CSSFontWeight> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"normal" => Value(WeightNumber(400u)),
+      ~"bold" => Value(WeightNumber(700u)),
+      ~"bolder" => Value(WeightBolder),
+      ~"lighter" => Value(WeightLighter),
+      ~"inherit" => CSSInherit,
+      _ => match uint::from_str(str) {
+        Some(weight) if weight >= 100u && weight <= 900u && weight % 100u == 0u => {
+            Value(WeightNumber(weight))
+        }
+        _ => Fail
+      }
+    }
+}
+
+fn parse_font_style(str: &str) -> ParseResult<CSSFontStyle> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"normal" => Value(FontStyleNormal),
+      ~"italic" => Value(FontStyleItalic),
+      ~"oblique" => Value(FontStyleOblique),
+      ~"inherit" => CSSInherit,
+      _ => Fail
+    }
+}
+
+fn parse_direction(str: &str) -> ParseResult<CSSDirection> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"ltr" => Value(DirectionLtr),
+      ~"rtl" => Value(DirectionRtl),
+      ~"inherit" => CSSInherit,
+      _ => Fail
+    }
+}
+
+fn parse_white_space(str: &str) -> ParseResult<CSSWhiteSpace> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"normal" => Value(WhiteSpaceNormal),
+      ~"pre" => Value(WhiteSpacePre),
+      ~"nowrap" => Value(WhiteSpaceNowrap),
+      ~"pre-wrap" => Value(WhiteSpacePreWrap),
+      ~"pre-line" => Value(WhiteSpacePreLine),
+      ~"inherit" => CSSInherit,
+      _ => Fail
+    }
+}
+
+fn parse_text_align(str: &str) -> ParseResult<CSSTextAlign> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"left" => Value(TextAlignLeft),
+      ~"right" => Value(TextAlignRight),
+      ~"center" => Value(TextAlignCenter),
+      ~"justify" => Value(TextAlignJustify),
+      ~"start" => Value(TextAlignStart),
+      ~"end" => Value(TextAlignEnd),
+      ~"inherit" => CSSInherit,
+      _ => Fail
+    }
+}
+
+// Like margins, but 'auto' is not allowed
+fn parse_text_indent(str: &str) -> ParseResult<BoxSizing> {
+    match parse_box_sizing(str) {
+      Value(BoxAuto) => Fail,
+      res => res
+    }
+}
+
+/**
+Parses the 'font' shorthand: an optional style, variant and weight, in
+any order, then a size with an optional line height after a '/', then
+a family list. The style and weight are reset when left out.
+*/
+fn parse_font(str: &str) -> Option<(CSSValue<CSSFontStyle>, CSSValue<CSSFontWeight>,
+                                     CSSValue<CSSFontSize>, CSSValue<~[CSSFontFamily]>)> {
+    if str == "inherit" {
+        return Some((Inherit, Inherit, Inherit, Inherit));
+    }
+
+    let words = split_components(str);
+    let mut style = None, weight = None;
+    let mut i = 0u;
+    while i < words.len() && i < 3u {
+        match (parse_font_style(words[i]), parse_font_weight(words[i])) {
+          // 'normal' sets nothing that would not be reset anyway
+          _ if words[i] == ~"normal" => {}
+          (Value(s), _) if style.is_none() => style = Some(Specified(s)),
+          (_, Value(w)) if weight.is_none() => weight = Some(Specified(w)),
+          // TODO: 'font-variant', once it is a property
+          _ if words[i] == ~"small-caps" => {}
+          _ => break
+        }
+        i += 1u;
+    }
+    if i >= words.len() { return None; }
+
+    // TODO: the line height, once 'line-height' is a property
+    let size_word = copy words[i];
+    let (size, next) = match str::find_char(size_word, '/') {
+      Some(slash) if slash + 1u == size_word.len() => (size_word.substr(0u, slash), i + 2u),
+      Some(slash) => (size_word.substr(0u, slash), i + 1u),
+      None if i + 1u < words.len() && words[i + 1u] == ~"/" => (copy size_word, i + 3u),
+      None if i + 1u < words.len() && words[i + 1u].starts_with("/") => {
+        (copy size_word, i + 2u)
+      }
+      None => (copy size_word, i + 1u)
+    };
+    let size = match parse_font_size(size) {
+      Value(size) => size,
+      _ => return None
+    };
+
+    if next >= words.len() { return None; }
+    let families = match parse_font_family(str::connect(vec::slice(words, next, words.len()),
+                                                        " ")) {
+      Value(move families) => move families,
+      _ => return None
+    };
+
+    Some((style.get_default(Initial), weight.get_default(Initial), Specified(size),
+          Specified(move families)))
 }
 
 // For width / height, and anything else with the same attribute values
@@ -75,24 +482,198 @@ fn parse_box_sizing(str : &str) -> ParseResult<BoxSizing> {
     match str.to_str() {
       ~"auto" => Value(BoxAuto),
       ~"inherit" => CSSInherit,
-        _ => Fail
+      s if s.ends_with("%") => match from_str(str.substr(0, str.len() - 1)) {
+        Some(f) => Value(BoxPercent(f)),
+        None => Fail
+      },
+      s if is_calc(s) => match parse_calc(s) {
+        Some(calc) if calc.percent == 0.0 => Value(BoxLength(Calc(calc))),
+        Some(calc) => Value(BoxCalc(calc)),
+        None => Fail
+      },
+      _ => match parse_length(str) {
+        Some(len) => Value(BoxLength(len)),
+        None => Fail
+      }
     }
 }
 
+fn parse_margin(str : &str) -> ParseResult<BoxSizing> {
+    parse_box_sizing(str)
+}
+
+// Like margins, but 'auto' and negative values are not allowed
+fn parse_padding(str : &str) -> ParseResult<BoxSizing> {
+    match parse_box_sizing(str) {
+      Value(BoxAuto) => Fail,
+      Value(BoxLength(len)) if len.is_negative() => Fail,
+      Value(BoxPercent(f)) if f < 0.0 => Fail,
+      res => res
+    }
+}
+
+fn parse_border_width(str : &str) -> ParseResult<Length> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"thin" => Value(Px(1.0)),
+      ~"medium" => Value(Px(3.0)),
+      ~"thick" => Value(Px(5.0)),
+      ~"inherit" => CSSInherit,
+      _ => match parse_length(str) {
+        Some(len) => Value(len),
+        None => Fail
+      }
+    }
+}
+
+fn parse_border_style(str : &str) -> ParseResult<CSSBorderStyle> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"none" => Value(BdrStyleNone),
+      ~"hidden" => Value(BdrStyleHidden),
+      ~"dotted" => Value(BdrStyleDotted),
+      ~"dashed" => Value(BdrStyleDashed),
+      ~"solid" => Value(BdrStyleSolid),
+      ~"double" => Value(BdrStyleDouble),
+      ~"groove" => Value(BdrStyleGroove),
+      ~"ridge" => Value(BdrStyleRidge),
+      ~"inset" => Value(BdrStyleInset),
+      ~"outset" => Value(BdrStyleOutset),
+      ~"inherit" => CSSInherit,
+      _ => Fail
+    }
+}
+
+fn parse_border_color(str : &str) -> ParseResult<CSSBorderColor> {
+    // FIXME: Bad copy. Can't match &str
+    match str.to_str() {
+      ~"transparent" => Value(BdrColorTransparent),
+      ~"inherit" => CSSInherit,
+      _ => match parse_color(str) {
+        Some(color) => Value(BdrColor(color)),
+        None => Fail
+      }
+    }
+}
+
+/**
+Parses the one to four space-separated values of a shorthand like 'margin'
+or 'border-width' into the values for the top, right, bottom and left sides.
+*/
+fn parse_box_sides<T: Copy>(str : &str, parse: fn(&str) -> ParseResult<T>)
+    -> Option<~[CSSValue<T>]> {
+    if str == "inherit" {
+        return Some(~[Inherit, Inherit, Inherit, Inherit]);
+    }
+
+    let mut values = ~[];
+    for split_components(str).each |word| {
+        match parse(*word) {
+          Value(v) => vec::push(&mut values, Specified(v)),
+          CSSInitial | CSSInherit | Fail => return None
+        }
+    }
+
+    match values.len() {
+      1 => Some(~[values[0], values[0], values[0], values[0]]),
+      2 => Some(~[values[0], values[1], values[0], values[1]]),
+      3 => Some(~[values[0], values[1], values[2], values[1]]),
+      4 => Some(move values),
+      _ => None
+    }
+}
+
+/**
+Parses the value of the 'border' shorthand, or of one of the per-side ones
+like 'border-top', into its width, style and color. These may come in any
+order, and the ones that are left out are reset to their initial values.
+*/
+fn parse_border(str : &str)
+    -> Option<(CSSValue<Length>, CSSValue<CSSBorderStyle>, CSSValue<CSSBorderColor>)> {
+    if str == "inherit" {
+        return Some((Inherit, Inherit, Inherit));
+    }
+
+    let mut width = None, style = None, color = None;
+    for split_components(str).each |word| {
+        match (parse_border_width(*word), parse_border_style(*word), parse_border_color(*word)) {
+          (Value(w), _, _) if width.is_none() => width = Some(Specified(w)),
+          (_, Value(s), _) if style.is_none() => style = Some(Specified(s)),
+          (_, _, Value(c)) if color.is_none() => color = Some(Specified(c)),
+          _ => return None
+        }
+    }
+
+    Some((width.get_default(Initial), style.get_default(Initial), color.get_default(Initial)))
+}
+
 fn parse_display_type(str : &str) -> ParseResult<CSSDisplay> {
     // FIXME: Bad copy. Can't match &str
     match str.to_str() {
       ~"inline" => Value(DisplayInline),
       ~"block" => Value(DisplayBlock),
+      ~"list-item" => Value(DisplayListItem),
       ~"none" => Value(DisplayNone),
       _ => { #debug["Recieved unknown display value '%s'", str]; Fail }
     }
 }
 
+// An optionally signed integer, as in the 'an+b' notation
+fn parse_signed_int(str : &str) -> Option<int> {
+    let (negative, digits) = if str.starts_with("-") {
+        (true, str.substr(1, str.len() - 1))
+    } else if str.starts_with("+") {
+        (false, str.substr(1, str.len() - 1))
+    } else {
+        (false, str.to_str())
+    };
+    if digits.is_empty() || !digits.all(|c| char::is_digit(c)) { return None; }
+    int::from_str(digits).map(|i| if negative { -*i } else { *i })
+}
+
+/**
+Parses the argument of ':nth-child()' (Selectors Level 3, Section
+6.6.5.2) into the `(a, b)` of 'an+b'. Whitespace is ignored.
+*/
+fn parse_nth(str : &str) -> Option<(int, int)> {
+    let str = str::to_lower(str::replace(str, " ", ""));
+    match str {
+      ~"odd" => return Some((2, 1)),
+      ~"even" => return Some((2, 0)),
+      _ => {}
+    }
+
+    match str::find_char(str, 'n') {
+      None => parse_signed_int(str).map(|b| (0, *b)),
+      Some(n) => {
+        let a = match str.substr(0, n) {
+          ~"" | ~"+" => Some(1),
+          ~"-" => Some(-1),
+          a_str => parse_signed_int(a_str)
+        };
+        let b_str = str.substr(n + 1, str.len() - n - 1);
+        let b = if b_str.is_empty() {
+            Some(0)
+        } else if b_str.starts_with("+") || b_str.starts_with("-") {
+            parse_signed_int(b_str)
+        } else {
+            None
+        };
+        match (a, b) {
+          (Some(a), Some(b)) => Some((a, b)),
+          _ => None
+        }
+      }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use au = gfx::geometry;
     use css::lexer::spawn_css_lexer_from_string;
-    use css::parser::build_stylesheet;
+    use tok = css::lexer;
+    use css::media::Device;
+    use css::parser::{build_stylesheet, parse_stylesheet, parse_stylesheet_with_imports};
     use css::values::{Stylesheet, Element, FontSize, Width, Height};
     
     // TODO: use helper methods to create test values
@@ -104,9 +685,10 @@ mod test {
         let _actual_rule = build_stylesheet(move token_port);
         let _expected_rule : Stylesheet = ~[~(~[~Element(~"*", ~[])],
                                              ~[FontSize(Specified(LengthSize(Px(12.0)))),
-                                               FontSize(Specified(PercentSize(100.0))),
+                                               FontSize(Inherit),
                                                FontSize(Specified(PercentSize(200.0))),
-                                               FontSize(Specified(LengthSize(Px(12.0))))])];
+                                               FontSize(Specified(AbsoluteSize(XSmall)))],
+                                             ~[], ~[])];
 
         // TODO: fix me once StyleDeclaration is a trait, not an enum
         //assert actual_rule == expected_rule;
@@ -121,11 +703,309 @@ mod test {
                                              ~[Width(Specified(BoxPercent(20.0))),
                                                Height(Specified(BoxAuto)),
                                                Width(Specified(BoxLength(Px(20.0)))),
-                                               Width(Specified(BoxLength(Px(216.0)))),
+                                               Width(Specified(BoxLength(Pt(216.0)))),
                                                Height(Specified(BoxLength(Px(70.0)))),
-                                               Height(Specified(BoxLength(Px(30.0))))])];
+                                               Height(Specified(BoxLength(Px(30.0))))],
+                                             ~[], ~[])];
 
         // TODO: fix me once StyleDeclaration is a trait, not an enum
         //assert actual_rule == expected_rule;
     }
+
+    #[test]
+    fn should_parse_length_units() {
+        assert parse_length("12px") == Some(Px(12.0));
+        assert parse_length("1.5EM") == Some(Em(1.5));
+        assert parse_length("2ex") == Some(Ex(2.0));
+        assert parse_length("2rem") == Some(Rem(2.0));
+        assert parse_length("50vw") == Some(Vw(50.0));
+        assert parse_length("-10vh") == Some(Vh(-10.0));
+        assert parse_length("5vmin") == Some(Vmin(5.0));
+        assert parse_length("5vmax") == Some(Vmax(5.0));
+        assert parse_length("10pt") == Some(Pt(10.0));
+        assert parse_length("2pc") == Some(Pt(24.0));
+        assert parse_length("1in") == Some(Pt(72.0));
+        assert parse_length("25.4mm") == Some(Pt(72.0));
+        assert parse_length("0") == Some(Px(0.0));
+        assert parse_length("3").is_none();
+        assert parse_length("3furlongs").is_none();
+        assert parse_length("px").is_none();
+
+        assert Pt(3.0).abs() == 4.0;
+        assert Vw(3.0).rel() == 3.0;
+    }
+
+    #[test]
+    fn should_parse_font_sizes() {
+        let size = |str: &str| parse_font_size(str).extract(|v| v);
+        assert size("x-small") == Some(Specified(AbsoluteSize(XSmall)));
+        assert size("larger") == Some(Specified(RelativeSize(Larger)));
+        assert size("150%") == Some(Specified(PercentSize(150.0)));
+        assert size("2em") == Some(Specified(LengthSize(Em(2.0))));
+        assert size("inherit") == Some(Inherit);
+        assert size("-2px").is_none();
+        assert size("-50%").is_none();
+        assert size("huge").is_none();
+    }
+
+    #[test]
+    fn should_parse_font_families() {
+        let families = |str: &str| parse_font_family(str).extract(|v| v);
+        assert families("\"Helvetica Neue\", Times  New Roman,sans-serif")
+            == Some(Specified(~[FamilyName(~"Helvetica Neue"), FamilyName(~"Times New Roman"),
+                                GenericSansSerif]));
+        assert families("\"Foo, Inc Sans\", 'Bar, \"Baz\"',serif")
+            == Some(Specified(~[FamilyName(~"Foo, Inc Sans"), FamilyName(~"Bar, \"Baz\""),
+                                GenericSerif]));
+        assert families("\"Foo, Inc Sans").is_none();
+        assert families("Monospace") == Some(Specified(~[GenericMonospace]));
+        assert families("inherit") == Some(Inherit);
+        assert families("serif,").is_none();
+        assert families("12px").is_none();
+    }
+
+    #[test]
+    fn should_parse_directions() {
+        assert parse_direction("rtl").extract(|v| v) == Some(Specified(DirectionRtl));
+        assert parse_direction("ltr").extract(|v| v) == Some(Specified(DirectionLtr));
+        assert parse_direction("inherit").extract(|v| v) == Some(Inherit);
+        assert parse_direction("left").extract(|v| v).is_none();
+    }
+
+    #[test]
+    fn should_parse_white_space() {
+        assert parse_white_space("pre").extract(|v| v) == Some(Specified(WhiteSpacePre));
+        assert parse_white_space("pre-line").extract(|v| v) == Some(Specified(WhiteSpacePreLine));
+        assert parse_white_space("nowrap").extract(|v| v) == Some(Specified(WhiteSpaceNowrap));
+        assert parse_white_space("inherit").extract(|v| v) == Some(Inherit);
+        assert parse_white_space("wrap").extract(|v| v).is_none();
+    }
+
+    #[test]
+    fn should_parse_text_align_and_indent() {
+        assert parse_text_align("justify").extract(|v| v) == Some(Specified(TextAlignJustify));
+        assert parse_text_align("end").extract(|v| v) == Some(Specified(TextAlignEnd));
+        assert parse_text_align("middle").extract(|v| v).is_none();
+        assert parse_text_indent("2em").extract(|v| v) == Some(Specified(BoxLength(Em(2.0))));
+        assert parse_text_indent("-10%").extract(|v| v) == Some(Specified(BoxPercent(-10.0)));
+        assert parse_text_indent("auto").extract(|v| v).is_none();
+    }
+
+    #[test]
+    fn should_parse_font_shorthand() {
+        let (style, weight, size, families) =
+            parse_font("italic bold 12px/1.5 \"DejaVu Sans\", serif").get();
+        assert style == Specified(FontStyleItalic);
+        assert weight == Specified(WeightNumber(700u));
+        assert size == Specified(LengthSize(Px(12.0)));
+        assert families == Specified(~[FamilyName(~"DejaVu Sans"), GenericSerif]);
+
+        // the style and weight are reset when left out
+        let (style, weight, size, families) = parse_font("normal 2em / 3 monospace").get();
+        assert style == Initial && weight == Initial;
+        assert size == Specified(LengthSize(Em(2.0)));
+        assert families == Specified(~[GenericMonospace]);
+
+        assert parse_font_weight("600").extract(|v| v) == Some(Specified(WeightNumber(600u)));
+        assert parse_font_weight("650").extract(|v| v).is_none();
+        assert parse_font("bold 12px").is_none();
+        assert parse_font("serif").is_none();
+    }
+
+    #[test]
+    fn should_expand_box_sides() {
+        let sides = parse_box_sides("1px 2%", parse_margin).get();
+        assert sides == ~[Specified(BoxLength(Px(1.0))), Specified(BoxPercent(2.0)),
+                          Specified(BoxLength(Px(1.0))), Specified(BoxPercent(2.0))];
+
+        let sides = parse_box_sides("1px 2px 3px", parse_padding).get();
+        assert sides[3] == Specified(BoxLength(Px(2.0)));
+
+        assert parse_box_sides("auto", parse_padding).is_none();
+        assert parse_box_sides("1px 2px 3px 4px 5px", parse_margin).is_none();
+    }
+
+    #[test]
+    fn should_parse_border_in_any_order() {
+        let (width, style, _) = parse_border("solid thick red").get();
+        assert width == Specified(Px(5.0));
+        assert style == Specified(BdrStyleSolid);
+
+        // omitted components are reset
+        let (width, style, _) = parse_border("dashed").get();
+        assert width == Initial;
+        assert style == Specified(BdrStyleDashed);
+
+        assert parse_border("solid dashed").is_none();
+    }
+
+    #[test]
+    fn should_parse_tokenized_sheet() {
+        let input = ~"/* comment */ @charset \"utf-8\";
+                      DIV.a, p > #b { margin: 1px 2px; COLOR: red ! important }";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let rules = build_stylesheet(move token_port);
+
+        assert rules.len() == 1;
+        let (selectors, decls, important, _) = copy *rules[0];
+        assert selectors == ~[~Element(~"div", ~[Class(~"a")]),
+                              ~Child(~Element(~"p", ~[]), ~Element(~"*", ~[Id(~"b")]))];
+        assert decls.len() == 4;
+        assert important.len() == 1;
+    }
+
+    #[test]
+    fn should_recover_from_invalid_constructs() {
+        let input = ~"a > > b { color: red }
+p { colr: red; width: 10px; height: {oops}; color blue; display: block }
+@page { margin: 1in }
+div { width: 5px";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let (rules, diagnostics) = parse_stylesheet(move token_port);
+
+        // the rule with the bad selector is dropped, and so are the bad declarations
+        assert rules.len() == 2;
+        let (_, decls, _, _) = copy *rules[0];
+        assert decls.len() == 2;
+        // a rule left open at the end of the sheet is still kept
+        let (_, decls, _, _) = copy *rules[1];
+        assert decls.len() == 1;
+
+        let positions = diagnostics.map(|d| (d.position.line, d.position.column));
+        assert positions == ~[(1u, 5u), (2u, 5u), (2u, 29u), (2u, 51u), (3u, 1u)];
+    }
+
+    #[test]
+    fn should_parse_nth() {
+        assert parse_nth("odd") == Some((2, 1));
+        assert parse_nth("EVEN") == Some((2, 0));
+        assert parse_nth("3") == Some((0, 3));
+        assert parse_nth("-n+3") == Some((-1, 3));
+        assert parse_nth("2n + 1") == Some((2, 1));
+        assert parse_nth("+n") == Some((1, 0));
+        assert parse_nth("-2n-1") == Some((-2, -1));
+        assert parse_nth("2n 1").is_none();
+        assert parse_nth("n+").is_none();
+        assert parse_nth("x").is_none();
+    }
+
+    #[test]
+    fn should_parse_pseudo_classes_and_sibling_combinators() {
+        let input = ~"li:first-child:not(.a) ~ LI:nth-child(2n+1), :root + *:hover {}
+                      p:visited, a:not(:link {}";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let (rules, diagnostics) = parse_stylesheet(move token_port);
+
+        assert rules.len() == 1;
+        assert diagnostics.len() == 1;
+        let (selectors, _, _, _) = copy *rules[0];
+        assert selectors == ~[
+            ~GeneralSibling(~Element(~"li", ~[Pseudo(FirstChild),
+                                             Pseudo(Not(~Element(~"*", ~[Class(~"a")])))]),
+                            ~Element(~"li", ~[Pseudo(NthChild(2, 1))])),
+            ~Sibling(~Element(~"*", ~[Pseudo(Root)]), ~Element(~"*", ~[Pseudo(Hover)]))];
+    }
+
+    #[test]
+    fn should_parse_attribute_operators() {
+        let input = ~"[a^=x][b$='y'][c*=z i][d|=w] {}";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let rules = build_stylesheet(move token_port);
+
+        assert rules.len() == 1;
+        let (selectors, _, _, _) = copy *rules[0];
+        assert selectors == ~[~Element(~"*", ~[Prefix(~"a", ~"x"), Suffix(~"b", ~"y"),
+                                               CaseInsensitive(~Substring(~"c", ~"z")),
+                                               DashMatch(~"d", ~"w")])];
+    }
+
+    #[test]
+    fn should_parse_media_rules() {
+        let input = ~"@media screen and (min-width: 600px), print {
+    p {} @media (orientation: portrait) { a {} }
+}
+@media nonsense and, (WIDTH: 10px) { b {} }
+i {}";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let (rules, diagnostics) = parse_stylesheet(move token_port);
+
+        assert diagnostics.len() == 1;
+        assert rules.len() == 4;
+        let media_lens = rules.map(|rule| {
+            let (_, _, _, media) = copy **rule;
+            media.map(|queries| queries.len())
+        });
+        assert media_lens == ~[~[2u], ~[2u, 1u], ~[2u], ~[]];
+
+        let device = Device(au::from_px(800), au::from_px(600));
+        let matching = rules.map(|rule| device.matches_rule(*rule));
+        assert matching == ~[true, false, false, true];
+
+        let device = Device(au::from_px(10), au::from_px(600));
+        let matching = rules.map(|rule| device.matches_rule(*rule));
+        assert matching == ~[false, false, true, true];
+    }
+
+    #[test]
+    fn should_parse_imports_before_other_rules() {
+        let input = ~"@import url(a.css) screen, print; @import \"b.css\"; @import 'c.css' {}
+p {} @import 'd.css';";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let (imports, rules, diagnostics) = parse_stylesheet_with_imports(move token_port);
+
+        let imports = imports.map(|import| (copy import.url, import.media.len()));
+        assert imports == ~[(~"a.css", 2u), (~"b.css", 0u)];
+        assert rules.len() == 1;
+        let positions = diagnostics.map(|d| (d.position.line, d.position.column));
+        assert positions == ~[(1u, 52u), (2u, 6u)];
+    }
+
+    #[test]
+    fn should_parse_calc() {
+        assert parse_calc("calc(100% - 2em)")
+            == Some(CalcLength { em: -2.0, percent: 100.0, .. CalcLength() });
+        assert parse_calc("CALC((1px + 3px) * 2 / 4)")
+            == Some(CalcLength { px: 2.0, .. CalcLength() });
+        assert parse_calc("calc(2 * calc(10% + 1px))")
+            == Some(CalcLength { px: 2.0, percent: 20.0, .. CalcLength() });
+        assert parse_calc("calc(1rem + 10vw - 2vh + 3vmin / 2 + 4vmax + 1ex)")
+            == Some(CalcLength { em: 0.5, rem: 1.0, vw: 10.0, vh: -2.0, vmin: 1.5, vmax: 4.0,
+                                 .. CalcLength() });
+        // '+' and '-' need whitespace, and lengths can't be multiplied together
+        assert parse_calc("calc(1px -2px)").is_none();
+        assert parse_calc("calc(1px * 2px)").is_none();
+        assert parse_calc("calc(1px + 2)").is_none();
+        assert parse_calc("calc(1px / 0)").is_none();
+
+        assert parse_length("calc(1em + 2px)")
+            == Some(Calc(CalcLength { px: 2.0, em: 1.0, .. CalcLength() }));
+        assert parse_length("calc(10% + 2px)").is_none();
+        assert parse_box_sizing("calc(10% + 2px)")
+            == Value(BoxCalc(CalcLength { px: 2.0, percent: 10.0, .. CalcLength() }));
+        let sides = parse_box_sides("calc(1px + 1px) 3px", parse_margin).get();
+        assert sides[1] == Specified(BoxLength(Px(3.0)));
+    }
+
+    #[test]
+    fn should_keep_custom_properties_and_var_declarations() {
+        let input = ~"p { --Main-Color: rgb(1, 2, 3) ; COLOR: var(--Main-Color);
+                      margin: 0 var(--m) }";
+        let token_port = spawn_css_lexer_from_string(move input);
+        let (rules, diagnostics) = parse_stylesheet(move token_port);
+
+        assert diagnostics.is_empty();
+        let (_, decls, _, _) = copy *rules[0];
+        assert decls.map(|d| d.property_name()) == ~[~"--Main-Color", ~"color", ~"margin"];
+        match copy decls[0] {
+          CustomProperty(_, value) => {
+            assert value[0] == tok::Function(~"rgb");
+            assert value.last() == tok::CloseParen;
+          }
+          _ => fail
+        }
+        match copy decls[2] {
+          VariableDeclaration(*) => {}
+          _ => fail
+        }
+    }
 }
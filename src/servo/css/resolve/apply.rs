@@ -3,13 +3,22 @@
 */
 
 use au = gfx::geometry;
-use css::styles::SpecifiedStyle;
+use au::Au;
+use css::styles::{ComputedStyle, SpecifiedStyle, StyleMethods};
 use dom::node::{Node, NodeTree};
 use dom::element::*;
 use layout::context::LayoutContext;
 use image::ImageHolder;
 use resource::image_cache_task::ImageCacheTask;
 use std::net::url::Url;
+use util::color::{Color, rgba};
+use util::color::css_colors::black;
+use util::tree;
+use dvec::DVec;
+use geom::size::Size2D;
+use std::arc::{ARC, clone};
+use css::resolve::sharing::CACHE_SIZE;
+use css::resolve::variables::{compute_custom_properties, substitute_declarations};
 
 use css::values::*;
 
@@ -21,6 +30,10 @@ impl CSSValue<CSSBackgroundColor> : ResolveMethods<CSSBackgroundColor> {
     pure fn initial() -> CSSBackgroundColor { return BgColorTransparent; }
 }
 
+impl CSSValue<CSSBackgroundImage> : ResolveMethods<CSSBackgroundImage> {
+    pure fn initial() -> CSSBackgroundImage { return BgImageNone; }
+}
+
 impl CSSValue<CSSDisplay> : ResolveMethods<CSSDisplay> {
     pure fn initial() -> CSSDisplay { return DisplayInline; }
 }
@@ -33,92 +46,587 @@ impl CSSValue<CSSFontSize> : ResolveMethods<CSSFontSize> {
     pure fn initial() -> CSSFontSize { return AbsoluteSize(Medium); }
 }
 
+impl CSSValue<~[CSSFontFamily]> : ResolveMethods<~[CSSFontFamily]> {
+    pure fn initial() -> ~[CSSFontFamily] { return ~[GenericSerif]; }
+}
 
-struct StyleApplicator {
-    node: Node,
+impl CSSValue<CSSFontWeight> : ResolveMethods<CSSFontWeight> {
+    pure fn initial() -> CSSFontWeight { return WeightNumber(400u); }
 }
 
-// TODO: normalize this into a normal preorder tree traversal function
-fn apply_style(layout_ctx: &LayoutContext, node: Node) {
-    let applicator = StyleApplicator {
-        node: node,
-    };
+impl CSSValue<CSSFontStyle> : ResolveMethods<CSSFontStyle> {
+    pure fn initial() -> CSSFontStyle { return FontStyleNormal; }
+}
 
-    applicator.apply_css_style(layout_ctx);
+impl CSSValue<CSSDirection> : ResolveMethods<CSSDirection> {
+    pure fn initial() -> CSSDirection { return DirectionLtr; }
 }
 
-// TODO: this is misleadingly-named. It is actually trying to resolve CSS 'inherit' values.
+impl CSSValue<CSSTextAlign> : ResolveMethods<CSSTextAlign> {
+    pure fn initial() -> CSSTextAlign { return TextAlignStart; }
+}
 
-/** A wrapper around a set of functions that can be applied as a
- * top-down traversal of layout boxes.
- */
-fn inheritance_wrapper(layout_ctx: &LayoutContext, node : Node) {
-    let applicator = StyleApplicator {
-        node: node,
-    };
-    applicator.resolve_style(layout_ctx);
+impl CSSValue<CSSWhiteSpace> : ResolveMethods<CSSWhiteSpace> {
+    pure fn initial() -> CSSWhiteSpace { return WhiteSpaceNormal; }
 }
 
-/*
-fn resolve_fontsize(box : @RenderBox) {
-    // TODO: complete this
-    return
+impl CSSValue<CSSColor> : ResolveMethods<CSSColor> {
+    pure fn initial() -> CSSColor { return TextColor(black()); }
 }
 
-fn resolve_height(box : @RenderBox) -> au {
-    let style = box.node.get_style();
-    let inherit_val = match box.tree.parent {
-        None => au(0),
-        Some(parent) => parent.data.computed_size.height
-    };
+impl CSSValue<CSSBorderStyle> : ResolveMethods<CSSBorderStyle> {
+    pure fn initial() -> CSSBorderStyle { return BdrStyleNone; }
+}
+
+impl CSSValue<CSSPosition> : ResolveMethods<CSSPosition> {
+    pure fn initial() -> CSSPosition { return PosStatic; }
+}
+
+/// The size of 'font-size: medium', in px.
+const MEDIUM_FONT_SIZE_PX: int = 16;
+/// The width of 'border-width: medium', in px.
+const MEDIUM_BORDER_WIDTH_PX: int = 3;
+
+/** Returns the computed value of a property, given its inherited value. */
+pure fn resolve<T: Copy>(value: CSSValue<T>, initial: T, inherited: T) -> T {
+    match value {
+        Specified(v) => v,
+        Initial => initial,
+        Inherit => inherited
+    }
+}
+
+/** What relative lengths are resolved against (CSS Values Level 3, Section 5.1). */
+pub struct LengthContext {
+    // the font size that 'em' and 'ex' refer to
+    font_size: Au,
+    // the font size of the root element, which 'rem' refers to
+    root_font_size: Au,
+    // the size of the viewport, which the viewport-percentage units refer to
+    viewport: Size2D<Au>
+}
+
+/** Converts a length to app units. */
+pure fn length_to_au(length: Length, lengths: &LengthContext) -> Au {
+    let font_size = au::to_frac_px(lengths.font_size);
+    match length {
+        Px(px) => au::from_frac_px(px),
+        Pt(pt) => au::from_pt(pt),
+        Em(em) => au::from_frac_px(em * font_size),
+        // without the font's x-height, an 'ex' is taken as half an 'em'
+        Ex(ex) => au::from_frac_px(0.5 * ex * font_size),
+        Rem(rem) => au::from_frac_px(rem * au::to_frac_px(lengths.root_font_size)),
+        Vw(pct) => percentage_of(lengths.viewport.width, pct),
+        Vh(pct) => percentage_of(lengths.viewport.height, pct),
+        Vmin(pct) => percentage_of(au::min(lengths.viewport.width, lengths.viewport.height), pct),
+        Vmax(pct) => percentage_of(au::max(lengths.viewport.width, lengths.viewport.height), pct),
+        Calc(calc) => au::from_frac_px(calc_to_px(&calc, lengths))
+    }
+}
+
+/** Adds up the lengths of a 'calc()' in 'px', leaving out its percentage. */
+pure fn calc_to_px(calc: &CalcLength, lengths: &LengthContext) -> float {
+    let width = au::to_frac_px(lengths.viewport.width);
+    let height = au::to_frac_px(lengths.viewport.height);
+    calc.px + calc.em * au::to_frac_px(lengths.font_size)
+        + calc.rem * au::to_frac_px(lengths.root_font_size)
+        + (calc.vw * width + calc.vh * height + calc.vmin * float::min(width, height)
+           + calc.vmax * float::max(width, height)) / 100.0
+}
+
+pure fn percentage_of(size: Au, pct: float) -> Au {
+    au::from_frac_px(pct / 100.0 * au::to_frac_px(size))
+}
 
-    box.appearance.height = match style.height {
-        Initial => style.height.initial(),
-        Inherit => inherit_val,
-        Specified(val) => match val { // BoxSizing
-            BoxPercent(*) | BoxAuto | BoxLength(Px(_)) => val,
-            BoxLength(Em(n)) => BoxLength(Px(n * box.appearance.font_size.abs()))
+/** Makes the length of a BoxSizing absolute, leaving percentages to layout. */
+pure fn resolve_box_sizing(sizing: BoxSizing, lengths: &LengthContext) -> BoxSizing {
+    match sizing {
+        BoxPercent(*) | BoxAuto | BoxLength(Px(_)) => sizing,
+        BoxLength(len) => BoxLength(Px(au::to_frac_px(length_to_au(len, lengths)))),
+        // the percentage of a 'calc()' is left to layout too
+        BoxCalc(calc) => BoxCalc(CalcLength {
+            px: calc_to_px(&calc, lengths),
+            percent: calc.percent,
+            .. CalcLength()
+        })
+    }
+}
+
+/**
+Resolves 'font-size', in which 'em' and percentages refer to the
+parent's font size. `lengths` holds the parent's font size.
+*/
+pure fn resolve_font_size(size: CSSValue<CSSFontSize>, lengths: &LengthContext) -> Au {
+    let parent_size = au::to_frac_px(lengths.font_size);
+    match resolve(size, size.initial(), LengthSize(Px(parent_size))) {
+        LengthSize(len) => length_to_au(len, lengths),
+        PercentSize(pct) => au::from_frac_px(pct / 100.0 * parent_size),
+        AbsoluteSize(size) => {
+            au::from_frac_px(absolute_size_factor(size) * (MEDIUM_FONT_SIZE_PX as float))
         }
+        RelativeSize(Larger) => au::from_frac_px(parent_size * FONT_SIZE_RATIO),
+        RelativeSize(Smaller) => au::from_frac_px(parent_size / FONT_SIZE_RATIO)
     }
 }
 
-fn resolve_width(box : @RenderBox) {
-    let style = box.node.get_specified_style();
-    let inherit_val = match box.tree.parent {
-        None => style.height.initial(),
-        Some(node) => node.appearance.width
-    };
+/// How much 'larger' and 'smaller' scale the parent's font size.
+const FONT_SIZE_RATIO: float = 1.2;
+
+/** The size of an absolute-size keyword relative to 'medium' (CSS Fonts Level 3, 3.5). */
+pure fn absolute_size_factor(size: AbsoluteSize) -> float {
+    match size {
+        XXSmall => 3.0 / 5.0,
+        XSmall => 3.0 / 4.0,
+        Small => 8.0 / 9.0,
+        Medium => 1.0,
+        Large => 6.0 / 5.0,
+        XLarge => 3.0 / 2.0,
+        XXLarge => 2.0
+    }
+}
+
+/**
+Resolves 'font-weight', where 'bolder' and 'lighter' step from the
+parent's weight (CSS Fonts Level 3, Section 3.2).
+*/
+pure fn resolve_font_weight(weight: CSSValue<CSSFontWeight>, inherited: uint) -> uint {
+    match resolve(weight, weight.initial(), WeightNumber(inherited)) {
+        WeightNumber(weight) => weight,
+        WeightBolder if inherited < 400u => 400u,
+        WeightBolder if inherited < 600u => 700u,
+        WeightBolder => 900u,
+        WeightLighter if inherited < 600u => 100u,
+        WeightLighter if inherited < 800u => 400u,
+        WeightLighter => 700u
+    }
+}
 
-    box.appearance.width = match style.width {
-        Initial => style.width.initial(),
-        Inherit => inherit_val,
-        Specified(val) => match val { // BoxSizing
-            BoxPercent(*) | BoxAuto | BoxLength(Px(_)) => val,
-            BoxLength(Em(n)) => BoxLength(Px(n * box.appearance.font_size.abs()))
+/** Resolves a margin, padding or text indent, whose initial value is zero. */
+pure fn resolve_edge(edge: CSSValue<BoxSizing>, inherited: BoxSizing,
+                     lengths: &LengthContext) -> BoxSizing {
+    resolve_box_sizing(resolve(edge, BoxLength(Px(0.0)), inherited), lengths)
+}
+
+/** Resolves a 'border-*-width', which computes to zero when the border has no style. */
+pure fn resolve_border_width(width: CSSValue<Length>, style: CSSBorderStyle, inherited: Au,
+                             lengths: &LengthContext) -> Au {
+    match style {
+        BdrStyleNone | BdrStyleHidden => Au(0),
+        _ => match width {
+            Specified(len) => length_to_au(len, lengths),
+            Initial => au::from_px(MEDIUM_BORDER_WIDTH_PX),
+            Inherit => inherited
         }
     }
-}*/
+}
 
-impl StyleApplicator {
-    fn apply_css_style(layout_ctx: &LayoutContext) {
+/** Resolves a 'border-*-color', whose initial value is the element's 'color'. */
+pure fn resolve_border_color(color: CSSValue<CSSBorderColor>, inherited: Color,
+                             text_color: Color) -> Color {
+    match color {
+        Specified(BdrColor(color)) => color,
+        Specified(BdrColorTransparent) => rgba(0, 0, 0, 0.0),
+        Inherit => inherited,
+        Initial => text_color
+    }
+}
+
+/** Resolves an offset property ('top', 'left', etc.), where 'initial' means 'auto'. */
+pure fn resolve_offset(offset: CSSValue<Length>, inherited: Option<Au>,
+                       lengths: &LengthContext) -> Option<Au> {
+    match offset {
+        Specified(len) => Some(length_to_au(len, lengths)),
+        Initial => None,
+        Inherit => inherited
+    }
+}
+
+/** The computed style of the root's (nonexistent) parent, which holds all the initial values. */
+fn initial_computed_style() -> ComputedStyle {
+    ComputedStyle {
+        background_color: BgColorTransparent,
+        background_image: BgImageNone,
+        display_type: DisplayInline,
+        font_size: au::from_px(MEDIUM_FONT_SIZE_PX),
+        font_family: ~[GenericSerif],
+        font_weight: 400u,
+        font_style: FontStyleNormal,
+        direction: DirectionLtr,
+        white_space: WhiteSpaceNormal,
+        text_align: TextAlignStart,
+        text_indent: BoxLength(Px(0.0)),
+        height: BoxAuto,
+        text_color: black(),
+        width: BoxAuto,
+        margin_top: BoxLength(Px(0.0)),
+        margin_right: BoxLength(Px(0.0)),
+        margin_bottom: BoxLength(Px(0.0)),
+        margin_left: BoxLength(Px(0.0)),
+        padding_top: BoxLength(Px(0.0)),
+        padding_right: BoxLength(Px(0.0)),
+        padding_bottom: BoxLength(Px(0.0)),
+        padding_left: BoxLength(Px(0.0)),
+        border_top_width: Au(0),
+        border_right_width: Au(0),
+        border_bottom_width: Au(0),
+        border_left_width: Au(0),
+        border_top_style: BdrStyleNone,
+        border_right_style: BdrStyleNone,
+        border_bottom_style: BdrStyleNone,
+        border_left_style: BdrStyleNone,
+        border_top_color: black(),
+        border_right_color: black(),
+        border_bottom_color: black(),
+        border_left_color: black(),
+        position: PosStatic,
+        top: None,
+        right: None,
+        bottom: None,
+        left: None,
+        custom_properties: ~[]
+    }
+}
+
+/**
+ * Computes a node's style from its cascaded, specified style and the computed style of its
+ * parent. `root_font_size` is the computed font size of the root element, or None when
+ * computing the style of the root itself, and `viewport` the size of the viewport.
+ */
+fn compute_style(style: &SpecifiedStyle, parent: &ComputedStyle, root_font_size: Option<Au>,
+                 viewport: Size2D<Au>) -> ComputedStyle {
+    // declarations using 'var()' can only be parsed once the custom properties are known
+    let custom_properties = compute_custom_properties(style.custom_properties,
+                                                      parent.custom_properties);
+    let substituted = substitute_declarations(style, custom_properties);
+    let style = &substituted;
+
+    // in the root's 'font-size', 'rem' refers to the initial font size
+    let font_size = resolve_font_size(style.font_size, &LengthContext {
+        font_size: parent.font_size,
+        root_font_size: root_font_size.get_default(au::from_px(MEDIUM_FONT_SIZE_PX)),
+        viewport: viewport
+    });
+    let lengths = LengthContext {
+        font_size: font_size,
+        root_font_size: root_font_size.get_default(font_size),
+        viewport: viewport
+    };
+    let text_color = match resolve(style.text_color, style.text_color.initial(),
+                                   TextColor(parent.text_color)) {
+        TextColor(color) => color
+    };
+    let border_top_style = resolve(style.border_top_style, style.border_top_style.initial(),
+                                   parent.border_top_style);
+    let border_right_style = resolve(style.border_right_style,
+                                     style.border_right_style.initial(),
+                                     parent.border_right_style);
+    let border_bottom_style = resolve(style.border_bottom_style,
+                                      style.border_bottom_style.initial(),
+                                      parent.border_bottom_style);
+    let border_left_style = resolve(style.border_left_style, style.border_left_style.initial(),
+                                    parent.border_left_style);
+
+    ComputedStyle {
+        background_color: resolve(style.background_color, style.background_color.initial(),
+                                  parent.background_color),
+        background_image: resolve(copy style.background_image,
+                                  style.background_image.initial(),
+                                  copy parent.background_image),
+        display_type: resolve(style.display_type, style.display_type.initial(),
+                              parent.display_type),
+        font_size: font_size,
+        font_family: resolve(copy style.font_family, style.font_family.initial(),
+                             copy parent.font_family),
+        font_weight: resolve_font_weight(style.font_weight, parent.font_weight),
+        font_style: resolve(style.font_style, style.font_style.initial(), parent.font_style),
+        direction: resolve(style.direction, style.direction.initial(), parent.direction),
+        white_space: resolve(style.white_space, style.white_space.initial(),
+                             parent.white_space),
+        text_align: resolve(style.text_align, style.text_align.initial(), parent.text_align),
+        text_indent: resolve_edge(style.text_indent, parent.text_indent, &lengths),
+        height: resolve_box_sizing(resolve(style.height, style.height.initial(), parent.height),
+                                   &lengths),
+        text_color: text_color,
+        width: resolve_box_sizing(resolve(style.width, style.width.initial(), parent.width),
+                                  &lengths),
+        margin_top: resolve_edge(style.margin_top, parent.margin_top, &lengths),
+        margin_right: resolve_edge(style.margin_right, parent.margin_right, &lengths),
+        margin_bottom: resolve_edge(style.margin_bottom, parent.margin_bottom, &lengths),
+        margin_left: resolve_edge(style.margin_left, parent.margin_left, &lengths),
+        padding_top: resolve_edge(style.padding_top, parent.padding_top, &lengths),
+        padding_right: resolve_edge(style.padding_right, parent.padding_right, &lengths),
+        padding_bottom: resolve_edge(style.padding_bottom, parent.padding_bottom, &lengths),
+        padding_left: resolve_edge(style.padding_left, parent.padding_left, &lengths),
+        border_top_width: resolve_border_width(style.border_top_width, border_top_style,
+                                               parent.border_top_width, &lengths),
+        border_right_width: resolve_border_width(style.border_right_width, border_right_style,
+                                                 parent.border_right_width, &lengths),
+        border_bottom_width: resolve_border_width(style.border_bottom_width, border_bottom_style,
+                                                  parent.border_bottom_width, &lengths),
+        border_left_width: resolve_border_width(style.border_left_width, border_left_style,
+                                                parent.border_left_width, &lengths),
+        border_top_style: border_top_style,
+        border_right_style: border_right_style,
+        border_bottom_style: border_bottom_style,
+        border_left_style: border_left_style,
+        border_top_color: resolve_border_color(style.border_top_color, parent.border_top_color,
+                                               text_color),
+        border_right_color: resolve_border_color(style.border_right_color,
+                                                 parent.border_right_color, text_color),
+        border_bottom_color: resolve_border_color(style.border_bottom_color,
+                                                  parent.border_bottom_color, text_color),
+        border_left_color: resolve_border_color(style.border_left_color, parent.border_left_color,
+                                                text_color),
+        position: resolve(style.position, style.position.initial(), parent.position),
+        top: resolve_offset(style.top, parent.top, &lengths),
+        right: resolve_offset(style.right, parent.right, &lengths),
+        bottom: resolve_offset(style.bottom, parent.bottom, &lengths),
+        left: resolve_offset(style.left, parent.left, &lengths),
+        custom_properties: move custom_properties
+    }
+}
+
+struct StyleApplicator {
+    node: Node,
+    // None for the root element, whose font size 'rem' refers to
+    root_font_size: Option<Au>
+}
+
+/**
+ * Resolves the styles of the subtree rooted at `node`. Selector matching must already have been
+ * performed on the subtree. Parents are resolved before their children, since inherited values
+ * come from the parent's computed style.
+ */
+fn apply_style(layout_ctx: &LayoutContext, node: Node) {
+    apply_style_with_siblings(layout_ctx, node, &DVec(), None);
+}
+
+/**
+ * Siblings given the same specified style by the style sharing cache also get the same
+ * computed style, since they have the same parent. `siblings` holds the elements resolved
+ * before `node` that did not share their style; the one a node shares with is among the last
+ * `CACHE_SIZE` of them, like in the cache.
+ */
+fn apply_style_with_siblings(layout_ctx: &LayoutContext, node: Node, siblings: &DVec<Node>,
+                             root_font_size: Option<Au>) {
+    let style = node.aux(|a| a.style);
+    let mut sharing = None;
+    let mut i = siblings.len();
+    while i > 0u && i + CACHE_SIZE > siblings.len() {
+        i -= 1u;
+        let sibling = siblings.get_elt(i);
+        if sibling.aux(|a| box::ptr_eq(a.style, style)) {
+            sharing = Some(sibling);
+            break;
+        }
+    }
 
-        for NodeTree.each_child(&self.node) |child| {
-            inheritance_wrapper(layout_ctx, *child)
+    match sharing {
+        Some(sibling) => {
+            let computed = sibling.aux(|a| {
+                match a.computed_style {
+                    Some(ref computed) => clone(computed),
+                    None => fail ~"sibling style was not resolved"
+                }
+            });
+            node.aux(|a| a.computed_style = Some(move computed));
+        }
+        None => {
+            let applicator = StyleApplicator {
+                node: node,
+                root_font_size: root_font_size
+            };
+            applicator.resolve_style(layout_ctx);
+            let is_element = do node.read |n| {
+                match *n.kind {
+                    dom::node::Element(*) => true,
+                    _ => false
+                }
+            };
+            if is_element { siblings.push(node); }
         }
     }
 
-    /** 
+    let root_font_size = match root_font_size {
+        Some(size) => size,
+        None => node.computed_style().font_size
+    };
+    let children = DVec();
+    for NodeTree.each_child(&node) |kid| {
+        apply_style_with_siblings(layout_ctx, *kid, &children, Some(root_font_size));
+    }
+}
+
+impl StyleApplicator {
+    /**
      * Convert the cascaded, specified style for this node into a resolved style:
-     * one which additionally resolves the values of Initial, Inherit based on 
-     * defaults and node parent style. It also converts Node attributes into 
-     * equivalent inline style declarations (TODO: where is this defined??)
+     * one which additionally resolves the values of Initial, Inherit based on
+     * defaults and node parent style, and makes relative lengths absolute.
      */
-    fn resolve_style(_layout_ctx: &LayoutContext) {
-        // TODO: implement
+    fn resolve_style(layout_ctx: &LayoutContext) {
+        let parent_style = match tree::get_parent(&NodeTree, &self.node) {
+            Some(parent) => parent.computed_style(),
+            None => initial_computed_style()
+        };
+
+        let computed = compute_style(&self.node.style(), &parent_style, self.root_font_size,
+                                     layout_ctx.screen_size.size);
+        debug!("Computed style: %?", computed);
+        self.node.aux(|a| a.computed_style = Some(ARC(move computed)));
     }
 }
 
 #[cfg(test)]
 mod test {
-    /* TODO: rewrite once cascade and resolve written. */
+    use css::lexer::spawn_css_lexer_from_string;
+    use css::parser::parse_style_attribute;
+    use css::resolve::matching::update_style;
+    use css::styles::empty_style_for_node_kind;
+    use dom::node::Text;
+
+    fn viewport() -> Size2D<Au> { Size2D(au::from_px(800), au::from_px(600)) }
+
+    #[test]
+    fn test_inherit_and_initial() {
+        let parent = initial_computed_style();
+        let parent = ComputedStyle { font_size: au::from_px(20),
+                                     text_color: rgba(255, 0, 0, 1.0),
+                                     width: BoxLength(Px(100.0)),
+                                     .. parent };
+
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        style.width = Inherit;
+        style.height = Specified(BoxLength(Em(2.0)));
+        style.border_top_width = Specified(Em(0.5));
+        style.border_top_style = Specified(BdrStyleSolid);
+        style.border_left_width = Specified(Em(0.5));
+        style.margin_left = Specified(BoxLength(Em(1.0)));
+        let computed = compute_style(&style, &parent, Some(au::from_px(16)), viewport());
+
+        // 'color' and 'font-size' are inherited by default
+        assert computed.text_color == rgba(255, 0, 0, 1.0);
+        assert computed.font_size == au::from_px(20);
+        assert computed.width == BoxLength(Px(100.0));
+        assert computed.height == BoxLength(Px(40.0));
+        assert computed.border_top_width == au::from_px(10);
+        // a border without a style has no width
+        assert computed.border_left_width == Au(0);
+        assert computed.margin_left == BoxLength(Px(20.0));
+        assert computed.padding_left == BoxLength(Px(0.0));
+        assert computed.background_color == BgColorTransparent;
+    }
+
+    #[test]
+    fn test_em_font_size_uses_parent() {
+        let parent = ComputedStyle { font_size: au::from_px(10), .. initial_computed_style() };
+
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        style.font_size = Specified(LengthSize(Em(1.5)));
+        style.width = Specified(BoxLength(Em(2.0)));
+        let computed = compute_style(&style, &parent, Some(au::from_px(16)), viewport());
+
+        assert computed.font_size == au::from_px(15);
+        // other lengths are relative to the element's own font size
+        assert computed.width == BoxLength(Px(30.0));
+    }
+
+    #[test]
+    fn test_keyword_font_sizes() {
+        let parent = ComputedStyle { font_size: au::from_px(10), .. initial_computed_style() };
+        let font_size = |size: CSSFontSize| {
+            let style = empty_style_for_node_kind(&Text(~"text"));
+            style.font_size = Specified(size);
+            compute_style(&style, &parent, Some(au::from_px(16)), viewport()).font_size
+        };
+
+        // absolute sizes do not depend on the parent's
+        assert font_size(AbsoluteSize(Medium)) == au::from_px(16);
+        assert font_size(AbsoluteSize(XXLarge)) == au::from_px(32);
+        assert font_size(AbsoluteSize(XSmall)) == au::from_px(12);
+        assert font_size(RelativeSize(Larger)) == au::from_px(12);
+        assert font_size(PercentSize(150.0)) == au::from_px(15);
+    }
+
+    #[test]
+    fn test_font_weight_and_family() {
+        let parent = ComputedStyle { font_weight: 700u,
+                                     font_family: ~[FamilyName(~"Arial"), GenericSansSerif],
+                                     .. initial_computed_style() };
+        let weight = |weight: CSSFontWeight| {
+            let style = empty_style_for_node_kind(&Text(~"text"));
+            style.font_weight = Specified(weight);
+            compute_style(&style, &parent, Some(au::from_px(16)), viewport()).font_weight
+        };
+        assert weight(WeightBolder) == 900u;
+        assert weight(WeightLighter) == 400u;
+        assert weight(WeightNumber(300u)) == 300u;
+
+        // the family, weight and style are inherited by default
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        let computed = compute_style(&style, &parent, Some(au::from_px(16)), viewport());
+        assert computed.font_family == parent.font_family;
+        assert computed.font_weight == 700u;
+        assert computed.font_style == FontStyleNormal;
+    }
+
+    #[test]
+    fn test_variables_and_calc() {
+        let ((decls, _), _) = parse_style_attribute(spawn_css_lexer_from_string(
+            ~"--gap: 2em; width: calc(50% - var(--gap)); margin-left: var(--gap)"));
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        for decls.each |decl| { update_style(&style, copy *decl); }
+        let parent = ComputedStyle { font_size: au::from_px(10), .. initial_computed_style() };
+        let computed = compute_style(&style, &parent, Some(au::from_px(16)), viewport());
+
+        // the percentage is resolved by layout
+        assert computed.width == BoxCalc(CalcLength { px: -20.0, percent: 50.0, .. CalcLength() });
+        assert computed.margin_left == BoxLength(Px(20.0));
+
+        // custom properties are inherited with 'var()' already substituted
+        let child = compute_style(&empty_style_for_node_kind(&Text(~"text")), &computed,
+                                  Some(au::from_px(16)), viewport());
+        assert child.custom_properties == computed.custom_properties;
+    }
+
+    #[test]
+    fn test_root_and_viewport_units() {
+        let parent = ComputedStyle { font_size: au::from_px(10), .. initial_computed_style() };
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        style.font_size = Specified(LengthSize(Rem(2.0)));
+        style.width = Specified(BoxLength(Vw(10.0)));
+        style.height = Specified(BoxLength(Vmin(50.0)));
+        style.margin_left = Specified(BoxLength(Ex(2.0)));
+        style.margin_right = Specified(BoxLength(Pt(3.0)));
+        style.padding_left = Specified(BoxLength(Rem(1.0)));
+        let computed = compute_style(&style, &parent, Some(au::from_px(12)), viewport());
+
+        assert computed.font_size == au::from_px(24);
+        assert computed.width == BoxLength(Px(80.0));
+        assert computed.height == BoxLength(Px(300.0));
+        assert computed.margin_left == BoxLength(Px(24.0));
+        assert computed.margin_right == BoxLength(Px(4.0));
+        assert computed.padding_left == BoxLength(Px(12.0));
+
+        // in the root's 'font-size', 'rem' is the initial font size, elsewhere the root's own
+        let root = compute_style(&style, &initial_computed_style(), None, viewport());
+        assert root.font_size == au::from_px(32);
+        assert root.padding_left == BoxLength(Px(32.0));
+    }
+
+    #[test]
+    fn test_root_and_viewport_units_in_calc() {
+        let parent = ComputedStyle { font_size: au::from_px(10), .. initial_computed_style() };
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        style.font_size = Specified(LengthSize(Calc(CalcLength { rem: 1.0, vw: 1.0,
+                                                                 .. CalcLength() })));
+        style.width = Specified(BoxCalc(CalcLength { vh: 1.0, rem: -1.0, percent: 50.0,
+                                                     .. CalcLength() }));
+        style.margin_left = Specified(BoxLength(Calc(CalcLength { vmin: 1.0, vmax: 1.0,
+                                                                  .. CalcLength() })));
+        let computed = compute_style(&style, &parent, Some(au::from_px(12)), viewport());
+
+        assert computed.font_size == au::from_px(20);
+        assert computed.width == BoxCalc(CalcLength { px: -6.0, percent: 50.0, .. CalcLength() });
+        assert computed.margin_left == BoxLength(Px(14.0));
+    }
 }
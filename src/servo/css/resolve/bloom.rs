@@ -0,0 +1,112 @@
+/*!
+A counting Bloom filter over the tag names, ids and classes of the
+ancestors of the node being styled. Selector matching asks it whether
+the ancestors a selector needs may be present, and skips the selector
+when they definitely are not.
+*/
+
+use css::resolve::snapshot::ElementInfo;
+use dom::element::ElementData;
+
+const FILTER_SIZE: uint = 4096u;
+const FILTER_MASK: u32 = 0xfffu32;
+
+/** Hashes a tag name, id or class (FNV-1a). */
+pub pure fn hash_name(name: &str) -> u32 {
+    let mut hash = 0x811c9dc5u32;
+    for str::each(name) |b| {
+        hash = (hash ^ (b as u32)) * 0x01000193u32;
+    }
+    hash
+}
+
+pub struct BloomFilter {
+    // Counts rather than bits, so that ancestors can be removed again
+    // when the traversal leaves them.
+    priv mut counters: ~[mut u8]
+}
+
+pub fn BloomFilter() -> BloomFilter {
+    BloomFilter { counters: vec::to_mut(vec::from_elem(FILTER_SIZE, 0u8)) }
+}
+
+impl BloomFilter {
+    // Each hash sets two counters, from its low and high bits.
+    priv pure fn slots(hash: u32) -> (uint, uint) {
+        ((hash & FILTER_MASK) as uint, ((hash >> 12) & FILTER_MASK) as uint)
+    }
+
+    fn insert_hash(hash: u32) {
+        let (a, b) = self.slots(hash);
+        for [a, b].each |slot| {
+            // a saturated counter stays saturated, since it may have been
+            // incremented more often than we can count
+            if self.counters[*slot] != 255u8 { self.counters[*slot] += 1u8; }
+        }
+    }
+
+    fn remove_hash(hash: u32) {
+        let (a, b) = self.slots(hash);
+        for [a, b].each |slot| {
+            let count = self.counters[*slot];
+            assert count != 0u8;
+            if count != 255u8 { self.counters[*slot] = count - 1u8; }
+        }
+    }
+
+    /** Returns false if the hash was definitely never inserted. */
+    pure fn might_contain_hash(hash: u32) -> bool {
+        let (a, b) = self.slots(hash);
+        self.counters[a] != 0u8 && self.counters[b] != 0u8
+    }
+
+    /** Adds the tag name, id and classes of an ancestor. */
+    fn insert_element<E: ElementInfo>(elmt: &E) {
+        do each_element_hash(elmt) |hash| { self.insert_hash(hash); true }
+    }
+
+    /** Removes an ancestor added by `insert_element`. */
+    fn remove_element<E: ElementInfo>(elmt: &E) {
+        do each_element_hash(elmt) |hash| { self.remove_hash(hash); true }
+    }
+}
+
+fn each_element_hash<E: ElementInfo>(elmt: &E, f: fn(u32) -> bool) {
+    if !f(hash_name(elmt.get_tag_name())) { return; }
+    match elmt.attr_value(~"id") {
+      Some(id) => { if !f(hash_name(id)) { return; } }
+      None => {}
+    }
+    match elmt.attr_value(~"class") {
+      Some(classes) => {
+        for str::split_char_nonempty(classes, ' ').each |class| {
+            if !f(hash_name(*class)) { return; }
+        }
+      }
+      None => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dom::element::{Attr, HTMLDivElement};
+
+    #[test]
+    fn should_forget_removed_elements() {
+        let filter = BloomFilter();
+        let elmt = ElementData(~"div", ~HTMLDivElement);
+        elmt.attrs.push(~Attr(~"class", ~"a b"));
+
+        assert !filter.might_contain_hash(hash_name("div"));
+        filter.insert_element(&elmt);
+        assert filter.might_contain_hash(hash_name("div"));
+        assert filter.might_contain_hash(hash_name("a"));
+        assert filter.might_contain_hash(hash_name("b"));
+        filter.insert_element(&elmt);
+        filter.remove_element(&elmt);
+        assert filter.might_contain_hash(hash_name("b"));
+        filter.remove_element(&elmt);
+        assert !filter.might_contain_hash(hash_name("div"));
+        assert !filter.might_contain_hash(hash_name("b"));
+    }
+}
@@ -2,26 +2,43 @@
    Performs CSS selector matching.
 */
 
-use dom::node::{LayoutData, Node, Text};
+use css::resolve::bloom::BloomFilter;
+use css::resolve::rule_hash::RuleHash;
+use css::resolve::snapshot::{ElementInfo, ElementSnapshot, SubtreeSnapshot, has_no_content};
+use dom::node::{LayoutData, Node};
 use dom::element::ElementData;
+use dvec::DVec;
+use std::arc::get;
+use std::sort;
 
 use values::*;
-use styles::{SpecifiedStyle};
+use css::parser::longhand_names;
+use styles::{PendingDeclaration, SpecifiedStyle, empty_style_for_node_kind};
 
 /** 
    Check if a CSS attribute matches the attribute of an HTML element.
 */
-fn attrs_match(attr: &Attr, elmt: &ElementData) -> bool {
+fn attrs_match<E: ElementInfo>(attr: &Attr, elmt: &E) -> bool {
+    match *attr {
+      CaseInsensitive(ref attr) => attr_value_matches(*attr, elmt, true),
+      _ => attr_value_matches(attr, elmt, false)
+    }
+}
+
+fn attr_value_matches<E: ElementInfo>(attr: &Attr, elmt: &E, ignore_case: bool) -> bool {
+    // Compares attribute values ASCII case-insensitively for the 'i' flag
+    let fold = |s: &~str| if ignore_case { str::to_lower(*s) } else { copy *s };
+
     match *attr {
       Exists(name) => {
-        match elmt.get_attr(name) {
+        match elmt.attr_value(name) {
           Some(_) => true,
           None => false
         }
       }
       Exact(name, val) => {
-        match elmt.get_attr(name) {
-          Some(value) => value == val,
+        match elmt.attr_value(name) {
+          Some(value) => fold(&value) == fold(&val),
           None => false
         }
       }
@@ -30,14 +47,15 @@ fn attrs_match(attr: &Attr, elmt: &ElementData) -> bool {
         // it cannot match.
         if val == ~"" { return false; }
 
-        match elmt.get_attr(name) {
-          Some(value) => value.split_char(' ').contains(&val),
+        match elmt.attr_value(name) {
+          Some(value) => fold(&value).split_char(' ').contains(&fold(&val)),
           None => false
         }
       }
-      StartsWith(name, val) => {
-        match elmt.get_attr(name) {
+      DashMatch(name, val) => {
+        match elmt.attr_value(name) {
           Some(value) => { 
+            let (value, val) = (fold(&value), fold(&val));
             //check that there is only one attribute value and it
             //starts with the perscribed value
             if !value.starts_with(val) || value.contains(~" ") { return false; }
@@ -51,185 +69,600 @@ fn attrs_match(attr: &Attr, elmt: &ElementData) -> bool {
           }
         }
       }
+      // An empty value never matches the substring operators
+      Prefix(name, val) => {
+        match elmt.attr_value(name) {
+          Some(value) => val != ~"" && fold(&value).starts_with(fold(&val)),
+          None => false
+        }
+      }
+      Suffix(name, val) => {
+        match elmt.attr_value(name) {
+          Some(value) => val != ~"" && fold(&value).ends_with(fold(&val)),
+          None => false
+        }
+      }
+      Substring(name, val) => {
+        match elmt.attr_value(name) {
+          Some(value) => val != ~"" && fold(&value).contains(fold(&val)),
+          None => false
+        }
+      }
+      Id(val) => {
+        match elmt.attr_value(~"id") {
+          Some(value) => value == val,
+          None => false
+        }
+      }
+      Class(val) => {
+        match elmt.attr_value(~"class") {
+          Some(value) => value.split_char(' ').contains(&val),
+          None => false
+        }
+      }
+      CaseInsensitive(_) => fail ~"the 'i' flag cannot be nested",
+      Pseudo(_) => fail ~"pseudo-classes are matched by Node::matches_pseudo_class"
     }
 }
 
-trait PrivMatchingMethods {
-    fn matches_element(sel: &Selector) -> bool;
-    fn matches_selector(sel: &Selector) -> bool;
+/** Checks whether `index` (counting from 1) is `a*n + b` for some `n >= 0`. */
+pure fn nth_matches(a: int, b: int, index: int) -> bool {
+    if a == 0 {
+        index == b
+    } else {
+        let diff = index - b;
+        diff % a == 0 && diff / a >= 0
+    }
+}
+
+/**
+An element that a selector is matched against: a node of the tree, or
+the copy of one around the subtree that a style worker matches.
+*/
+enum MatchTarget {
+    LiveElement(Node),
+    // the element of a level of the snapshot when None, or else the
+    // element sibling before it at that index
+    SnapshotElement(uint, Option<uint>)
 }
 
-impl Node : PrivMatchingMethods { 
+/**
+Selector matching over the tree, and over the snapshot of the elements
+around a style worker's subtree when there is one: the worker never
+reads the nodes outside its subtree.
+*/
+struct Matcher {
+    around: Option<&self/SubtreeSnapshot>
+}
+
+impl Matcher {
+    priv fn snapshot() -> &self/SubtreeSnapshot {
+        match self.around {
+          Some(snapshot) => snapshot,
+          None => fail ~"only a worker's matcher has a snapshot"
+        }
+    }
+
+    priv fn is_worker_root(node: Node) -> bool {
+        match self.around {
+          Some(snapshot) => node == snapshot.root,
+          None => false
+        }
+    }
+
+    priv fn snapshot_element(level: uint, sib: Option<uint>) -> Option<&self/ElementSnapshot> {
+        let level = &self.snapshot().levels[level];
+        match sib {
+          Some(i) => Some(&level.prev_siblings[i]),
+          None => match level.element {
+            Some(ref elmt) => Some(elmt),
+            None => None
+          }
+        }
+    }
+
+    fn is_element(target: MatchTarget) -> bool {
+        match target {
+          LiveElement(node) => do node.read |n| {
+            match *n.kind {
+              dom::node::Element(*) => true,
+              _ => false
+            }
+          },
+          SnapshotElement(level, sib) => self.snapshot_element(level, sib).is_some()
+        }
+    }
+
+    fn parent(target: MatchTarget) -> Option<MatchTarget> {
+        match target {
+          LiveElement(node) if self.is_worker_root(node) => {
+            if self.snapshot().levels.len() > 1u { Some(SnapshotElement(1u, None)) } else { None }
+          }
+          LiveElement(node) => node.read(|n| n.tree.parent).map(|p| LiveElement(*p)),
+          SnapshotElement(level, _) => {
+            if level + 1u < self.snapshot().levels.len() {
+                Some(SnapshotElement(level + 1u, None))
+            } else {
+                None
+            }
+          }
+        }
+    }
+
+    /** Finds the closest previous sibling that is an element, skipping text and comments. */
+    fn prev_element_sibling(target: MatchTarget) -> Option<MatchTarget> {
+        match target {
+          LiveElement(node) if self.is_worker_root(node) => {
+            let count = self.snapshot().levels[0].prev_siblings.len();
+            if count > 0u { Some(SnapshotElement(0u, Some(count - 1u))) } else { None }
+          }
+          LiveElement(node) => {
+            let mut cur_sib = node.read(|n| n.tree.prev_sibling);
+            loop {
+                match cur_sib {
+                  Some(sib) if !self.is_element(LiveElement(sib)) => {
+                    cur_sib = sib.read(|n| n.tree.prev_sibling);
+                  }
+                  Some(sib) => { return Some(LiveElement(sib)); }
+                  None => { return None; }
+                }
+            }
+          }
+          SnapshotElement(level, None) => {
+            let count = self.snapshot().levels[level].prev_siblings.len();
+            if count > 0u { Some(SnapshotElement(level, Some(count - 1u))) } else { None }
+          }
+          SnapshotElement(level, Some(i)) => {
+            if i > 0u { Some(SnapshotElement(level, Some(i - 1u))) } else { None }
+          }
+        }
+    }
+
+    fn has_next_element_sibling(target: MatchTarget) -> bool {
+        match target {
+          LiveElement(node) if self.is_worker_root(node) => {
+            self.snapshot().levels[0].has_next_sibling
+          }
+          LiveElement(node) => {
+            let mut cur_sib = node.read(|n| n.tree.next_sibling);
+            loop {
+                match cur_sib {
+                  Some(sib) if !self.is_element(LiveElement(sib)) => {
+                    cur_sib = sib.read(|n| n.tree.next_sibling);
+                  }
+                  Some(_) => { return true; }
+                  None => { return false; }
+                }
+            }
+          }
+          SnapshotElement(level, None) => self.snapshot().levels[level].has_next_sibling,
+          // the element after it at the same level is a sibling
+          SnapshotElement(_, Some(_)) => true
+        }
+    }
+
+    fn is_empty(target: MatchTarget) -> bool {
+        match target {
+          LiveElement(node) => has_no_content(node),
+          SnapshotElement(level, sib) => match self.snapshot_element(level, sib) {
+            Some(elmt) => elmt.empty,
+            None => false
+          }
+        }
+    }
+
+    /** Checks a pseudo-class (Selectors Level 3, Section 6.6) against an element. */
+    fn matches_pseudo_class<E: ElementInfo>(target: MatchTarget, pc: &PseudoClass,
+                                            elmt: &E) -> bool {
+        match *pc {
+          FirstChild => self.prev_element_sibling(target).is_none(),
+          LastChild => !self.has_next_element_sibling(target),
+          NthChild(a, b) => {
+            let mut index = 1;
+            let mut cur_sib = self.prev_element_sibling(target);
+            loop {
+                match cur_sib {
+                  Some(sib) => { index += 1; cur_sib = self.prev_element_sibling(sib); }
+                  None => { break; }
+                }
+            }
+            nth_matches(a, b, index)
+          }
+          Not(ref sel) => !self.matches_element(target, &**sel),
+          Root => self.parent(target).is_none(),
+          Empty => self.is_empty(target),
+          Link => {
+            let tag_name = elmt.get_tag_name();
+            (tag_name == ~"a" || tag_name == ~"area" || tag_name == ~"link")
+                && elmt.attr_value(~"href").is_some()
+          }
+          Hover => elmt.is_hovered(),
+          Focus => elmt.is_focused()
+        }
+    }
+
+    /** Checks the tag, attributes and pseudo-classes of a compound selector. */
+    fn matches_compound<E: ElementInfo>(target: MatchTarget, tag: &~str, attrs: &~[Attr],
+                                        elmt: &E) -> bool {
+        if !(*tag == ~"*" || *tag == elmt.get_tag_name()) {
+            return false;
+        }
+        for attrs.each |attr| {
+            let matches = match *attr {
+              Pseudo(ref pc) => self.matches_pseudo_class(target, pc, elmt),
+              _ => attrs_match(attr, elmt)
+            };
+            if !matches { return false; }
+        }
+        true
+    }
 
     /** 
     Checks if the given CSS selector, which must describe a single
     element with no relational information, describes the given HTML
     element.
     */
-    fn matches_element(sel: &Selector) -> bool {
+    fn matches_element(target: MatchTarget, sel: &Selector) -> bool {
         match *sel {
-          Child(_, _) | Descendant(_, _) | Sibling(_, _) => { return false; }
-          Element(tag, attrs) => {
-            match self.read(|n| copy *n.kind) {
-              dom::node::Element(elmt) => {
-                if !(tag == ~"*" || tag == elmt.tag_name) {
-                    return false;
-                }
-                
-                let mut i = 0u;
-                while i < attrs.len() {
-                    if !attrs_match(&attrs[i], &elmt) { return false; }
-                    i += 1u;
-                }
-
-                return true;
-              }
-              _ => { /*fall through, currently unsupported*/ }
+          Child(_, _) | Descendant(_, _) | Sibling(_, _) | GeneralSibling(_, _) => false,
+          Element(ref tag, ref attrs) => match target {
+            LiveElement(node) => match node.read(|n| copy *n.kind) {
+              dom::node::Element(elmt) => self.matches_compound(target, tag, attrs, &elmt),
+              _ => false
+            },
+            SnapshotElement(level, sib) => match self.snapshot_element(level, sib) {
+              Some(elmt) => self.matches_compound(target, tag, attrs, elmt),
+              None => false
             }
           }
         }
-
-        return false; //If we got this far it was because something was
-                   //unsupported.
     }
 
     /**
     Checks if a generic CSS selector matches a given HTML element
     */
-    fn matches_selector(sel : &Selector) -> bool {
+    fn matches_selector(target: MatchTarget, sel: &Selector) -> bool {
         match *sel {
-          Element(*) => { return self.matches_element(sel); }
+          Element(*) => self.matches_element(target, sel),
           Child(sel1, sel2) => {
-            return match self.read(|n| n.tree.parent) {
-              Some(parent) => self.matches_element(sel2) && parent.matches_selector(sel1),
+            match self.parent(target) {
+              Some(parent) => self.matches_element(target, sel2)
+                  && self.matches_selector(parent, sel1),
               None => false
             }
           }
           Descendant(sel1, sel2) => {
-            if !self.matches_element(sel2) { return false; }
+            if !self.matches_element(target, sel2) { return false; }
 
             //loop over all ancestors to check if they are the person
             //we should be descended from.
-            let mut cur_parent = match self.read(|n| n.tree.parent) {
-              Some(parent) => parent,
-              None => return false
-            };
-
+            let mut cur_parent = self.parent(target);
             loop {
-                if cur_parent.matches_selector(sel1) { return true; }
-
-                cur_parent = match cur_parent.read(|n| n.tree.parent) {
-                  Some(parent) => parent,
-                  None => return false
-                };
+                match cur_parent {
+                  Some(parent) => {
+                    if self.matches_selector(parent, sel1) { return true; }
+                    cur_parent = self.parent(parent);
+                  }
+                  None => { return false; }
+                }
             }
           }
           Sibling(sel1, sel2) => {
-            if !self.matches_element(sel2) { return false; }
-
-            // Loop over this node's previous siblings to see if they match.
-            match self.read(|n| n.tree.prev_sibling) {
-              Some(sib) => {
-                let mut cur_sib = sib;
-                loop {
-                    if cur_sib.matches_selector(sel1) { return true; }
-                    
-                    cur_sib = match cur_sib.read(|n| n.tree.prev_sibling) {
-                      Some(sib) => sib,
-                      None => { break; }
-                    };
-                }
-              }
-              None => { }
+            if !self.matches_element(target, sel2) { return false; }
+
+            // Only the closest previous element sibling can match.
+            match self.prev_element_sibling(target) {
+              Some(sib) => self.matches_selector(sib, sel1),
+              None => false
             }
+          }
+          GeneralSibling(sel1, sel2) => {
+            if !self.matches_element(target, sel2) { return false; }
 
-            // check the rest of the siblings
-            match self.read(|n| n.tree.next_sibling) {
-                Some(sib) => {
-                    let mut cur_sib = sib;
-                    loop {
-                        if cur_sib.matches_selector(sel1) { return true; }
-                
-                        cur_sib = match cur_sib.read(|n| n.tree.next_sibling) {
-                            Some(sib) => sib,
-                            None => { break; }
-                        };
-                    }
+            // Loop over this element's previous element siblings to see if they match.
+            let mut cur_sib = self.prev_element_sibling(target);
+            loop {
+                match cur_sib {
+                  Some(sib) => {
+                    if self.matches_selector(sib, sel1) { return true; }
+                    cur_sib = self.prev_element_sibling(sib);
+                  }
+                  None => { return false; }
                 }
-                None => { }
             }
-
-            return false;
           }
         }
     }
 }
 
-trait PrivStyleMethods {
-    fn update_style(decl : StyleDeclaration);
+trait PrivMatchingMethods {
+    fn matches_selector(sel: &Selector) -> bool;
+}
+
+impl Node : PrivMatchingMethods {
+    /** Checks if a generic CSS selector matches this node, reading the whole tree. */
+    fn matches_selector(sel: &Selector) -> bool {
+        Matcher { around: None }.matches_selector(LiveElement(self), sel)
+    }
+}
+
+/**
+Update the specified style of an HTML element with a declaration from CSS.
+Declarations must come in cascade order, so that a declaration using
+'var()' is overridden by the ones after it.
+*/
+fn update_style(style : &SpecifiedStyle, decl : StyleDeclaration) {
+    let overridden = match decl {
+      CustomProperty(*) => ~[],
+      VariableDeclaration(ref prop, _) => longhand_names(*prop),
+      _ => ~[decl.property_name()]
+    };
+    if overridden.is_not_empty() && style.variable_declarations.is_not_empty() {
+        style.variable_declarations = do style.variable_declarations.map |pending| {
+            PendingDeclaration { overridden: pending.overridden + overridden, .. copy *pending }
+        };
+    }
+
+    match move decl {
+      CustomProperty(move name, move value) => {
+        let existing = do style.custom_properties.position |prop| {
+            let (ref prop_name, _) = *prop;
+            *prop_name == name
+        };
+        match existing {
+          Some(i) => style.custom_properties[i] = (move name, move value),
+          None => vec::push(&mut style.custom_properties, (move name, move value))
+        }
+      }
+      VariableDeclaration(move prop, move value) => {
+        vec::push(&mut style.variable_declarations, PendingDeclaration {
+            property: move prop,
+            value: move value,
+            overridden: ~[]
+        });
+      }
+      BackgroundColor(col) => style.background_color = col,
+      Display(dis) => style.display_type = dis,
+      FontSize(size) => style.font_size = size,
+      FontFamily(move families) => style.font_family = move families,
+      FontWeight(weight) => style.font_weight = weight,
+      FontStyle(font_style) => style.font_style = font_style,
+      Direction(direction) => style.direction = direction,
+      WhiteSpace(white_space) => style.white_space = white_space,
+      TextAlign(align) => style.text_align = align,
+      TextIndent(indent) => style.text_indent = indent,
+      Height(size) => style.height = size,
+      Color(col) => style.text_color = col,
+      Width(size) => style.width = size,
+      MarginTop(size) => style.margin_top = size,
+      MarginRight(size) => style.margin_right = size,
+      MarginBottom(size) => style.margin_bottom = size,
+      MarginLeft(size) => style.margin_left = size,
+      PaddingTop(size) => style.padding_top = size,
+      PaddingRight(size) => style.padding_right = size,
+      PaddingBottom(size) => style.padding_bottom = size,
+      PaddingLeft(size) => style.padding_left = size,
+      BorderTopWidth(size) => style.border_top_width = size,
+      BorderRightWidth(size) => style.border_right_width = size,
+      BorderBottomWidth(size) => style.border_bottom_width = size,
+      BorderLeftWidth(size) => style.border_left_width = size,
+      BorderTopStyle(border_style) => style.border_top_style = border_style,
+      BorderRightStyle(border_style) => style.border_right_style = border_style,
+      BorderBottomStyle(border_style) => style.border_bottom_style = border_style,
+      BorderLeftStyle(border_style) => style.border_left_style = border_style,
+      BorderTopColor(col) => style.border_top_color = col,
+      BorderRightColor(col) => style.border_right_color = col,
+      BorderBottomColor(col) => style.border_bottom_color = col,
+      BorderLeftColor(col) => style.border_left_color = col,
+      Position(pos) => style.position = pos,
+      Top(pos) => style.top = pos,
+      Right(pos) => style.right = pos,
+      Bottom(pos) => style.bottom = pos,
+      Left(pos) => style.left = pos,
+    };
+}
+
+/**
+A declaration that applies to a node, together with everything the
+cascade needs to order it against the other applicable declarations.
+*/
+struct ApplicableDeclaration {
+    decl: StyleDeclaration,
+    level: uint,
+    specificity: u32,
+    // source order of the declaration, across all style sheets
+    order: uint
+}
+
+/**
+Returns the cascade level of a declaration (CSS 2.1 Section 6.4.1).
+Inline declarations share the author level, and win over author
+rules through their specificity instead.
+*/
+pure fn cascade_level(origin: Origin, important: bool) -> uint {
+    match (origin, important) {
+      (OriginUserAgent, false) => 0u,
+      (OriginAuthor, false) | (OriginInline, false) => 1u,
+      (OriginAuthor, true) | (OriginInline, true) => 2u,
+      (OriginUserAgent, true) => 3u
+    }
+}
+
+/// Orders declarations by level, then specificity, then source order.
+pure fn cascade_le(a: &ApplicableDeclaration, b: &ApplicableDeclaration) -> bool {
+    if a.level != b.level { return a.level < b.level; }
+    if a.specificity != b.specificity { return a.specificity < b.specificity; }
+    a.order <= b.order
+}
+
+/** Appends a block of normal and '!important' declarations that apply with the given priority. */
+fn push_declarations(decls: &[StyleDeclaration], important_decls: &[StyleDeclaration],
+                     origin: Origin, specificity: u32,
+                     applicable: &DVec<ApplicableDeclaration>) {
+    for decls.each |decl| {
+        applicable.push(ApplicableDeclaration {
+            decl: copy *decl,
+            level: cascade_level(origin, false),
+            specificity: specificity,
+            order: applicable.len()
+        });
+    }
+    for important_decls.each |decl| {
+        applicable.push(ApplicableDeclaration {
+            decl: copy *decl,
+            level: cascade_level(origin, true),
+            specificity: specificity,
+            order: applicable.len()
+        });
+    }
+}
+
+trait PrivCascadeMethods {
+    fn collect_declarations(rules: &RuleHash, ancestors: &BloomFilter,
+                            around: Option<&SubtreeSnapshot>, origin: Origin,
+                            applicable: &DVec<ApplicableDeclaration>);
+    fn collect_inline_declarations(applicable: &DVec<ApplicableDeclaration>);
 }
 
-impl Node : PrivStyleMethods {
+impl Node : PrivCascadeMethods {
     /**
-    Update the computed style of an HTML element with a style specified by CSS.
+    Appends the declarations of every rule in the stylesheet that
+    matches this node, in stylesheet order. When several selectors of a
+    rule match, the rule applies with the highest of their specificities.
+
+    Only the selectors indexed under this element's id, classes or tag
+    are tested, and those whose ancestors are missing from the ancestor
+    filter are rejected without walking the tree. The elements outside a
+    style worker's subtree are read from `around`.
     */
-    fn update_style(decl : StyleDeclaration) {
-        self.aux(|layout| {
-            match decl {
-              BackgroundColor(col) => layout.style.background_color = col,
-              Display(dis) => layout.style.display_type = dis,
-              FontSize(size) => layout.style.font_size = size,
-              Height(size) => layout.style.height = size,
-              Color(col) => layout.style.text_color = col,
-              Width(size) => layout.style.width = size,
-              BorderColor(col) => layout.style.border_color = col,
-              BorderWidth(size) => layout.style.border_width = size,
-              Position(pos) => layout.style.position = pos,
-              Top(pos) => layout.style.top = pos,
-              Right(pos) => layout.style.right = pos,
-              Bottom(pos) => layout.style.bottom = pos,
-              Left(pos) => layout.style.left = pos,
-            };
-        })
+    fn collect_declarations(rules: &RuleHash, ancestors: &BloomFilter,
+                            around: Option<&SubtreeSnapshot>, origin: Origin,
+                            applicable: &DVec<ApplicableDeclaration>) {
+        let elmt = match self.read(|n| copy *n.kind) {
+          dom::node::Element(elmt) => elmt,
+          _ => { return; }
+        };
+        let styles = get(&rules.styles);
+        let matcher = Matcher { around: around };
+
+        // (rule, specificity) of every matching selector
+        let mut matched = ~[];
+        for rules.each_candidate(&elmt) |sel_ref| {
+            if !sel_ref.ancestor_hashes.all(|hash| ancestors.might_contain_hash(*hash)) {
+                loop;
+            }
+            let (ref selectors, _, _, _) = *styles[sel_ref.rule];
+            let sel = &selectors[sel_ref.selector];
+            if matcher.matches_selector(LiveElement(self), *sel) {
+                vec::push(&mut matched, (sel_ref.rule, sel.specificity()));
+            }
+        }
+
+        let matched = sort::merge_sort(matched, |a, b| {
+            let ((rule_a, _), (rule_b, _)) = (*a, *b);
+            rule_a <= rule_b
+        });
+        let mut i = 0u;
+        while i < matched.len() {
+            let (rule, mut specificity) = matched[i];
+            i += 1u;
+            loop {
+                if i == matched.len() { break; }
+                let (next_rule, next_specificity) = matched[i];
+                if next_rule != rule { break; }
+                if next_specificity > specificity { specificity = next_specificity; }
+                i += 1u;
+            }
+
+            let (_, ref decls, ref important_decls, _) = *styles[rule];
+            push_declarations(*decls, *important_decls, origin, specificity, applicable);
+        }
+    }
+
+    /**
+    Appends the declarations of this node's 'style' attribute, which
+    are more specific than any selector (CSS 2.1 Section 6.4.3).
+    */
+    fn collect_inline_declarations(applicable: &DVec<ApplicableDeclaration>) {
+        let style_attribute = do self.read |n| {
+            match *n.kind {
+              dom::node::Element(ref elmt) => copy elmt.style_attribute,
+              _ => None
+            }
+        };
+
+        match style_attribute {
+          Some((decls, important_decls)) => {
+            push_declarations(decls, important_decls, OriginInline, INLINE_SPECIFICITY,
+                              applicable);
+          }
+          None => {}
+        }
     }
 }
 
 trait MatchingMethods {
-    fn match_css_style(styles : &Stylesheet);
+    fn cascade_css_style(ua_rules : &RuleHash, rules : &RuleHash, ancestors : &BloomFilter,
+                         around : Option<&SubtreeSnapshot>) -> SpecifiedStyle;
+    fn match_css_style(ua_rules : &RuleHash, rules : &RuleHash, ancestors : &BloomFilter);
 }
 
 impl Node : MatchingMethods {
     /**
-    Compare an html element to a list of css rules and update its
-    style according to the rules matching it.
+    Compare an html element to the user agent and author css rules and
+    return the style that the rules matching it give it. `ancestors`
+    holds the names of this element's ancestors.
+
+    This only reads the node, never its layout data. A style worker
+    passes the snapshot of the elements around its subtree as `around`,
+    so that it reads no node that another task may be reading.
     */
-    fn match_css_style(styles : &Stylesheet) {
-        // Loop over each rule, see if our node matches what is
-        // described in the rule. If it matches, update its style. As
-        // we don't currently have priorities of style information,
-        // the latest rule takes precedence over the others. So we
-        // just overwrite style information as we go.
-
-        for styles.each |sty| {
-            let (selectors, decls) = copy **sty;
-            for selectors.each |sel| {
-                if self.matches_selector(*sel) {
-                    for decls.each |decl| {
-                        self.update_style(*decl);
-                    }
-                }
-            }
+    fn cascade_css_style(ua_rules : &RuleHash, rules : &RuleHash, ancestors : &BloomFilter,
+                         around : Option<&SubtreeSnapshot>) -> SpecifiedStyle {
+        let style = self.read(|n| empty_style_for_node_kind(&*n.kind));
+        let applicable = DVec();
+        self.collect_declarations(ua_rules, ancestors, around, OriginUserAgent, &applicable);
+        self.collect_declarations(rules, ancestors, around, OriginAuthor, &applicable);
+        self.collect_inline_declarations(&applicable);
+
+        // Apply the matched declarations from lowest to highest
+        // precedence, so that each property ends up with the value of
+        // the declaration that wins the cascade.
+        let sorted = sort::merge_sort(dvec::unwrap(move applicable), cascade_le);
+        for sorted.each |applicable_decl| {
+            update_style(&style, copy applicable_decl.decl);
         }
-        
-        self.aux(|a| debug!("Changed the style to: %?", copy *a.style));
+        move style
+    }
+
+    /**
+    Replaces the style of the node with the one the css rules give it.
+    */
+    fn match_css_style(ua_rules : &RuleHash, rules : &RuleHash, ancestors : &BloomFilter) {
+        let style = @self.cascade_css_style(ua_rules, rules, ancestors, None);
+        debug!("Changed the style to: %?", copy *style);
+        self.aux(|a| a.style = style);
     }
 }
 
 #[cfg(test)]
 mod test {
-    use dom::element::{Attr, HTMLDivElement, HTMLHeadElement, HTMLImageElement, UnknownElement};
+    use au = gfx::geometry;
+    use css::media::Device;
+    use dom::element::{Attr, HTMLAnchorElement, HTMLDivElement, HTMLHeadElement, HTMLImageElement,
+                       UnknownElement};
+    use css::resolve::snapshot::snapshot_surroundings;
     use dom::node::NodeScope;
     use dvec::DVec;
+    use css::styles::StyleMethods;
+    use std::arc::ARC;
+
+    fn match_sheets(node: Node, ua_sheet: Stylesheet, sheet: Stylesheet) {
+        let device = Device(au::from_px(800), au::from_px(600));
+        let ua_rules = RuleHash(&ARC(move ua_sheet), &device);
+        let rules = RuleHash(&ARC(move sheet), &device);
+        node.match_css_style(&ua_rules, &rules, &BloomFilter());
+    }
 
     #[allow(non_implicitly_copyable_typarams)]
     fn new_node_from_attr(scope: &NodeScope, name: ~str, val: ~str) -> Node {
@@ -244,7 +677,7 @@ mod test {
         let scope = NodeScope();
         let node = new_node_from_attr(&scope, ~"lang", ~"en-us");
 
-        let sel = Element(~"*", ~[StartsWith(~"lang", ~"en")]);
+        let sel = Element(~"*", ~[DashMatch(~"lang", ~"en")]);
 
         assert node.matches_selector(~move sel);
     }
@@ -254,7 +687,7 @@ mod test {
         let scope = NodeScope();
         let node = new_node_from_attr(&scope, ~"lang", ~"en");
 
-        let sel = Element(~"*", ~[StartsWith(~"lang", ~"en")]);
+        let sel = Element(~"*", ~[DashMatch(~"lang", ~"en")]);
 
         assert node.matches_selector(~move sel);
     }
@@ -264,7 +697,7 @@ mod test {
         let scope = NodeScope();
         let node = new_node_from_attr(&scope, ~"lang", ~"english");
 
-        let sel = Element(~"*", ~[StartsWith(~"lang", ~"en")]);
+        let sel = Element(~"*", ~[DashMatch(~"lang", ~"en")]);
 
         assert !node.matches_selector(~move sel);
     }
@@ -303,6 +736,33 @@ mod test {
         assert node2.matches_selector(~move sel);
     }
 
+    #[test]
+    fn test_match_substring_operators() {
+        let scope = NodeScope();
+        let node = new_node_from_attr(&scope, ~"href", ~"HTTP://example.com/index.html");
+
+        assert node.matches_selector(~Element(~"*", ~[Prefix(~"href", ~"HTTP:")]));
+        assert !node.matches_selector(~Element(~"*", ~[Prefix(~"href", ~"http:")]));
+        assert node.matches_selector(~Element(~"*", ~[Suffix(~"href", ~".html")]));
+        assert !node.matches_selector(~Element(~"*", ~[Suffix(~"href", ~".htm")]));
+        assert node.matches_selector(~Element(~"*", ~[Substring(~"href", ~"example")]));
+        assert !node.matches_selector(~Element(~"*", ~[Substring(~"href", ~"")]));
+    }
+
+    #[test]
+    fn test_match_case_insensitive() {
+        let scope = NodeScope();
+        let node = new_node_from_attr(&scope, ~"type", ~"Text");
+
+        let sel1 = Element(~"*", ~[Exact(~"type", ~"text")]);
+        let sel2 = Element(~"*", ~[CaseInsensitive(~Exact(~"type", ~"text"))]);
+        let sel3 = Element(~"*", ~[CaseInsensitive(~Prefix(~"type", ~"TE"))]);
+
+        assert !node.matches_selector(~move sel1);
+        assert node.matches_selector(~move sel2);
+        assert node.matches_selector(~move sel3);
+    }
+
     #[test]
     fn match_tree() {
         let scope = NodeScope();
@@ -343,7 +803,7 @@ mod test {
         let sel3 = Sibling(~Element(~"*", ~[]), ~Element(~"*", ~[]));
 
         assert !root.matches_selector(~copy sel3);
-        assert child1.matches_selector(~copy sel3);
+        assert !child1.matches_selector(~copy sel3);
         assert child2.matches_selector(~copy sel3);
         assert !gchild.matches_selector(~copy sel3);
         assert !ggchild.matches_selector(~copy sel3);
@@ -359,4 +819,261 @@ mod test {
         assert ggchild.matches_selector(~copy sel4);
         assert gggchild.matches_selector(~move sel4);
     }
+
+    #[test]
+    fn match_structural_pseudo_classes() {
+        let scope = NodeScope();
+
+        let root = new_node_from_attr(&scope, ~"class", ~"blue");
+        let child1 = new_node_from_attr(&scope, ~"id", ~"green");
+        let text = scope.new_node(dom::node::Text(~"text"));
+        let child2 = new_node_from_attr(&scope, ~"flag", ~"black");
+        let child3 = new_node_from_attr(&scope, ~"flag", ~"white");
+        let gchild = new_node_from_attr(&scope, ~"flag", ~"grey");
+
+        scope.add_child(root, child1);
+        scope.add_child(root, text);
+        scope.add_child(root, child2);
+        scope.add_child(root, child3);
+        scope.add_child(child2, gchild);
+
+        let sel1 = Element(~"*", ~[Pseudo(FirstChild)]);
+
+        assert root.matches_selector(~copy sel1);
+        assert child1.matches_selector(~copy sel1);
+        assert !child2.matches_selector(~copy sel1);
+        assert !child3.matches_selector(~copy sel1);
+        assert gchild.matches_selector(~move sel1);
+
+        let sel2 = Element(~"*", ~[Pseudo(LastChild)]);
+
+        assert !child1.matches_selector(~copy sel2);
+        assert !child2.matches_selector(~copy sel2);
+        assert child3.matches_selector(~move sel2);
+
+        let sel3 = Element(~"*", ~[Pseudo(NthChild(2, 1))]);
+
+        assert child1.matches_selector(~copy sel3);
+        assert !child2.matches_selector(~copy sel3);
+        assert child3.matches_selector(~move sel3);
+
+        let sel4 = Element(~"*", ~[Pseudo(NthChild(-1, 2))]);
+
+        assert child1.matches_selector(~copy sel4);
+        assert child2.matches_selector(~copy sel4);
+        assert !child3.matches_selector(~move sel4);
+
+        let sel5 = Element(~"*", ~[Pseudo(Root)]);
+
+        assert root.matches_selector(~copy sel5);
+        assert !child1.matches_selector(~move sel5);
+
+        let sel6 = Element(~"*", ~[Pseudo(Empty)]);
+
+        assert !root.matches_selector(~copy sel6);
+        assert child1.matches_selector(~copy sel6);
+        assert !child2.matches_selector(~copy sel6);
+        assert gchild.matches_selector(~move sel6);
+
+        let sel7 = Element(~"div", ~[Pseudo(Not(~Element(~"*", ~[Exists(~"flag")])))]);
+
+        assert root.matches_selector(~copy sel7);
+        assert child1.matches_selector(~copy sel7);
+        assert !child2.matches_selector(~move sel7);
+
+        let sel8 = GeneralSibling(~Element(~"*", ~[Id(~"green")]), ~Element(~"*", ~[]));
+
+        assert !root.matches_selector(~copy sel8);
+        assert !child1.matches_selector(~copy sel8);
+        assert child2.matches_selector(~copy sel8);
+        assert child3.matches_selector(~copy sel8);
+        assert !gchild.matches_selector(~move sel8);
+
+        // unlike '~', '+' only looks at the closest previous element
+        let sel9 = Sibling(~Element(~"*", ~[Id(~"green")]), ~Element(~"*", ~[]));
+
+        assert !child1.matches_selector(~copy sel9);
+        assert child2.matches_selector(~copy sel9);
+        assert !child3.matches_selector(~move sel9);
+    }
+
+    #[test]
+    fn match_around_a_worker_subtree_from_its_snapshot() {
+        let scope = NodeScope();
+        let html = scope.new_node(dom::node::Element(ElementData(~"html", ~HTMLDivElement)));
+        let first = new_node_from_attr(&scope, ~"class", ~"intro");
+        let before = new_node_from_attr(&scope, ~"flag", ~"before");
+        let root = scope.new_node(dom::node::Element(ElementData(~"div", ~HTMLDivElement)));
+        let after = new_node_from_attr(&scope, ~"flag", ~"after");
+        let kid = scope.new_node(dom::node::Element(ElementData(~"span", ~HTMLDivElement)));
+        for [first, before, root, after].each |node| { scope.add_child(html, *node); }
+        scope.add_child(root, kid);
+
+        let snapshot = snapshot_surroundings(root);
+        let matcher = Matcher { around: Some(&snapshot) };
+
+        // Selectors reach the ancestors and siblings through the copies.
+        let sel1 = Descendant(~Element(~"html", ~[]), ~Element(~"span", ~[]));
+        assert matcher.matches_selector(LiveElement(kid), &sel1);
+        let sel2 = GeneralSibling(~Element(~"*", ~[Class(~"intro")]), ~Element(~"div", ~[]));
+        assert matcher.matches_selector(LiveElement(root), &sel2);
+        let sel3 = Element(~"div", ~[Pseudo(NthChild(0, 3))]);
+        assert matcher.matches_selector(LiveElement(root), &sel3);
+        let sel4 = Element(~"div", ~[Pseudo(LastChild)]);
+        assert !matcher.matches_selector(LiveElement(root), &sel4);
+        let sel5 = Child(~Element(~"*", ~[Pseudo(Root)]), ~Element(~"div", ~[]));
+        assert matcher.matches_selector(LiveElement(root), &sel5);
+
+        // Changes to the nodes around the subtree are not seen.
+        do before.read |n| {
+            match *n.kind {
+              dom::node::Element(ref elmt) => elmt.attrs.push(~Attr(~"class", ~"late")),
+              _ => fail
+            }
+        }
+        let sel6 = Sibling(~Element(~"*", ~[Class(~"late")]), ~Element(~"div", ~[]));
+        assert root.matches_selector(~copy sel6);
+        assert !matcher.matches_selector(LiveElement(root), &sel6);
+    }
+
+    #[test]
+    fn match_link_and_state_pseudo_classes() {
+        let scope = NodeScope();
+        let link = ElementData(~"a", ~HTMLAnchorElement);
+        link.attrs.push(~Attr(~"href", ~"http://example.com/"));
+        link.hover = true;
+        let link = scope.new_node(dom::node::Element(move link));
+        let anchor = scope.new_node(dom::node::Element(ElementData(~"a", ~HTMLAnchorElement)));
+
+        let sel1 = Element(~"*", ~[Pseudo(Link)]);
+
+        assert link.matches_selector(~copy sel1);
+        assert !anchor.matches_selector(~move sel1);
+
+        let sel2 = Element(~"a", ~[Pseudo(Hover)]);
+
+        assert link.matches_selector(~copy sel2);
+        assert !anchor.matches_selector(~move sel2);
+
+        let sel3 = Element(~"a", ~[Pseudo(Focus)]);
+
+        assert !link.matches_selector(~move sel3);
+    }
+
+    #[test]
+    fn test_specificity() {
+        let sel1 = Element(~"*", ~[]);
+        let sel2 = Element(~"div", ~[Class(~"x")]);
+        let sel3 = Descendant(~Element(~"body", ~[]), ~Element(~"*", ~[Id(~"main")]));
+
+        assert sel1.specificity() == 0x000000u32;
+        assert sel2.specificity() == 0x000101u32;
+        assert sel3.specificity() == 0x010001u32;
+        assert sel1.specificity() < sel2.specificity();
+        assert sel2.specificity() < sel3.specificity();
+
+        // ':not()' counts as its argument, other pseudo-classes as attributes
+        let sel4 = Element(~"li", ~[Pseudo(FirstChild),
+                                    Pseudo(Not(~Element(~"*", ~[Id(~"x")])))]);
+        assert sel4.specificity() == 0x010101u32;
+    }
+
+    #[test]
+    fn test_cascade_order() {
+        let scope = NodeScope();
+        let node = new_node_from_attr(&scope, ~"id", ~"main");
+        node.initialize_layout_data();
+
+        // The id rule wins over the later, less specific rule, but
+        // loses to the '!important' declaration.
+        let sheet : Stylesheet = ~[
+            ~(~[~Element(~"*", ~[Id(~"main")])],
+              ~[Display(Specified(DisplayBlock)), Width(Specified(BoxAuto))], ~[], ~[]),
+            ~(~[~Element(~"div", ~[])],
+              ~[Display(Specified(DisplayInline))], ~[Width(Specified(BoxPercent(50.0)))], ~[])
+        ];
+        match_sheets(node, ~[], move sheet);
+
+        let style = node.style();
+        assert style.display_type == Specified(DisplayBlock);
+        assert style.width == Specified(BoxPercent(50.0));
+    }
+
+    #[test]
+    fn test_user_agent_origin() {
+        let scope = NodeScope();
+        let node = new_node_from_attr(&scope, ~"id", ~"main");
+        node.initialize_layout_data();
+
+        // Any author declaration beats the user agent, whatever the specificity.
+        let ua_sheet : Stylesheet = ~[
+            ~(~[~Element(~"div", ~[Id(~"main")])],
+              ~[Display(Specified(DisplayBlock)), Width(Specified(BoxPercent(50.0)))], ~[], ~[])
+        ];
+        let sheet : Stylesheet = ~[
+            ~(~[~Element(~"*", ~[])], ~[Display(Specified(DisplayNone))], ~[], ~[])
+        ];
+        match_sheets(node, move ua_sheet, move sheet);
+
+        let style = node.style();
+        assert style.display_type == Specified(DisplayNone);
+        assert style.width == Specified(BoxPercent(50.0));
+    }
+
+    #[test]
+    fn test_ancestor_filter() {
+        let scope = NodeScope();
+        let parent = new_node_from_attr(&scope, ~"class", ~"outer");
+        let node = new_node_from_attr(&scope, ~"id", ~"main");
+        scope.add_child(parent, node);
+        node.initialize_layout_data();
+
+        let sheet : Stylesheet = ~[
+            ~(~[~Descendant(~Element(~"*", ~[Class(~"outer")]), ~Element(~"div", ~[]))],
+              ~[Display(Specified(DisplayNone))], ~[], ~[])
+        ];
+        let device = Device(au::from_px(800), au::from_px(600));
+        let ua_rules = RuleHash(&ARC(~[]), &device);
+        let rules = RuleHash(&ARC(move sheet), &device);
+
+        // The filter is trusted: without the parent in it the rule is skipped.
+        let ancestors = BloomFilter();
+        node.match_css_style(&ua_rules, &rules, &ancestors);
+        assert node.style().display_type == Initial;
+
+        do parent.read |n| {
+            match *n.kind {
+              dom::node::Element(ref elmt) => ancestors.insert_element(elmt),
+              _ => fail
+            }
+        }
+        node.match_css_style(&ua_rules, &rules, &ancestors);
+        assert node.style().display_type == Specified(DisplayNone);
+    }
+
+    #[test]
+    fn test_inline_style() {
+        let scope = NodeScope();
+        let elmt = ElementData(~"div", ~HTMLDivElement);
+        elmt.attrs.push(~Attr(~"id", ~"main"));
+        elmt.style_attribute = Some((~[Display(Specified(DisplayInline)),
+                                       Height(Specified(BoxPercent(10.0)))],
+                                     ~[Width(Specified(BoxPercent(25.0)))]));
+        let node = scope.new_node(dom::node::Element(move elmt));
+        node.initialize_layout_data();
+
+        // Inline declarations beat any selector, but an author '!important'
+        // declaration beats a normal inline one.
+        let sheet : Stylesheet = ~[
+            ~(~[~Element(~"*", ~[Id(~"main")])],
+              ~[Display(Specified(DisplayBlock))],
+              ~[Height(Specified(BoxAuto)), Width(Specified(BoxAuto))], ~[])
+        ];
+        match_sheets(node, ~[], move sheet);
+
+        let style = node.style();
+        assert style.display_type == Specified(DisplayInline);
+        assert style.height == Specified(BoxAuto);
+        assert style.width == Specified(BoxPercent(25.0));
+    }
 }
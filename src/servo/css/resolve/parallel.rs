@@ -0,0 +1,284 @@
+/*!
+Selector matching spread over a pool of worker tasks. The layout task
+matches the top of the tree itself, then splits the subtrees below
+between the workers. Layout data belongs to the layout task, so the
+workers send the styles back and the layout task stores them.
+
+A node may only be read by one task at a time, and the ancestors and
+siblings of the subtree roots are shared by several workers, so each
+worker gets a copy of the elements around its roots and reads only the
+nodes of its own subtrees.
+*/
+
+use css::media::Device;
+use css::resolve::bloom::BloomFilter;
+use css::resolve::matching::MatchingMethods;
+use css::resolve::rule_hash::{RuleHash, RuleHashCache};
+use css::resolve::sharing::StyleSharingCache;
+use css::resolve::snapshot::{SubtreeSnapshot, snapshot_surroundings};
+use css::styles::{Matched, MatchedStyle, match_subtree, store_style};
+use css::values::Stylesheet;
+use dom::node::{Element, Node, NodeTree};
+use dvec::DVec;
+use pipes::{Chan, Port, stream};
+use std::arc::{ARC, clone};
+use std::sort;
+use util::actor;
+use util::actor::{Actor, ActorRef};
+use util::tree;
+
+/// The number of worker tasks in a pool
+pub const WORKER_COUNT: uint = 4u;
+
+// Subtrees to hand each worker, so that a few large subtrees can be balanced
+// against many small ones
+const SUBTREES_PER_WORKER: uint = 4u;
+// How many levels of the tree the layout task matches at most before
+// handing out subtrees, even if they are fewer than it would like
+const MAX_SERIAL_DEPTH: uint = 8u;
+
+enum StyleWorkerMsg {
+    // Match the subtrees under the roots of these snapshots against the
+    // UA and the given author style sheet for the device, and send back
+    // the styles of their nodes.
+    MatchSubtreesMsg(~[SubtreeSnapshot], ARC<Stylesheet>, Device,
+                     Chan<~[(Node, MatchedStyle)]>),
+    ExitMsg
+}
+
+struct StyleWorker {
+    ua_styles: ARC<Stylesheet>,
+    // the indexes of the style sheets, kept while the device and the sheets stay the same
+    ua_rules: RuleHashCache,
+    rules: RuleHashCache
+}
+
+impl StyleWorker : Actor<StyleWorkerMsg> {
+    fn handle(&self, msg: StyleWorkerMsg) -> bool {
+        match move msg {
+          MatchSubtreesMsg(move snapshots, move styles, device, move result_chan) => {
+            let ua_rules = self.ua_rules.rules_for(&self.ua_styles, &device);
+            let rules = self.rules.rules_for(&styles, &device);
+            let can_share = !ua_rules.depends_on_siblings && !rules.depends_on_siblings;
+            let results = DVec();
+            for snapshots.each |snapshot| {
+                let ancestors = snapshot_ancestor_filter(snapshot);
+                do match_subtree(snapshot.root, &*ua_rules, &*rules, &ancestors, Some(snapshot),
+                                 &StyleSharingCache(), can_share) |node, matched| {
+                    results.push((node, move matched));
+                }
+            }
+            result_chan.send(dvec::unwrap(move results));
+            true
+          }
+          ExitMsg => false
+        }
+    }
+}
+
+/** Builds the ancestor filter of a subtree root from the copies of the elements above it. */
+fn snapshot_ancestor_filter(snapshot: &SubtreeSnapshot) -> BloomFilter {
+    let ancestors = BloomFilter();
+    for snapshot.levels.each |level| {
+        match level.element {
+          Some(ref elmt) => ancestors.insert_element(elmt),
+          None => {}
+        }
+    }
+    move ancestors
+}
+
+/**
+Builds the ancestor filter of a node from the elements above it. Only
+the layout task may do this, before it hands any subtree out.
+*/
+fn ancestor_filter(node: Node) -> BloomFilter {
+    let ancestors = BloomFilter();
+    let mut cur = tree::get_parent(&NodeTree, &node);
+    loop {
+        match cur {
+          Some(parent) => {
+            do parent.read |n| {
+                match *n.kind {
+                  Element(ref elmt) => ancestors.insert_element(elmt),
+                  _ => {}
+                }
+            }
+            cur = tree::get_parent(&NodeTree, &parent);
+          }
+          None => { return move ancestors; }
+        }
+    }
+}
+
+/** Counts the nodes of a subtree. */
+fn subtree_size(node: Node) -> uint {
+    let mut size = 0u;
+    do node.traverse_preorder |_n| { size += 1u; }
+    size
+}
+
+pub struct StylePool {
+    priv workers: ~[ActorRef<StyleWorkerMsg>],
+
+    drop {
+        for self.workers.each |worker| {
+            worker.send(ExitMsg);
+        }
+    }
+}
+
+/** Starts `worker_count` workers matching against the given user agent style sheet. */
+pub fn StylePool(ua_styles: &ARC<Stylesheet>, worker_count: uint) -> StylePool {
+    assert worker_count > 0u;
+    let workers = do vec::from_fn(worker_count) |_i| {
+        let ua_styles = clone(ua_styles);
+        do actor::spawn |move ua_styles| {
+            StyleWorker {
+                ua_styles: clone(&ua_styles),
+                ua_rules: RuleHashCache(),
+                rules: RuleHashCache()
+            }
+        }
+    };
+    StylePool { workers: move workers }
+}
+
+impl StylePool {
+    /**
+    Performs CSS selector matching on a subtree, storing the style of
+    each node in its layout data, exactly as `recompute_style_for_subtree`
+    would. `ua_rules` must index the style sheet the pool was started with,
+    and both indexes must be for the same device.
+    */
+    fn recompute_style(root: Node, ua_rules: &RuleHash, rules: &RuleHash) {
+        // Match the top of the tree level by level, until there are enough
+        // subtrees below it to keep every worker busy.
+        let wanted = self.workers.len() * SUBTREES_PER_WORKER;
+        let mut level = ~[root];
+        let mut depth = 0u;
+        while level.len() > 0u && level.len() < wanted && depth < MAX_SERIAL_DEPTH {
+            let mut next = ~[];
+            for level.each |node| {
+                let ancestors = ancestor_filter(*node);
+                let style = node.cascade_css_style(ua_rules, rules, &ancestors, None);
+                store_style(*node, Matched(move style));
+                for NodeTree.each_child(node) |kid| {
+                    vec::push(&mut next, *kid);
+                }
+            }
+            level = move next;
+            depth += 1u;
+        }
+
+        // Hand the largest subtrees out first, each to the worker with the
+        // fewest nodes so far.
+        let sized = do level.map |node| { (subtree_size(*node), *node) };
+        let sized = sort::merge_sort(sized, |a, b| {
+            let ((size_a, _), (size_b, _)) = (*a, *b);
+            size_a >= size_b
+        });
+        let loads = vec::to_mut(vec::from_elem(self.workers.len(), 0u));
+        let mut roots = vec::from_fn(self.workers.len(), |_i| ~[]);
+        for sized.each |entry| {
+            let (size, node) = *entry;
+            let mut lightest = 0u;
+            for uint::range(1u, loads.len()) |i| {
+                if loads[i] < loads[lightest] { lightest = i; }
+            }
+            loads[lightest] += size;
+            vec::push(&mut roots[lightest], snapshot_surroundings(node));
+        }
+
+        let mut ports: ~[Port<~[(Node, MatchedStyle)]>] = ~[];
+        do vec::consume(move roots) |i, snapshots| {
+            if !snapshots.is_empty() {
+                let (chan, port) = stream();
+                self.workers[i].send(MatchSubtreesMsg(move snapshots, clone(&rules.styles),
+                                                      rules.device, move chan));
+                vec::push(&mut ports, move port);
+            }
+        }
+
+        // A style shared with a sibling comes after that sibling's own
+        // style in the same worker's results, so storing them in order
+        // always finds the sibling's style in place.
+        for ports.each |port| {
+            do vec::consume(port.recv()) |_i, result| {
+                let (node, matched) = move result;
+                store_style(node, move matched);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use au = gfx::geometry;
+    use css::styles::{StyleMethods, empty_style_for_node_kind, recompute_style};
+    use css::values::{BoxLength, BoxSizing, CSSDisplay, CSSValue, Child, Class, Descendant,
+                      Display, DisplayBlock, DisplayNone, GeneralSibling, Px, Specified,
+                      Width};
+    use dom::element::{Attr, ElementData, HTMLDivElement};
+    use dom::node::NodeScope;
+
+    // Builds a tree of `depth` levels of divs, each with `width` children
+    // alternating between the classes 'a' and 'b'.
+    fn build_tree(scope: &NodeScope, parent: Node, width: uint, depth: uint) {
+        if depth == 0u { return; }
+        for uint::range(0u, width) |i| {
+            let elmt = ElementData(~"div", ~HTMLDivElement);
+            elmt.attrs.push(~Attr(~"class", if i % 2u == 0u { ~"a" } else { ~"b" }));
+            let kid = scope.new_node(Element(move elmt));
+            scope.add_child(parent, kid);
+            build_tree(scope, kid, width, depth - 1u);
+        }
+    }
+
+    fn styles_of(root: Node) -> ~[(CSSValue<CSSDisplay>, CSSValue<BoxSizing>)] {
+        let mut styles = ~[];
+        do root.traverse_preorder |node| {
+            let style = node.style();
+            vec::push(&mut styles, (style.display_type, style.width));
+        }
+        move styles
+    }
+
+    #[test]
+    fn should_match_like_serial_matching() {
+        let scope = NodeScope();
+        let root = scope.new_node(Element(ElementData(~"html", ~HTMLDivElement)));
+        build_tree(&scope, root, 3u, 4u);
+        do root.traverse_preorder |node| { node.initialize_layout_data(); }
+
+        let ua_sheet: Stylesheet = ~[
+            ~(~[~css::values::Element(~"div", ~[])], ~[Display(Specified(DisplayBlock))], ~[], ~[])
+        ];
+        let sheet: Stylesheet = ~[
+            ~(~[~Child(~css::values::Element(~"*", ~[Class(~"a")]),
+                       ~css::values::Element(~"*", ~[Class(~"b")]))],
+              ~[Display(Specified(DisplayNone))], ~[], ~[]),
+            ~(~[~Descendant(~css::values::Element(~"*", ~[Class(~"b")]),
+                            ~css::values::Element(~"*", ~[Class(~"a")]))],
+              ~[Width(Specified(BoxLength(Px(10.0))))], ~[], ~[]),
+            ~(~[~GeneralSibling(~css::values::Element(~"*", ~[Class(~"b")]),
+                                ~css::values::Element(~"*", ~[Class(~"a")]))],
+              ~[Width(Specified(BoxLength(Px(20.0))))], ~[], ~[])
+        ];
+        let ua_styles = ARC(move ua_sheet);
+        let device = Device(au::from_px(800), au::from_px(600));
+        let ua_rules = RuleHash(&ua_styles, &device);
+        let rules = RuleHash(&ARC(move sheet), &device);
+
+        recompute_style(root, &ua_rules, &rules, &BloomFilter(), &StyleSharingCache(), true);
+        let serial = styles_of(root);
+
+        let pool = StylePool(&ua_styles, 2u);
+        do root.traverse_preorder |node| {
+            let kind = node.read(|n| copy *n.kind);
+            node.aux(|a| a.style = @empty_style_for_node_kind(&kind));
+        }
+        pool.recompute_style(root, &ua_rules, &rules);
+        assert styles_of(root) == serial;
+    }
+}
@@ -0,0 +1,327 @@
+/*!
+Indexes the selectors of a style sheet by the id, class or tag name of
+their rightmost element selector, so that matching a node only tests the
+selectors that could apply to it instead of the whole style sheet.
+
+Rules inside '@media' rules whose queries don't match the device are
+left out of the index, so the index has to be rebuilt when the device
+changes, e.g. when the window is resized. A `RuleHashCache` keeps an
+index until then.
+*/
+
+use css::media::Device;
+use css::resolve::bloom::hash_name;
+use css::values::*;
+use dom::element::ElementData;
+use dvec::DVec;
+use std::arc::{ARC, clone, get};
+use std::map::HashMap;
+
+/** A selector of the indexed style sheet. */
+pub struct SelectorRef {
+    // position of the rule in the style sheet, and of the selector in the rule
+    rule: uint,
+    selector: uint,
+    // hashes of names that an ancestor of the matched node must have
+    ancestor_hashes: ~[u32]
+}
+
+pub struct RuleHash {
+    styles: ARC<Stylesheet>,
+    // the device whose media queries the indexed rules match
+    device: Device,
+    priv by_id: HashMap<~str, @DVec<SelectorRef>>,
+    priv by_class: HashMap<~str, @DVec<SelectorRef>>,
+    priv by_tag: HashMap<~str, @DVec<SelectorRef>>,
+    // selectors whose rightmost element has no id, class or tag name
+    priv universal: DVec<SelectorRef>,
+    // whether a selector depends on where elements are among their
+    // siblings, so that identical siblings may not share their style
+    depends_on_siblings: bool
+}
+
+pub fn RuleHash(styles: &ARC<Stylesheet>, device: &Device) -> RuleHash {
+    let hash = RuleHash {
+        styles: clone(styles),
+        device: *device,
+        by_id: HashMap(),
+        by_class: HashMap(),
+        by_tag: HashMap(),
+        universal: DVec(),
+        depends_on_siblings: do get(styles).any |rule| {
+            let (ref selectors, _, _, _) = **rule;
+            device.matches_rule(*rule) && selectors.any(|sel| depends_on_siblings(*sel))
+        }
+    };
+
+    for get(styles).eachi |i, rule| {
+        if !device.matches_rule(*rule) { loop; }
+        let (ref selectors, _, _, _) = **rule;
+        for selectors.eachi |j, sel| {
+            let sel_ref = SelectorRef {
+                rule: i,
+                selector: j,
+                ancestor_hashes: ancestor_hashes(*sel)
+            };
+            hash.insert(*sel, move sel_ref);
+        }
+    }
+    move hash
+}
+
+/**
+Keeps the index of a style sheet from one restyle to the next, and builds
+it again only when the style sheet or the device changes.
+*/
+pub struct RuleHashCache {
+    mut hash: Option<@RuleHash>
+}
+
+pub fn RuleHashCache() -> RuleHashCache {
+    RuleHashCache { hash: None }
+}
+
+impl RuleHashCache {
+    /** Returns the index of a style sheet for a device. */
+    fn rules_for(styles: &ARC<Stylesheet>, device: &Device) -> @RuleHash {
+        match self.hash {
+          Some(hash) if hash.indexes(styles, device) => { return hash; }
+          _ => {}
+        }
+        let hash = @RuleHash(styles, device);
+        self.hash = Some(hash);
+        hash
+    }
+}
+
+/** Returns the element selector that the matched node itself has to match. */
+pure fn rightmost_element(sel: &a/Selector) -> &a/Selector {
+    match *sel {
+      Element(*) => sel,
+      Child(_, ref sel2)
+      | Descendant(_, ref sel2)
+      | Sibling(_, ref sel2)
+      | GeneralSibling(_, ref sel2) => rightmost_element(*sel2)
+    }
+}
+
+/** Checks for sibling combinators and pseudo-classes that look at siblings or children. */
+pure fn depends_on_siblings(sel: &Selector) -> bool {
+    match *sel {
+      Element(_, ref attrs) => {
+        do attrs.any |attr| {
+          match *attr {
+            Pseudo(FirstChild) | Pseudo(LastChild) | Pseudo(NthChild(*)) | Pseudo(Empty) => true,
+            Pseudo(Not(ref sel)) => depends_on_siblings(*sel),
+            _ => false
+          }
+        }
+      }
+      Child(ref sel1, ref sel2) | Descendant(ref sel1, ref sel2) => {
+        depends_on_siblings(*sel1) || depends_on_siblings(*sel2)
+      }
+      Sibling(*) | GeneralSibling(*) => true
+    }
+}
+
+/**
+Collects hashes of the tag names, ids and classes that the ancestors of
+a node must have for it to match `sel`. Siblings of the node and of its
+ancestors are not in the ancestor filter, so they contribute nothing.
+*/
+fn ancestor_hashes(sel: &Selector) -> ~[u32] {
+    let mut hashes = ~[];
+    let mut cur_sel = sel;
+    let mut is_ancestor = false;
+    loop {
+        if is_ancestor {
+            match *rightmost_element(cur_sel) {
+              Element(ref tag, ref attrs) => {
+                if *tag != ~"*" { vec::push(&mut hashes, hash_name(*tag)); }
+                for attrs.each |attr| {
+                    match *attr {
+                      Id(ref name) | Class(ref name) => {
+                        vec::push(&mut hashes, hash_name(*name));
+                      }
+                      _ => {}
+                    }
+                }
+              }
+              _ => fail ~"rightmost_element returned a combinator"
+            }
+        }
+        cur_sel = match *cur_sel {
+          Element(*) => { return move hashes; }
+          Child(ref sel1, _) | Descendant(ref sel1, _) => { is_ancestor = true; &**sel1 }
+          Sibling(ref sel1, _) | GeneralSibling(ref sel1, _) => { is_ancestor = false; &**sel1 }
+        };
+    }
+}
+
+impl RuleHash {
+    /** Whether this is the index of the given style sheet for the given device. */
+    pure fn indexes(styles: &ARC<Stylesheet>, device: &Device) -> bool {
+        ptr::ref_eq(get(&self.styles), get(styles)) && self.device == *device
+    }
+
+    priv fn insert(sel: &Selector, sel_ref: SelectorRef) {
+        let (tag, attrs) = match *rightmost_element(sel) {
+          Element(ref tag, ref attrs) => (tag, attrs),
+          _ => fail ~"rightmost_element returned a combinator"
+        };
+
+        // Prefer the most selective key: few elements share an id.
+        for attrs.each |attr| {
+            match *attr {
+              Id(ref name) => { return insert_into(&self.by_id, *name, move sel_ref); }
+              _ => {}
+            }
+        }
+        for attrs.each |attr| {
+            match *attr {
+              Class(ref name) => { return insert_into(&self.by_class, *name, move sel_ref); }
+              _ => {}
+            }
+        }
+        if *tag != ~"*" {
+            return insert_into(&self.by_tag, *tag, move sel_ref);
+        }
+        self.universal.push(move sel_ref);
+    }
+
+    /**
+    Calls `f` with every selector that may match an element with the
+    given data, each once, in no particular order.
+    */
+    fn each_candidate(elmt: &ElementData, f: fn(&SelectorRef) -> bool) {
+        match elmt.get_attr(~"id") {
+          Some(id) => { if !each_in(&self.by_id, id, f) { return; } }
+          None => {}
+        }
+        match elmt.get_attr(~"class") {
+          Some(classes) => {
+            let mut seen = ~[];
+            for str::split_char_nonempty(classes, ' ').each |class| {
+                // a class listed twice must not yield its selectors twice
+                if seen.contains(class) { loop; }
+                vec::push(&mut seen, copy *class);
+                if !each_in(&self.by_class, copy *class, f) { return; }
+            }
+          }
+          None => {}
+        }
+        if !each_in(&self.by_tag, copy elmt.tag_name, f) { return; }
+        for self.universal.each |sel_ref| {
+            if !f(sel_ref) { return; }
+        }
+    }
+}
+
+fn insert_into(map: &HashMap<~str, @DVec<SelectorRef>>, key: ~str, sel_ref: SelectorRef) {
+    match map.find(copy key) {
+      Some(list) => list.push(move sel_ref),
+      None => {
+        let list = @DVec();
+        list.push(move sel_ref);
+        map.insert(move key, list);
+      }
+    }
+}
+
+fn each_in(map: &HashMap<~str, @DVec<SelectorRef>>, key: ~str,
+           f: fn(&SelectorRef) -> bool) -> bool {
+    match map.find(move key) {
+      Some(list) => {
+        for list.each |sel_ref| {
+            if !f(sel_ref) { return false; }
+        }
+        true
+      }
+      None => true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use au = gfx::geometry;
+    use dom::element::{Attr, HTMLDivElement};
+
+    #[test]
+    fn should_find_candidates_by_rightmost_element() {
+        let sheet : Stylesheet = ~[
+            ~(~[~Element(~"div", ~[Id(~"main"), Class(~"a")])], ~[], ~[], ~[]),
+            ~(~[~Descendant(~Element(~"p", ~[]), ~Element(~"*", ~[Class(~"a")])),
+                ~Element(~"span", ~[])], ~[], ~[], ~[]),
+            ~(~[~Element(~"div", ~[])], ~[], ~[], ~[]),
+            ~(~[~Element(~"*", ~[Exists(~"title")])], ~[], ~[], ~[])
+        ];
+        let hash = RuleHash(&ARC(move sheet), &Device(au::from_px(800), au::from_px(600)));
+
+        let elmt = ElementData(~"div", ~HTMLDivElement);
+        elmt.attrs.push(~Attr(~"class", ~"a a b"));
+        let mut candidates = ~[];
+        for hash.each_candidate(&elmt) |sel_ref| {
+            vec::push(&mut candidates, (sel_ref.rule, sel_ref.selector));
+        }
+        assert candidates == ~[(1u, 0u), (2u, 0u), (3u, 0u)];
+    }
+
+    #[test]
+    fn should_leave_out_rules_for_other_media() {
+        let print = ~[MediaQuery { negated: false, media_type: MediaPrint, features: ~[] }];
+        let narrow = ~[MediaQuery { negated: false, media_type: MediaAll,
+                                    features: ~[MediaWidth(MediaMax, Px(600.0))] }];
+        let sheet : Stylesheet = ~[
+            ~(~[~Element(~"div", ~[])], ~[], ~[], ~[copy print]),
+            ~(~[~Element(~"div", ~[])], ~[], ~[], ~[copy narrow]),
+            ~(~[~Element(~"div", ~[])], ~[], ~[], ~[])
+        ];
+        let sheet = ARC(move sheet);
+        let elmt = ElementData(~"div", ~HTMLDivElement);
+
+        let wide = RuleHash(&sheet, &Device(au::from_px(800), au::from_px(600)));
+        let mut candidates = ~[];
+        for wide.each_candidate(&elmt) |sel_ref| { vec::push(&mut candidates, sel_ref.rule); }
+        assert candidates == ~[2u];
+
+        let narrow = RuleHash(&sheet, &Device(au::from_px(600), au::from_px(600)));
+        let mut candidates = ~[];
+        for narrow.each_candidate(&elmt) |sel_ref| { vec::push(&mut candidates, sel_ref.rule); }
+        assert candidates == ~[1u, 2u];
+    }
+
+    #[test]
+    fn should_rebuild_cached_indexes_when_the_device_or_sheet_changes() {
+        let sheet = ARC(~[~(~[~Element(~"div", ~[])], ~[], ~[], ~[])]);
+        let other_sheet = ARC(~[~(~[~Element(~"div", ~[])], ~[], ~[], ~[])]);
+        let device = Device(au::from_px(800), au::from_px(600));
+        let cache = RuleHashCache();
+
+        let hash = cache.rules_for(&sheet, &device);
+        assert box::ptr_eq(hash, cache.rules_for(&clone(&sheet), &device));
+        let resized = cache.rules_for(&sheet, &Device(au::from_px(600), au::from_px(600)));
+        assert !box::ptr_eq(hash, resized);
+        assert !box::ptr_eq(resized, cache.rules_for(&other_sheet, &resized.device));
+    }
+
+    #[test]
+    fn should_collect_only_ancestor_hashes() {
+        let sel = Child(~Sibling(~Element(~"h1", ~[]), ~Element(~"div", ~[Class(~"x")])),
+                        ~Element(~"p", ~[]));
+        assert ancestor_hashes(&sel) == ~[hash_name("div"), hash_name("x")];
+
+        let sel = Descendant(~Element(~"body", ~[]), ~Element(~"*", ~[Id(~"y")]));
+        assert ancestor_hashes(&sel) == ~[hash_name("body")];
+    }
+
+    #[test]
+    fn should_detect_sibling_dependencies() {
+        assert !depends_on_siblings(&Descendant(~Element(~"ul", ~[]),
+                                                ~Element(~"li", ~[Pseudo(Hover)])));
+        assert depends_on_siblings(&Descendant(~Element(~"ul", ~[Pseudo(FirstChild)]),
+                                               ~Element(~"li", ~[])));
+        assert depends_on_siblings(&Element(~"li", ~[Pseudo(Not(~Element(~"*",
+                                                                   ~[Pseudo(Empty)])))]));
+        assert depends_on_siblings(&Sibling(~Element(~"h1", ~[]), ~Element(~"p", ~[])));
+    }
+}
@@ -0,0 +1,136 @@
+/*!
+The style sharing cache. Siblings with the same tag name, attributes and
+state match exactly the same rules, as long as no rule looks at their
+position among their siblings, so the style of the first of them can be
+given to the others without matching them.
+*/
+
+use dom::element::ElementData;
+use dom::node::{Element, Node};
+
+/// How many recently matched siblings are remembered
+pub const CACHE_SIZE: uint = 8u;
+
+pub struct StyleSharingCache {
+    // the most recently matched first
+    priv mut candidates: ~[Node]
+}
+
+pub fn StyleSharingCache() -> StyleSharingCache {
+    StyleSharingCache { candidates: ~[] }
+}
+
+/** Checks whether two elements with the same parent are guaranteed to match the same rules. */
+fn can_share_style(elmt: &ElementData, candidate: &ElementData) -> bool {
+    if elmt.tag_name != candidate.tag_name { return false; }
+    if elmt.hover != candidate.hover || elmt.focus != candidate.focus { return false; }
+
+    // This also compares ids, classes and 'style' attributes.
+    if elmt.attrs.len() != candidate.attrs.len() { return false; }
+    for elmt.attrs.each |attr| {
+        match candidate.get_attr(attr.name) {
+          Some(value) if value == attr.value => {}
+          _ => { return false; }
+        }
+    }
+    true
+}
+
+impl StyleSharingCache {
+    /**
+    Finds a sibling of this node, matched earlier, whose style this node
+    can use. Every node given to `find` and `insert` must have the same
+    parent.
+    */
+    fn find(elmt: &ElementData) -> Option<Node> {
+        for self.candidates.each |candidate| {
+            let shareable = do candidate.read |n| {
+                match *n.kind {
+                  Element(ref candidate_elmt) => can_share_style(elmt, candidate_elmt),
+                  _ => false
+                }
+            };
+            if shareable { return Some(*candidate); }
+        }
+        None
+    }
+
+    /** Remembers a node whose style was matched, forgetting the oldest one if full. */
+    fn insert(node: Node) {
+        let mut candidates = ~[node];
+        for self.candidates.each |candidate| {
+            if candidates.len() == CACHE_SIZE { break; }
+            vec::push(&mut candidates, *candidate);
+        }
+        self.candidates = move candidates;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use au = gfx::geometry;
+    use css::media::Device;
+    use css::resolve::bloom::BloomFilter;
+    use css::resolve::rule_hash::RuleHash;
+    use css::styles::{StyleMethods, recompute_style};
+    use css::values::{Class, Display, DisplayNone, Initial, Specified, Stylesheet};
+    use dom::element::{Attr, HTMLDivElement};
+    use dom::node::NodeScope;
+    use std::arc::ARC;
+
+    fn new_element(tag: ~str, attrs: ~[(~str, ~str)]) -> ElementData {
+        let elmt = ElementData(move tag, ~HTMLDivElement);
+        for attrs.each |attr| {
+            let (name, value) = copy *attr;
+            elmt.attrs.push(~Attr(move name, move value));
+        }
+        move elmt
+    }
+
+    #[test]
+    fn should_share_only_identical_elements() {
+        let a = new_element(~"li", ~[(~"class", ~"x"), (~"title", ~"t")]);
+        let b = new_element(~"li", ~[(~"title", ~"t"), (~"class", ~"x")]);
+        let c = new_element(~"li", ~[(~"class", ~"x")]);
+        let d = new_element(~"li", ~[(~"class", ~"x"), (~"title", ~"u")]);
+        let e = new_element(~"td", ~[(~"class", ~"x"), (~"title", ~"t")]);
+
+        assert can_share_style(&a, &b);
+        assert !can_share_style(&a, &c);
+        assert !can_share_style(&c, &a);
+        assert !can_share_style(&a, &d);
+        assert !can_share_style(&a, &e);
+
+        b.hover = true;
+        assert !can_share_style(&a, &b);
+    }
+
+    #[test]
+    fn should_share_style_between_siblings() {
+        let scope = NodeScope();
+        let parent = scope.new_node(Element(new_element(~"ul", ~[])));
+        let kids = ~[scope.new_node(Element(new_element(~"li", ~[(~"class", ~"x")]))),
+                     scope.new_node(Element(new_element(~"li", ~[(~"class", ~"y")]))),
+                     scope.new_node(Element(new_element(~"li", ~[(~"class", ~"x")])))];
+        parent.initialize_layout_data();
+        for kids.each |kid| {
+            scope.add_child(parent, *kid);
+            kid.initialize_layout_data();
+        }
+
+        let sheet : Stylesheet = ~[
+            ~(~[~css::values::Element(~"*", ~[Class(~"x")])], ~[Display(Specified(DisplayNone))],
+              ~[], ~[])
+        ];
+        let device = Device(au::from_px(800), au::from_px(600));
+        let ua_rules = RuleHash(&ARC(~[]), &device);
+        let rules = RuleHash(&ARC(move sheet), &device);
+        recompute_style(parent, &ua_rules, &rules, &BloomFilter(), &StyleSharingCache(), true);
+
+        let styles = kids.map(|kid| kid.aux(|a| a.style));
+        assert box::ptr_eq(styles[0], styles[2]);
+        assert !box::ptr_eq(styles[0], styles[1]);
+        assert kids[2].style().display_type == Specified(DisplayNone);
+        assert kids[1].style().display_type == Initial;
+    }
+}
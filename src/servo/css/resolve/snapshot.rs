@@ -0,0 +1,196 @@
+/*!
+Copies of the elements around a subtree, for style workers to match
+against. Other workers read the ancestors and siblings of a worker's
+subtree root at the same time, and reading a node is not safe from
+several tasks, so the layout task copies what selectors test of those
+elements before it hands the subtree out.
+*/
+
+use dom::element::ElementData;
+use dom::node::{Element, Node, NodeTree, Text};
+use util::tree;
+
+/** What selectors test of an element itself: its tag name, attributes and state. */
+trait ElementInfo {
+    pure fn get_tag_name() -> ~str;
+    fn attr_value(name: &str) -> Option<~str>;
+    pure fn is_hovered() -> bool;
+    pure fn is_focused() -> bool;
+}
+
+impl ElementData : ElementInfo {
+    pure fn get_tag_name() -> ~str { copy self.tag_name }
+    fn attr_value(name: &str) -> Option<~str> { self.get_attr(name) }
+    pure fn is_hovered() -> bool { self.hover }
+    pure fn is_focused() -> bool { self.focus }
+}
+
+/** An element as selectors see it, copied out of the tree. */
+pub struct ElementSnapshot {
+    tag_name: ~str,
+    attrs: ~[(~str, ~str)],
+    hover: bool,
+    focus: bool,
+    // whether it has no children but comments and empty text
+    empty: bool
+}
+
+impl ElementSnapshot : ElementInfo {
+    pure fn get_tag_name() -> ~str { copy self.tag_name }
+    fn attr_value(name: &str) -> Option<~str> {
+        for self.attrs.each |attr| {
+            let (ref attr_name, ref value) = *attr;
+            if name == *attr_name { return Some(copy *value); }
+        }
+        None
+    }
+    pure fn is_hovered() -> bool { self.hover }
+    pure fn is_focused() -> bool { self.focus }
+}
+
+/** One of the subtree root and its ancestors, with the element siblings before it. */
+pub struct LevelSnapshot {
+    // the element itself; None at the subtree root, which the worker reads itself,
+    // and for an ancestor that isn't an element
+    element: Option<ElementSnapshot>,
+    // the element siblings before it, in document order
+    prev_siblings: ~[ElementSnapshot],
+    has_next_sibling: bool
+}
+
+/**
+The elements around a subtree root: `levels[0]` is the root and its
+siblings, and each level after it the parent of the one before.
+*/
+pub struct SubtreeSnapshot {
+    root: Node,
+    levels: ~[LevelSnapshot]
+}
+
+/** Copies what selectors may test of the elements around a subtree root. */
+pub fn snapshot_surroundings(root: Node) -> SubtreeSnapshot {
+    let mut levels = ~[];
+    let mut cur = Some(root);
+    loop {
+        match cur {
+          Some(node) => {
+            let element = if node == root { None } else { snapshot_element(node) };
+            let mut prev_siblings = ~[];
+            let mut sib = tree::prev_sibling(&NodeTree, &node);
+            loop {
+                match sib {
+                  Some(s) => {
+                    match snapshot_element(s) {
+                      Some(snapshot) => vec::unshift(&mut prev_siblings, move snapshot),
+                      None => {}
+                    }
+                    sib = tree::prev_sibling(&NodeTree, &s);
+                  }
+                  None => { break; }
+                }
+            }
+            let mut has_next_sibling = false;
+            let mut sib = tree::next_sibling(&NodeTree, &node);
+            loop {
+                match sib {
+                  Some(s) if is_element(s) => { has_next_sibling = true; break; }
+                  Some(s) => { sib = tree::next_sibling(&NodeTree, &s); }
+                  None => { break; }
+                }
+            }
+            vec::push(&mut levels, LevelSnapshot {
+                element: move element,
+                prev_siblings: move prev_siblings,
+                has_next_sibling: has_next_sibling
+            });
+            cur = tree::get_parent(&NodeTree, &node);
+          }
+          None => { break; }
+        }
+    }
+    SubtreeSnapshot { root: root, levels: move levels }
+}
+
+fn is_element(node: Node) -> bool {
+    do node.read |n| {
+        match *n.kind {
+          Element(*) => true,
+          _ => false
+        }
+    }
+}
+
+/** Whether a node has no children but comments and empty text, as `:empty` requires. */
+pub fn has_no_content(node: Node) -> bool {
+    // only comments may appear in an empty element
+    let mut empty = true;
+    for NodeTree.each_child(&node) |kid| {
+        empty = do kid.read |n| {
+            match *n.kind {
+              Element(*) => false,
+              Text(ref data) => str::is_empty(*data),
+              _ => true
+            }
+        };
+        if !empty { break; }
+    }
+    empty
+}
+
+/** Copies an element, or returns None for other nodes. */
+fn snapshot_element(node: Node) -> Option<ElementSnapshot> {
+    let copied = do node.read |n| {
+        match *n.kind {
+          Element(ref elmt) => {
+            let attrs = do elmt.attrs.borrow |attrs| {
+                attrs.map(|attr| (copy attr.name, copy attr.value))
+            };
+            Some((copy elmt.tag_name, move attrs, elmt.hover, elmt.focus))
+          }
+          _ => None
+        }
+    };
+    match move copied {
+      Some((move tag_name, move attrs, hover, focus)) => {
+        Some(ElementSnapshot {
+            tag_name: move tag_name,
+            attrs: move attrs,
+            hover: hover,
+            focus: focus,
+            empty: has_no_content(node)
+        })
+      }
+      None => None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dom::element::{Attr, HTMLDivElement};
+    use dom::node::NodeScope;
+
+    #[test]
+    fn should_copy_ancestors_and_siblings() {
+        let scope = NodeScope();
+        let html = scope.new_node(Element(ElementData(~"html", ~HTMLDivElement)));
+        let first = ElementData(~"p", ~HTMLDivElement);
+        first.attrs.push(~Attr(~"class", ~"intro"));
+        let first = scope.new_node(Element(move first));
+        let root = scope.new_node(Element(ElementData(~"div", ~HTMLDivElement)));
+        let last = scope.new_node(Element(ElementData(~"p", ~HTMLDivElement)));
+        for [first, root, last].each |kid| { scope.add_child(html, *kid); }
+
+        let snapshot = snapshot_surroundings(root);
+        assert snapshot.levels.len() == 2;
+        assert snapshot.levels[0].element.is_none();
+        assert snapshot.levels[0].has_next_sibling;
+        assert snapshot.levels[0].prev_siblings.len() == 1;
+        assert snapshot.levels[0].prev_siblings[0].attr_value("class") == Some(~"intro");
+        assert snapshot.levels[0].prev_siblings[0].empty;
+        match snapshot.levels[1].element {
+          Some(ref html) => assert html.get_tag_name() == ~"html",
+          None => fail
+        }
+        assert !snapshot.levels[1].has_next_sibling;
+    }
+}
@@ -0,0 +1,295 @@
+/*!
+Computes custom properties and substitutes them for 'var()' references
+(CSS Variables Level 1).
+
+Custom properties are inherited, so they are only known once the
+parent's style is computed; declarations that use 'var()' are kept
+as tokens by the parser, and are parsed here, at computed-value time.
+*/
+
+use css::parser::{longhand_names, parse_declaration, serialize_value};
+use css::resolve::matching::update_style;
+use css::styles::{SpecifiedStyle, empty_style_for_node_kind};
+use css::values::*;
+use dom::node::Text;
+use lexer::Token;
+use tok = lexer;
+
+/** Returns the value of a custom property in a list of them. */
+fn find_custom_property(properties: &[(~str, ~[Token])], name: &str) -> Option<~[Token]> {
+    for properties.each |prop| {
+        let (ref prop_name, ref value) = *prop;
+        if str::eq_slice(*prop_name, name) { return Some(copy *value); }
+    }
+    None
+}
+
+/** Drops the whitespace at the start and end of a value. */
+fn trim_whitespace(value: &[Token]) -> ~[Token] {
+    let mut start = 0u, end = value.len();
+    while start < end && value[start] == tok::Whitespace { start += 1u; }
+    while end > start && value[end - 1u] == tok::Whitespace { end -= 1u; }
+    vec::slice(value, start, end)
+}
+
+/**
+Replaces each 'var()' in a value with the value that `lookup` gives
+for the custom property it names, or else with its fallback. Returns
+None if there is neither, or if a 'var()' is malformed, in which case
+the value is invalid at computed-value time.
+*/
+fn substitute_variables(value: &[Token], lookup: fn(&str) -> Option<~[Token]>)
+    -> Option<~[Token]> {
+    let mut result = ~[];
+    let mut i = 0u;
+    while i < value.len() {
+        match value[i] {
+          tok::Function(ref name) if str::to_lower(*name) == ~"var" => {
+            // find the ')' that closes the 'var(', or the end of the value
+            let start = i + 1u;
+            let mut depth = 0u;
+            i = start;
+            while i < value.len() {
+                match value[i] {
+                  tok::Function(_) | tok::OpenParen => depth += 1u,
+                  tok::CloseParen if depth == 0u => break,
+                  tok::CloseParen => depth -= 1u,
+                  _ => {}
+                }
+                i += 1u;
+            }
+            match substitute_reference(vec::slice(value, start, i), lookup) {
+              Some(move tokens) => vec::push_all_move(&mut result, move tokens),
+              None => return None
+            }
+          }
+          ref token => vec::push(&mut result, copy *token)
+        }
+        i += 1u;
+    }
+    Some(move result)
+}
+
+/** Substitutes a single 'var()', given the tokens between its parentheses. */
+fn substitute_reference(args: &[Token], lookup: fn(&str) -> Option<~[Token]>)
+    -> Option<~[Token]> {
+    let args = trim_whitespace(args);
+    if args.is_empty() { return None; }
+    let name = match args[0] {
+      tok::Ident(ref name) if name.starts_with("--") => copy *name,
+      _ => return None
+    };
+
+    let rest = trim_whitespace(vec::slice(args, 1u, args.len()));
+    if rest.is_empty() { return lookup(name); }
+    if rest[0] != tok::Comma { return None; }
+    match lookup(name) {
+      Some(move value) => Some(move value),
+      None => substitute_variables(trim_whitespace(vec::slice(rest, 1u, rest.len())), lookup)
+    }
+}
+
+/**
+Computes the custom properties of a node from the ones it declares and
+the ones of its parent, which it inherits. The parent's have already
+been substituted. A declared property that refers to itself, directly
+or through others, or that is invalid after substitution, is left out,
+which makes references to it use their fallback.
+*/
+fn compute_custom_properties(declared: &[(~str, ~[Token])], inherited: &[(~str, ~[Token])])
+    -> ~[(~str, ~[Token])] {
+    let mut result = do vec::filter(inherited) |prop| {
+        let (ref name, _) = *prop;
+        find_custom_property(declared, *name).is_none()
+    };
+    for declared.each |prop| {
+        let (ref name, _) = *prop;
+        match resolve_custom_property(*name, declared, inherited, []) {
+          Some(move value) => vec::push(&mut result, (copy *name, move value)),
+          None => {}
+        }
+    }
+    move result
+}
+
+/**
+Returns the substituted value of a custom property. `resolving` holds
+the declared properties whose values are being substituted, so that
+cycles are found.
+*/
+fn resolve_custom_property(name: &str, declared: &[(~str, ~[Token])],
+                           inherited: &[(~str, ~[Token])], resolving: &[~str])
+    -> Option<~[Token]> {
+    match find_custom_property(declared, name) {
+      None => find_custom_property(inherited, name),
+      Some(_) if resolving.any(|other| str::eq_slice(*other, name)) => None,
+      Some(value) => {
+        let resolving = vec::append_one(vec::from_slice(resolving), name.to_str());
+        substitute_variables(value, |other| {
+            resolve_custom_property(other, declared, inherited, resolving)
+        })
+      }
+    }
+}
+
+/**
+Returns a copy of a specified style with its declarations that use
+'var()' substituted and parsed, given the node's computed custom
+properties. Each one only sets the longhands that no declaration after
+it in the cascade did. A declaration that is invalid after substitution
+still wins the cascade, and sets its longhands as 'unset' would (CSS
+Variables Section 3.1): to 'inherit' for the inherited properties and
+to 'initial' for the others, as in a style that no declaration applies to.
+*/
+fn substitute_declarations(style: &SpecifiedStyle, custom_properties: &[(~str, ~[Token])])
+    -> SpecifiedStyle {
+    let result = copy *style;
+    result.variable_declarations = ~[];
+    for style.variable_declarations.each |pending| {
+        let value = substitute_variables(pending.value, |name| {
+            find_custom_property(custom_properties, name)
+        });
+        let decls = match value.chain(|value| serialize_value(value)) {
+          Some(val) => match parse_declaration(copy pending.property, val) {
+            Ok(move decls) => move decls,
+            Err(message) => {
+                debug!("Unsetting declaration invalid after var() substitution: %s", message);
+                unset_declarations(pending.property)
+            }
+          },
+          None => unset_declarations(pending.property)
+        };
+        for decls.each |decl| {
+            if !pending.overridden.contains(&decl.property_name()) {
+                update_style(&result, copy *decl);
+            }
+        }
+    }
+    move result
+}
+
+/**
+Returns the declarations that set each longhand of a property to 'unset',
+the value that it has when no declaration sets it.
+*/
+fn unset_declarations(prop: &str) -> ~[StyleDeclaration] {
+    let unset = empty_style_for_node_kind(&Text(~""));
+    do longhand_names(prop).map |name| {
+        match *name {
+          ~"background-color" => BackgroundColor(unset.background_color),
+          ~"display" => Display(unset.display_type),
+          ~"font-size" => FontSize(unset.font_size),
+          ~"font-family" => FontFamily(copy unset.font_family),
+          ~"font-weight" => FontWeight(unset.font_weight),
+          ~"font-style" => FontStyle(unset.font_style),
+          ~"direction" => Direction(unset.direction),
+          ~"white-space" => WhiteSpace(unset.white_space),
+          ~"text-align" => TextAlign(unset.text_align),
+          ~"text-indent" => TextIndent(unset.text_indent),
+          ~"height" => Height(unset.height),
+          ~"color" => Color(unset.text_color),
+          ~"width" => Width(unset.width),
+          ~"margin-top" => MarginTop(unset.margin_top),
+          ~"margin-right" => MarginRight(unset.margin_right),
+          ~"margin-bottom" => MarginBottom(unset.margin_bottom),
+          ~"margin-left" => MarginLeft(unset.margin_left),
+          ~"padding-top" => PaddingTop(unset.padding_top),
+          ~"padding-right" => PaddingRight(unset.padding_right),
+          ~"padding-bottom" => PaddingBottom(unset.padding_bottom),
+          ~"padding-left" => PaddingLeft(unset.padding_left),
+          ~"border-top-width" => BorderTopWidth(unset.border_top_width),
+          ~"border-right-width" => BorderRightWidth(unset.border_right_width),
+          ~"border-bottom-width" => BorderBottomWidth(unset.border_bottom_width),
+          ~"border-left-width" => BorderLeftWidth(unset.border_left_width),
+          ~"border-top-style" => BorderTopStyle(unset.border_top_style),
+          ~"border-right-style" => BorderRightStyle(unset.border_right_style),
+          ~"border-bottom-style" => BorderBottomStyle(unset.border_bottom_style),
+          ~"border-left-style" => BorderLeftStyle(unset.border_left_style),
+          ~"border-top-color" => BorderTopColor(unset.border_top_color),
+          ~"border-right-color" => BorderRightColor(unset.border_right_color),
+          ~"border-bottom-color" => BorderBottomColor(unset.border_bottom_color),
+          ~"border-left-color" => BorderLeftColor(unset.border_left_color),
+          ~"position" => Position(unset.position),
+          ~"top" => Top(unset.top),
+          ~"right" => Right(unset.right),
+          ~"bottom" => Bottom(unset.bottom),
+          ~"left" => Left(unset.left),
+          // only known properties are ever pending
+          _ => fail fmt!("unset_declarations: unknown longhand %s", *name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use lexer::spawn_css_lexer_from_string;
+    use css::parser::parse_style_attribute;
+
+    fn tokens(str: &str) -> ~[Token] {
+        let port = spawn_css_lexer_from_string(str.to_str());
+        let mut tokens = ~[];
+        loop {
+            match port.recv() {
+              (tok::Eof, _) => return move tokens,
+              (token, _) => vec::push(&mut tokens, move token)
+            }
+        }
+    }
+
+    #[test]
+    fn should_substitute_variables_and_fallbacks() {
+        let props = ~[(~"--a", tokens("1px")), (~"--b", tokens("var(--a) 2px"))];
+        let computed = compute_custom_properties(props, []);
+        assert find_custom_property(computed, "--b") == Some(tokens("1px 2px"));
+
+        let substitute = |value: &str| {
+            substitute_variables(tokens(value), |name| find_custom_property(computed, name))
+        };
+        assert substitute("var(--a)") == Some(tokens("1px"));
+        assert substitute("var(--c, 3px)") == Some(tokens("3px"));
+        assert substitute("var(--c, var(--a))") == Some(tokens("1px"));
+        assert substitute("var(--c)") == None;
+    }
+
+    #[test]
+    fn should_inherit_and_leave_out_cycles() {
+        let inherited = ~[(~"--a", tokens("red")), (~"--b", tokens("blue"))];
+        let declared = ~[(~"--b", tokens("var(--c)")), (~"--c", tokens("var(--b)")),
+                         (~"--d", tokens("var(--a)"))];
+        let computed = compute_custom_properties(declared, inherited);
+
+        assert find_custom_property(computed, "--a") == Some(tokens("red"));
+        assert find_custom_property(computed, "--b") == None;
+        assert find_custom_property(computed, "--c") == None;
+        assert find_custom_property(computed, "--d") == Some(tokens("red"));
+    }
+
+    #[test]
+    fn should_not_override_later_declarations() {
+        let ((decls, _), _) = parse_style_attribute(spawn_css_lexer_from_string(
+            ~"--w: 5px; margin: var(--w); margin-left: 1px; width: 2px; width: var(--w)"));
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        for decls.each |decl| { update_style(&style, copy *decl); }
+
+        let style = substitute_declarations(&style, [(~"--w", tokens("5px"))]);
+        assert style.margin_top == Specified(BoxLength(Px(5.0)));
+        assert style.margin_left == Specified(BoxLength(Px(1.0)));
+        assert style.width == Specified(BoxLength(Px(5.0)));
+    }
+
+    #[test]
+    fn should_unset_declarations_invalid_after_substitution() {
+        let ((decls, _), _) = parse_style_attribute(spawn_css_lexer_from_string(
+            ~"width: 2px; width: var(--color); font-size: 10px; font-size: var(--missing); "
+            + "margin: 1px; margin: var(--color)"));
+        let style = empty_style_for_node_kind(&Text(~"text"));
+        for decls.each |decl| { update_style(&style, copy *decl); }
+
+        // the earlier valid declarations lose to the invalid ones
+        let style = substitute_declarations(&style, [(~"--color", tokens("red"))]);
+        assert style.width == Initial;
+        assert style.font_size == Inherit;
+        assert style.margin_top == Initial;
+        assert style.margin_left == Initial;
+    }
+}
@@ -1,39 +1,136 @@
 /**
  * High-level interface to CSS selector matching.
  */
-use std::arc::{ARC, get, clone};
-
 use core::dvec::DVec;
 use css::values::*;
-use css::values::Stylesheet;
-use dom::element::{HTMLDivElement, HTMLHeadElement, HTMLImageElement, UnknownElement, HTMLScriptElement};
-use dom::node::{Comment, Doctype, Element, Text,
+use css::resolve::bloom::BloomFilter;
+use css::resolve::rule_hash::RuleHash;
+use css::resolve::sharing::StyleSharingCache;
+use css::resolve::snapshot::SubtreeSnapshot;
+use std::arc::get;
+use dom::node::{Element, Text,
                 Node, NodeKind, NodeTree, LayoutData};
 use util::color::{Color, rgb};
 use util::color::css_colors::{white, black};
 use layout::context::LayoutContext;
+use gfx::geometry::Au;
+use lexer::Token;
 
 #[allow(non_implicitly_copyable_typarams)]
 type SpecifiedStyle = {mut background_color : CSSValue<CSSBackgroundColor>,
                        mut background_image : CSSValue<CSSBackgroundImage>,
                         mut display_type : CSSValue<CSSDisplay>,
                         mut font_size : CSSValue<CSSFontSize>,
+                        mut font_family : CSSValue<~[CSSFontFamily]>,
+                        mut font_weight : CSSValue<CSSFontWeight>,
+                        mut font_style : CSSValue<CSSFontStyle>,
+                        mut direction : CSSValue<CSSDirection>,
+                        mut white_space : CSSValue<CSSWhiteSpace>,
+                        mut text_align : CSSValue<CSSTextAlign>,
+                        mut text_indent : CSSValue<BoxSizing>,
                         mut height : CSSValue<BoxSizing>,
                         mut text_color : CSSValue<CSSColor>,
                         mut width : CSSValue<BoxSizing>,
-                       mut border_color : CSSValue<CSSBorderColor>,
-                       mut border_style : CSSValue<CSSBorderStyle>,
-                       mut border_width : CSSValue<Length>,
+                       mut margin_top : CSSValue<BoxSizing>,
+                       mut margin_right : CSSValue<BoxSizing>,
+                       mut margin_bottom : CSSValue<BoxSizing>,
+                       mut margin_left : CSSValue<BoxSizing>,
+                       mut padding_top : CSSValue<BoxSizing>,
+                       mut padding_right : CSSValue<BoxSizing>,
+                       mut padding_bottom : CSSValue<BoxSizing>,
+                       mut padding_left : CSSValue<BoxSizing>,
+                       mut border_top_width : CSSValue<Length>,
+                       mut border_right_width : CSSValue<Length>,
+                       mut border_bottom_width : CSSValue<Length>,
+                       mut border_left_width : CSSValue<Length>,
+                       mut border_top_style : CSSValue<CSSBorderStyle>,
+                       mut border_right_style : CSSValue<CSSBorderStyle>,
+                       mut border_bottom_style : CSSValue<CSSBorderStyle>,
+                       mut border_left_style : CSSValue<CSSBorderStyle>,
+                       mut border_top_color : CSSValue<CSSBorderColor>,
+                       mut border_right_color : CSSValue<CSSBorderColor>,
+                       mut border_bottom_color : CSSValue<CSSBorderColor>,
+                       mut border_left_color : CSSValue<CSSBorderColor>,
                        mut position : CSSValue<CSSPosition>,
                        mut top : CSSValue<Length>,
                        mut right : CSSValue<Length>,
                        mut bottom : CSSValue<Length>,
-                       mut left : CSSValue<Length>
+                       mut left : CSSValue<Length>,
+                       // '--*' properties, in the order they were first declared
+                       mut custom_properties : ~[(~str, ~[Token])],
+                       // declarations using 'var()', in cascade order
+                       mut variable_declarations : ~[PendingDeclaration]
                        };
 
+/**
+ * A declaration whose value uses 'var()'. It is only parsed once the custom properties of the
+ * node are computed, and then only sets the longhands that no later declaration set.
+ */
+struct PendingDeclaration {
+    property: ~str,
+    value: ~[Token],
+    overridden: ~[~str]
+}
+
+/**
+ * The style of a node once 'inherit' and 'initial' have been replaced by actual values and
+ * relative lengths have been made absolute (CSS 2.1 Section 6.1.2). This is what layout reads;
+ * see `css::resolve::apply`.
+ */
+struct ComputedStyle {
+    background_color: CSSBackgroundColor,
+    background_image: CSSBackgroundImage,
+    display_type: CSSDisplay,
+    font_size: Au,
+    font_family: ~[CSSFontFamily],
+    // from 100 to 900
+    font_weight: uint,
+    font_style: CSSFontStyle,
+    // the base direction of the text of the block, when it starts an inline flow
+    direction: CSSDirection,
+    // how the whitespace and newlines of text are collapsed, and whether its lines wrap
+    white_space: CSSWhiteSpace,
+    // how the lines of a block's inline flow are aligned, and how far its first line is indented
+    text_align: CSSTextAlign,
+    text_indent: BoxSizing,
+    // lengths are in 'px'; percentages are resolved by layout
+    height: BoxSizing,
+    text_color: Color,
+    width: BoxSizing,
+    // margins and padding may still be percentages of the containing block's width
+    margin_top: BoxSizing,
+    margin_right: BoxSizing,
+    margin_bottom: BoxSizing,
+    margin_left: BoxSizing,
+    padding_top: BoxSizing,
+    padding_right: BoxSizing,
+    padding_bottom: BoxSizing,
+    padding_left: BoxSizing,
+    // a border whose style is 'none' or 'hidden' has a computed width of zero
+    border_top_width: Au,
+    border_right_width: Au,
+    border_bottom_width: Au,
+    border_left_width: Au,
+    border_top_style: CSSBorderStyle,
+    border_right_style: CSSBorderStyle,
+    border_bottom_style: CSSBorderStyle,
+    border_left_style: CSSBorderStyle,
+    border_top_color: Color,
+    border_right_color: Color,
+    border_bottom_color: Color,
+    border_left_color: Color,
+    position: CSSPosition,
+    // None for 'auto'
+    top: Option<Au>,
+    right: Option<Au>,
+    bottom: Option<Au>,
+    left: Option<Au>,
+    // inherited from the parent, with 'var()' already substituted in the values
+    custom_properties: ~[(~str, ~[Token])]
+}
+
 trait DefaultStyleMethods {
     fn default_color() -> Color;
-    fn default_display_type() -> CSSDisplay;
     fn default_width() -> BoxSizing;
     fn default_height() -> BoxSizing;
 }
@@ -48,23 +145,6 @@ impl NodeKind : DefaultStyleMethods {
         }
     }
 
-    /* TODO: this belongs in the UA stylesheet */
-    fn default_display_type() -> CSSDisplay {
-        match self {
-          Text(*) => DisplayInline,
-          Element(element) => {
-            match *element.kind {
-              HTMLDivElement => DisplayBlock,
-              HTMLHeadElement => DisplayNone,
-              HTMLImageElement(*) => DisplayInline,
-              HTMLScriptElement => DisplayNone,
-              _ => DisplayInline,
-            }
-          },
-          Comment(*) | Doctype(*) => DisplayNone
-        }
-    }
-    
     fn default_width() -> BoxSizing {
         BoxAuto
     }
@@ -77,37 +157,63 @@ impl NodeKind : DefaultStyleMethods {
 /**
  * Create a specified style that can be used to initialize a node before selector matching.
  *
- * Everything is initialized to none except the display style. The default value of the display
- * style is computed so that it can be used to short-circuit selector matching to avoid computing
- * style for children of display:none objects.
+ * Properties that are inherited by default start out as 'inherit', everything else as
+ * 'initial', which is also what 'unset' sets them to. Per-element defaults, such as the
+ * display type, come from the user agent style sheet.
  */
 #[allow(non_implicitly_copyable_typarams)]
-fn empty_style_for_node_kind(kind: &NodeKind) -> SpecifiedStyle {
-    let display_type = kind.default_display_type();
-
+fn empty_style_for_node_kind(_kind: &NodeKind) -> SpecifiedStyle {
     {mut background_color : Initial,
      mut background_image: Initial,
-     mut display_type : Specified(display_type),
-     mut font_size : Initial,
+     mut display_type : Initial,
+     mut font_size : Inherit,
+     mut font_family : Inherit,
+     mut font_weight : Inherit,
+     mut font_style : Inherit,
+     mut direction : Inherit,
+     mut white_space : Inherit,
+     mut text_align : Inherit,
+     mut text_indent : Inherit,
      mut height : Initial,
-     mut text_color : Initial,
+     mut text_color : Inherit,
      mut width : Initial,
-     mut border_color : Initial,
-     mut border_style : Initial,
-     mut border_width : Initial,
+     mut margin_top : Initial,
+     mut margin_right : Initial,
+     mut margin_bottom : Initial,
+     mut margin_left : Initial,
+     mut padding_top : Initial,
+     mut padding_right : Initial,
+     mut padding_bottom : Initial,
+     mut padding_left : Initial,
+     mut border_top_width : Initial,
+     mut border_right_width : Initial,
+     mut border_bottom_width : Initial,
+     mut border_left_width : Initial,
+     mut border_top_style : Initial,
+     mut border_right_style : Initial,
+     mut border_bottom_style : Initial,
+     mut border_left_style : Initial,
+     mut border_top_color : Initial,
+     mut border_right_color : Initial,
+     mut border_bottom_color : Initial,
+     mut border_left_color : Initial,
      mut position : Initial,
      mut top : Initial,
      mut right : Initial,
      mut bottom : Initial,
-     mut left : Initial}
+     mut left : Initial,
+     mut custom_properties : ~[],
+     mut variable_declarations : ~[]}
 }
 
 trait StyleMethods {
     fn initialize_layout_data() -> Option<@LayoutData>;
 
     fn style() -> SpecifiedStyle;
+    fn computed_style() -> ComputedStyle;
     fn initialize_style_for_subtree(ctx: &LayoutContext, refs: &DVec<@LayoutData>);
-    fn recompute_style_for_subtree(ctx: &LayoutContext, styles : &ARC<Stylesheet>);
+    fn recompute_style_for_subtree(ctx: &LayoutContext, ua_rules : &RuleHash,
+                                   rules : &RuleHash);
 }
 
 impl Node : StyleMethods {
@@ -119,7 +225,8 @@ impl Node : StyleMethods {
             false => {
                 let node_kind = self.read(|n| copy *n.kind);
                 let data = @LayoutData({
-                    mut style : ~empty_style_for_node_kind(&node_kind),
+                    mut style : @empty_style_for_node_kind(&node_kind),
+                    mut computed_style : None,
                     mut flow  : None
                 });
                 self.set_aux(data); Some(data)
@@ -137,7 +244,23 @@ impl Node : StyleMethods {
             fail ~"get_style() called on a node without a style!";
         }
         // TODO: return a safe reference; don't copy!
-        return copy *self.aux(|x| copy *x).style;
+        return self.aux(|x| copy *x.style);
+    }
+
+    /**
+     * Returns the computed style for the given node. If styles have not yet been resolved by
+     * `css::resolve::apply::apply_style`, fails.
+     */
+    fn computed_style() -> ComputedStyle {
+        if !self.has_aux() {
+            fail ~"computed_style() called on a node without a style!";
+        }
+        do self.aux |x| {
+            match x.computed_style {
+                Some(ref style) => copy *get(style),
+                None => fail ~"computed_style() called before styles were resolved!"
+            }
+        }
     }
 
     /**
@@ -160,15 +283,84 @@ impl Node : StyleMethods {
      * the node (the reader-auxiliary box in the COW model) with the
      * computed style.
      */
-    fn recompute_style_for_subtree(ctx: &LayoutContext, styles : &ARC<Stylesheet>) {
-        let mut i = 0u;
-        
-        // Compute the styles of each of our children in parallel
-        for NodeTree.each_child(&self) |kid| {
-            i = i + 1u;
-            kid.recompute_style_for_subtree(ctx, styles); 
+    fn recompute_style_for_subtree(_ctx: &LayoutContext, ua_rules : &RuleHash,
+                                   rules : &RuleHash) {
+        let can_share = !ua_rules.depends_on_siblings && !rules.depends_on_siblings;
+        recompute_style(self, ua_rules, rules, &BloomFilter(), &StyleSharingCache(), can_share);
+    }
+}
+
+/** The style that matching gave a node. */
+pub enum MatchedStyle {
+    // the style of the rules matching the node
+    Matched(SpecifiedStyle),
+    // the very style object of a sibling that was matched earlier
+    SharedWith(Node)
+}
+
+/** Writes a matched style into the layout data of its node. */
+fn store_style(node: Node, matched: MatchedStyle) {
+    let style = match move matched {
+      Matched(move style) => @move style,
+      SharedWith(sibling) => sibling.aux(|a| a.style)
+    };
+    node.aux(|a| a.style = style);
+}
+
+/**
+Matches a node and then its descendants, storing their styles as it goes.
+*/
+fn recompute_style(node: Node, ua_rules : &RuleHash, rules : &RuleHash,
+                   ancestors : &BloomFilter, siblings : &StyleSharingCache, can_share : bool) {
+    do match_subtree(node, ua_rules, rules, ancestors, None, siblings,
+                     can_share) |node, matched| {
+        store_style(node, move matched);
+    }
+}
+
+/**
+Matches a node and then its descendants, and calls `f` with the style of
+each node, in preorder. `ancestors` holds the names of the node's
+ancestors, and the node's own while its children are matched.
+`around` holds the elements around the node when it is the root of a
+style worker's subtree. `siblings` holds the siblings matched before
+this node.
+
+This never touches layout data, so that it can run outside the layout
+task; a node's style is shared with a sibling only after `f` has been
+called with the style of that sibling.
+*/
+fn match_subtree(node: Node, ua_rules : &RuleHash, rules : &RuleHash,
+                 ancestors : &BloomFilter, around : Option<&SubtreeSnapshot>,
+                 siblings : &StyleSharingCache, can_share : bool,
+                 f: fn(Node, +v: MatchedStyle)) {
+    let node_kind = node.read(|n| copy *n.kind);
+    let shared = match node_kind {
+      Element(ref elmt) if can_share => siblings.find(elmt),
+      _ => None
+    };
+
+    match shared {
+      Some(sibling) => f(node, SharedWith(sibling)),
+      None => {
+        f(node, Matched(node.cascade_css_style(ua_rules, rules, ancestors, around)));
+        match node_kind {
+          Element(*) => siblings.insert(node),
+          _ => {}
         }
+      }
+    }
 
-        self.match_css_style(get(styles));
+    match node_kind {
+      Element(ref elmt) => ancestors.insert_element(elmt),
+      _ => {}
+    }
+    let children = StyleSharingCache();
+    for NodeTree.each_child(&node) |kid| {
+        match_subtree(*kid, ua_rules, rules, ancestors, around, &children, can_share, f);
+    }
+    match node_kind {
+      Element(ref elmt) => ancestors.remove_element(elmt),
+      _ => {}
     }
 }
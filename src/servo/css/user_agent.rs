@@ -0,0 +1,36 @@
+/*!
+The built-in user agent style sheet, which gives HTML elements their
+default rendering (display types, margins, heading sizes...). It is
+cascaded at user agent origin, below every author declaration.
+*/
+
+use css::lexer::spawn_css_lexer_from_string;
+use css::parser::{Diagnostic, parse_stylesheet};
+use css::values::Stylesheet;
+
+const USER_AGENT_STYLESHEET: &static/str = #include_str("user_agent.css");
+
+fn parse_user_agent_stylesheet() -> (Stylesheet, ~[Diagnostic]) {
+    let token_port = spawn_css_lexer_from_string(USER_AGENT_STYLESHEET.to_str());
+    return parse_stylesheet(move token_port);
+}
+
+/** Parses the user agent style sheet. Meant to be called once, at startup. */
+pub fn user_agent_stylesheet() -> Stylesheet {
+    let (rules, diagnostics) = parse_user_agent_stylesheet();
+    for diagnostics.each |diagnostic| {
+        #error("user agent style sheet:%u:%u: %s", diagnostic.position.line,
+               diagnostic.position.column, diagnostic.message);
+    }
+    return move rules;
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn should_parse_without_errors() {
+        let (rules, diagnostics) = parse_user_agent_stylesheet();
+        assert diagnostics.len() == 0u;
+        assert rules.len() > 0u;
+    }
+}
@@ -7,6 +7,7 @@ http://www.w3.org/TR/CSS2/selector.html are represented by nested types.
 use SharedColor = util::color::Color;
 use cmp::Eq;
 use std::net::url::Url;
+use lexer::Token;
 
 // CSS Units
 
@@ -54,20 +55,96 @@ enum Auto = ();
 
 pub enum Length {
     Em(float), // normalized to 'em'
-    Px(float) // normalized to 'px'
+    Ex(float),
+    Rem(float),
+    // percentages of the viewport's width, height, and smaller and larger dimension
+    Vw(float),
+    Vh(float),
+    Vmin(float),
+    Vmax(float),
+    Px(float), // normalized to 'px'
+    Pt(float), // 'pt', 'pc', 'in', 'cm', 'mm' and 'q', normalized to 'pt'
+    Calc(CalcLength) // never has a percentage
+}
+
+/**
+The value of a 'calc()' expression (CSS Values Level 3, Section 8.1),
+folded into a sum of lengths in 'px', in each relative unit, and a
+percentage.
+*/
+pub struct CalcLength {
+    px: float,
+    em: float,
+    rem: float,
+    vw: float,
+    vh: float,
+    vmin: float,
+    vmax: float,
+    percent: float
+}
+
+/// The empty sum, to which a 'calc()' adds its terms.
+pub pure fn CalcLength() -> CalcLength {
+    CalcLength { px: 0.0, em: 0.0, rem: 0.0, vw: 0.0, vh: 0.0, vmin: 0.0, vmax: 0.0,
+                 percent: 0.0 }
+}
+
+impl CalcLength {
+    pure fn add(other: &CalcLength) -> CalcLength {
+        CalcLength { px: self.px + other.px, em: self.em + other.em, rem: self.rem + other.rem,
+                     vw: self.vw + other.vw, vh: self.vh + other.vh,
+                     vmin: self.vmin + other.vmin, vmax: self.vmax + other.vmax,
+                     percent: self.percent + other.percent }
+    }
+    pure fn scale(factor: float) -> CalcLength {
+        CalcLength { px: self.px * factor, em: self.em * factor, rem: self.rem * factor,
+                     vw: self.vw * factor, vh: self.vh * factor, vmin: self.vmin * factor,
+                     vmax: self.vmax * factor, percent: self.percent * factor }
+    }
+    /// Adds a length, which must not be a 'calc()' itself.
+    pure fn add_length(length: Length) -> CalcLength {
+        let zero = CalcLength();
+        self.add(&match length {
+            Px(px) => CalcLength { px: px, .. zero },
+            Pt(pt) => CalcLength { px: pt * PX_PER_PT, .. zero },
+            Em(em) => CalcLength { em: em, .. zero },
+            // like when it is computed, 'ex' is taken as half an 'em'
+            Ex(ex) => CalcLength { em: 0.5 * ex, .. zero },
+            Rem(rem) => CalcLength { rem: rem, .. zero },
+            Vw(vw) => CalcLength { vw: vw, .. zero },
+            Vh(vh) => CalcLength { vh: vh, .. zero },
+            Vmin(vmin) => CalcLength { vmin: vmin, .. zero },
+            Vmax(vmax) => CalcLength { vmax: vmax, .. zero },
+            Calc(_) => fail ~"a 'calc()' can't be a term of another"
+        })
+    }
 }
 
+/// 'px' per 'pt', at 96 px and 72 pt to the inch (CSS Values Level 3, Section 5.2).
+pub const PX_PER_PT: float = 4.0 / 3.0;
+
 impl Length {
+    /// The number of a length relative to the font or the viewport.
     pure fn rel() -> float {
         match self {
-            Em(x) => x,
+            Em(x) | Ex(x) | Rem(x) | Vw(x) | Vh(x) | Vmin(x) | Vmax(x) => x,
             _ => fail ~"attempted to access relative unit of an absolute length"
         }
     }
+    /// The size of an absolute length, in 'px'.
     pure fn abs() -> float {
         match self {
-            Em(x) => x,
-            _ => fail ~"attempted to access relative unit of an absolute length"
+            Px(x) => x,
+            Pt(x) => x * PX_PER_PT,
+            _ => fail ~"attempted to access absolute unit of a relative length"
+        }
+    }
+    /// Whether a length is known to be negative before it is computed.
+    pure fn is_negative() -> bool {
+        match self {
+            Em(x) | Ex(x) | Rem(x) | Vw(x) | Vh(x) | Vmin(x) | Vmax(x) | Px(x) | Pt(x) => x < 0.0,
+            // the sign of a 'calc()' may depend on the font size
+            Calc(_) => false
         }
     }
 }
@@ -75,6 +152,8 @@ impl Length {
 pub enum BoxSizing { // used by width, height, top, left, etc
     BoxLength(Length),
     BoxPercent(float),
+    // a 'calc()' with a percentage, which layout resolves like BoxPercent
+    BoxCalc(CalcLength),
     BoxAuto
 }
 
@@ -175,6 +254,43 @@ enum CSSFontSize {
     PercentSize(float)
 }
 
+enum CSSFontFamily {
+    // an installed family, by name
+    FamilyName(~str),
+    GenericSerif,
+    GenericSansSerif,
+    GenericCursive,
+    GenericFantasy,
+    GenericMonospace
+}
+
+impl CSSFontFamily {
+    /** The name to look the family up by; generic families go by their keyword. */
+    pure fn family_name() -> ~str {
+        match self {
+          FamilyName(ref name) => copy *name,
+          GenericSerif => ~"serif",
+          GenericSansSerif => ~"sans-serif",
+          GenericCursive => ~"cursive",
+          GenericFantasy => ~"fantasy",
+          GenericMonospace => ~"monospace"
+        }
+    }
+}
+
+enum CSSFontWeight {
+    // from 100 to 900; 'normal' is 400 and 'bold' is 700
+    WeightNumber(uint),
+    WeightBolder,
+    WeightLighter
+}
+
+enum CSSFontStyle {
+    FontStyleNormal,
+    FontStyleItalic,
+    FontStyleOblique
+}
+
 enum CSSPosition {
     PosStatic,
     PosRelative,
@@ -182,48 +298,288 @@ enum CSSPosition {
     PosFixed
 }
 
+enum CSSTextAlign {
+    TextAlignLeft,
+    TextAlignRight,
+    TextAlignCenter,
+    TextAlignJustify,
+    // the side that lines start on in the block's 'direction'
+    TextAlignStart,
+    TextAlignEnd
+}
+
+enum CSSWhiteSpace {
+    WhiteSpaceNormal,
+    WhiteSpacePre,
+    WhiteSpaceNowrap,
+    WhiteSpacePreWrap,
+    WhiteSpacePreLine
+}
+
+impl CSSWhiteSpace {
+    /** Whether lines of text may wrap at its break opportunities. */
+    pure fn wraps() -> bool {
+        match self {
+          WhiteSpacePre | WhiteSpaceNowrap => false,
+          WhiteSpaceNormal | WhiteSpacePreWrap | WhiteSpacePreLine => true
+        }
+    }
+}
+
 // Stylesheet parts
 
 enum StyleDeclaration {
     BackgroundColor(CSSValue<CSSBackgroundColor>),
     Display(CSSValue<CSSDisplay>),
     FontSize(CSSValue<CSSFontSize>),
+    FontFamily(CSSValue<~[CSSFontFamily]>),
+    FontWeight(CSSValue<CSSFontWeight>),
+    FontStyle(CSSValue<CSSFontStyle>),
+    Direction(CSSValue<CSSDirection>),
+    WhiteSpace(CSSValue<CSSWhiteSpace>),
+    TextAlign(CSSValue<CSSTextAlign>),
+    TextIndent(CSSValue<BoxSizing>),
     Height(CSSValue<BoxSizing>),
     Color(CSSValue<CSSColor>),
     Width(CSSValue<BoxSizing>),
-    BorderColor(CSSValue<CSSBorderColor>),
-    BorderWidth(CSSValue<Length>),
+    // padding is never BoxAuto
+    MarginTop(CSSValue<BoxSizing>),
+    MarginRight(CSSValue<BoxSizing>),
+    MarginBottom(CSSValue<BoxSizing>),
+    MarginLeft(CSSValue<BoxSizing>),
+    PaddingTop(CSSValue<BoxSizing>),
+    PaddingRight(CSSValue<BoxSizing>),
+    PaddingBottom(CSSValue<BoxSizing>),
+    PaddingLeft(CSSValue<BoxSizing>),
+    BorderTopWidth(CSSValue<Length>),
+    BorderRightWidth(CSSValue<Length>),
+    BorderBottomWidth(CSSValue<Length>),
+    BorderLeftWidth(CSSValue<Length>),
+    BorderTopStyle(CSSValue<CSSBorderStyle>),
+    BorderRightStyle(CSSValue<CSSBorderStyle>),
+    BorderBottomStyle(CSSValue<CSSBorderStyle>),
+    BorderLeftStyle(CSSValue<CSSBorderStyle>),
+    BorderTopColor(CSSValue<CSSBorderColor>),
+    BorderRightColor(CSSValue<CSSBorderColor>),
+    BorderBottomColor(CSSValue<CSSBorderColor>),
+    BorderLeftColor(CSSValue<CSSBorderColor>),
     Position(CSSValue<CSSPosition>),
     Top(CSSValue<Length>),
     Right(CSSValue<Length>),
     Bottom(CSSValue<Length>),
     Left(CSSValue<Length>),
+    // a '--*' custom property (CSS Variables Level 1), with its value as written
+    CustomProperty(~str, ~[Token]),
+    // a property whose value refers to custom properties through 'var()', which is only
+    // parsed once they are known, at computed-value time
+    VariableDeclaration(~str, ~[Token]),
+}
+
+impl StyleDeclaration {
+    /**
+    The name of the property a declaration sets. Shorthands are always
+    expanded by the parser, so this is the name of a longhand.
+    */
+    pure fn property_name() -> ~str {
+        match self {
+          BackgroundColor(*) => ~"background-color",
+          Display(*) => ~"display",
+          FontSize(*) => ~"font-size",
+          FontFamily(*) => ~"font-family",
+          FontWeight(*) => ~"font-weight",
+          FontStyle(*) => ~"font-style",
+          Direction(*) => ~"direction",
+          WhiteSpace(*) => ~"white-space",
+          TextAlign(*) => ~"text-align",
+          TextIndent(*) => ~"text-indent",
+          Height(*) => ~"height",
+          Color(*) => ~"color",
+          Width(*) => ~"width",
+          MarginTop(*) => ~"margin-top",
+          MarginRight(*) => ~"margin-right",
+          MarginBottom(*) => ~"margin-bottom",
+          MarginLeft(*) => ~"margin-left",
+          PaddingTop(*) => ~"padding-top",
+          PaddingRight(*) => ~"padding-right",
+          PaddingBottom(*) => ~"padding-bottom",
+          PaddingLeft(*) => ~"padding-left",
+          BorderTopWidth(*) => ~"border-top-width",
+          BorderRightWidth(*) => ~"border-right-width",
+          BorderBottomWidth(*) => ~"border-bottom-width",
+          BorderLeftWidth(*) => ~"border-left-width",
+          BorderTopStyle(*) => ~"border-top-style",
+          BorderRightStyle(*) => ~"border-right-style",
+          BorderBottomStyle(*) => ~"border-bottom-style",
+          BorderLeftStyle(*) => ~"border-left-style",
+          BorderTopColor(*) => ~"border-top-color",
+          BorderRightColor(*) => ~"border-right-color",
+          BorderBottomColor(*) => ~"border-bottom-color",
+          BorderLeftColor(*) => ~"border-left-color",
+          Position(*) => ~"position",
+          Top(*) => ~"top",
+          Right(*) => ~"right",
+          Bottom(*) => ~"bottom",
+          Left(*) => ~"left",
+          CustomProperty(ref name, _) | VariableDeclaration(ref name, _) => copy *name
+        }
+    }
 }
 
 pub enum Attr {
     Exists(~str),
     Exact(~str, ~str),
     Includes(~str, ~str),
-    StartsWith(~str, ~str)
+    // '|='
+    DashMatch(~str, ~str),
+    // '^=', '$=' and '*='
+    Prefix(~str, ~str),
+    Suffix(~str, ~str),
+    Substring(~str, ~str),
+    // an attribute test with the 'i' flag, which compares values ignoring case
+    CaseInsensitive(~Attr),
+    // '#foo' and '.foo' are kept apart from the equivalent attribute
+    // tests, since they contribute differently to specificity.
+    Id(~str),
+    Class(~str),
+    // pseudo-classes need the element's place in the tree to match
+    Pseudo(PseudoClass)
+}
+
+pub enum PseudoClass {
+    FirstChild,
+    LastChild,
+    // ':nth-child(an+b)', as (a, b)
+    NthChild(int, int),
+    // the argument is a single element selector
+    Not(~Selector),
+    Root,
+    Empty,
+    Link,
+    Hover,
+    Focus
 }
     
 pub enum Selector {
     Element(~str, ~[Attr]),
     Child(~Selector, ~Selector),
     Descendant(~Selector, ~Selector),
-    Sibling(~Selector, ~Selector)
+    Sibling(~Selector, ~Selector),
+    // 'a ~ b'
+    GeneralSibling(~Selector, ~Selector)
+}
+
+/** A media type (Media Queries Section 2). Unknown types never match. */
+pub enum MediaType {
+    MediaAll,
+    MediaScreen,
+    MediaPrint,
+    MediaUnknown
+}
+
+/** Which way a media feature compares the device with its value. */
+pub enum MediaRange {
+    // 'min-' prefix
+    MediaMin,
+    // 'max-' prefix
+    MediaMax,
+    MediaExact
 }
 
-pub type Rule = (~[~Selector], ~[StyleDeclaration]);
+pub enum MediaFeature {
+    MediaWidth(MediaRange, Length),
+    MediaHeight(MediaRange, Length),
+    // true for 'portrait', false for 'landscape'
+    MediaPortrait(bool),
+    // in dots per 'px'
+    MediaResolution(MediaRange, float)
+}
+
+/** A media query, such as 'not screen and (min-width: 600px)'. */
+pub struct MediaQuery {
+    negated: bool,
+    media_type: MediaType,
+    // all of these must hold, as well as the media type
+    features: ~[MediaFeature]
+}
+
+/** A comma-separated list of media queries. It applies if any of them does. */
+pub type MediaQueryList = ~[MediaQuery];
+
+/** A rule is its selectors, followed by its normal declarations, its
+'!important' declarations, and the media query lists of the '@media'
+rules it is nested in, which all have to match for it to apply. */
+pub type Rule = (~[~Selector], ~[StyleDeclaration], ~[StyleDeclaration], ~[MediaQueryList]);
 
 type Stylesheet = ~[~Rule];
 
+/** Where a declaration came from. This decides, together with
+'!important', which cascade level it participates in (CSS 2.1
+Section 6.4.1). */
+pub enum Origin {
+    OriginUserAgent,
+    OriginAuthor,
+    // declarations from an element's 'style' attribute
+    OriginInline
+}
+
+/// Specificity given to inline declarations, above any selector.
+pub const INLINE_SPECIFICITY: u32 = 0x01000000u32;
+
+impl Selector {
+    /**
+    Computes the specificity of this selector (CSS 2.1 Section
+    6.4.3). The counts of id selectors, other attribute selectors and
+    element names are packed into one integer, eight bits each, so
+    that specificities compare numerically.
+    */
+    pure fn specificity() -> u32 {
+        let (a, b, c) = self.specificity_counts();
+        (uint::min(a, 255u) << 16 | uint::min(b, 255u) << 8 | uint::min(c, 255u)) as u32
+    }
+
+    priv pure fn specificity_counts() -> (uint, uint, uint) {
+        match self {
+          Element(ref tag, ref attrs) => {
+            let mut ids = 0u, others = 0u, tags = if *tag == ~"*" { 0u } else { 1u };
+            for attrs.each |attr| {
+                match *attr {
+                  Id(*) => ids += 1u,
+                  // ':not()' counts as its argument (Selectors Level 3, Section 9)
+                  Pseudo(Not(ref sel)) => {
+                    let (a, b, c) = sel.specificity_counts();
+                    ids += a; others += b; tags += c;
+                  }
+                  _ => others += 1u
+                }
+            }
+            (ids, others, tags)
+          }
+          Child(ref sel1, ref sel2)
+          | Descendant(ref sel1, ref sel2)
+          | Sibling(ref sel1, ref sel2)
+          | GeneralSibling(ref sel1, ref sel2) => {
+            let (a1, b1, c1) = sel1.specificity_counts();
+            let (a2, b2, c2) = sel2.specificity_counts();
+            (a1 + a2, b1 + b2, c1 + c2)
+          }
+        }
+    }
+}
+
 
 impl Length: cmp::Eq {
     pure fn eq(other: &Length) -> bool {
         match (self, *other) {
           (Em(a), Em(b)) => a == b,
+          (Ex(a), Ex(b)) => a == b,
+          (Rem(a), Rem(b)) => a == b,
+          (Vw(a), Vw(b)) => a == b,
+          (Vh(a), Vh(b)) => a == b,
+          (Vmin(a), Vmin(b)) => a == b,
+          (Vmax(a), Vmax(b)) => a == b,
           (Px(a), Px(b)) => a == b,
+          (Pt(a), Pt(b)) => a == b,
+          (Calc(a), Calc(b)) => a == b,
           (_, _) => false
         }
     }
@@ -232,11 +588,23 @@ impl Length: cmp::Eq {
     }
 }
 
+impl CalcLength: cmp::Eq {
+    pure fn eq(other: &CalcLength) -> bool {
+        self.px == other.px && self.em == other.em && self.rem == other.rem
+            && self.vw == other.vw && self.vh == other.vh && self.vmin == other.vmin
+            && self.vmax == other.vmax && self.percent == other.percent
+    }
+    pure fn ne(other: &CalcLength) -> bool {
+        return !self.eq(other);
+    }
+}
+
 impl BoxSizing: cmp::Eq {
     pure fn eq(other: &BoxSizing) -> bool {
         match (self, *other) {
           (BoxLength(a), BoxLength(b)) => a == b,
           (BoxPercent(a), BoxPercent(b)) => a == b,
+          (BoxCalc(a), BoxCalc(b)) => a == b,
           (BoxAuto, BoxAuto) => true,
           (_, _) => false
         }
@@ -291,6 +659,24 @@ impl CSSColor: cmp::Eq {
     }
 }
 
+impl Origin: cmp::Eq {
+    pure fn eq(other: &Origin) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &Origin) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl CSSBorderStyle: cmp::Eq {
+    pure fn eq(other: &CSSBorderStyle) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &CSSBorderStyle) -> bool {
+        return !self.eq(other);
+    }
+}
+
 impl CSSDisplay: cmp::Eq {
     pure fn eq(other: &CSSDisplay) -> bool {
         self as uint == (*other) as uint
@@ -315,6 +701,70 @@ impl CSSFontSize: cmp::Eq {
         return !self.eq(other);
     }
 }
+impl CSSFontFamily: cmp::Eq {
+    pure fn eq(other: &CSSFontFamily) -> bool {
+        match (copy self, copy *other) {
+          (FamilyName(a), FamilyName(b)) => a == b,
+          (GenericSerif, GenericSerif) | (GenericSansSerif, GenericSansSerif)
+          | (GenericCursive, GenericCursive) | (GenericFantasy, GenericFantasy)
+          | (GenericMonospace, GenericMonospace) => true,
+          (_, _) => false
+        }
+    }
+    pure fn ne(other: &CSSFontFamily) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl CSSFontWeight: cmp::Eq {
+    pure fn eq(other: &CSSFontWeight) -> bool {
+        match (self, *other) {
+          (WeightNumber(a), WeightNumber(b)) => a == b,
+          (WeightBolder, WeightBolder) | (WeightLighter, WeightLighter) => true,
+          (WeightNumber(*), _) | (WeightBolder, _) | (WeightLighter, _) => false
+        }
+    }
+    pure fn ne(other: &CSSFontWeight) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl CSSDirection: cmp::Eq {
+    pure fn eq(other: &CSSDirection) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &CSSDirection) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl CSSTextAlign: cmp::Eq {
+    pure fn eq(other: &CSSTextAlign) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &CSSTextAlign) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl CSSWhiteSpace: cmp::Eq {
+    pure fn eq(other: &CSSWhiteSpace) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &CSSWhiteSpace) -> bool {
+        return !self.eq(other);
+    }
+}
+
+impl CSSFontStyle: cmp::Eq {
+    pure fn eq(other: &CSSFontStyle) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &CSSFontStyle) -> bool {
+        return !self.eq(other);
+    }
+}
+
 /*
 impl StyleDeclaration: cmp::Eq {
     pure fn eq(&&other: StyleDeclaration) -> bool {
@@ -339,16 +789,32 @@ impl StyleDeclaration: cmp::Eq {
 impl Attr: cmp::Eq {
     pure fn eq(other: &Attr) -> bool {
         match (copy self, copy *other) {
-          (Exists(a), Exists(b)) => a == b,
+          (Exists(a), Exists(b))
+          | (Id(a), Id(b))
+          | (Class(a), Class(b)) => a == b,
 
           (Exact(a, aa), Exact(b, bb))
           | (Includes(a, aa), Includes(b, bb))
-          | (StartsWith(a, aa), StartsWith(b, bb)) => a == b && aa == bb,
+          | (DashMatch(a, aa), DashMatch(b, bb))
+          | (Prefix(a, aa), Prefix(b, bb))
+          | (Suffix(a, aa), Suffix(b, bb))
+          | (Substring(a, aa), Substring(b, bb)) => a == b && aa == bb,
+
+          (CaseInsensitive(a), CaseInsensitive(b)) => a == b,
+
+          (Pseudo(a), Pseudo(b)) => a == b,
 
           (Exists(*), _)
           | (Exact(*), _)
           | (Includes(*), _)
-          | (StartsWith(*), _) => false
+          | (DashMatch(*), _)
+          | (Prefix(*), _)
+          | (Suffix(*), _)
+          | (Substring(*), _)
+          | (CaseInsensitive(*), _)
+          | (Id(*), _)
+          | (Class(*), _)
+          | (Pseudo(*), _) => false
         }
     }
     pure fn ne(other: &Attr) -> bool {
@@ -356,6 +822,23 @@ impl Attr: cmp::Eq {
     }
 }
 
+impl PseudoClass: cmp::Eq {
+    pure fn eq(other: &PseudoClass) -> bool {
+        match (copy self, copy *other) {
+          (NthChild(a1, b1), NthChild(a2, b2)) => a1 == a2 && b1 == b2,
+          (Not(a), Not(b)) => a == b,
+          (FirstChild, FirstChild) | (LastChild, LastChild) | (Root, Root) | (Empty, Empty)
+          | (Link, Link) | (Hover, Hover) | (Focus, Focus) => true,
+
+          (FirstChild, _) | (LastChild, _) | (NthChild(*), _) | (Not(*), _) | (Root, _)
+          | (Empty, _) | (Link, _) | (Hover, _) | (Focus, _) => false
+        }
+    }
+    pure fn ne(other: &PseudoClass) -> bool {
+        return !self.eq(other);
+    }
+}
+
 impl Selector: cmp::Eq {
     pure fn eq(other: &Selector) -> bool {
         // FIXME: Lots of copying here
@@ -364,14 +847,16 @@ impl Selector: cmp::Eq {
 
           (Child(s1a, s2a), Child(s1b, s2b))
           | (Descendant(s1a, s2a), Descendant(s1b, s2b))
-          | (Sibling(s1a, s2a), Sibling(s1b, s2b)) => {
+          | (Sibling(s1a, s2a), Sibling(s1b, s2b))
+          | (GeneralSibling(s1a, s2a), GeneralSibling(s1b, s2b)) => {
             s1a == s1b && s2a == s2b
           }
 
           (Element(*), _) => false,
           (Child(*), _) => false,
           (Descendant(*), _) => false,
-          (Sibling(*), _) => false
+          (Sibling(*), _) => false,
+          (GeneralSibling(*), _) => false
         }
     }
     pure fn ne(other: &Selector) -> bool {
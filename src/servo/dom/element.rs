@@ -1,5 +1,6 @@
 use au = gfx::geometry;
 use au::au;
+use css::values::StyleDeclaration;
 use dvec::DVec;
 use geom::size::Size2D;
 use std::net::url::Url;
@@ -8,6 +9,11 @@ struct ElementData {
     tag_name: ~str,
     kind: ~ElementKind,
     attrs: DVec<~Attr>,
+    // the declarations of the 'style' attribute, normal and '!important'
+    mut style_attribute: Option<(~[StyleDeclaration], ~[StyleDeclaration])>,
+    // user action state, for ':hover' and ':focus'; nothing sets these yet
+    mut hover: bool,
+    mut focus: bool,
 }
 
 #[allow(non_implicitly_copyable_typarams)]
@@ -34,6 +40,9 @@ fn ElementData(tag_name: ~str, kind: ~ElementKind) -> ElementData {
         tag_name : move tag_name,
         kind : move kind,
         attrs : DVec(),
+        style_attribute : None,
+        hover : false,
+        focus : false,
     }
 }
 
@@ -1,5 +1,5 @@
 /* The core DOM types. Defines the basic DOM hierarchy as well as all the HTML elements. */
-use css::styles::SpecifiedStyle;
+use css::styles::{SpecifiedStyle, ComputedStyle};
 use css::values::Stylesheet;
 use dom::bindings;
 use dom::document::Document;
@@ -108,12 +108,17 @@ fn define_bindings(compartment: &bare_compartment, doc: @Document,
 
 
 /** The COW rd_aux data is a (weak) pointer to the layout data,
-   defined by this `LayoutData` enum. It contains the CSS style object
-   as well as the primary `RenderBox`.
+   defined by this `LayoutData` enum. It contains the CSS style object,
+   the computed style resolved from it, as well as the primary
+   `RenderBox`.
+
+   Siblings that match the same rules share their style objects (see
+   `css::resolve::sharing`).
 
    Note that there may be multiple boxes per DOM node. */
 enum LayoutData = {
-    mut style: ~SpecifiedStyle,
+    mut style: @SpecifiedStyle,
+    mut computed_style: Option<ARC<ComputedStyle>>,
     mut flow:  Option<@FlowContext>
 };
 
@@ -22,10 +22,11 @@ struct DisplayItem {
 
 pub enum DisplayItemData {
     SolidColorData(u8, u8, u8),
-    // TODO: need to provide spacing data for text run.
+    // the Au is the space added after each expansion opportunity, for justification.
+    // TODO: need to provide more spacing data for text run.
     // (i.e, to support rendering of CSS 'word-spacing' and 'letter-spacing')
     // TODO: don't copy text runs, ever.
-    TextData(~SendableTextRun, Range),
+    TextData(~SendableTextRun, Range, Au),
     ImageData(ARC<~image::base::Image>),
     BorderData(Au, u8, u8, u8)
 }
@@ -39,9 +40,9 @@ fn draw_SolidColor(self: &DisplayItem, ctx: &RenderContext) {
 
 fn draw_Text(self: &DisplayItem, ctx: &RenderContext) {
     match self.data {
-        TextData(run, range) => {
+        TextData(run, range, expansion) => {
             let new_run = text_run::deserialize(ctx.font_cache, run);
-            ctx.draw_text(self.bounds, new_run, range)
+            ctx.draw_text(self.bounds, new_run, range, expansion)
         },
         _ => fail
     }        
@@ -77,11 +78,12 @@ pub fn Border(bounds: Rect<Au>, width: Au, r: u8, g: u8, b: u8) -> DisplayItem {
     }
 }
 
-pub fn Text(bounds: Rect<Au>, run: ~SendableTextRun, range: Range) -> DisplayItem {
+pub fn Text(bounds: Rect<Au>, run: ~SendableTextRun, range: Range,
+            expansion: Au) -> DisplayItem {
     DisplayItem {
         draw: |self, ctx| draw_Text(self, ctx),
         bounds: bounds,
-        data: TextData(move run, move range)
+        data: TextData(move run, move range, expansion)
     }
 }
 
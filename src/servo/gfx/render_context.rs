@@ -68,13 +68,15 @@ impl RenderContext  {
                                      draw_surface_options, draw_options);
     }
 
-    pub fn draw_text(&self, bounds: Rect<Au>, run: &TextRun, range: Range) {
+    // `expansion` is the space added after each of the run's expansion opportunities
+    pub fn draw_text(&self, bounds: Rect<Au>, run: &TextRun, range: Range, expansion: Au) {
         use ptr::{null};
         use vec::raw::to_ptr;
         use libc::types::common::c99::{uint16_t, uint32_t};
         use geom::point::Point2D;
         use text::font_cache::FontCache;
         use text::font::Font;
+        use text::bidi::is_rtl;
         use azure::{AzNativeFont, AzFloat, AZ_NATIVE_FONT_CAIRO_FONT_FACE};
         use azure::bindgen::{AzCreateScaledFontWithCairo,
                              AzReleaseScaledFont,
@@ -82,18 +84,11 @@ impl RenderContext  {
                              AzReleaseColorPattern};
         use azure::cairo::bindgen::cairo_scaled_font_destroy;
 
-        let font = run.font;
-
         let nfont: AzNativeFont = {
             mType: AZ_NATIVE_FONT_CAIRO_FONT_FACE,
             mFont: null()
         };
 
-        let cfont = get_cairo_font(font);
-        let azfont = AzCreateScaledFontWithCairo(to_unsafe_ptr(&nfont), 1f as AzFloat, cfont);
-        assert azfont.is_not_null();
-        cairo_scaled_font_destroy(cfont);
-
         let color = {
             r: 0f as AzFloat,
             g: 0f as AzFloat,
@@ -109,37 +104,63 @@ impl RenderContext  {
         };
 
         let mut origin = Point2D(bounds.origin.x, bounds.origin.y.add(&bounds.size.height));
-        let azglyphs = DVec();
-        azglyphs.reserve(range.length());
-
-        do run.glyphs.iter_glyphs_for_range(range) |_i, glyph| {
-            let glyph_advance = glyph.advance();
-            let glyph_offset = glyph.offset().get_default(au::zero_point());
-
-            let azglyph: AzGlyph = {
-                mIndex: glyph.index() as uint32_t,
-                mPosition: {
-                    x: au::to_px(origin.x + glyph_offset.x) as AzFloat,
-                    y: au::to_px(origin.y + glyph_offset.y) as AzFloat
+
+        // each segment of the run is drawn in its own font, from left to right, so the
+        // segments and glyphs of right-to-left text are drawn from its end
+        let rtl = is_rtl(run.level_for_range(range));
+        let mut segments = ~[];
+        for run.iter_segments_for_range(range) |segment| {
+            vec::push(&mut segments, *segment);
+        }
+        if rtl { segments = vec::reversed(segments); }
+
+        for segments.each |segment| {
+            let cfont = get_cairo_font(segment.font);
+            let azfont = AzCreateScaledFontWithCairo(to_unsafe_ptr(&nfont), 1f as AzFloat, cfont);
+            assert azfont.is_not_null();
+            cairo_scaled_font_destroy(cfont);
+
+            let azglyphs = DVec();
+            azglyphs.reserve(segment.range.length());
+
+            let mut offsets = vec::from_fn(segment.range.length(), |j| segment.range.begin() + j);
+            if rtl { offsets = vec::reversed(offsets); }
+            for offsets.each |offset| {
+                do run.glyphs.iter_glyphs_for_index(*offset) |_i, glyph| {
+                    let glyph_advance = glyph.advance();
+                    let glyph_offset = glyph.offset().get_default(au::zero_point());
+
+                    let azglyph: AzGlyph = {
+                        mIndex: glyph.index() as uint32_t,
+                        mPosition: {
+                            x: au::to_px(origin.x + glyph_offset.x) as AzFloat,
+                            y: au::to_px(origin.y + glyph_offset.y) as AzFloat
+                        }
+                    };
+                    origin = Point2D(origin.x + glyph_advance, origin.y);
+                    azglyphs.push(move azglyph)
+                };
+                if run.glyphs.char_is_space(*offset) {
+                    origin = Point2D(origin.x + expansion, origin.y);
                 }
-            };
-            origin = Point2D(origin.x + glyph_advance, origin.y);
-            azglyphs.push(move azglyph)
-        };
+            }
+
+            let azglyph_buf_len = azglyphs.len();
+            let azglyph_buf = dvec::unwrap(move azglyphs);
+            let glyphbuf: AzGlyphBuffer = unsafe {{
+                mGlyphs: to_ptr(azglyph_buf),
+                mNumGlyphs: azglyph_buf_len as uint32_t            
+            }};
 
-        let azglyph_buf_len = azglyphs.len();
-        let azglyph_buf = dvec::unwrap(move azglyphs);
-        let glyphbuf: AzGlyphBuffer = unsafe {{
-            mGlyphs: to_ptr(azglyph_buf),
-            mNumGlyphs: azglyph_buf_len as uint32_t            
-        }};
+            // TODO: this call needs to move into azure_hl.rs
+            AzDrawTargetFillGlyphs(self.canvas.draw_target.azure_draw_target, azfont,
+                                   to_unsafe_ptr(&glyphbuf), pattern, to_unsafe_ptr(&options),
+                                   null());
 
-        // TODO: this call needs to move into azure_hl.rs
-        AzDrawTargetFillGlyphs(self.canvas.draw_target.azure_draw_target, azfont,
-                               to_unsafe_ptr(&glyphbuf), pattern, to_unsafe_ptr(&options), null());
+            AzReleaseScaledFont(azfont);
+        }
 
         AzReleaseColorPattern(pattern);
-        AzReleaseScaledFont(azfont);
     }
 
     fn clear(&self) {
@@ -220,7 +241,8 @@ fn get_cairo_font(font: &Font) -> *cairo_scaled_font_t {
     cairo_matrix_init_identity(to_unsafe_ptr(&idmatrix));
 
     let fontmatrix = idmatrix;
-    cairo_matrix_scale(to_unsafe_ptr(&fontmatrix), 20f as c_double, 20f as c_double);
+    let size = au::to_frac_px(font.descriptor.size) as c_double;
+    cairo_matrix_scale(to_unsafe_ptr(&fontmatrix), size, size);
     let options = cairo_font_options_create();
     let cfont = cairo_scaled_font_create(face, to_unsafe_ptr(&fontmatrix),
                                          to_unsafe_ptr(&idmatrix), options);
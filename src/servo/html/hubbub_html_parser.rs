@@ -10,6 +10,7 @@ use resource::image_cache_task;
 use resource::resource_task::{Done, Load, Payload, ResourceTask};
 
 use comm::{Chan, Port};
+use dvec::DVec;
 use str::from_slice;
 use cast::reinterpret_cast;
 use std::net::url::Url;
@@ -18,6 +19,11 @@ type JSResult = ~[~[u8]];
 
 enum CSSMessage {
     CSSTaskNewFile(Url),
+    // reserves the place of a <style> element among the style sheets
+    CSSTaskNewStyleElement,
+    // the contents of the oldest <style> element that is still missing them,
+    // and the URL of the document, against which its imports are resolved
+    CSSTaskStyleElementText(~str, Url),
     CSSTaskExit   
 }
 
@@ -41,6 +47,13 @@ tasks to parse each link.  When the html process finishes, it notifies
 the listener, who then collects the css rules from each task it
 spawned, collates them, and sends them to the given result channel.
 
+The text of a <style> element is only known once the element has been
+parsed, so its place in document order is reserved when the element is
+created and its rules are filled in later.
+
+Style sheets are loaded with `css::loader`, so the rules of the style
+sheets they import are spliced in as well.
+
 # Arguments
 
 * `to_parent` - A channel on which to send back the full set of rules.
@@ -50,6 +63,7 @@ spawned, collates them, and sends them to the given result channel.
 fn css_link_listener(to_parent : comm::Chan<Stylesheet>, from_parent : comm::Port<CSSMessage>,
                      resource_task: ResourceTask) {
     let mut result_vec = ~[];
+    let mut style_element_chans = ~[];
 
     loop {
         match from_parent.recv() {
@@ -60,13 +74,26 @@ fn css_link_listener(to_parent : comm::Chan<Stylesheet>, from_parent : comm::Por
                 let url = copy url;
                 do task::spawn |move url, copy resource_task| {
                     // TODO: change copy to move once we can move out of closures
-                    let css_stream = css::lexer::spawn_css_lexer_task(copy url, resource_task);
-                    let mut css_rules = css::parser::build_stylesheet(move css_stream);
+                    let css_rules = css::loader::load_stylesheet(copy url, resource_task);
                     result_chan.send(move css_rules);
                 }
 
                 vec::push(&mut result_vec, result_port);
             }
+            CSSTaskNewStyleElement => {
+                let result_port = comm::Port();
+                vec::push(&mut style_element_chans, comm::Chan(&result_port));
+                vec::push(&mut result_vec, result_port);
+            }
+            CSSTaskStyleElementText(move text, move document_url) => {
+                let result_chan = vec::shift(&mut style_element_chans);
+                do task::spawn |move text, move document_url, copy resource_task| {
+                    let css_rules = css::loader::load_stylesheet_from_string(copy text,
+                                                                             copy document_url,
+                                                                             resource_task);
+                    result_chan.send(move css_rules);
+                }
+            }
             CSSTaskExit => {
                 break;
             }
@@ -163,6 +190,20 @@ fn build_element_kind(tag: &str) -> ~ElementKind {
     else { ~UnknownElement }
 }
 
+/** Concatenates the text children of a <style> element. */
+fn style_element_text(scope: NodeScope, node: Node) -> ~str {
+    let mut text = ~"";
+    for scope.each_child(&node) |child| {
+        do scope.read(child) |n| {
+            match *n.kind {
+                Text(ref data) => { text += *data; }
+                _ => {}
+            }
+        }
+    }
+    return move text;
+}
+
 pub fn parse_html(scope: NodeScope,
                   url: Url,
                   resource_task: ResourceTask,
@@ -182,6 +223,8 @@ pub fn parse_html(scope: NodeScope,
     };
 
     let (scope, url) = (@copy scope, @move url);
+    // The <style> elements, in document order, whose text is sent once parsing is done
+    let style_elements = @DVec();
 
     // Build the root node.
     let root = scope.new_node(Element(ElementData(~"html", ~HTMLDivElement)));
@@ -222,6 +265,17 @@ pub fn parse_html(scope: NodeScope,
                                       from_slice(attribute.value)));
             }
 
+            // Parse the 'style' attribute now, so that the cascade can use it directly
+            do elem.get_attr(~"style").iter |style| {
+                let css_stream = css::lexer::spawn_css_lexer_from_string(copy *style);
+                let (decls, diagnostics) = css::parser::parse_style_attribute(move css_stream);
+                for diagnostics.each |diagnostic| {
+                    #warn("style attribute of <%s>:%u: %s", elem.tag_name,
+                          diagnostic.position.column, diagnostic.message);
+                }
+                elem.style_attribute = Some(move decls);
+            }
+
             // Spawn additional parsing, network loads, etc. from tag and attrs
             match elem.kind {
                 //Handle CSS style sheets from <link> elements
@@ -237,6 +291,10 @@ pub fn parse_html(scope: NodeScope,
                         _ => {}
                     }
                 },
+                //Handle embedded CSS style sheets from <style> elements
+                ~HTMLStyleElement => {
+                    css_chan.send(CSSTaskNewStyleElement);
+                }
                 ~HTMLImageElement(copy d) => {  // FIXME: Bad copy.
                     do elem.get_attr(~"src").iter |img_url_str| {
                         let img_url = make_url(copy *img_url_str, Some(copy *url));
@@ -246,10 +304,16 @@ pub fn parse_html(scope: NodeScope,
                         image_cache_task.send(image_cache_task::Prefetch(move img_url));
                     }
                 }
-                //TODO (Issue #86): handle inline styles ('style' attr)
                 _ => {}
             }
+            let is_style_element = match elem.kind {
+                ~HTMLStyleElement => true,
+                _ => false
+            };
             let node = scope.new_node(Element(move elem));
+            if is_style_element {
+                style_elements.push(node);
+            }
             unsafe { reinterpret_cast(&node) }
         },
         create_text: |data| {
@@ -341,6 +405,10 @@ pub fn parse_html(scope: NodeScope,
         }
     }
 
+    for style_elements.each |style_element| {
+        css_chan.send(CSSTaskStyleElementText(style_element_text(*scope, *style_element),
+                                              copy *url));
+    }
     css_chan.send(CSSTaskExit);
     js_chan.send(JSTaskExit);
 
@@ -5,7 +5,7 @@ use geom::point::Point2D;
 use geom::rect::Rect;
 use geom::size::Size2D;
 use gfx::geometry::Au;
-use layout::box::{RenderBox};
+use layout::box::{RenderBox, resolve_calc};
 use layout::context::LayoutContext;
 use layout::flow::{FlowContext, FlowTree, InlineBlockFlow, BlockFlow, RootFlow};
 use util::tree;
@@ -82,8 +82,13 @@ impl FlowContext : BlockLayout {
         /* if not an anonymous block context, add in block box's widths.
            these widths will not include child elements, just padding etc. */
         do self.with_block_box |box| {
-            min_width = min_width.add(&box.get_min_width(ctx));
-            pref_width = pref_width.add(&box.get_pref_width(ctx));
+            // percentages can't be resolved before the containing block's width is known,
+            // so they count as zero here.
+            box.compute_edges(Au(0));
+            let (left_used, right_used) = box.get_used_width();
+            let fringe = left_used.add(&right_used);
+            min_width = min_width.add(&box.get_min_width(ctx)).add(&fringe);
+            pref_width = pref_width.add(&box.get_pref_width(ctx)).add(&fringe);
         }
 
         self.d().min_width = min_width;
@@ -100,16 +105,48 @@ impl FlowContext : BlockLayout {
     fn assign_widths_block(@self, _ctx: &LayoutContext) { 
         assert self.starts_block_flow();
 
-        let mut remaining_width = self.d().position.size.width;
-        let mut _right_used = Au(0);
+        let cb_width = self.d().position.size.width;
+        let mut remaining_width = cb_width;
         let mut left_used = Au(0);
 
         /* Let the box consume some width. It will return the amount remaining
-           for its children. */
+           for its children. See CSS 2.1, Section 10.3.3. */
         do self.with_block_box |box| {
-            box.d().position.size.width = remaining_width;
-            let (left_used, right_used) = box.get_used_width();
-            remaining_width -= left_used.add(&right_used);
+            box.compute_edges(cb_width);
+            let style = box.d().node.computed_style();
+            let d = box.d();
+            let content_width = match style.width {
+                BoxLength(Px(px)) => Some(au::from_frac_px(px)),
+                BoxPercent(pct) => Some(au::from_frac_px(pct / 100.0 * au::to_frac_px(cb_width))),
+                BoxCalc(calc) => Some(resolve_calc(calc, cb_width)),
+                _ => None
+            };
+            let content_width = match content_width {
+                Some(width) => {
+                    let border_width = d.border.left + d.padding.left + width
+                        + d.padding.right + d.border.right;
+                    let (left, right) = block_margins(cb_width, border_width,
+                                                      used_margin(style.margin_left, d.margin.left),
+                                                      used_margin(style.margin_right,
+                                                                  d.margin.right),
+                                                      containing_block_is_rtl(self));
+                    d.margin.left = left;
+                    d.margin.right = right;
+                    width
+                }
+                // 'auto' margins are zero, and the width takes up the rest
+                None => {
+                    let (box_left, box_right) = box.get_used_width();
+                    cb_width - box_left.add(&box_right)
+                }
+            };
+
+            d.position.origin.x = d.margin.left;
+            d.position.size.width = d.border.left + d.padding.left + content_width
+                + d.padding.right + d.border.right;
+            let (box_left, _) = box.get_used_width();
+            left_used = box_left;
+            remaining_width = content_width;
         }
 
         for FlowTree.each_child(self) |child_ctx| {
@@ -122,23 +159,47 @@ impl FlowContext : BlockLayout {
     fn assign_height_block(@self, _ctx: &LayoutContext) {
         assert self.starts_block_flow();
 
-        let mut cur_y = Au(0);
+        let mut top_used = Au(0);
+        do self.with_block_box |box| {
+            let (box_top, _) = box.get_used_height();
+            top_used = box_top;
+        }
+
+        // The bottom margin of each child collapses with the top margin of the
+        // next one (CSS 2.1, Section 8.3.1).
+        // TODO: collapse the margins of a block with those of its first and last children
+        let mut cur_y = top_used;
+        let mut prev_bottom_margin = None;
 
         for FlowTree.each_child(self) |child_ctx| {
+            let (top_margin, bottom_margin) = vertical_margins(child_ctx);
+            match prev_bottom_margin {
+                Some(prev) => cur_y -= prev + top_margin - collapse_margins(prev, top_margin),
+                None => {}
+            }
             child_ctx.d().position.origin.y = cur_y;
             cur_y += child_ctx.d().position.size.height;
+            prev_bottom_margin = Some(bottom_margin);
         }
 
-        self.d().position.size.height = cur_y;
+        let mut height = cur_y;
 
-        let _used_top = Au(0);
-        let _used_bot = Au(0);
-        
         do self.with_block_box |box| {
-            box.d().position.origin.y = Au(0);
-            box.d().position.size.height = cur_y;
-            let (_used_top, _used_bot) = box.get_used_height();
+            let (_, box_bottom) = box.get_used_height();
+            let d = box.d();
+            let content_height = match box.d().node.computed_style().height {
+                BoxLength(Px(px)) => au::from_frac_px(px),
+                // TODO: percentage heights, once the containing block's height is known
+                _ => cur_y - top_used
+            };
+
+            d.position.origin.y = d.margin.top;
+            d.position.size.height = d.border.top + d.padding.top + content_height
+                + d.padding.bottom + d.border.bottom;
+            height = top_used + content_height + box_bottom;
         }
+
+        self.d().position.size.height = height;
     }
 
     fn build_display_list_block(@self, builder: &dl::DisplayListBuilder, dirty: &Rect<Au>, 
@@ -159,3 +220,115 @@ impl FlowContext : BlockLayout {
         }
     }
 }
+
+/* The used margin on one side of a block box: None for an 'auto' margin,
+   which block layout resolves, or else its width from `compute_edges`. */
+pure fn used_margin(margin: BoxSizing, used: Au) -> Option<Au> {
+    match margin {
+        BoxAuto => None,
+        _ => Some(used)
+    }
+}
+
+/* Resolves the left and right margins of a block box whose width is not
+   'auto', given the width of its border box, so that they fill the width
+   of the containing block (CSS 2.1, Section 10.3.3). Two 'auto' margins
+   split what is left evenly. When the box is too wide, or neither margin
+   is 'auto', the margin on the end side takes up the difference: the left
+   one if the containing block is right-to-left. */
+pure fn block_margins(cb_width: Au, border_width: Au, left: Option<Au>,
+                      right: Option<Au>, rtl: bool) -> (Au, Au) {
+    let (used_left, used_right) = (left.get_default(Au(0)), right.get_default(Au(0)));
+    let slack = cb_width - border_width - used_left - used_right;
+    let at_end = if rtl {
+        (used_left + slack, used_right)
+    } else {
+        (used_left, used_right + slack)
+    };
+    match (left, right) {
+        _ if slack < Au(0) => at_end,
+        (None, None) => {
+            let half = Au(*slack / 2);
+            (half, slack - half)
+        }
+        (None, Some(_)) => (slack, used_right),
+        (Some(_), None) => (used_left, slack),
+        (Some(_), Some(_)) => at_end
+    }
+}
+
+/* Whether the block that contains a flow has 'direction: rtl'. */
+fn containing_block_is_rtl(flow: @FlowContext) -> bool {
+    let mut rtl = false;
+    do tree::parent(&FlowTree, &flow).iter |parent| {
+        match *parent {
+            @BlockFlow(*) | @RootFlow(*) => do parent.with_block_box |box| {
+                rtl = match box.d().node.computed_style().direction {
+                    DirectionRtl => true,
+                    DirectionLtr => false
+                };
+            },
+            _ => {}
+        }
+    }
+    rtl
+}
+
+/* The top and bottom margins of a flow's block box, or zero for a flow
+   without one. */
+fn vertical_margins(flow: @FlowContext) -> (Au, Au) {
+    let mut margins = (Au(0), Au(0));
+    match *flow {
+        BlockFlow(*) => do flow.with_block_box |box| {
+            margins = (box.d().margin.top, box.d().margin.bottom);
+        },
+        _ => {}
+    }
+    margins
+}
+
+/* The margin that two adjoining vertical margins collapse into: the
+   larger of them, or the sum of the largest positive and most negative
+   one (CSS 2.1, Section 8.3.1). */
+pure fn collapse_margins(a: Au, b: Au) -> Au {
+    if a >= Au(0) && b >= Au(0) {
+        au::max(a, b)
+    } else if a < Au(0) && b < Au(0) {
+        au::min(a, b)
+    } else {
+        a + b
+    }
+}
+
+#[test]
+fn should_center_fixed_width_blocks_between_auto_margins() {
+    let (left, right) = block_margins(au::from_px(500), au::from_px(100), None, None, false);
+    assert left == au::from_px(200) && right == au::from_px(200);
+
+    let (left, right) = block_margins(au::from_px(500), au::from_px(100),
+                                      Some(au::from_px(50)), None, false);
+    assert left == au::from_px(50) && right == au::from_px(350);
+
+    // a block wider than its containing block overflows on the right
+    let (left, right) = block_margins(au::from_px(500), au::from_px(600), None, None, false);
+    assert left == Au(0) && right == au::from_px(-100);
+    let (left, right) = block_margins(au::from_px(500), au::from_px(100),
+                                      Some(au::from_px(10)), Some(au::from_px(10)), false);
+    assert left == au::from_px(10) && right == au::from_px(390);
+}
+
+#[test]
+fn should_give_the_end_margin_the_rest_of_the_width_in_rtl_blocks() {
+    let (left, right) = block_margins(au::from_px(500), au::from_px(600), None, None, true);
+    assert left == au::from_px(-100) && right == Au(0);
+    let (left, right) = block_margins(au::from_px(500), au::from_px(100),
+                                      Some(au::from_px(10)), Some(au::from_px(10)), true);
+    assert left == au::from_px(390) && right == au::from_px(10);
+}
+
+#[test]
+fn should_collapse_adjoining_margins() {
+    assert collapse_margins(au::from_px(16), au::from_px(10)) == au::from_px(16);
+    assert collapse_margins(au::from_px(16), au::from_px(-10)) == au::from_px(6);
+    assert collapse_margins(au::from_px(-16), au::from_px(-10)) == au::from_px(-16);
+}
@@ -8,8 +8,9 @@ use au::Au;
 use core::dvec::DVec;
 use core::to_str::ToStr;
 use core::rand;
-use css::styles::SpecifiedStyle;
-use css::values::{BoxSizing, Length, Px, CSSDisplay, Specified, BgColor, BgColorTransparent, BdrColor, PosAbsolute};
+use css::styles::{SpecifiedStyle, ComputedStyle};
+use css::values::{BoxSizing, BoxLength, BoxPercent, BoxCalc, BoxAuto, CalcLength, Length, Px,
+                  CSSDisplay, BgColor, BgColorTransparent, PosAbsolute};
 use dl = gfx::display_list;
 use dom::element::{ElementKind, HTMLDivElement, HTMLImageElement};
 use dom::node::{Element, Node, NodeData, NodeKind, NodeTree};
@@ -23,6 +24,7 @@ use layout::flow::FlowContext;
 use layout::text::TextBoxData;
 use servo_text::text_run;
 use servo_text::text_run::TextRun;
+use servo_text::util::CompressNone;
 use std::net::url::Url;
 use task::spawn;
 use util::color::Color;
@@ -73,13 +75,49 @@ struct RenderBoxData {
     /* reference to containing flow context, which this box
        participates in */
     ctx  : @FlowContext,
-    /* position of this box relative to owning flow */
+    /* position of this box's border edge relative to owning flow */
     mut position : Rect<Au>,
+    /* used widths of the margins, borders and padding; see `compute_edges` */
+    mut margin : BoxEdges,
+    mut border : BoxEdges,
+    mut padding : BoxEdges,
     font_size : Length,
     /* TODO (Issue #87): debug only */
     mut id: int
 }
 
+/* The widths of a box's margins, borders or padding on each side. */
+struct BoxEdges {
+    top: Au,
+    right: Au,
+    bottom: Au,
+    left: Au
+}
+
+pure fn zero_edges() -> BoxEdges {
+    BoxEdges { top: Au(0), right: Au(0), bottom: Au(0), left: Au(0) }
+}
+
+/* Resolves a computed margin or padding against the width of the
+   containing block (CSS 2.1 Section 8.3, 8.4). */
+pure fn resolve_edge(edge: BoxSizing, cb_width: Au) -> Au {
+    match edge {
+        BoxLength(Px(px)) => au::from_frac_px(px),
+        BoxPercent(pct) => au::from_frac_px(pct / 100.0 * au::to_frac_px(cb_width)),
+        BoxCalc(calc) => resolve_calc(calc, cb_width),
+        // block layout resolves 'auto' margins of blocks (CSS 2.1 Section 10.3.3)
+        BoxAuto => Au(0),
+        BoxLength(*) => fail ~"relative lengths should have been resolved by style resolution"
+    }
+}
+
+/* Resolves a computed 'calc()' length, whose relative lengths style
+   resolution has already folded into 'px', against the length its
+   percentage refers to. */
+pure fn resolve_calc(calc: CalcLength, percent_of: Au) -> Au {
+    au::from_frac_px(calc.px + calc.percent / 100.0 * au::to_frac_px(percent_of))
+}
+
 enum RenderBoxType {
     RenderBox_Generic,
     RenderBox_Image,
@@ -121,6 +159,7 @@ trait RenderBoxMethods {
     fn split_to_width(@self, &LayoutContext, Au, starts_line: bool) -> SplitBoxResult;
     fn get_min_width(&LayoutContext) -> Au;
     fn get_pref_width(&LayoutContext) -> Au;
+    fn compute_edges(cb_width: Au);
     fn get_used_width() -> (Au, Au);
     fn get_used_height() -> (Au, Au);
     fn create_inline_spacer_for_side(&LayoutContext, InlineSpacerSide) -> Option<@RenderBox>;
@@ -133,6 +172,9 @@ fn RenderBoxData(node: Node, ctx: @FlowContext, id: int) -> RenderBoxData {
         node : node,
         mut ctx  : ctx,
         mut position : au::zero_rect(),
+        mut margin : zero_edges(),
+        mut border : zero_edges(),
+        mut padding : zero_edges(),
         font_size: Px(0.0),
         id : id
     }
@@ -190,6 +232,8 @@ impl RenderBox : RenderBoxMethods {
                 let mut remaining_width : Au = max_width;
                 let left_range = MutableRange(data.range.begin(), 0);
                 let mut right_range : Option<Range> = None;
+                // spaces that start a line are removed only where 'white-space' collapses them
+                let trims_leading = layout::text::compression_mode_for_box(self) != CompressNone;
                 debug!("split_to_width: splitting text box (strlen=%u, range=%?, avail_width=%?)",
                        data.run.text.len(), data.range, max_width);
                 do data.run.iter_indivisible_pieces_for_range(data.range) |piece_range| {
@@ -201,7 +245,7 @@ impl RenderBox : RenderBoxMethods {
 
                     if advance <= remaining_width {
                         should_continue = true;
-                        if starts_line && pieces_processed_count == 0 
+                        if starts_line && trims_leading && pieces_processed_count == 0
                             && data.run.range_is_trimmable_whitespace(piece_range) {
                             debug!("split_to_width: case=skipping leading trimmable whitespace");
                             left_range.shift_by(piece_range.length() as int); 
@@ -266,7 +310,11 @@ impl RenderBox : RenderBoxMethods {
             // TODO: consult CSS 'width', margin, border.
             // TODO: If image isn't available, consult 'width'.
             ImageBox(_,i) => au::from_px(i.get_size().get_default(Size2D(0,0)).width),
-            TextBox(_,d) => d.run.min_width_for_range(d.range),
+            TextBox(ref b, ref d) => if b.node.computed_style().white_space.wraps() {
+                d.run.min_width_for_range(d.range)
+            } else {
+                d.run.metrics_for_range(d.range).advance_width
+            },
             UnscannedTextBox(*) => fail ~"Shouldn't see unscanned boxes here."
         }
     }
@@ -303,22 +351,46 @@ impl RenderBox : RenderBoxMethods {
         }
     }
 
-    /* Returns the amount of left, right "fringe" used by this
-    box. This should be based on margin, border, padding, width. */
-    fn get_used_width() -> (Au, Au) {
-        // TODO: this should actually do some computation!
-        // See CSS 2.1, Section 10.3, 10.4.
+    /* Computes the used widths of this box's margins, borders and
+    padding. Percentages refer to the width of the containing block,
+    even for the top and bottom sides. */
+    fn compute_edges(cb_width: Au) {
+        let style = self.d().node.computed_style();
+        self.d().margin = BoxEdges {
+            top: resolve_edge(style.margin_top, cb_width),
+            right: resolve_edge(style.margin_right, cb_width),
+            bottom: resolve_edge(style.margin_bottom, cb_width),
+            left: resolve_edge(style.margin_left, cb_width)
+        };
+        self.d().border = BoxEdges {
+            top: style.border_top_width,
+            right: style.border_right_width,
+            bottom: style.border_bottom_width,
+            left: style.border_left_width
+        };
+        self.d().padding = BoxEdges {
+            top: resolve_edge(style.padding_top, cb_width),
+            right: resolve_edge(style.padding_right, cb_width),
+            bottom: resolve_edge(style.padding_bottom, cb_width),
+            left: resolve_edge(style.padding_left, cb_width)
+        };
+    }
 
-        (Au(0), Au(0))
+    /* Returns the amount of left, right "fringe" used by this box:
+    the sum of its margin, border and padding on each side. Only valid
+    after `compute_edges`. See CSS 2.1, Section 10.3, 10.4. */
+    fn get_used_width() -> (Au, Au) {
+        let d = self.d();
+        (d.margin.left + d.border.left + d.padding.left,
+         d.margin.right + d.border.right + d.padding.right)
     }
     
-    /* Returns the amount of left, right "fringe" used by this
-    box. This should be based on margin, border, padding, width. */
+    /* Returns the amount of top, bottom "fringe" used by this box.
+    Only valid after `compute_edges`. See CSS 2.1, Section 10.5, 10.6. */
     fn get_used_height() -> (Au, Au) {
-        // TODO: this should actually do some computation!
-        // See CSS 2.1, Section 10.5, 10.6.
-
-        (Au(0), Au(0))
+        let d = self.d();
+        (d.margin.top + d.border.top + d.padding.top,
+         d.margin.bottom + d.border.bottom + d.padding.bottom)
     }
 
     /* Whether "spacer" boxes are needed to stand in for this DOM node */
@@ -329,32 +401,27 @@ impl RenderBox : RenderBoxMethods {
     /* The box formed by the content edge, as defined in CSS 2.1 Section 8.1.
        Coordinates are relative to the owning flow. */
     pure fn content_box() -> Rect<Au> {
+        let d = self.d();
+        let origin = Point2D(d.position.origin.x + d.border.left + d.padding.left,
+                             d.position.origin.y + d.border.top + d.padding.top);
         match self {
             ImageBox(_,i) => {
                 let size = i.size();
                 Rect {
-                    origin: copy self.d().position.origin,
+                    origin: origin,
                     size:   Size2D(au::from_px(size.width),
                                    au::from_px(size.height))
                 }
             },
-            GenericBox(*) => {
-                copy self.d().position
-                /* FIXME: The following hits an ICE for whatever reason
-
-                let origin = self.d().position.origin;
-                let size   = self.d().position.size;
-                let (offset_left, offset_right) = self.get_used_width();
-                let (offset_top, offset_bottom) = self.get_used_height();
-
+            GenericBox(*) | TextBox(*) => {
+                let size = d.position.size;
                 Rect {
-                    origin: Point2D(origin.x + offset_left, origin.y + offset_top),
-                    size:   Size2D(size.width - (offset_left + offset_right),
-                                   size.height - (offset_top + offset_bottom))
-                }*/
-            },
-            TextBox(*) => {
-                copy self.d().position
+                    origin: origin,
+                    size:   Size2D(size.width - (d.border.left + d.padding.left
+                                                 + d.padding.right + d.border.right),
+                                   size.height - (d.border.top + d.padding.top
+                                                  + d.padding.bottom + d.border.bottom))
+                }
             },
             UnscannedTextBox(*) => fail ~"Shouldn't see unscanned boxes here."
         }
@@ -363,15 +430,19 @@ impl RenderBox : RenderBoxMethods {
     /* The box formed by the border edge, as defined in CSS 2.1 Section 8.1.
        Coordinates are relative to the owning flow. */
     pure fn border_box() -> Rect<Au> {
-        // TODO: actually compute content_box + padding + border
-        self.content_box()
+        copy self.d().position
     }
 
     /* The box fromed by the margin edge, as defined in CSS 2.1 Section 8.1.
        Coordinates are relative to the owning flow. */
     pure fn margin_box() -> Rect<Au> {
-        // TODO: actually compute content_box + padding + border + margin
-        self.content_box()
+        let d = self.d();
+        Rect {
+            origin: Point2D(d.position.origin.x - d.margin.left,
+                            d.position.origin.y - d.margin.top),
+            size:   Size2D(d.position.size.width + d.margin.left + d.margin.right,
+                           d.position.size.height + d.margin.top + d.margin.bottom)
+        }
     }
 
     // TODO: implement this, generating spacer 
@@ -400,17 +471,11 @@ impl RenderBox : RenderBoxMethods {
     fn build_display_list(@self, builder: &dl::DisplayListBuilder, dirty: &Rect<Au>,
                           offset: &Point2D<Au>, list: &dl::DisplayList) {
 
-        let style = self.d().node.style();
+        let style = self.d().node.computed_style();
         let box_bounds : Rect<Au> = match style.position {
-            Specified(PosAbsolute) => {
-                let x_offset = match style.left {
-                    Specified(Px(px)) => au::from_frac_px(px),
-                    _ => self.d().position.origin.x
-                };
-                let y_offset = match style.top {
-                    Specified(Px(px)) => au::from_frac_px(px),
-                    _ => self.d().position.origin.y
-                };
+            PosAbsolute => {
+                let x_offset = style.left.get_default(self.d().position.origin.x);
+                let y_offset = style.top.get_default(self.d().position.origin.y);
                 Rect(Point2D(x_offset, y_offset), copy self.d().position.size)
             }
             _ => {
@@ -429,65 +494,71 @@ impl RenderBox : RenderBoxMethods {
             return;
         }
 
+        // the content box, moved along with the border box
+        let content_box = self.content_box();
+        let inset_x = content_box.origin.x - self.d().position.origin.x;
+        let inset_y = content_box.origin.y - self.d().position.origin.y;
+        let abs_content_box = Rect(Point2D(abs_box_bounds.origin.x + inset_x,
+                                           abs_box_bounds.origin.y + inset_y),
+                                   content_box.size);
+
         self.add_bgcolor_to_list(list, &abs_box_bounds); 
 
         match *self {
             UnscannedTextBox(*) => fail ~"Shouldn't see unscanned boxes here.",
             TextBox(_,d) => {
-                list.append_item(~dl::Text(copy abs_box_bounds, text_run::serialize(builder.ctx.font_cache, d.run),
-                                           d.range))
+                list.append_item(~dl::Text(copy abs_content_box, text_run::serialize(builder.ctx.font_cache, d.run),
+                                           d.range, d.expansion))
             },
             // TODO: items for background, border, outline
             GenericBox(_) => {
             },
             ImageBox(_,i) => {
                 match i.get_image() {
-                    Some(image) => list.append_item(~dl::Image(copy abs_box_bounds, arc::clone(&image))),
+                    Some(image) => list.append_item(~dl::Image(copy abs_content_box, arc::clone(&image))),
                     /* No image data at all? Okay, add some fallback content instead. */
                     None => ()
                 }
             }
         }
 
-        self.add_border_to_list(list, abs_box_bounds);
+        self.add_border_to_list(list, &abs_box_bounds);
     }
 
     fn add_bgcolor_to_list(list: &dl::DisplayList, abs_bounds: &Rect<Au>) {
         use std::cmp::FuzzyEq;
-        // TODO: shouldn't need to unbox CSSValue by now
-        let boxed_bgcolor = self.d().node.style().background_color;
-        let bgcolor = match boxed_bgcolor {
-            Specified(BgColor(c)) => c,
-            Specified(BgColorTransparent) | _ => util::color::rgba(0,0,0,0.0)
+        let bgcolor = match self.d().node.computed_style().background_color {
+            BgColor(c) => c,
+            BgColorTransparent => util::color::rgba(0,0,0,0.0)
         };
         if !bgcolor.alpha.fuzzy_eq(&0.0) {
             list.append_item(~dl::SolidColor(copy *abs_bounds, bgcolor.red, bgcolor.green, bgcolor.blue));
         }
     }
 
-    fn add_border_to_list(list: &dl::DisplayList, abs_bounds: Rect<Au>) {
-        let style = self.d().node.style();
-        match style.border_width {
-            Specified(Px(px)) => {
-                // If there's a border, let's try to display *something*
-                let border_width = au::from_frac_px(px);
-                let abs_bounds = Rect {
-                    origin: Point2D {
-                        x: abs_bounds.origin.x - border_width / Au(2),
-                        y: abs_bounds.origin.y - border_width / Au(2),
-                    },
-                    size: Size2D {
-                        width: abs_bounds.size.width + border_width,
-                        height: abs_bounds.size.height + border_width
-                    }
-                };
-                let color = match style.border_color {
-                    Specified(BdrColor(color)) => color,
-                    _ => rgb(0, 0, 0) // FIXME
-                };
-                list.push(~dl::Border(abs_bounds, border_width, color.red, color.green, color.blue));
+    /* Draws each side of the border as a solid rectangle inside the border edge.
+       TODO: honor the other border styles, and draw the corners as mitres. */
+    fn add_border_to_list(list: &dl::DisplayList, abs_bounds: &Rect<Au>) {
+        use std::cmp::FuzzyEq;
+        let style = self.d().node.computed_style();
+        let origin = abs_bounds.origin, size = abs_bounds.size;
+        let top = style.border_top_width, right = style.border_right_width;
+        let bottom = style.border_bottom_width, left = style.border_left_width;
+
+        let sides = ~[
+            (Rect(origin, Size2D(size.width, top)), style.border_top_color),
+            (Rect(Point2D(origin.x + size.width - right, origin.y), Size2D(right, size.height)),
+             style.border_right_color),
+            (Rect(Point2D(origin.x, origin.y + size.height - bottom), Size2D(size.width, bottom)),
+             style.border_bottom_color),
+            (Rect(origin, Size2D(left, size.height)), style.border_left_color)
+        ];
+        for sides.each |side| {
+            let (bounds, color) = *side;
+            if bounds.size.width > Au(0) && bounds.size.height > Au(0)
+                && !color.alpha.fuzzy_eq(&0.0) {
+                list.append_item(~dl::SolidColor(bounds, color.red, color.green, color.blue));
             }
-            _ => () // TODO
         }
     }
 }
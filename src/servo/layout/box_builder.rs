@@ -1,9 +1,9 @@
 /** Creates CSS boxes from a DOM. */
 use au = gfx::geometry;
 use core::dvec::DVec;
-use css::styles::{SpecifiedStyle, empty_style_for_node_kind};
-use css::values::{CSSDisplay, DisplayBlock, DisplayInline, DisplayInlineBlock, DisplayNone};
-use css::values::{Inherit, Initial, Specified};
+use css::styles::empty_style_for_node_kind;
+use css::values::{CSSDisplay, DisplayBlock, DisplayInline, DisplayInlineBlock, DisplayListItem,
+                  DisplayNone};
 use dom::element::*;
 use dom::node::{Comment, Doctype, Element, Text, Node, LayoutData};
 use image::holder::ImageHolder;
@@ -91,8 +91,11 @@ impl BuilderContext {
     fn containing_context_for_display(display: CSSDisplay,
                                       builder: &LayoutTreeBuilder) -> BuilderContext {
         match (display, self.flow) { 
-            (DisplayBlock, @RootFlow(*)) => self.create_child_flow_of_type(Flow_Block, builder),
-            (DisplayBlock, @BlockFlow(*)) => {
+            // TODO: generate list item markers
+            (DisplayBlock, @RootFlow(*)) | (DisplayListItem, @RootFlow(*)) => {
+                self.create_child_flow_of_type(Flow_Block, builder)
+            },
+            (DisplayBlock, @BlockFlow(*)) | (DisplayListItem, @BlockFlow(*)) => {
                 self.clear_inline_collector();
                 self.create_child_flow_of_type(Flow_Block, builder)
             },
@@ -113,20 +116,28 @@ impl LayoutTreeBuilder {
     /** Creates necessary box(es) and flow context(s) for the current DOM node,
     and recurses on its children. */
     fn construct_recursively(layout_ctx: &LayoutContext, cur_node: Node, parent_ctx: &BuilderContext) {
-        let style = cur_node.style();
+        let style = cur_node.computed_style();
         // DEBUG
         debug!("Considering node: %?", fmt!("%?", cur_node.read(|n| copy n.kind )));
 
-        // TODO: remove this once UA styles work
+        // doctypes and comments are never rendered
+        let renderable = do cur_node.read |n| {
+            match n.kind {
+                ~Doctype(*) | ~Comment(*) => false,
+                _ => true
+            }
+        };
+        if !renderable { return; }
+
         // TODO: handle interactions with 'float', 'position' (CSS 2.1, Section 9.7)
-        let simulated_display = match self.simulate_UA_display_rules(cur_node, &style) {
+        let display = match style.display_type {
             DisplayNone => return, // tree ends here if 'display: none'
             v => v
         };
 
         // first, determine the box type, based on node characteristics
-        let box_type = self.decide_box_type(cur_node, simulated_display);
-        let this_ctx = parent_ctx.containing_context_for_display(simulated_display, &self);
+        let box_type = self.decide_box_type(cur_node, display);
+        let this_ctx = parent_ctx.containing_context_for_display(display, &self);
         let new_box = self.make_box(layout_ctx, box_type, cur_node, this_ctx.flow);
         this_ctx.consumer.push_box(layout_ctx, new_box);
 
@@ -212,34 +223,6 @@ impl LayoutTreeBuilder {
         fail ~"TODO: handle case where an inline is split by a block"
     }
 
-    priv fn simulate_UA_display_rules(node: Node, style: &SpecifiedStyle) -> CSSDisplay {
-        let resolved = match style.display_type {
-            Inherit | Initial => DisplayInline, // TODO: remove once resolve works
-            Specified(v) => v
-        };
-
-        if (resolved == DisplayNone) { return resolved; }
-
-        do node.read |n| {
-            match n.kind {
-                ~Doctype(*) | ~Comment(*) => DisplayNone,
-                ~Text(*) => DisplayInline,
-                ~Element(e) => match e.kind {
-                    ~HTMLHeadElement(*) => DisplayNone,
-                    ~HTMLScriptElement(*) => DisplayNone,
-                    ~HTMLParagraphElement(*) => DisplayBlock,
-                    ~HTMLDivElement(*) => DisplayBlock,
-                    ~HTMLBodyElement(*) => DisplayBlock,
-                    ~HTMLHeadingElement(*) => DisplayBlock,
-                    ~HTMLHtmlElement(*) => DisplayBlock,
-                    ~HTMLUListElement(*) => DisplayBlock,
-                    ~HTMLOListElement(*) => DisplayBlock,
-                    _ => resolved
-                }
-            }
-        }
-    }
-
     /** entry point for box creation. Should only be 
     called on root DOM element. */
     fn construct_trees(layout_ctx: &LayoutContext, root: Node) -> Result<@FlowContext, ()> {
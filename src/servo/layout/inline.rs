@@ -1,7 +1,9 @@
 use au = gfx::geometry;
 use core::dlist::DList;
 use core::dvec::DVec;
-use css::values::{BoxAuto, BoxLength, Px};
+use css::values::{BoxAuto, BoxLength, Px, DirectionLtr, DirectionRtl, CSSTextAlign,
+                  TextAlignLeft, TextAlignRight, TextAlignCenter, TextAlignJustify,
+                  TextAlignStart, TextAlignEnd};
 use dl = gfx::display_list;
 use dom::node::Node;
 use geom::point::Point2D;
@@ -10,9 +12,10 @@ use geom::size::Size2D;
 use gfx::geometry::Au;
 use layout::box::*;
 use layout::context::LayoutContext;
-use layout::flow::{FlowContext, InlineFlow};
-use layout::text::TextBoxData;
+use layout::flow::{FlowContext, FlowTree, InlineFlow, BlockFlow, RootFlow};
+use layout::text::{TextBoxData, font_descriptor_for_box, compression_mode_for_box};
 use num::Num;
+use servo_text::bidi::{Level, is_rtl, resolve_levels, visual_order};
 use servo_text::text_run::TextRun;
 use servo_text::util::*;
 use std::arc;
@@ -52,17 +55,64 @@ impl NodeRange {
     }
 }
 
+/**
+Calls `f` with the node of the block that contains an inline flow, whose
+style sets the direction, alignment and indent of the flow's lines.
+*/
+fn with_block_node(flow: @FlowContext, f: fn&(Node)) {
+    do tree::parent(&FlowTree, &flow).iter |parent| {
+        match *parent {
+            @BlockFlow(*) | @RootFlow(*) => do parent.with_block_box |box| {
+                f(box.d().node);
+            },
+            _ => {}
+        }
+    }
+}
+
+/**
+The embedding level of the paragraph that an inline flow lays out: 1 if
+the block that contains it has 'direction: rtl', and 0 otherwise.
+*/
+fn paragraph_level(flow: @FlowContext) -> Level {
+    let mut level = 0u8;
+    do with_block_node(flow) |node| {
+        level = match node.computed_style().direction {
+            DirectionLtr => 0u8,
+            DirectionRtl => 1u8
+        };
+    }
+    level
+}
+
+/**
+The indent of the first line of an inline flow, given the width that a
+percentage 'text-indent' refers to. 'text-indent' is only for the first
+line of the block, not of any flow after a child.
+*/
+fn first_line_indent(flow: @FlowContext, width: Au) -> Au {
+    let mut indent = Au(0);
+    if tree::prev_sibling(&FlowTree, &flow).is_none() {
+        do with_block_node(flow) |node| {
+            indent = resolve_edge(node.computed_style().text_indent, width);
+        }
+    }
+    indent
+}
+
 // stack-allocated object for scanning an inline flow into
 // TextRun-containing TextBoxes.
 struct TextRunScanner {
     clump: MutableRange,
     flow: @FlowContext,
+    paragraph_level: Level,
 }
 
 fn TextRunScanner(flow: @FlowContext) -> TextRunScanner {
     TextRunScanner {
         clump: util::range::empty_mut(),
         flow: flow,
+        paragraph_level: paragraph_level(flow),
     }
 }
 
@@ -73,17 +123,18 @@ impl TextRunScanner {
         do self.flow.inline().boxes.swap |in_boxes| {
             debug!("TextRunScanner: scanning %u boxes for text runs...", in_boxes.len());
             let out_boxes = DVec();
+            let (texts, levels) = self.resolve_flow_levels(in_boxes);
 
             for uint::range(0, in_boxes.len()) |box_i| {
                 debug!("TextRunScanner: considering box: %?", in_boxes[box_i].debug_str());
                 if box_i > 0 && !can_coalesce_text_nodes(in_boxes, box_i-1, box_i) {
-                    self.flush_clump_to_list(ctx, in_boxes, &out_boxes);
+                    self.flush_clump_to_list(ctx, in_boxes, texts, levels, &out_boxes);
                 }
                 self.clump.extend_by(1);
             }
             // handle remaining clumps
             if self.clump.length() > 0 {
-                self.flush_clump_to_list(ctx, in_boxes, &out_boxes);
+                self.flush_clump_to_list(ctx, in_boxes, texts, levels, &out_boxes);
             }
 
             debug!("TextRunScanner: swapping out boxes.");
@@ -93,20 +144,66 @@ impl TextRunScanner {
         }
 
         // helper functions
-        pure fn can_coalesce_text_nodes(boxes: &[@RenderBox], left_i: uint, right_i: uint) -> bool {
+        fn can_coalesce_text_nodes(boxes: &[@RenderBox], left_i: uint, right_i: uint) -> bool {
             assert left_i >= 0 && left_i < boxes.len();
             assert right_i > 0 && right_i < boxes.len();
             assert left_i != right_i;
 
             let (left, right) = (boxes[left_i], boxes[right_i]);
             match (left, right) {
-                // TODO(Issue #117): check whether text styles, fonts are the same.
-                (@UnscannedTextBox(*), @UnscannedTextBox(*)) => left.can_merge_with_box(right),
+                // TODO(Issue #117): check whether the other text styles are the same.
+                (@UnscannedTextBox(*), @UnscannedTextBox(*)) => {
+                    left.can_merge_with_box(right)
+                        && font_descriptor_for_box(left) == font_descriptor_for_box(right)
+                        && left.d().node.computed_style().white_space
+                            == right.d().node.computed_style().white_space
+                }
                 (_, _) => false
             }
         }
     }
 
+    // transforms the text of each text box, and resolves the embedding level of each of
+    // its bytes over the text of the whole flow, where each other box stands for an object
+    // replacement character (UAX #9 rule P1 and HTML's treatment of inline elements).
+    priv fn resolve_flow_levels(in_boxes: &[@RenderBox]) -> (~[~str], ~[~[Level]]) {
+        let texts = do in_boxes.map |box| {
+            // TODO(Issue #113): we shoud be passing compression context
+            // between calls to transform_text, so that boxes
+            // starting/ending with whitespace &c can be
+            // compressed correctly w.r.t. the TextRun.
+            match *box {
+                @UnscannedTextBox(*) => transform_text(box.raw_text(),
+                                                       compression_mode_for_box(*box)),
+                _ => ~""
+            }
+        };
+
+        let mut flow_text = ~"";
+        for in_boxes.eachi |i, box| {
+            match *box {
+                @UnscannedTextBox(*) => str::push_str(&mut flow_text, texts[i]),
+                _ => str::push_char(&mut flow_text, '\ufffc')
+            }
+        }
+        let flow_levels = resolve_levels(flow_text, self.paragraph_level);
+
+        let mut start = 0;
+        let levels = do in_boxes.mapi |i, box| {
+            let length = match *box {
+                @UnscannedTextBox(*) => texts[i].len(),
+                _ => str::len("\ufffc")
+            };
+            let box_levels = vec::slice(flow_levels, start, start + length);
+            start += length;
+            match *box {
+                @UnscannedTextBox(*) => move box_levels,
+                _ => ~[]
+            }
+        };
+        (move texts, move levels)
+    }
+
     // a 'clump' is a range of inline flow leaves that can be merged
     // together into a single RenderBox. Adjacent text with the same
     // style can be merged, and nothing else can. 
@@ -119,8 +216,11 @@ impl TextRunScanner {
     // N.B. in_boxes is passed by reference, since we cannot
     // recursively borrow or swap the flow's dvec of boxes. When all
     // boxes are appended, the caller swaps the flow's box list.
-    fn flush_clump_to_list(ctx: &LayoutContext, 
-                           in_boxes: &[@RenderBox], out_boxes: &DVec<@RenderBox>) {
+    //
+    // `texts` and `levels` hold the transformed text of each box, and the embedding level
+    // of each of its bytes; see resolve_flow_levels().
+    fn flush_clump_to_list(ctx: &LayoutContext, in_boxes: &[@RenderBox], texts: &[~str],
+                           levels: &[~[Level]], out_boxes: &DVec<@RenderBox>) {
         assert self.clump.length() > 0;
 
         debug!("TextRunScanner: flushing boxes in range=%?", self.clump);
@@ -137,64 +237,107 @@ impl TextRunScanner {
                 out_boxes.push(in_boxes[self.clump.begin()]);
             },
             (true, true)  => { 
-                let text = in_boxes[self.clump.begin()].raw_text();
-                // TODO(Issue #115): use actual CSS 'white-space' property of relevant style.
-                let compression = CompressWhitespaceNewline;
-                let transformed_text = transform_text(text, compression);
-                // TODO(Issue #116): use actual font for corresponding DOM node to create text run.
-                let run = @TextRun(ctx.font_cache.get_test_font(), move transformed_text);
+                let desc = font_descriptor_for_box(in_boxes[self.clump.begin()]);
+                let run = @TextRun(ctx.font_cache, &desc, copy texts[self.clump.begin()],
+                                   levels[self.clump.begin()]);
                 debug!("TextRunScanner: pushing single text box in range: %?", self.clump);
-                let new_box = layout::text::adapt_textbox_with_range(in_boxes[self.clump.begin()].d(), run,
-                                                                     Range(0, run.text.len()));
-                out_boxes.push(new_box);
+                self.push_text_boxes(in_boxes[self.clump.begin()], run, Range(0, run.text.len()),
+                                     out_boxes);
             },
             (false, true) => {
-                // TODO(Issue #115): use actual CSS 'white-space' property of relevant style.
-                let compression = CompressWhitespaceNewline;
-
-                // first, transform/compress text of all the nodes
-                let transformed_strs : ~[~str] = vec::from_fn(self.clump.length(), |i| {
-                    // TODO(Issue #113): we shoud be passing compression context
-                    // between calls to transform_text, so that boxes
-                    // starting/ending with whitespace &c can be
-                    // compressed correctly w.r.t. the TextRun.
-                    let idx = i + self.clump.begin();
-                    transform_text(in_boxes[idx].raw_text(), compression)
-                });
-
-                // next, concatenate all of the transformed strings together, saving the new text indices
+                // concatenate the transformed strings of the boxes, saving the new text indices
 
                 // TODO(Issue #118): use a rope, simply give ownership of  nonzero strs to rope
                 let mut run_str : ~str = ~"";
+                let mut run_levels : ~[Level] = ~[];
                 let new_ranges : DVec<Range> = DVec();
-                for uint::range(0, transformed_strs.len()) |i| {
-                    // XXX: if transformed_strs[i].len() == 0 { loop }
-                    new_ranges.push(Range(run_str.len(), transformed_strs[i].len()));
-                    str::push_str(&mut run_str, transformed_strs[i]);
+                for self.clump.eachi |i| {
+                    // XXX: if texts[i].len() == 0 { loop }
+                    new_ranges.push(Range(run_str.len(), texts[i].len()));
+                    str::push_str(&mut run_str, texts[i]);
+                    vec::push_all(&mut run_levels, levels[i]);
                 }
 
                 // TODO: adjust containing ranges to account for any elided boxes (see XXX above, below)
                 
                 // create the run, then make new boxes with the run and adjusted text indices
 
-                // TODO(Issue #116): use actual font for corresponding DOM node to create text run.
-                let run = @TextRun(ctx.font_cache.get_test_font(), move run_str);
+                // the clump's boxes all have the same font; see can_coalesce_text_nodes()
+                let desc = font_descriptor_for_box(in_boxes[self.clump.begin()]);
+                let run = @TextRun(ctx.font_cache, &desc, move run_str, run_levels);
                 debug!("TextRunScanner: pushing box(es) in range: %?", self.clump);
                 for self.clump.eachi |i| {
                     let range = new_ranges[i - self.clump.begin()];
                     if range.length() == 0 { loop } // XXX
-                    let new_box = layout::text::adapt_textbox_with_range(in_boxes[i].d(), run, range);
-                    out_boxes.push(new_box);
+                    self.push_text_boxes(in_boxes[i], run, range, out_boxes);
                 }
             }
         } /* /match */
     
         self.clump.reset(self.clump.end(), 0);
     } /* /fn flush_clump_to_list */
+
+    // pushes a text box for each part of a box's range that is at one embedding level, so
+    // that LineboxScanner can reorder them, and one for each preserved newline, which
+    // LineboxScanner ends a line after.
+    priv fn push_text_boxes(in_box: @RenderBox, run: @TextRun, range: Range,
+                            out_boxes: &DVec<@RenderBox>) {
+        if range.length() == 0 {
+            out_boxes.push(layout::text::adapt_textbox_with_range(in_box.d(), run, range));
+            return;
+        }
+        let mut line_start = range.begin();
+        for range.eachi |i| {
+            if run.glyphs.char_is_newline(i) {
+                push_level_runs(in_box, run, Range(line_start, i - line_start), out_boxes);
+                out_boxes.push(layout::text::adapt_textbox_with_range(in_box.d(), run,
+                                                                      Range(i, 1)));
+                line_start = i + 1;
+            }
+        }
+        push_level_runs(in_box, run, Range(line_start, range.end() - line_start), out_boxes);
+
+        fn push_level_runs(in_box: @RenderBox, run: @TextRun, range: Range,
+                           out_boxes: &DVec<@RenderBox>) {
+            if range.length() == 0 { return; }
+            for run.iter_level_runs_for_range(range) |_level, level_range| {
+                out_boxes.push(layout::text::adapt_textbox_with_range(in_box.d(), run,
+                                                                      level_range));
+            }
+        }
+    }
+}
+
+// whether a box holds a newline that 'white-space' preserved, which ends its line
+fn is_preserved_newline(box: @RenderBox) -> bool {
+    match box {
+        @TextBox(_, data) => data.range.length() == 1
+            && data.run.glyphs.char_is_newline(data.range.begin()),
+        _ => false
+    }
+}
+
+// whether a line may break between two boxes that follow each other in a flow
+fn can_break_between(prev_box: @RenderBox, box: @RenderBox) -> bool {
+    match box {
+        @TextBox(_, data) if !data.run.can_break_before(data.range.begin()) => false,
+        @TextBox(*) if !box.d().node.computed_style().white_space.wraps() => {
+            // text that doesn't wrap only goes to a new line after text that does
+            match prev_box {
+                @TextBox(*) => prev_box.d().node.computed_style().white_space.wraps(),
+                _ => true
+            }
+        },
+        _ => true
+    }
 }
 
 struct LineboxScanner {
     flow: @FlowContext,
+    paragraph_level: Level,
+    text_align: CSSTextAlign,
+    // the indent of the first line, if the flow starts its block
+    first_line_indent: Au,
     new_boxes: DVec<@RenderBox>,
     work_list: DList<@RenderBox>,
     pending_line: {range: MutableRange, mut width: Au},
@@ -208,8 +351,16 @@ struct LineboxScanner {
 fn LineboxScanner(inline: @FlowContext) -> LineboxScanner {
     assert inline.starts_inline_flow();
 
+    let mut text_align = TextAlignStart;
+    do with_block_node(inline) |node| {
+        text_align = node.computed_style().text_align;
+    }
+
     LineboxScanner {
         flow: inline,
+        paragraph_level: paragraph_level(inline),
+        text_align: text_align,
+        first_line_indent: first_line_indent(inline, inline.d().position.size.width),
         new_boxes: DVec(),
         work_list: DList(),
         pending_line: {range: util::range::empty_mut(), mut width: Au(0)},
@@ -255,7 +406,11 @@ impl LineboxScanner {
             if !box_was_appended {
                 debug!("LineboxScanner: Box wasn't appended, because line %u was full.",
                        self.line_spans.len());
-                self.flush_current_line();
+                self.flush_current_line(false);
+            } else if is_preserved_newline(cur_box) {
+                debug!("LineboxScanner: appended a newline, so ending line %u",
+                       self.line_spans.len());
+                self.flush_current_line(true);
             } else {
                 debug!("LineboxScanner: appended a box to line %u", self.line_spans.len());
             }
@@ -264,7 +419,7 @@ impl LineboxScanner {
         if self.pending_line.range.length() > 0 {
             debug!("LineboxScanner: Partially full linebox %u left at end of scanning.",
                    self.line_spans.len());
-            self.flush_current_line();
+            self.flush_current_line(true);
         }
 
         self.repair_elem_ranges();
@@ -349,20 +504,65 @@ impl LineboxScanner {
         debug!("----------------------------------");
     }
 
-    priv fn flush_current_line() {
+    priv fn line_indent() -> Au {
+        if self.line_spans.len() == 0 { self.first_line_indent } else { Au(0) }
+    }
+
+    // the width that the pending line has for its boxes, after any indent
+    priv fn line_width() -> Au {
+        self.flow.d().position.size.width - self.line_indent()
+    }
+
+    // `ends_paragraph` is whether the line is the last before a forced break or the flow's
+    // end, which 'text-align: justify' leaves aligned to the start.
+    priv fn flush_current_line(ends_paragraph: bool) {
         debug!("LineboxScanner: Flushing line %u: %?",
                self.line_spans.len(), self.pending_line);
-        // set box horizontal offsets
         let line_range = self.pending_line.range.as_immutable();
-        let mut offset_x = Au(0);
-        // TODO: interpretation of CSS 'text-direction' and 'text-align' 
-        // will change from which side we start laying out the line.
+        let rtl = is_rtl(self.paragraph_level);
+        let mut slack = au::max(Au(0), self.line_width() - self.pending_line.width);
+
+        if self.text_align == TextAlignJustify && !ends_paragraph && slack > Au(0) {
+            self.justify_line(line_range, slack);
+            slack = au::max(Au(0), self.line_width() - self.pending_line.width);
+        }
+
+        // set box horizontal offsets, from the side that 'text-align' puts the line at
+        let align_offset = match self.text_align {
+            TextAlignLeft => Au(0),
+            TextAlignRight => slack,
+            TextAlignCenter => Au(*slack / 2),
+            TextAlignStart | TextAlignJustify => if rtl { slack } else { Au(0) },
+            TextAlignEnd => if rtl { Au(0) } else { slack }
+        };
+        // the indent is on the side lines start at, so it only moves left-to-right lines
+        let mut offset_x = if rtl { align_offset } else { align_offset + self.line_indent() };
+        // boxes are laid out in the order their embedding levels give (UAX #9 rule L2);
+        // each text box is at one level, and other boxes are at the paragraph's.
+        let levels = vec::to_mut(vec::from_fn(line_range.length(), |j| {
+            match self.new_boxes[line_range.begin() + j] {
+                @TextBox(_, data) => data.run.level_for_range(data.range),
+                _ => self.paragraph_level
+            }
+        }));
+        // the whitespace that ends the line goes back to the paragraph's level (rule L1)
+        let mut j = line_range.length();
+        while j > 0 {
+            j -= 1;
+            match self.new_boxes[line_range.begin() + j] {
+                @TextBox(_, data) if data.run.range_is_trimmable_whitespace(data.range) => {
+                    levels[j] = self.paragraph_level;
+                },
+                _ => break
+            }
+        }
         debug!("LineboxScanner: Setting horizontal offsets for boxes in line %u range: %?",
                self.line_spans.len(), line_range);
-        for line_range.eachi |i| {
-            let box_data = &self.new_boxes[i].d();
+        for visual_order(levels).each |j| {
+            let box_data = &self.new_boxes[line_range.begin() + *j].d();
+            offset_x += box_data.margin.left;
             box_data.position.origin.x = offset_x;
-            offset_x += box_data.position.size.width;
+            offset_x += box_data.position.size.width + box_data.margin.right;
         }
 
         // clear line and add line mapping
@@ -371,11 +571,51 @@ impl LineboxScanner {
         self.reset_linebox();
     }
 
+    // widens the text boxes of a line by spreading `slack` over their expansion
+    // opportunities, leaving out the whitespace that ends the line.
+    priv fn justify_line(line_range: Range, slack: Au) {
+        let last_j = line_range.length() - 1;
+        let counts = vec::from_fn(line_range.length(), |j| {
+            match self.new_boxes[line_range.begin() + j] {
+                @TextBox(_, data) => {
+                    let mut end = data.range.end();
+                    if j == last_j {
+                        // a no-break space isn't collapsible, so it keeps its expansion
+                        while end > data.range.begin()
+                            && data.run.range_is_trimmable_whitespace(Range(end - 1, 1)) {
+                            end -= 1;
+                        }
+                    }
+                    let range = Range(data.range.begin(), end - data.range.begin());
+                    data.run.expansion_opportunities_for_range(range)
+                },
+                _ => 0
+            }
+        });
+        let total = vec::foldl(0, counts, |a, b| a + *b);
+        if total == 0 { return; }
+
+        let expansion = Au(*slack / (total as i32));
+        debug!("LineboxScanner: Justifying line %u with %? after each of %u opportunities",
+               self.line_spans.len(), expansion, total);
+        for uint::range(0, line_range.length()) |j| {
+            match self.new_boxes[line_range.begin() + j] {
+                @TextBox(ref box_data, ref text_data) => {
+                    let added = Au(*expansion * (counts[j] as i32));
+                    text_data.expansion = expansion;
+                    box_data.position.size.width += added;
+                    self.pending_line.width += added;
+                },
+                _ => {}
+            }
+        }
+    }
+
     // return value: whether any box was appended.
     priv fn try_append_to_line(ctx: &LayoutContext, in_box: @RenderBox) -> bool {
-        let remaining_width = self.flow.d().position.size.width - self.pending_line.width;
-        let in_box_width = in_box.d().position.size.width;
-        let line_is_empty: bool = self.pending_line.range.length() == 0;
+        let remaining_width = self.line_width() - self.pending_line.width;
+        let in_box_width = in_box.margin_box().size.width;
+        let starts_line: bool = self.pending_line.range.length() == 0;
 
         debug!("LineboxScanner: Trying to append box to line %u (box width: %?, remaining width: %?): %s",
                self.line_spans.len(), in_box_width, remaining_width, in_box.debug_str());
@@ -386,10 +626,10 @@ impl LineboxScanner {
             return true;
         }
 
-        if !in_box.can_split() {
+        if !in_box.can_split() || !in_box.d().node.computed_style().white_space.wraps() {
             // force it onto the line anyway, if its otherwise empty
             // TODO: signal that horizontal overflow happened?
-            if line_is_empty {
+            if starts_line || !self.defer_to_next_line(in_box) {
                 debug!("LineboxScanner: case=box can't split and line %u is empty, so overflowing.",
                       self.line_spans.len());
                 self.push_box_to_line(in_box);
@@ -401,7 +641,7 @@ impl LineboxScanner {
         }
 
         // not enough width; try splitting?
-        match in_box.split_to_width(ctx, remaining_width, line_is_empty) {
+        match in_box.split_to_width(ctx, remaining_width, starts_line) {
             CannotSplit(_) => {
                 error!("LineboxScanner: Tried to split unsplittable render box! %s", in_box.debug_str());
                 return false;
@@ -422,7 +662,7 @@ impl LineboxScanner {
                 return true;
             },
             SplitDidNotFit(left, right) => {
-                if line_is_empty {
+                if starts_line || !self.defer_to_next_line(in_box) {
                     debug!("LineboxScanner: case=split box didn't fit and line %u is empty, so overflowing and deferring remainder box.",
                           self.line_spans.len());
                     // TODO: signal that horizontal overflow happened?
@@ -444,13 +684,71 @@ impl LineboxScanner {
                     return true;
                 } else {
                     debug!("LineboxScanner: case=split box didn't fit, not appending and deferring original box.");
-                    self.work_list.push_head(in_box);
                     return false;
                 }
             }
         }
     }
 
+    // whether the pending line may end before a box, rather than the box continuing it
+    priv fn can_break_before_box(in_box: @RenderBox) -> bool {
+        can_break_between(self.new_boxes[self.pending_line.range.end() - 1], in_box)
+    }
+
+    // puts a box that doesn't fit on the pending line back on the work list. A box that the
+    // line can't break before takes the boxes after the line's last break opportunity with it,
+    // splitting the box that the opportunity is in; if the line has none, returns false and
+    // the box has to overflow the line.
+    priv fn defer_to_next_line(in_box: @RenderBox) -> bool {
+        if self.can_break_before_box(in_box) {
+            self.work_list.push_head(in_box);
+            return true;
+        }
+
+        let line_begin = self.pending_line.range.begin();
+        let mut j = self.pending_line.range.end();
+        while j > line_begin {
+            j -= 1;
+            let box = self.new_boxes[j];
+            match box {
+                @TextBox(_, data) if box.d().node.computed_style().white_space.wraps() => {
+                    let mut i = data.range.end();
+                    while i > data.range.begin() + 1 {
+                        i -= 1;
+                        if !data.run.can_break_before(i) { loop; }
+                        debug!("LineboxScanner: backing up to break inside box b%d", box.d().id);
+                        let begin = data.range.begin(), end = data.range.end();
+                        let left = layout::text::adapt_textbox_with_range(box.d(), data.run,
+                                                                          Range(begin, i - begin));
+                        let right = layout::text::adapt_textbox_with_range(box.d(), data.run,
+                                                                           Range(i, end - i));
+                        self.requeue_boxes_from(j + 1, in_box);
+                        self.pop_box_from_line();
+                        self.work_list.push_head(right);
+                        self.push_box_to_line(left);
+                        return true;
+                    }
+                },
+                _ => ()
+            }
+            if j > line_begin && can_break_between(self.new_boxes[j - 1], box) {
+                debug!("LineboxScanner: backing up to break before box b%d", box.d().id);
+                self.requeue_boxes_from(j, in_box);
+                return true;
+            }
+        }
+        false
+    }
+
+    // takes the boxes from the `j`th of `new_boxes` on off the pending line, and puts them
+    // back on the work list ahead of `next`
+    priv fn requeue_boxes_from(j: uint, next: @RenderBox) {
+        self.work_list.push_head(next);
+        while self.new_boxes.len() > j {
+            self.work_list.push_head(self.pop_box_from_line());
+        }
+    }
+
     // unconditional push
     priv fn push_box_to_line(box: @RenderBox) {
         debug!("LineboxScanner: Pushing box b%d to line %u", box.d().id, self.line_spans.len());
@@ -460,9 +758,16 @@ impl LineboxScanner {
             self.pending_line.range.reset(self.new_boxes.len(), 0);
         }
         self.pending_line.range.extend_by(1);
-        self.pending_line.width += box.d().position.size.width;
+        self.pending_line.width += box.margin_box().size.width;
         self.new_boxes.push(box);
     }
+
+    priv fn pop_box_from_line() -> @RenderBox {
+        let box = self.new_boxes.pop();
+        self.pending_line.range.extend_by(-1);
+        self.pending_line.width -= box.margin_box().size.width;
+        box
+    }
 }
 
 struct InlineFlowData {
@@ -506,11 +811,18 @@ impl FlowContext : InlineLayout {
 
         let mut min_width = Au(0);
         let mut pref_width = Au(0);
+        // percentages count as zero until the containing block's width is known
+        let indent = first_line_indent(self, Au(0));
 
-        for self.inline().boxes.each |box| {
+        for self.inline().boxes.eachi |i, box| {
             debug!("FlowContext[%d]: measuring %s", self.d().id, box.debug_str());
-            min_width = au::max(min_width, box.get_min_width(ctx));
-            pref_width = au::max(pref_width, box.get_pref_width(ctx));
+            box.compute_edges(Au(0));
+            let (left_used, right_used) = box.get_used_width();
+            let fringe = left_used.add(&right_used);
+            // the first box starts the first line, after the indent
+            let fringe = if i == 0 { fringe + indent } else { fringe };
+            min_width = au::max(min_width, box.get_min_width(ctx).add(&fringe));
+            pref_width = au::max(pref_width, box.get_pref_width(ctx).add(&fringe));
         }
 
         self.d().min_width = min_width;
@@ -526,11 +838,17 @@ impl FlowContext : InlineLayout {
         // initialize (content) box widths, if they haven't been
         // already. This could be combined with LineboxScanner's walk
         // over the box list, and/or put into RenderBox.
+        let cb_width = self.d().position.size.width;
         for self.inline().boxes.each |box| {
-            box.d().position.size.width = match *box {
+            box.compute_edges(cb_width);
+            let d = box.d();
+            let border_padding = d.border.left + d.padding.left + d.padding.right + d.border.right;
+            box.d().position.size.width = border_padding + match *box {
                 @ImageBox(_,img) => au::from_px(img.get_size().get_default(Size2D(0,0)).width),
-                @TextBox(*) => { /* text boxes are initialized with dimensions */
-                                   box.d().position.size.width
+                @TextBox(_, ref data) => {
+                    // text boxes are measured afresh, without the spacing of any justification
+                    data.expansion = Au(0);
+                    data.run.metrics_for_range(data.range).advance_width
                 },
                 @GenericBox(*) => au::from_px(45), /* TODO: should use CSS 'width'? */
                 _ => fail fmt!("Tried to assign width to unknown Box variant: %?", box)
@@ -563,7 +881,10 @@ impl FlowContext : InlineLayout {
                 let cur_box = boxes[box_i];
 
                 // compute box height.
-                cur_box.d().position.size.height = match cur_box {
+                let d = cur_box.d();
+                let border_padding = d.border.top + d.padding.top
+                    + d.padding.bottom + d.border.bottom;
+                cur_box.d().position.size.height = border_padding + match cur_box {
                     @ImageBox(_,img) => au::from_px(img.size().height),
                     @TextBox(*) => { /* text boxes are initialized with dimensions */
                         cur_box.d().position.size.height
@@ -577,10 +898,9 @@ impl FlowContext : InlineLayout {
                 // and then using the union of all these rects.
                 let bounding_box = match cur_box {
                     // adjust to baseline coords
-                    // TODO: account for padding, margin, border in bounding box?
                     @ImageBox(*) | @GenericBox(*) => {
-                        let box_bounds = cur_box.d().position;
-                        box_bounds.translate(&Point2D(Au(0), -cur_box.d().position.size.height))
+                        let box_bounds = cur_box.margin_box();
+                        box_bounds.translate(&Point2D(Au(0), -box_bounds.size.height))
                     },
                     // adjust bounding box metric to box's horizontal offset
                     // TODO: can we trust the leading provided by font metrics?
@@ -590,7 +910,7 @@ impl FlowContext : InlineLayout {
                     },
                     _ => fail fmt!("Tried to compute bounding box of unknown Box variant: %s", cur_box.debug_str())
                 };
-                cur_box.d().position.origin.y = cur_y;
+                cur_box.d().position.origin.y = cur_y + cur_box.d().margin.top;
                 debug!("assign_height_inline: bounding box for box b%d = %?", cur_box.d().id, bounding_box);
                 linebox_bounding_box = linebox_bounding_box.union(&bounding_box);
                 debug!("assign_height_inline: linebox bounding box = %?", linebox_bounding_box);
@@ -626,3 +946,196 @@ impl FlowContext : InlineLayout {
     }
 
 } // @FlowContext : InlineLayout
+
+#[cfg(test)]
+mod test {
+    use css::lexer::spawn_css_lexer_from_string;
+    use css::media::Device;
+    use css::parser::parse_stylesheet;
+    use css::resolve::apply::apply_style;
+    use css::resolve::bloom::BloomFilter;
+    use css::resolve::rule_hash::RuleHash;
+    use css::resolve::sharing::StyleSharingCache;
+    use css::styles::{StyleMethods, recompute_style};
+    use css::user_agent::user_agent_stylesheet;
+    use dom::element::{ElementData, ElementKind, HTMLBoldElement, HTMLItalicElement,
+                       HTMLParagraphElement};
+    use dom::node::{Element, NodeScope, Text};
+    use layout::box_builder::LayoutTreeBuilder;
+    use layout::traverse::*;
+    use resource::image_cache_task::ImageCacheTask;
+    use resource::local_image_cache::LocalImageCache;
+    use resource::resource_task::{Exit, ResourceTask};
+    use servo_text::font_cache::FontCache;
+    use std::net::url;
+
+    // Lays out a tree of nodes in a viewport `width` pixels wide, styled by the user agent
+    // style sheet and `css`, and returns its inline flows.
+    fn lay_out(root: Node, css: ~str, width: int) -> ~[@FlowContext] {
+        let resource_task = ResourceTask();
+        let image_cache_task = ImageCacheTask(resource_task);
+        let viewport = Size2D(au::from_px(width), au::from_px(600));
+        let ctx = LayoutContext {
+            font_cache: FontCache(),
+            image_cache: @LocalImageCache(image_cache_task),
+            doc_url: url::from_str(~"file:///test.html").get(),
+            screen_size: Rect(Point2D(Au(0), Au(0)), viewport)
+        };
+
+        root.initialize_style_for_subtree(&ctx, &DVec());
+        let device = Device(viewport.width, viewport.height);
+        let ua_rules = RuleHash(&arc::ARC(user_agent_stylesheet()), &device);
+        let (sheet, _) = parse_stylesheet(spawn_css_lexer_from_string(move css));
+        let rules = RuleHash(&arc::ARC(move sheet), &device);
+        recompute_style(root, &ua_rules, &rules, &BloomFilter(), &StyleSharingCache(), true);
+        apply_style(&ctx, root);
+
+        let flow_root = LayoutTreeBuilder().construct_trees(&ctx, root).get();
+        do flow_root.traverse_postorder |f| { f.bubble_widths(&ctx) }
+        do flow_root.traverse_preorder |f| { f.assign_widths(&ctx) }
+        do flow_root.traverse_postorder |f| { f.assign_height(&ctx) }
+
+        let mut flows = ~[];
+        do flow_root.traverse_preorder |f| {
+            if f.starts_inline_flow() { vec::push(&mut flows, f); }
+        }
+        image_cache_task.exit();
+        resource_task.send(Exit);
+        move flows
+    }
+
+    fn element(scope: &NodeScope, tag: ~str, kind: ~ElementKind, kids: &[Node]) -> Node {
+        let node = scope.new_node(Element(ElementData(move tag, move kind)));
+        for kids.each |kid| { scope.add_child(node, *kid); }
+        node
+    }
+
+    fn x_of(box: @RenderBox) -> Au { box.d().position.origin.x }
+
+    fn text_of(box: @RenderBox) -> ~str {
+        match box {
+            @TextBox(_, data) => str::slice(data.run.text, data.range.begin(), data.range.end()),
+            _ => fail
+        }
+    }
+
+    fn paragraph(scope: &NodeScope, text: ~str) -> Node {
+        element(scope, ~"p", ~HTMLParagraphElement, [scope.new_node(Text(move text))])
+    }
+
+    // the left and right edges of the boxes of a line
+    fn line_extent(flow: @FlowContext, line: uint) -> (Au, Au) {
+        let range = flow.inline().lines[line];
+        let boxes = flow.inline().boxes.get();
+        let mut left = x_of(boxes[range.begin()]), right = left;
+        for range.eachi |i| {
+            left = au::min(left, x_of(boxes[i]));
+            right = au::max(right, x_of(boxes[i]) + boxes[i].d().position.size.width);
+        }
+        (left, right)
+    }
+
+    #[test]
+    fn should_resolve_levels_over_differently_styled_boxes() {
+        let scope = NodeScope();
+        let bold = element(&scope, ~"b", ~HTMLBoldElement, [scope.new_node(Text(~"עולם"))]);
+        let para = element(&scope, ~"p", ~HTMLParagraphElement,
+                           [scope.new_node(Text(~"שלום ")), bold]);
+        let boxes = lay_out(para, ~"", 800)[0].inline().boxes.get();
+
+        // the space between the words is right-to-left like them, so the second word is
+        // left of the first on the line, and the space is between them
+        assert boxes.len() == 2;
+        for boxes.each |box| {
+            match *box {
+                @TextBox(_, data) => assert data.run.level_for_range(data.range) == 1u8,
+                _ => fail
+            }
+        }
+        assert x_of(boxes[1]) < x_of(boxes[0]);
+        assert x_of(boxes[1]) + boxes[1].d().position.size.width == x_of(boxes[0]);
+    }
+
+    #[test]
+    fn should_align_lines_by_text_align() {
+        let scope = NodeScope();
+        let flow = lay_out(paragraph(&scope, ~"one two"), ~"p { text-align: right }", 400)[0];
+        let width = flow.d().position.size.width;
+        let (left, right) = line_extent(flow, 0);
+        assert left > Au(0) && right == width;
+
+        let flow = lay_out(paragraph(&scope, ~"one two"), ~"p { text-align: center }", 400)[0];
+        let (left, right) = line_extent(flow, 0);
+        assert left > Au(0) && au::max(left - (width - right), (width - right) - left) <= Au(1);
+    }
+
+    #[test]
+    fn should_justify_all_lines_but_the_last() {
+        let scope = NodeScope();
+        let text = ~"one two six ten one two six ten one two six ten one two six ten";
+        let flow = lay_out(paragraph(&scope, text), ~"p { text-align: justify }", 100)[0];
+        let width = flow.d().position.size.width;
+        let lines = flow.inline().lines.len();
+        assert lines > 1;
+
+        // the share of the space that is left over is less than a pixel
+        for uint::range(0, lines - 1) |line| {
+            let (left, right) = line_extent(flow, line);
+            assert left == Au(0) && right <= width && width - right < au::from_px(1);
+        }
+    }
+
+    #[test]
+    fn should_indent_the_first_line_only() {
+        let scope = NodeScope();
+        let text = ~"one two six ten one two six ten one two six ten";
+        let flow = lay_out(paragraph(&scope, text), ~"p { text-indent: -10px }", 100)[0];
+        assert flow.inline().lines.len() > 1;
+
+        let (left, _) = line_extent(flow, 0);
+        assert left == au::from_px(-10);
+        let (left, _) = line_extent(flow, 1);
+        assert left == Au(0);
+    }
+
+    #[test]
+    fn should_keep_spaces_that_start_a_line_where_preserved() {
+        let scope = NodeScope();
+        let text = ~"  one two six ten one two six ten";
+        let flow = lay_out(paragraph(&scope, copy text), ~"p { white-space: pre-wrap }", 60)[0];
+        assert flow.inline().lines.len() > 1;
+        match flow.inline().boxes[0] {
+            @TextBox(_, data) => assert data.range.begin() == 0 && data.run.text == text,
+            _ => fail
+        }
+
+        // where spaces collapse, the one left at the start of the line is removed
+        let flow = lay_out(paragraph(&scope, copy text), ~"", 60)[0];
+        match flow.inline().boxes[0] {
+            @TextBox(_, data) => assert data.range.begin() == 1,
+            _ => fail
+        }
+    }
+
+    #[test]
+    fn should_wrap_at_the_last_break_before_a_box_that_cannot_break() {
+        fn para(scope: &NodeScope) -> Node {
+            let italic = element(scope, ~"i", ~HTMLItalicElement, [scope.new_node(Text(~"bar"))]);
+            element(scope, ~"p", ~HTMLParagraphElement,
+                    [scope.new_node(Text(~"one two foo")), italic])
+        }
+        let scope = NodeScope();
+        let boxes = lay_out(para(&scope), ~"", 800)[0].inline().boxes.get();
+        let width = au::to_frac_px(x_of(boxes[boxes.len() - 1])) as int + 1;
+
+        // "foobar" is one word, so it goes to the next line whole
+        let flow = lay_out(para(&scope), ~"", width)[0];
+        let boxes = flow.inline().boxes.get();
+        assert flow.inline().lines.len() == 2;
+        let line = flow.inline().lines[1];
+        assert line.length() == 2;
+        assert text_of(boxes[line.begin()]) == ~"foo";
+        assert text_of(boxes[line.begin() + 1]) == ~"bar";
+        assert x_of(boxes[line.begin()]) == Au(0);
+    }
+}
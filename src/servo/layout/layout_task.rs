@@ -7,7 +7,11 @@ use au = gfx::geometry;
 use au::Au;
 use content::content_task;
 use core::dvec::DVec;
+use css::media::Device;
 use css::resolve::apply::apply_style;
+use css::resolve::parallel::{StylePool, WORKER_COUNT};
+use css::resolve::rule_hash::RuleHashCache;
+use css::user_agent::user_agent_stylesheet;
 use css::values::Stylesheet;
 use dl = gfx::display_list;
 use dom::event::{Event, ReflowEvent};
@@ -75,6 +79,13 @@ struct Layout {
     from_content: comm::Port<Msg>,
 
     font_cache: @FontCache,
+    // The user agent style sheet, parsed once when the task starts
+    ua_styles: ARC<Stylesheet>,
+    // Worker tasks that match styles, each with its own copy of `ua_styles`
+    style_pool: StylePool,
+    // The indexes of the UA and the author style sheets for the window's size
+    ua_rules: RuleHashCache,
+    rules: RuleHashCache,
     // This is used to root auxilliary RCU reader data
     layout_refs: DVec<@LayoutData>
 }
@@ -83,12 +94,17 @@ fn Layout(render_task: RenderTask,
          image_cache_task: ImageCacheTask,
          from_content: comm::Port<Msg>) -> Layout {
 
+    let ua_styles = ARC(user_agent_stylesheet());
     Layout {
         render_task: render_task,
         image_cache_task: image_cache_task.clone(),
         local_image_cache: @LocalImageCache(move image_cache_task),
         from_content: from_content,
         font_cache: FontCache(),
+        style_pool: StylePool(&ua_styles, WORKER_COUNT),
+        ua_styles: move ua_styles,
+        ua_rules: RuleHashCache(),
+        rules: RuleHashCache(),
         layout_refs: DVec()
     }
 }
@@ -154,7 +170,13 @@ impl Layout {
         let layout_root: @FlowContext = do time("layout: tree construction") {
             // TODO: this is dumb. we don't need 3 separate traversals.
             node.initialize_style_for_subtree(&layout_ctx, &self.layout_refs);
-            node.recompute_style_for_subtree(&layout_ctx, &data.style);
+            // Media queries are evaluated against the window, so resizing it
+            // across a breakpoint restyles the page. The style sheets are only
+            // indexed again when the window's size or the sheets change.
+            let device = Device(screen_size.width, screen_size.height);
+            let ua_rules = self.ua_rules.rules_for(&self.ua_styles, &device);
+            let rules = self.rules.rules_for(&data.style, &device);
+            self.style_pool.recompute_style(*node, &*ua_rules, &*rules);
             /* resolve styles (convert relative values) down the node tree */
             apply_style(&layout_ctx, *node);
             
@@ -207,7 +229,7 @@ impl Layout {
         match query {
             ContentBox(node) => {
                 // TODO: extract me to a method when I get sibling arms
-                let response = match node.aux(|a| copy *a).flow {
+                let response = match node.aux(|a| a.flow) {
                     None => Err(()),
                     Some(flow) => {
                         let start_val : Option<Rect<Au>> = None;
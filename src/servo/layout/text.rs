@@ -1,18 +1,27 @@
 /** Text layout. */
 
+use css::values::{FontStyleNormal, WhiteSpaceNormal, WhiteSpacePre, WhiteSpaceNowrap,
+                  WhiteSpacePreWrap, WhiteSpacePreLine};
+use gfx::geometry::Au;
+use servo_text::font::FontDescriptor;
 use servo_text::text_run::TextRun;
+use servo_text::util::{CompressionMode, CompressNone, CompressWhitespaceAroundNewline,
+                       CompressWhitespaceNewline};
 use layout::box::{TextBox, RenderBox, RenderBoxData, UnscannedTextBox};
 use util::range::Range;
 
 pub struct TextBoxData {
     run: @TextRun,
     range: Range,
+    // the space added after each expansion opportunity when the box's line is justified
+    mut expansion: Au,
 }
 
 pub fn TextBoxData(run: @TextRun, range: Range) -> TextBoxData {
     TextBoxData {
         run: run,
         range: range,
+        expansion: Au(0),
     }
 }
 
@@ -27,6 +36,29 @@ pub fn adapt_textbox_with_range(box_data: &RenderBoxData, run: @TextRun,
     @TextBox(move new_box_data, move new_text_data)
 }
 
+/** Describes the font that a box's text is drawn in, from its node's computed style. */
+pub fn font_descriptor_for_box(box: @RenderBox) -> FontDescriptor {
+    let style = box.d().node.computed_style();
+    FontDescriptor {
+        families: style.font_family.map(|family| family.family_name()),
+        weight: style.font_weight,
+        italic: style.font_style != FontStyleNormal,
+        size: style.font_size
+    }
+}
+
+/**
+How the whitespace and newlines of a box's text are compressed, from its
+node's 'white-space' style.
+*/
+pub fn compression_mode_for_box(box: @RenderBox) -> CompressionMode {
+    match box.d().node.computed_style().white_space {
+        WhiteSpaceNormal | WhiteSpaceNowrap => CompressWhitespaceNewline,
+        WhiteSpacePre | WhiteSpacePreWrap => CompressNone,
+        WhiteSpacePreLine => CompressWhitespaceAroundNewline
+    }
+}
+
 trait UnscannedMethods {
     pure fn raw_text() -> ~str;
 }
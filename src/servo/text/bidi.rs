@@ -0,0 +1,373 @@
+/*!
+The Unicode Bidirectional Algorithm (UAX #9): finds the embedding level
+of each character of a paragraph, and the order that the parts of a
+line are drawn in.
+
+Isolates are not supported yet, and the classes of characters are those
+of the scripts and punctuation in common use rather than the whole of
+the Unicode Character Database.
+*/
+
+/** An embedding level; even levels are left-to-right and odd ones right-to-left. */
+pub type Level = u8;
+
+// the deepest embedding that explicit formatting characters can make
+const MAX_DEPTH: Level = 61u8;
+
+pub pure fn is_rtl(level: Level) -> bool { level % 2u8 == 1u8 }
+
+/** The bidirectional character types of UAX #9. */
+enum BidiClass {
+    L,   // left-to-right
+    R,   // right-to-left
+    AL,  // Arabic letter
+    EN,  // European number
+    ES,  // European number separator
+    ET,  // European number terminator
+    AN,  // Arabic number
+    CS,  // common number separator
+    NSM, // nonspacing mark
+    BN,  // boundary neutral
+    B,   // paragraph separator
+    S,   // segment separator
+    WS,  // whitespace
+    ON,  // other neutral
+    LRE, // left-to-right embedding
+    LRO, // left-to-right override
+    RLE, // right-to-left embedding
+    RLO, // right-to-left override
+    PDF  // pop directional format
+}
+
+impl BidiClass : cmp::Eq {
+    pure fn eq(other: &BidiClass) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &BidiClass) -> bool {
+        return !self.eq(other);
+    }
+}
+
+pure fn bidi_class(c: char) -> BidiClass {
+    match c as uint {
+      0x09 | 0x0B | 0x1F => S,
+      0x0A | 0x0D | 0x1C .. 0x1E | 0x85 | 0x2029 => B,
+      0x20 | 0x0C | 0x1680 | 0x2000 .. 0x200A | 0x2028 | 0x205F | 0x3000 => WS,
+      0x00 .. 0x08 | 0x0E .. 0x1B | 0x7F .. 0x9F | 0xAD | 0x200B .. 0x200D
+          | 0x2060 .. 0x206F | 0xFEFF => BN,
+      0x30 .. 0x39 | 0xB2 | 0xB3 | 0xB9 | 0x06F0 .. 0x06F9 | 0x2070 .. 0x2079
+          | 0x2080 .. 0x2089 | 0xFF10 .. 0xFF19 => EN,
+      0x2B | 0x2D | 0x207A | 0x207B | 0x208A | 0x208B | 0x2212 | 0xFE62 | 0xFE63
+          | 0xFF0B | 0xFF0D => ES,
+      0x23 .. 0x25 | 0xA2 .. 0xA5 | 0xB0 | 0xB1 | 0x066A | 0x2030 .. 0x2034
+          | 0x20A0 .. 0x20CF | 0x2213 | 0xFE5F | 0xFE69 | 0xFE6A | 0xFF03 .. 0xFF05 => ET,
+      0x2C | 0x2E | 0x2F | 0x3A | 0xA0 | 0x060C | 0x202F | 0x2044 | 0xFE50 | 0xFE52
+          | 0xFE55 | 0xFF0C | 0xFF0E | 0xFF0F | 0xFF1A => CS,
+      0x0600 .. 0x0605 | 0x0660 .. 0x0669 | 0x066B | 0x066C | 0x06DD => AN,
+      0x0300 .. 0x036F | 0x0483 .. 0x0489 | 0x0591 .. 0x05BD | 0x05BF | 0x05C1 | 0x05C2
+          | 0x05C4 | 0x05C5 | 0x05C7 | 0x0610 .. 0x061A | 0x064B .. 0x065F | 0x0670
+          | 0x06D6 .. 0x06DC | 0x06DF .. 0x06E4 | 0x06E7 | 0x06E8 | 0x06EA .. 0x06ED
+          | 0x0711 | 0x0730 .. 0x074A | 0x07A6 .. 0x07B0 | 0x20D0 .. 0x20FF
+          | 0xFB1E | 0xFE00 .. 0xFE0F | 0xFE20 .. 0xFE2F => NSM,
+      0x200E => L,
+      0x200F | 0x0590 .. 0x05FF | 0x07C0 .. 0x085F | 0xFB1D .. 0xFB4F => R,
+      0x061C | 0x0608 .. 0x06FF | 0x0700 .. 0x07BF | 0x0860 .. 0x08FF | 0xFB50 .. 0xFDFF
+          | 0xFE70 .. 0xFEFE => AL,
+      0x202A => LRE,
+      0x202B => RLE,
+      0x202C => PDF,
+      0x202D => LRO,
+      0x202E => RLO,
+      0x21 | 0x22 | 0x26 .. 0x2A | 0x3B .. 0x40 | 0x5B .. 0x60 | 0x7B .. 0x7E | 0xA1
+          | 0xA6 .. 0xA9 | 0xAB | 0xAC | 0xAE | 0xAF | 0xB4 | 0xB6 .. 0xB8 | 0xBB .. 0xBF
+          | 0xD7 | 0xF7 | 0x2010 .. 0x2027 | 0x2035 .. 0x2043 | 0x2045 .. 0x205E
+          | 0x2190 .. 0x2211 | 0x2214 .. 0x2BFF | 0x3001 .. 0x3004 | 0x3008 .. 0x3020
+          | 0xFE30 .. 0xFE4F | 0xFF01 | 0xFF02 | 0xFF06 .. 0xFF0A | 0xFF1B .. 0xFF20
+          | 0xFF3B .. 0xFF40 | 0xFF5B .. 0xFF65 => ON,
+      _ => L
+    }
+}
+
+/** Whether a class is left out of the implicit rules (rule X9). */
+pure fn is_removed(class: BidiClass) -> bool {
+    match class {
+      RLE | LRE | RLO | LRO | PDF | BN => true,
+      _ => false
+    }
+}
+
+/** The strong direction of an embedding level. */
+pure fn embedding_direction(level: Level) -> BidiClass {
+    if is_rtl(level) { R } else { L }
+}
+
+/**
+Returns the embedding level of each byte of the text of a paragraph,
+given the paragraph's own level: 0 for 'direction: ltr', and 1 for
+'direction: rtl'. The bytes of a character share its level.
+*/
+pub fn resolve_levels(text: &str, paragraph_level: Level) -> ~[Level] {
+    let mut chars = ~[], offsets = ~[];
+    let mut i = 0u;
+    while i < text.len() {
+        let {ch, next} = str::char_range_at(text, i);
+        vec::push(&mut chars, ch);
+        vec::push(&mut offsets, i);
+        i = next;
+    }
+
+    let char_levels = resolve_char_levels(chars, paragraph_level);
+    let mut levels = vec::from_elem(text.len(), paragraph_level);
+    for offsets.eachi |j, offset| {
+        let end = if j + 1u < offsets.len() { offsets[j + 1u] } else { text.len() };
+        for uint::range(*offset, end) |k| { levels[k] = char_levels[j]; }
+    }
+    move levels
+}
+
+fn resolve_char_levels(chars: &[char], paragraph_level: Level) -> ~[Level] {
+    let original = chars.map(|c| bidi_class(*c));
+    let mut classes = copy original;
+    let mut levels = vec::from_elem(chars.len(), paragraph_level);
+
+    // explicit embeddings and overrides (rules X1 to X8)
+    let mut stack: ~[(Level, Option<BidiClass>)] = ~[];
+    let mut level = paragraph_level, overriding = None;
+    let mut overflow = 0u;
+    for uint::range(0u, chars.len()) |i| {
+        match original[i] {
+          RLE | RLO | LRE | LRO => {
+            let next = match original[i] {
+              RLE | RLO => (level + 1u8) | 1u8,
+              _ => (level + 2u8) & !1u8
+            };
+            if next <= MAX_DEPTH && overflow == 0u {
+                vec::push(&mut stack, (level, overriding));
+                level = next;
+                overriding = match original[i] {
+                  RLO => Some(R),
+                  LRO => Some(L),
+                  _ => None
+                };
+            } else {
+                overflow += 1u;
+            }
+          }
+          PDF => {
+            if overflow > 0u {
+                overflow -= 1u;
+            } else if stack.is_not_empty() {
+                let (outer_level, outer_overriding) = vec::pop(&mut stack);
+                level = outer_level;
+                overriding = outer_overriding;
+            }
+          }
+          B => level = paragraph_level,
+          BN => {}
+          _ => match overriding {
+            Some(class) => classes[i] = class,
+            None => {}
+          }
+        }
+        levels[i] = level;
+    }
+
+    // the implicit rules apply to each run of characters at the same level, leaving out
+    // the explicit formatting characters (rules X9 and X10)
+    let kept = vec::filter(vec::from_fn(chars.len(), |i| i), |i| !is_removed(original[*i]));
+    let explicit = copy levels;
+    let mut start = 0u;
+    while start < kept.len() {
+        let run_level = explicit[kept[start]];
+        let mut end = start + 1u;
+        while end < kept.len() && explicit[kept[end]] == run_level { end += 1u; }
+
+        let before = if start == 0u { paragraph_level } else { explicit[kept[start - 1u]] };
+        let after = if end == kept.len() { paragraph_level } else { explicit[kept[end]] };
+        let sos = embedding_direction(uint::max(run_level as uint, before as uint) as Level);
+        let eos = embedding_direction(uint::max(run_level as uint, after as uint) as Level);
+
+        let run = vec::slice(kept, start, end);
+        let types = resolve_weak_types(run.map(|i| classes[*i]), sos);
+        let types = resolve_neutral_types(types, sos, eos, run_level);
+        for run.eachi |j, i| {
+            // rules I1 and I2
+            levels[*i] = match (is_rtl(run_level), types[j]) {
+              (false, R) => run_level + 1u8,
+              (false, AN) | (false, EN) => run_level + 2u8,
+              (true, L) | (true, EN) | (true, AN) => run_level + 1u8,
+              _ => run_level
+            };
+        }
+        start = end;
+    }
+
+    // removed characters take the level before them; then separators, and the
+    // whitespace before them, go back to the paragraph's level (rule L1). The
+    // whitespace at the end of each line is left to the code that breaks lines.
+    for uint::range(0u, chars.len()) |i| {
+        if is_removed(original[i]) {
+            levels[i] = if i == 0u { paragraph_level } else { levels[i - 1u] };
+        }
+    }
+    let mut trailing = false;
+    let mut i = chars.len();
+    while i > 0u {
+        i -= 1u;
+        match original[i] {
+          S | B => {
+            levels[i] = paragraph_level;
+            trailing = true;
+          }
+          WS | RLE | RLO | LRE | LRO | PDF | BN if trailing => levels[i] = paragraph_level,
+          _ => trailing = false
+        }
+    }
+    move levels
+}
+
+/** Applies the rules for weak types (W1 to W7) to a level run. */
+fn resolve_weak_types(types: &[BidiClass], sos: BidiClass) -> ~[BidiClass] {
+    let mut types = vec::from_slice(types);
+    let n = types.len();
+
+    // W1: a nonspacing mark takes the type of the character before it
+    for uint::range(0u, n) |i| {
+        if types[i] == NSM { types[i] = if i == 0u { sos } else { types[i - 1u] }; }
+    }
+    // W2 and W3: European numbers after Arabic letters are Arabic numbers, and Arabic
+    // letters are right-to-left
+    let mut last_strong = sos;
+    for uint::range(0u, n) |i| {
+        match types[i] {
+          L | R | AL => last_strong = types[i],
+          EN if last_strong == AL => types[i] = AN,
+          _ => {}
+        }
+    }
+    for uint::range(0u, n) |i| {
+        if types[i] == AL { types[i] = R; }
+    }
+    // W4: a single separator between two numbers of the same type joins them
+    for uint::range(1u, uint::max(n, 1u) - 1u) |i| {
+        match (types[i - 1u], types[i], types[i + 1u]) {
+          (EN, ES, EN) | (EN, CS, EN) => types[i] = EN,
+          (AN, CS, AN) => types[i] = AN,
+          _ => {}
+        }
+    }
+    // W5: terminators next to European numbers are part of them
+    let mut i = 0u;
+    while i < n {
+        if types[i] != ET { i += 1u; loop; }
+        let mut end = i;
+        while end < n && types[end] == ET { end += 1u; }
+        if (i > 0u && types[i - 1u] == EN) || (end < n && types[end] == EN) {
+            for uint::range(i, end) |j| { types[j] = EN; }
+        }
+        i = end;
+    }
+    // W6 and W7: other separators and terminators are neutral, and European numbers after
+    // left-to-right text are left-to-right
+    let mut last_strong = sos;
+    for uint::range(0u, n) |i| {
+        match types[i] {
+          ES | ET | CS => types[i] = ON,
+          L | R => last_strong = types[i],
+          EN if last_strong == L => types[i] = L,
+          _ => {}
+        }
+    }
+    move types
+}
+
+/** Applies the rules for neutral types (N1 and N2) to a level run. */
+fn resolve_neutral_types(types: &[BidiClass], sos: BidiClass, eos: BidiClass,
+                         level: Level) -> ~[BidiClass] {
+    pure fn is_neutral(class: BidiClass) -> bool {
+        match class { B | S | WS | ON => true, _ => false }
+    }
+    // numbers count as right-to-left text around neutrals
+    pure fn strong_direction(class: BidiClass) -> BidiClass {
+        match class { EN | AN => R, _ => class }
+    }
+
+    let mut types = vec::from_slice(types);
+    let n = types.len();
+    let mut i = 0u;
+    while i < n {
+        if !is_neutral(types[i]) { i += 1u; loop; }
+        let mut end = i;
+        while end < n && is_neutral(types[end]) { end += 1u; }
+        let before = if i == 0u { sos } else { strong_direction(types[i - 1u]) };
+        let after = if end == n { eos } else { strong_direction(types[end]) };
+        let class = if before == after { before } else { embedding_direction(level) };
+        for uint::range(i, end) |j| { types[j] = class; }
+        i = end;
+    }
+    move types
+}
+
+/**
+Returns the order that the parts of a line at the given levels are drawn
+in, from left to right, as indices into `levels` (rule L2).
+*/
+pub fn visual_order(levels: &[Level]) -> ~[uint] {
+    let mut order = vec::from_fn(levels.len(), |i| i);
+    if levels.is_empty() { return move order; }
+
+    let highest = levels.foldl(0u8, |a, b| if *b > a { *b } else { a });
+    let lowest = levels.foldl(highest, |a, b| if *b < a { *b } else { a });
+    let lowest_odd = lowest | 1u8;
+
+    let mut level = highest;
+    while level >= lowest_odd {
+        let mut i = 0u;
+        while i < order.len() {
+            if levels[order[i]] < level { i += 1u; loop; }
+            let mut end = i;
+            while end < order.len() && levels[order[end]] >= level { end += 1u; }
+            let mut a = i, b = end - 1u;
+            while a < b {
+                order[a] <-> order[b];
+                a += 1u;
+                b -= 1u;
+            }
+            i = end;
+        }
+        level -= 1u8;
+    }
+    move order
+}
+
+#[test]
+fn should_resolve_levels_of_mixed_text() {
+    // Latin, then two Hebrew letters of two bytes each, then a number
+    let text = ~"abc אב 12";
+    assert resolve_levels(text, 0u8) == ~[0u8, 0u8, 0u8, 0u8, 1u8, 1u8, 1u8, 1u8, 1u8, 2u8, 2u8];
+
+    // Latin text is embedded in a right-to-left paragraph
+    assert resolve_levels("abc d", 1u8) == ~[2u8, 2u8, 2u8, 2u8, 2u8];
+    assert resolve_levels("abc ", 1u8) == ~[2u8, 2u8, 2u8, 1u8];
+
+    // whitespace before a tab goes back to the paragraph's level, but not that at the end
+    assert resolve_levels("\u202bא \t", 0u8) == ~[0u8, 0u8, 0u8, 1u8, 1u8, 0u8, 0u8];
+    assert resolve_levels("\u202bא ", 0u8) == ~[0u8, 0u8, 0u8, 1u8, 1u8, 1u8];
+
+    // numbers after Arabic letters are Arabic numbers
+    assert resolve_levels("ا 1", 0u8) == ~[1u8, 1u8, 1u8, 2u8];
+}
+
+#[test]
+fn should_resolve_explicit_embeddings() {
+    // a right-to-left override makes Latin letters right-to-left
+    assert resolve_levels("a\u202eb\u202cc", 0u8)
+        == ~[0u8, 0u8, 0u8, 0u8, 1u8, 1u8, 1u8, 1u8, 0u8];
+}
+
+#[test]
+fn should_reverse_right_to_left_parts_of_lines() {
+    assert visual_order([0u8, 0u8, 1u8, 1u8, 1u8, 2u8, 2u8]) == ~[0u, 1u, 5u, 6u, 4u, 3u, 2u];
+    assert visual_order([1u8, 1u8, 1u8]) == ~[2u, 1u, 0u];
+    assert visual_order([0u8, 0u8]) == ~[0u, 1u];
+}
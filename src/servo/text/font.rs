@@ -9,6 +9,7 @@ use glyph::GlyphIndex;
 use libc::{ c_int, c_double, c_ulong };
 use native_font::NativeFont;
 use ptr::{null, addr_of};
+use shaper::HarfBuzzFont;
 use text::text_run::TextRun;
 use vec_to_ptr = vec::raw::to_ptr;
 use servo_util::range::Range;
@@ -16,6 +17,70 @@ use servo_util::range::Range;
 // Used to abstract over the shaper's choice of fixed int representation.
 type FractionalPixel = float;
 
+/**
+What text is styled with that picks the font it is drawn in. Text runs
+carry this, instead of the font itself, between tasks.
+*/
+struct FontDescriptor {
+    // family names, in order of preference; generic families go by their CSS keyword
+    families: ~[~str],
+    // from 100 to 900, as in CSS
+    weight: uint,
+    // oblique text is drawn with an italic face too
+    italic: bool,
+    // the em size, from the computed 'font-size'
+    size: Au,
+}
+
+impl FontDescriptor : cmp::Eq {
+    pure fn eq(other: &FontDescriptor) -> bool {
+        self.families == other.families && self.weight == other.weight
+            && self.italic == other.italic && self.size == other.size
+    }
+    pure fn ne(other: &FontDescriptor) -> bool { !self.eq(other) }
+}
+
+/** Where the data of a font comes from. */
+enum FontFace {
+    // the font built into servo, for when no installed one is found
+    TestFace,
+    // a face in an installed font file, by its index in the file
+    FileFace(~str, uint)
+}
+
+impl FontFace {
+    pure fn index() -> uint {
+        match self {
+          TestFace => 0,
+          FileFace(_, index) => index
+        }
+    }
+}
+
+impl FontFace : cmp::Eq {
+    pure fn eq(other: &FontFace) -> bool {
+        match (copy self, copy *other) {
+          (TestFace, TestFace) => true,
+          (FileFace(path_a, index_a), FileFace(path_b, index_b)) => {
+            path_a == path_b && index_a == index_b
+          }
+          (TestFace, _) | (FileFace(*), _) => false
+        }
+    }
+    pure fn ne(other: &FontFace) -> bool { !self.eq(other) }
+}
+
+/** Whether a family name is one of the CSS generic families. */
+pure fn is_generic_family(name: &str) -> bool {
+    match str::to_lower(name) {
+      ~"serif" | ~"sans-serif" | ~"cursive" | ~"fantasy" | ~"monospace" => true,
+      _ => false
+    }
+}
+
+/** The family used when none of a descriptor's families is installed. */
+pure fn default_font_family() -> ~str { ~"serif" }
+
 /**
 A font handle. Layout can use this to calculate glyph metrics
 and the renderer can use it to render text.
@@ -26,6 +91,11 @@ struct Font {
     fontbuf: @~[u8],
     native_font: NativeFont,
     metrics: FontMetrics,
+    // the descriptor the font was first created for; others may match the same face and size
+    descriptor: FontDescriptor,
+    face: FontFace,
+    // made by the shaper the first time it shapes text in the font
+    mut harfbuzz_font: Option<@HarfBuzzFont>,
 }
 
 struct RunMetrics {
@@ -55,14 +125,20 @@ pub impl Font : FontMethods {
     fn measure_text(run: &TextRun, range: Range) -> RunMetrics {
         assert range.is_valid_for_string(run.text);
 
-        // TODO: alter advance direction for RTL
+        // glyphs advance the same way in right-to-left text; only the order they are drawn in
+        // differs
         // TODO(Issue #98): using inter-char and inter-word spacing settings  when measuring text
         let mut advance = Au(0);
         for run.glyphs.iter_glyphs_for_range(range) |_i, glyph| {
             advance += glyph.advance();
         }
-        let mut bounds = Rect(Point2D(Au(0), -self.metrics.ascent),
-                              Size2D(advance, self.metrics.ascent + self.metrics.descent));
+        // fallback fonts drawing part of the range may be taller
+        let mut ascent = self.metrics.ascent, descent = self.metrics.descent;
+        for run.iter_segments_for_range(range) |segment| {
+            ascent = au::max(ascent, segment.font.metrics.ascent);
+            descent = au::max(descent, segment.font.metrics.descent);
+        }
+        let mut bounds = Rect(Point2D(Au(0), -ascent), Size2D(advance, ascent + descent));
 
         // TODO(Issue #125): support loose and tight bounding boxes; using the
         // ascent+descent and advance is sometimes too generous and
@@ -70,8 +146,8 @@ pub impl Font : FontMethods {
 
         let metrics = RunMetrics { advance_width: advance,
                                   bounding_box: bounds,
-                                  ascent: self.metrics.ascent,
-                                  descent: self.metrics.descent,
+                                  ascent: ascent,
+                                  descent: descent,
                                  };
         debug!("Measured text range '%s' with metrics:", run.text.substr(range.begin(), range.length()));
         debug!("%?", metrics);
@@ -96,7 +172,8 @@ pub impl Font : FontMethods {
 }
 
 // TODO: who should own fontbuf?
-fn Font(lib: @FontCache, fontbuf: @~[u8], native_font: NativeFont) -> Font {
+fn Font(lib: @FontCache, fontbuf: @~[u8], native_font: NativeFont,
+        descriptor: FontDescriptor, face: FontFace) -> Font {
     let metrics = native_font.get_metrics();
 
     Font {
@@ -104,6 +181,9 @@ fn Font(lib: @FontCache, fontbuf: @~[u8], native_font: NativeFont) -> Font {
         fontbuf : fontbuf,
         metrics: move metrics,
         native_font : move native_font,
+        descriptor: move descriptor,
+        face: move face,
+        harfbuzz_font: None,
     }
 }
 
@@ -120,6 +200,18 @@ struct FontMetrics {
 
 const TEST_FONT: [u8 * 33004] = #include_bin("JosefinSans-SemiBold.ttf");
 
+/// The size of the test font, that of 'font-size: medium', in px.
+const TEST_FONT_SIZE_PX: int = 16;
+
+fn test_font_descriptor() -> FontDescriptor {
+    FontDescriptor {
+        families: ~[default_font_family()],
+        weight: 400,
+        italic: false,
+        size: au::from_px(TEST_FONT_SIZE_PX)
+    }
+}
+
 fn test_font_bin() -> ~[u8] {
     return vec::from_fn(33004, |i| TEST_FONT[i]);
 }
@@ -1,9 +1,16 @@
 export FontCache, native;
-use font::{Font, test_font_bin};
+use font::{Font, FontDescriptor, FontFace, TestFace, FileFace};
+use font::{test_font_bin, test_font_descriptor};
+use gfx::geometry;
 
 struct FontCache {
     native_lib: native::NativeFontCache,
-    mut cached_font: Option<@Font>,
+    // the font that each descriptor asked for so far was given
+    mut matched_fonts: ~[(FontDescriptor, @Font)],
+    // one font per face and size, shared by the descriptors that match them
+    mut instances: ~[@Font],
+    // the characters that no installed face had a glyph for, with the descriptor asked for
+    mut uncovered_chars: ~[(char, FontDescriptor)],
 
     drop {
         native::destroy_native_lib(&self.native_lib);
@@ -11,40 +18,97 @@ struct FontCache {
 }
 
 impl FontCache {
-    fn get_font(@self) -> @Font {
-        match self.cached_font {
+    /**
+    Returns the font for a descriptor. The face is matched by the
+    platform, once per descriptor; the test font is used if there is
+    no match.
+    */
+    fn get_font(@self, desc: &FontDescriptor) -> @Font {
+        for self.matched_fonts.each |entry| {
+            let (ref matched, font) = *entry;
+            if *matched == *desc { return font; }
+        }
+
+        let face = native_font::match_face(&self.native_lib, desc).get_default(TestFace);
+        let font = self.get_font_for_face(move face, desc);
+        vec::push(&mut self.matched_fonts, (copy *desc, font));
+        font
+    }
+
+    /**
+    Returns a font to draw a character that the font of a descriptor has
+    no glyph for, or None if no installed face has one. A character that
+    none has is remembered with the descriptor, so it isn't matched again.
+    */
+    fn get_fallback_font(@self, desc: &FontDescriptor, c: char) -> Option<@Font> {
+        for self.uncovered_chars.each |entry| {
+            let (uncovered, ref uncovered_desc) = *entry;
+            if uncovered == c && *uncovered_desc == *desc { return None; }
+        }
+
+        let fallback = do native_font::match_face_for_char(&self.native_lib, desc, c).chain |face| {
+            let font = self.get_font_for_face(move face, desc);
+            if font.glyph_index(c).is_some() { Some(font) } else { None }
+        };
+        if fallback.is_none() {
+            vec::push(&mut self.uncovered_chars, (c, copy *desc));
+        }
+        fallback
+    }
+
+    fn get_test_font(@self) -> @Font {
+        self.get_font_for_face(TestFace, &test_font_descriptor())
+    }
+
+    /** Returns the font for a face at the size of a descriptor. */
+    fn get_font_for_face(@self, face: FontFace, desc: &FontDescriptor) -> @Font {
+        let existing = do self.instances.find |font| {
+            font.face == face && font.descriptor.size == desc.size
+        };
+        match existing {
             Some(font) => font,
-            None => match create_font(self, &self.native_lib) {
+            None => match create_font(self, &self.native_lib, copy face, desc) {
                 Ok(font) => {
-                    self.cached_font = Some(font);
+                    vec::push(&mut self.instances, font);
                     font
                 }
-                Err(*) => /* FIXME */ fail
+                Err(*) => match face {
+                    FileFace(*) => self.get_font_for_face(TestFace, desc),
+                    TestFace => /* FIXME */ fail
+                }
             }
         }
     }
-
-    fn get_test_font(@self) -> @Font {
-        self.get_font()
-    }
 }
 
 fn FontCache() -> @FontCache {
     @FontCache {
         native_lib: native::create_native_lib(),
-        cached_font: None
+        matched_fonts: ~[],
+        instances: ~[],
+        uncovered_chars: ~[]
     }
 }
 
-fn create_font(lib: @FontCache, native_lib: &native::NativeFontCache) -> Result<@Font, ()> {
-    let font_bin = @test_font_bin();
-    let native_font = native_font::create(native_lib, font_bin);
+fn create_font(lib: @FontCache, native_lib: &native::NativeFontCache, face: FontFace,
+               desc: &FontDescriptor) -> Result<@Font, ()> {
+    let font_bin = match face {
+        TestFace => @test_font_bin(),
+        FileFace(ref path, _) => match io::read_whole_file(&Path(*path)) {
+            Ok(move bytes) => @move bytes,
+            Err(move message) => {
+                debug!("Unable to read font file %s: %s", *path, message);
+                return Err(());
+            }
+        }
+    };
+    let native_font = native_font::create(native_lib, font_bin, face.index(), desc.size);
     let native_font = if native_font.is_ok() {
         result::unwrap(move native_font)
     } else {
         return Err(native_font.get_err());
     };
-    return Ok(@Font(lib, font_bin, move native_font));
+    return Ok(@Font(lib, font_bin, move native_font, copy *desc, move face));
 }
 
 #[cfg(target_os = "linux")]
@@ -53,6 +117,8 @@ pub mod native {
     use azure::freetype;
     use freetype::{FT_Library, FT_Error};
     use freetype::bindgen::{FT_Init_FreeType, FT_Done_FreeType};
+    use text::native_font::fontconfig::FcTrue;
+    use text::native_font::fontconfig::bindgen::FcInit;
 
     pub type NativeFontCache = FT_Library;
 
@@ -61,6 +127,10 @@ pub mod native {
         let res = FT_Init_FreeType(addr_of(&lib));
         // FIXME: error handling
         assert res == 0 as FT_Error;
+        // fontconfig is shared by all the caches; this only loads its configuration once
+        if FcInit() != FcTrue {
+            debug!("Unable to load the fontconfig configuration; only the test font is used");
+        }
         return lib;
     }
 
@@ -81,5 +151,36 @@ pub mod native {
 #[test]
 pub fn should_get_fonts() {
     let lib = FontCache();
-    lib.get_font();
+    lib.get_test_font();
+}
+
+#[test]
+pub fn should_cache_fonts_by_size() {
+    let lib = FontCache();
+    let desc = |size: int| {
+        FontDescriptor { size: geometry::from_px(size), .. test_font_descriptor() }
+    };
+    let small = lib.get_font(&desc(10));
+    let large = lib.get_font(&desc(30));
+    assert box::ptr_eq(small, lib.get_font(&desc(10)));
+    assert !box::ptr_eq(small, large);
+    assert small.metrics.ascent < large.metrics.ascent;
+}
+
+#[test]
+pub fn should_fall_back_from_missing_families() {
+    let lib = FontCache();
+    let missing = FontDescriptor { families: ~[~"No Such Family"], .. test_font_descriptor() };
+    let default = lib.get_font(&test_font_descriptor());
+    assert box::ptr_eq(lib.get_font(&missing), default);
+}
+
+#[test]
+pub fn should_remember_chars_without_fallback_fonts() {
+    let lib = FontCache();
+    let desc = test_font_descriptor();
+    // a private use character, which no installed face should cover
+    assert lib.get_fallback_font(&desc, '\U000F0000').is_none();
+    assert lib.get_fallback_font(&desc, '\U000F0000').is_none();
+    assert lib.uncovered_chars.len() == 1;
 }
@@ -42,10 +42,12 @@ const BREAK_TYPE_NORMAL : u8 = 0x1u8;
 const BREAK_TYPE_HYPHEN  : u8 = 0x2u8;
 
 pure fn break_flag_to_enum(flag: u8) -> BreakType {
-    if (flag & BREAK_TYPE_NONE) as bool   { return BreakTypeNone; }
-    if (flag & BREAK_TYPE_NORMAL) as bool { return BreakTypeNormal; }
-    if (flag & BREAK_TYPE_HYPHEN) as bool { return  BreakTypeHyphen; }
-    fail ~"Unknown break setting"
+    match flag {
+        BREAK_TYPE_NONE => BreakTypeNone,
+        BREAK_TYPE_NORMAL => BreakTypeNormal,
+        BREAK_TYPE_HYPHEN => BreakTypeHyphen,
+        _ => fail ~"Unknown break setting"
+    }
 }
 
 pure fn break_enum_to_flag(e: BreakType) -> u8 {
@@ -176,8 +178,9 @@ impl GlyphEntry {
         self.has_flag(!FLAG_NOT_CLUSTER_START)
     }
     
-    // True if original char was normal (U+0020) space. Other chars may
-    // map to space glyph, but this does not account for them.
+    // True if original char was a normal (U+0020) or no-break (U+00A0)
+    // space: the word separators, which justification widens. Other chars
+    // may map to space glyph, but this does not account for them.
     pure fn char_is_space() -> bool {
         self.has_flag(FLAG_CHAR_IS_SPACE)
     }
@@ -526,6 +529,13 @@ impl GlyphStore {
         self.entry_buffer.set_elt(i, entry);
     }
 
+    // a newline is drawn with no glyphs; it only ends its line
+    fn add_newline_for_index(i: uint) {
+        assert i < self.entry_buffer.len();
+        let entry = ComplexGlyphEntry(true, true, 0);
+        self.entry_buffer.set_elt(i, entry.set_char_is_newline());
+    }
+
     fn iter_glyphs_for_index<T>(&self, i: uint, cb: fn&(uint, GlyphInfo/&) -> T) {
         assert i < self.entry_buffer.len();
 
@@ -536,6 +546,8 @@ impl GlyphStore {
                 cb(i, proxy);
             },
             false => {
+                // the entries of newlines and of the rest of a char's bytes have no glyphs
+                if entry.glyph_count() == 0 { return; }
                 let glyphs = self.detail_store.get_detailed_glyphs_for_entry(i, entry.glyph_count());
                 for uint::range(0, glyphs.len()) |j| {
                     let proxy = DetailGlyphInfo(self, i, j as u16);
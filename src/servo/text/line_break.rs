@@ -0,0 +1,257 @@
+/*!
+Line breaking (UAX #14): finds where text may wrap, with the pair table
+of the algorithm.
+
+The line breaking classes are those of the scripts and punctuation in
+common use rather than the whole of the Unicode Character Database.
+Southeast Asian scripts, which need a dictionary to find word
+boundaries, are treated as alphabetic.
+*/
+
+use glyph::{BreakType, BreakTypeNormal, BreakTypeHyphen};
+
+/** The line breaking classes of UAX #14 that the pair table is indexed by, in its order. */
+enum LineBreakClass {
+    OP, // open punctuation
+    CL, // close punctuation
+    CP, // close parenthesis
+    QU, // quotation
+    GL, // non-breaking ("glue")
+    NS, // nonstarter
+    EX, // exclamation or interrogation
+    SY, // symbols allowing a break after
+    IS, // infix numeric separator
+    PR, // prefix numeric
+    PO, // postfix numeric
+    NU, // numeric
+    AL, // alphabetic
+    ID, // ideographic
+    IN, // inseparable
+    HY, // hyphen
+    BA, // break after
+    BB, // break before
+    B2, // break opportunity before and after
+    ZW, // zero width space
+    CM, // combining mark
+    WJ, // word joiner
+    H2, // Hangul LV syllable
+    H3, // Hangul LVT syllable
+    JL, // Hangul L jamo
+    JV, // Hangul V jamo
+    JT, // Hangul T jamo
+    // the classes below are resolved before the pair table is used
+    BK, // mandatory break
+    CR, // carriage return
+    LF, // line feed
+    SP  // space
+}
+
+impl LineBreakClass : cmp::Eq {
+    pure fn eq(other: &LineBreakClass) -> bool {
+        self as uint == (*other) as uint
+    }
+    pure fn ne(other: &LineBreakClass) -> bool {
+        return !self.eq(other);
+    }
+}
+
+/*
+The pair table of UAX #14, one row for each class before a position and
+one column for each class after it, in the order of `LineBreakClass`:
+  '_' a break is allowed
+  '%' a break is allowed only if there are spaces between the two
+  '#' as '%', but a combining mark after no spaces takes the class before it
+  '@' no break, and a combining mark takes the class before it
+  '^' no break, even with spaces between
+*/
+const PAIR_TABLE: [&static/str * 27] = [
+    //OP CL CP QU GL NS EX SY IS PR PO NU AL ID IN HY BA BB B2 ZW CM WJ H2 H3 JL JV JT
+    "^^^^^^^^^^^^^^^^^^^^@^^^^^^", // OP
+    "_^^%%^^^^%%____%%__^#^_____", // CL
+    "_^^%%^^^^%%%%__%%__^#^_____", // CP
+    "^^^%%%^^^%%%%%%%%%%^#^%%%%%", // QU
+    "%^^%%%^^^%%%%%%%%%%^#^%%%%%", // GL
+    "_^^%%%^^^______%%__^#^_____", // NS
+    "_^^%%%^^^_____%%%__^#^_____", // EX
+    "_^^%%%^^^__%___%%__^#^_____", // SY
+    "_^^%%%^^^__%%__%%__^#^_____", // IS
+    "%^^%%%^^^__%%%_%%__^#^%%%%%", // PR
+    "%^^%%%^^^__%%__%%__^#^_____", // PO
+    "%^^%%%^^^%%%%_%%%__^#^_____", // NU
+    "%^^%%%^^^__%%_%%%__^#^_____", // AL
+    "_^^%%%^^^_%___%%%__^#^_____", // ID
+    "_^^%%%^^^_____%%%__^#^_____", // IN
+    "_^^%_%^^^__%___%%__^#^_____", // HY
+    "_^^%_%^^^______%%__^#^_____", // BA
+    "%^^%%%^^^%%%%%%%%%%^#^%%%%%", // BB
+    "_^^%%%^^^______%%_^^#^_____", // B2
+    "___________________^_______", // ZW
+    "%^^%%%^^^__%%_%%%__^#^_____", // CM
+    "%^^%%%^^^%%%%%%%%%%^#^%%%%%", // WJ
+    "_^^%%%^^^_%___%%%__^#^___%%", // H2
+    "_^^%%%^^^_%___%%%__^#^____%", // H3
+    "_^^%%%^^^_%___%%%__^#^%%%%_", // JL
+    "_^^%%%^^^_%___%%%__^#^___%%", // JV
+    "_^^%%%^^^_%___%%%__^#^____%"  // JT
+];
+
+pure fn line_break_class(c: char) -> LineBreakClass {
+    let c = c as uint;
+    match c {
+      0x0B | 0x0C | 0x2028 | 0x2029 => BK,
+      0x0D => CR,
+      0x0A | 0x85 => LF,
+      0x20 => SP,
+      0x200B => ZW,
+      0x2060 | 0xFEFF => WJ,
+      0xA0 | 0x034F | 0x2007 | 0x2011 | 0x202F | 0x0F08 | 0x0F0C | 0x0F12 | 0x180E => GL,
+      0x00 .. 0x08 | 0x0E .. 0x1F | 0x7F .. 0x84 | 0x86 .. 0x9F | 0x0300 .. 0x036F
+          | 0x0483 .. 0x0489 | 0x0591 .. 0x05BD | 0x0610 .. 0x061A | 0x064B .. 0x065F
+          | 0x0670 | 0x06D6 .. 0x06DC | 0x06DF .. 0x06E4 | 0x06E7 | 0x06E8
+          | 0x06EA .. 0x06ED | 0x200C .. 0x200F | 0x202A .. 0x202E | 0x20D0 .. 0x20FF
+          | 0x3099 | 0x309A | 0xFE00 .. 0xFE0F | 0xFE20 .. 0xFE2F => CM,
+      0x09 | 0x7C | 0xAD | 0x058A | 0x05BE | 0x1680 | 0x2000 .. 0x2006 | 0x2008 .. 0x200A
+          | 0x2010 | 0x2012 | 0x2013 | 0x2027 | 0x205F | 0x3000 => BA,
+      0xB4 | 0x02C8 | 0x02CC | 0x02DF => BB,
+      0x2014 => B2,
+      0x2D => HY,
+      0x2024 .. 0x2026 | 0xFE19 => IN,
+      0x28 | 0x5B | 0x7B | 0xA1 | 0xBF | 0x201A | 0x201E | 0x2045 | 0x207D | 0x208D
+          | 0x2329 | 0x3008 | 0x300A | 0x300C | 0x300E | 0x3010 | 0x3014 | 0x3016
+          | 0x3018 | 0x301A | 0x301D | 0xFF08 | 0xFF3B | 0xFF5B | 0xFF5F | 0xFF62 => OP,
+      0x29 | 0x5D | 0xFF09 | 0xFF3D => CP,
+      0x7D | 0x2046 | 0x207E | 0x208E | 0x232A | 0x3001 | 0x3002 | 0x3009 | 0x300B
+          | 0x300D | 0x300F | 0x3011 | 0x3015 | 0x3017 | 0x3019 | 0x301B | 0x301E
+          | 0x301F | 0xFE50 | 0xFE52 | 0xFF0C | 0xFF0E | 0xFF5D | 0xFF60 | 0xFF61
+          | 0xFF63 | 0xFF64 => CL,
+      0x22 | 0x27 | 0xAB | 0xBB | 0x2018 | 0x2019 | 0x201B .. 0x201D | 0x201F | 0x2039
+          | 0x203A | 0x275B .. 0x275E => QU,
+      0x21 | 0x3F | 0x05C6 | 0x061B | 0x061E | 0x061F | 0x06D4 | 0x07F9 | 0x0F0D .. 0x0F11
+          | 0xFE15 | 0xFE16 | 0xFE56 | 0xFE57 | 0xFF01 | 0xFF1F => EX,
+      0x2F => SY,
+      0x2C | 0x2E | 0x3A | 0x3B | 0x037E | 0x0589 | 0x060C | 0x060D | 0x07F8 | 0x2044
+          | 0xFE10 | 0xFE13 | 0xFE14 => IS,
+      0x24 | 0x2B | 0x5C | 0xA3 .. 0xA5 | 0xB1 | 0x20A0 .. 0x20A6 | 0x20A8 .. 0x20CF
+          | 0x2116 | 0x2212 | 0x2213 | 0xFE69 | 0xFF04 | 0xFFE1 | 0xFFE5 | 0xFFE6 => PR,
+      0x25 | 0xA2 | 0xB0 | 0x0609 .. 0x060B | 0x066A | 0x20A7 | 0x2030 .. 0x2037 | 0x2103
+          | 0x2109 | 0xFE6A | 0xFF05 | 0xFFE0 => PO,
+      0x30 .. 0x39 | 0x0660 .. 0x0669 | 0x066B | 0x066C | 0x06F0 .. 0x06F9 | 0x07C0 .. 0x07C9
+          | 0x0966 .. 0x096F | 0x0E50 .. 0x0E59 => NU,
+      // small kana, and other characters that don't start a line
+      0x17D6 | 0x203C | 0x203D | 0x2047 .. 0x2049 | 0x3005 | 0x301C | 0x303B | 0x303C
+          | 0x3041 | 0x3043 | 0x3045 | 0x3047 | 0x3049 | 0x3063 | 0x3083 | 0x3085 | 0x3087
+          | 0x308E | 0x3095 | 0x3096 | 0x309B .. 0x309E | 0x30A0 | 0x30A1 | 0x30A3 | 0x30A5
+          | 0x30A7 | 0x30A9 | 0x30C3 | 0x30E3 | 0x30E5 | 0x30E7 | 0x30EE | 0x30F5 | 0x30F6
+          | 0x30FB .. 0x30FE | 0x31F0 .. 0x31FF | 0xA015 | 0xFE54 | 0xFE55 | 0xFF1A | 0xFF1B
+          | 0xFF65 | 0xFF67 .. 0xFF70 | 0xFF9E | 0xFF9F => NS,
+      0x1100 .. 0x115F | 0xA960 .. 0xA97C => JL,
+      0x1160 .. 0x11A7 | 0xD7B0 .. 0xD7C6 => JV,
+      0x11A8 .. 0x11FF | 0xD7CB .. 0xD7FB => JT,
+      0xAC00 .. 0xD7A3 => if (c - 0xAC00) % 28 == 0 { H2 } else { H3 },
+      0x2E80 .. 0x2FFF | 0x3003 .. 0x3004 | 0x3006 .. 0x3007 | 0x3012 .. 0x3013
+          | 0x3020 .. 0x303A | 0x303D .. 0x30FF | 0x3100 .. 0x31EF | 0x3200 .. 0x4DBF
+          | 0x4E00 .. 0x9FFF | 0xA000 .. 0xA4CF | 0xF900 .. 0xFAFF | 0xFE30 .. 0xFE4F
+          | 0xFF02 .. 0xFF5A | 0xFFE2 .. 0xFFE4 | 0x1F000 .. 0x1FAFF | 0x20000 .. 0x3FFFD => ID,
+      _ => AL
+    }
+}
+
+/**
+Returns the offsets in some text that a line may break before, with the
+kind of each break. A break after a soft hyphen is a hyphen break, though
+nothing draws a hyphen at the end of its line yet. The start of the text
+and mandatory breaks, after newlines, are left out.
+*/
+pub fn break_opportunities(text: &str) -> ~[(uint, BreakType)] {
+    let mut result = ~[];
+    if text.is_empty() { return move result; }
+
+    let {ch, next} = str::char_range_at(text, 0u);
+    // the class of the text before the position, where spaces and combining marks
+    // don't count; spaces at the start, and marks after them, are treated as word joiners
+    // and letters (rule LB10)
+    let mut before = match line_break_class(ch) {
+      SP => WJ,
+      LF | CR | BK => BK,
+      CM => AL,
+      class => class
+    };
+    let mut last_char = ch;
+    let mut after_spaces = false;
+    let mut i = next;
+    while i < text.len() {
+        let {ch, next} = str::char_range_at(text, i);
+        let class = line_break_class(ch);
+        match class {
+          SP => after_spaces = true,
+          BK | CR | LF => {
+            before = BK;
+            after_spaces = false;
+          }
+          _ if before == BK => {
+            // after a mandatory break, which the caller handles
+            before = resolved_class(class);
+            after_spaces = false;
+          }
+          _ => {
+            let action = PAIR_TABLE[before as uint][class as uint] as char;
+            let can_break = match action {
+              '_' => true,
+              '%' | '#' => after_spaces,
+              _ => false
+            };
+            if can_break {
+                let kind = if last_char == '\u00ad' { BreakTypeHyphen } else { BreakTypeNormal };
+                vec::push(&mut result, (i, kind));
+            }
+            // a combining mark is part of what it follows (rule LB9)
+            let attaches = class == CM && !after_spaces && (action == '#' || action == '@');
+            if !attaches {
+                before = resolved_class(class);
+            }
+            after_spaces = false;
+          }
+        }
+        last_char = ch;
+        i = next;
+    }
+    move result
+}
+
+/** The class a character after a mandatory break or spaces is looked up as. */
+pure fn resolved_class(class: LineBreakClass) -> LineBreakClass {
+    match class {
+      CM => AL,
+      _ => class
+    }
+}
+
+#[test]
+fn should_break_after_spaces_and_hyphens() {
+    let offsets = |text: &str| break_opportunities(text).map(|b| { let (i, _) = *b; i });
+    assert offsets("one two  three") == ~[4u, 9u];
+    assert offsets("well-known") == ~[5u];
+    // no break before closing punctuation, or between a number and its unit
+    assert offsets("(foo)! 10%") == ~[7u];
+    assert offsets("") == ~[];
+}
+
+#[test]
+fn should_break_between_ideographs() {
+    // three ideographs of three bytes each, then a full stop that can't start a line
+    let text = ~"漢字詞。";
+    let offsets = break_opportunities(text).map(|b| { let (i, _) = *b; i });
+    assert offsets == ~[3u, 6u];
+}
+
+#[test]
+fn should_mark_breaks_after_soft_hyphens() {
+    let breaks = break_opportunities("hy\u00adphen");
+    assert breaks.len() == 1;
+    let (offset, kind) = breaks[0];
+    assert offset == 4u;
+    match kind {
+      BreakTypeHyphen => {}
+      _ => fail ~"expected a hyphen break"
+    }
+}
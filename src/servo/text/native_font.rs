@@ -7,6 +7,8 @@ font resources needed by the graphics layer to draw glyphs.
 
 use glyph::GlyphIndex;
 use font_cache::native::NativeFontCache;
+use gfx::geometry::Au;
+use font::{FontDescriptor, FontFace};
 
 #[cfg(target_os = "macos")]
 pub type NativeFont/& = quartz_native_font::QuartzNativeFont;
@@ -15,13 +17,43 @@ pub type NativeFont/& = quartz_native_font::QuartzNativeFont;
 pub type NativeFont/& = ft_native_font::FreeTypeNativeFont;
 
 #[cfg(target_os = "macos")]
-pub fn create(_native_lib: &NativeFontCache, buf: @~[u8]) -> Result<NativeFont, ()> {
-    quartz_native_font::create(buf)
+pub fn create(_native_lib: &NativeFontCache, buf: @~[u8], _index: uint,
+              size: Au) -> Result<NativeFont, ()> {
+    quartz_native_font::create(buf, size)
 }
 
 #[cfg(target_os = "linux")]
-pub fn create(native_lib: &NativeFontCache, buf: @~[u8]) -> Result<NativeFont, ()> {
-    ft_native_font::create(native_lib, buf)
+pub fn create(native_lib: &NativeFontCache, buf: @~[u8], index: uint,
+              size: Au) -> Result<NativeFont, ()> {
+    ft_native_font::create(native_lib, buf, index, size)
+}
+
+/** Finds an installed face for a descriptor, falling back to a generic family. */
+#[cfg(target_os = "macos")]
+pub fn match_face(_native_lib: &NativeFontCache, _desc: &FontDescriptor) -> Option<FontFace> {
+    // TODO: match faces with Core Text; until then, only the test font is used
+    None
+}
+
+/** Finds an installed face for a descriptor, falling back to a generic family. */
+#[cfg(target_os = "linux")]
+pub fn match_face(_native_lib: &NativeFontCache, desc: &FontDescriptor) -> Option<FontFace> {
+    ft_native_font::match_face(desc)
+}
+
+/** Finds an installed face with a glyph for a character, preferring the descriptor's families. */
+#[cfg(target_os = "macos")]
+pub fn match_face_for_char(_native_lib: &NativeFontCache, _desc: &FontDescriptor,
+                           _c: char) -> Option<FontFace> {
+    // TODO: use Core Text's cascade list
+    None
+}
+
+/** Finds an installed face with a glyph for a character, preferring the descriptor's families. */
+#[cfg(target_os = "linux")]
+pub fn match_face_for_char(_native_lib: &NativeFontCache, desc: &FontDescriptor,
+                           c: char) -> Option<FontFace> {
+    ft_native_font::match_face_for_char(desc, c)
 }
 
 #[cfg(target_os = "macos")]
@@ -0,0 +1,61 @@
+/*!
+The parts of the fontconfig API that font matching uses.
+*/
+
+use libc::{c_char, c_int, c_void};
+
+pub type FcBool = c_int;
+pub type FcChar8 = u8;
+pub type FcChar32 = u32;
+pub type FcResult = c_int;
+pub type FcMatchKind = c_int;
+pub type FcConfig = c_void;
+pub type FcPattern = c_void;
+pub type FcCharSet = c_void;
+
+pub const FcTrue: FcBool = 1;
+
+pub const FcResultMatch: FcResult = 0;
+
+pub const FcMatchPattern: FcMatchKind = 0;
+
+// pattern properties
+pub const FC_FAMILY: &static/str = "family";
+pub const FC_FILE: &static/str = "file";
+pub const FC_INDEX: &static/str = "index";
+pub const FC_WEIGHT: &static/str = "weight";
+pub const FC_SLANT: &static/str = "slant";
+pub const FC_CHARSET: &static/str = "charset";
+
+pub const FC_WEIGHT_THIN: c_int = 0;
+pub const FC_WEIGHT_EXTRALIGHT: c_int = 40;
+pub const FC_WEIGHT_LIGHT: c_int = 50;
+pub const FC_WEIGHT_REGULAR: c_int = 80;
+pub const FC_WEIGHT_MEDIUM: c_int = 100;
+pub const FC_WEIGHT_DEMIBOLD: c_int = 180;
+pub const FC_WEIGHT_BOLD: c_int = 200;
+pub const FC_WEIGHT_EXTRABOLD: c_int = 205;
+pub const FC_WEIGHT_BLACK: c_int = 210;
+
+pub const FC_SLANT_ROMAN: c_int = 0;
+pub const FC_SLANT_ITALIC: c_int = 100;
+
+#[link_name = "fontconfig"]
+pub extern mod bindgen {
+    pub fn FcInit() -> FcBool;
+    pub fn FcPatternCreate() -> *FcPattern;
+    pub fn FcPatternDestroy(p: *FcPattern);
+    pub fn FcPatternAddString(p: *FcPattern, object: *c_char, s: *FcChar8) -> FcBool;
+    pub fn FcPatternAddInteger(p: *FcPattern, object: *c_char, i: c_int) -> FcBool;
+    pub fn FcPatternAddCharSet(p: *FcPattern, object: *c_char, c: *FcCharSet) -> FcBool;
+    pub fn FcPatternGetString(p: *FcPattern, object: *c_char, n: c_int,
+                              s: **FcChar8) -> FcResult;
+    pub fn FcPatternGetInteger(p: *FcPattern, object: *c_char, n: c_int,
+                               i: *c_int) -> FcResult;
+    pub fn FcConfigSubstitute(config: *FcConfig, p: *FcPattern, kind: FcMatchKind) -> FcBool;
+    pub fn FcDefaultSubstitute(pattern: *FcPattern);
+    pub fn FcCharSetCreate() -> *FcCharSet;
+    pub fn FcCharSetDestroy(fcs: *FcCharSet);
+    pub fn FcCharSetAddChar(fcs: *FcCharSet, ucs4: FcChar32) -> FcBool;
+    pub fn FcFontMatch(config: *FcConfig, p: *FcPattern, result: *FcResult) -> *FcPattern;
+}
@@ -1,11 +1,13 @@
 #[legacy_exports];
-export FreeTypeNativeFont, with_test_native_font, create;
+export FreeTypeNativeFont, with_test_native_font, create, match_face, match_face_for_char;
 
-use font::{FontMetrics, FractionalPixel};
+use font::{FontMetrics, FractionalPixel, FontDescriptor, FontFace, FileFace};
+use font::{is_generic_family, default_font_family};
 
 use gfx::geometry;
 use gfx::geometry::Au;
 use util::*;
+use libc::{c_char, c_int};
 use vec_as_buf = vec::as_imm_buf;
 use ptr::{addr_of, null};
 use cast::reinterpret_cast;
@@ -13,7 +15,7 @@ use glyph::GlyphIndex;
 use font::FontMetrics;
 use azure::freetype;
 use freetype::{ FT_Error, FT_Library, FT_Face, FT_Long, FT_ULong, FT_Size, FT_SizeRec,
-               FT_UInt, FT_GlyphSlot, FT_Size_Metrics, FT_FaceRec };
+               FT_UInt, FT_GlyphSlot, FT_Size_Metrics, FT_FaceRec, FT_F26Dot6 };
 use freetype::bindgen::{
     FT_Init_FreeType,
     FT_Done_FreeType,
@@ -23,6 +25,22 @@ use freetype::bindgen::{
     FT_Load_Glyph,
     FT_Set_Char_Size
 };
+use text::native_font::fontconfig::*;
+use text::native_font::fontconfig::bindgen::{
+    FcPatternCreate,
+    FcPatternDestroy,
+    FcPatternAddString,
+    FcPatternAddInteger,
+    FcPatternAddCharSet,
+    FcCharSetCreate,
+    FcCharSetDestroy,
+    FcCharSetAddChar,
+    FcPatternGetString,
+    FcPatternGetInteger,
+    FcConfigSubstitute,
+    FcDefaultSubstitute,
+    FcFontMatch
+};
 
 fn float_to_fixed_ft(f: float) -> i32 {
     float_to_fixed(6, f)
@@ -131,14 +149,16 @@ impl FreeTypeNativeFont {
     }
 }
 
-pub fn create(lib: &FT_Library, buf: @~[u8]) -> Result<FreeTypeNativeFont, ()> {
+pub fn create(lib: &FT_Library, buf: @~[u8], index: uint,
+              size: Au) -> Result<FreeTypeNativeFont, ()> {
     assert lib.is_not_null();
     let face: FT_Face = null();
     return vec_as_buf(*buf, |cbuf, _len| {
            if FT_New_Memory_Face(*lib, cbuf, (*buf).len() as FT_Long,
-                                 0 as FT_Long, addr_of(&face)).succeeded() {
-               // FIXME: These values are placeholders
-               let res = FT_Set_Char_Size(face, 0, 20*64, 0, 72);
+                                 index as FT_Long, addr_of(&face)).succeeded() {
+               // at 72 dpi, a point is a pixel
+               let char_size = float_to_fixed_ft(geometry::to_frac_px(size)) as FT_F26Dot6;
+               let res = FT_Set_Char_Size(face, 0, char_size, 0, 72);
                if !res.succeeded() { fail ~"unable to set font char size" }
                Ok(FreeTypeNativeFont(face, buf))
            } else {
@@ -147,6 +167,129 @@ pub fn create(lib: &FT_Library, buf: @~[u8]) -> Result<FreeTypeNativeFont, ()> {
     })
 }
 
+/**
+Finds the face that fontconfig gives for a descriptor. The families
+are tried in order: a named family is only used if it is installed,
+since fontconfig otherwise substitutes another one, while a generic
+family always matches. If none does, the default family is used.
+*/
+fn match_face(desc: &FontDescriptor) -> Option<FontFace> {
+    for desc.families.each |family| {
+        match fc_match([copy *family], desc, None) {
+          Some((move face, move matched_family)) => {
+            let installed = str::to_lower(matched_family) == str::to_lower(*family);
+            if installed || is_generic_family(*family) {
+                return Some(move face);
+            }
+            debug!("Font family '%s' is not installed", *family);
+          }
+          None => {}
+        }
+    }
+    fc_match([default_font_family()], desc, None).map(|matched| {
+        let (ref face, _) = *matched;
+        copy *face
+    })
+}
+
+/**
+Finds a face that has a glyph for a character. Fontconfig ranks
+covering the character above the family, so this is the first of the
+descriptor's families that covers it, or else some other installed
+face that does.
+*/
+fn match_face_for_char(desc: &FontDescriptor, c: char) -> Option<FontFace> {
+    fc_match(desc.families, desc, Some(c)).map(|matched| {
+        let (ref face, _) = *matched;
+        copy *face
+    })
+}
+
+/**
+Asks fontconfig for the best face of the given families, in order of
+preference, optionally one that covers a character. Returns the face
+and the family it is in.
+*/
+fn fc_match(families: &[~str], desc: &FontDescriptor,
+            c: Option<char>) -> Option<(FontFace, ~str)> unsafe {
+    let pattern = FcPatternCreate();
+    assert pattern.is_not_null();
+    do str::as_c_str(FC_FAMILY) |object| {
+        for families.each |family| {
+            do str::as_c_str(*family) |name| {
+                FcPatternAddString(pattern, object, name as *FcChar8);
+            }
+        }
+    }
+    match c {
+      Some(c) => {
+        let charset = FcCharSetCreate();
+        FcCharSetAddChar(charset, c as FcChar32);
+        do str::as_c_str(FC_CHARSET) |object| {
+            FcPatternAddCharSet(pattern, object, charset);
+        }
+        // the pattern holds its own copy
+        FcCharSetDestroy(charset);
+      }
+      None => {}
+    }
+    do str::as_c_str(FC_WEIGHT) |object| {
+        FcPatternAddInteger(pattern, object, fc_weight(desc.weight));
+    }
+    do str::as_c_str(FC_SLANT) |object| {
+        FcPatternAddInteger(pattern, object, if desc.italic { FC_SLANT_ITALIC }
+                                             else { FC_SLANT_ROMAN });
+    }
+    FcConfigSubstitute(null(), pattern, FcMatchPattern);
+    FcDefaultSubstitute(pattern);
+
+    let result: FcResult = FcResultMatch;
+    let matched = FcFontMatch(null(), pattern, addr_of(&result));
+    FcPatternDestroy(pattern);
+    if matched.is_null() { return None; }
+
+    let face = match (get_string(matched, FC_FILE), get_integer(matched, FC_INDEX),
+                      get_string(matched, FC_FAMILY)) {
+      (Some(move file), Some(index), Some(move name)) if result == FcResultMatch => {
+        Some((FileFace(move file, index as uint), move name))
+      }
+      _ => None
+    };
+    FcPatternDestroy(matched);
+    move face
+}
+
+fn get_string(pattern: *FcPattern, object: &str) -> Option<~str> unsafe {
+    let value: *FcChar8 = null();
+    let result = do str::as_c_str(object) |object| {
+        FcPatternGetString(pattern, object, 0, addr_of(&value))
+    };
+    if result == FcResultMatch { Some(str::raw::from_c_str(value as *c_char)) } else { None }
+}
+
+fn get_integer(pattern: *FcPattern, object: &str) -> Option<c_int> unsafe {
+    let value: c_int = 0;
+    let result = do str::as_c_str(object) |object| {
+        FcPatternGetInteger(pattern, object, 0, addr_of(&value))
+    };
+    if result == FcResultMatch { Some(value) } else { None }
+}
+
+/** Converts a CSS font weight to fontconfig's scale. */
+pure fn fc_weight(weight: uint) -> c_int {
+    match weight / 100u {
+      0u | 1u => FC_WEIGHT_THIN,
+      2u => FC_WEIGHT_EXTRALIGHT,
+      3u => FC_WEIGHT_LIGHT,
+      4u => FC_WEIGHT_REGULAR,
+      5u => FC_WEIGHT_MEDIUM,
+      6u => FC_WEIGHT_DEMIBOLD,
+      7u => FC_WEIGHT_BOLD,
+      8u => FC_WEIGHT_EXTRABOLD,
+      _ => FC_WEIGHT_BLACK
+    }
+}
+
 trait FTErrorMethods {
     fn succeeded() -> bool;
 }
@@ -156,12 +299,12 @@ impl FT_Error : FTErrorMethods {
 }
 
 fn with_test_native_font(f: fn@(nf: &NativeFont)) {
-    use font::test_font_bin;
+    use font::{test_font_bin, TEST_FONT_SIZE_PX};
     use unwrap_result = result::unwrap;
 
     with_lib(|lib| {
         let buf = @test_font_bin();
-        let font = unwrap_result(create(lib, move buf));
+        let font = unwrap_result(create(lib, move buf, 0, geometry::from_px(TEST_FONT_SIZE_PX)));
         f(&font);
     })
 }
@@ -177,6 +320,6 @@ fn with_lib(f: fn@((&FT_Library))) {
 fn create_should_return_err_if_buf_is_bogus() {
     with_lib(|lib| {
         let buf = @~[];
-        assert create(lib, buf).is_err();
+        assert create(lib, buf, 0, geometry::from_px(16)).is_err();
     })
 }
@@ -7,6 +7,7 @@ export QuartzNativeFont, with_test_native_font, create;
 use font::{FontMetrics, FractionalPixel};
 
 use au = gfx::geometry;
+use au::Au;
 use cast::transmute;
 use libc::size_t;
 use ptr::null;
@@ -68,11 +69,12 @@ pub struct QuartzNativeFont {
     }
 }
 
-fn QuartzNativeFont(fontprov: CGDataProviderRef, cgfont: CGFontRef) -> QuartzNativeFont {
+fn QuartzNativeFont(fontprov: CGDataProviderRef, cgfont: CGFontRef,
+                    size: Au) -> QuartzNativeFont {
     assert fontprov.is_not_null();
     assert cgfont.is_not_null();
 
-    let ctfont = ctfont_from_cgfont(cgfont);
+    let ctfont = ctfont_from_cgfont(cgfont, size);
     assert ctfont.is_not_null();
 
     QuartzNativeFont {
@@ -121,36 +123,35 @@ impl QuartzNativeFont {
         assert ctfont.is_not_null();
 
         let bounding_rect: CGRect = CTFontGetBoundingBox(ctfont);
-        let ascent = au::from_pt(CTFontGetAscent(ctfont) as float);
-        let descent = au::from_pt(CTFontGetDescent(ctfont) as float);
+        let ascent = au::from_frac_px(CTFontGetAscent(ctfont) as float);
+        let descent = au::from_frac_px(CTFontGetDescent(ctfont) as float);
 
         let metrics =  FontMetrics {
-            underline_size:   au::from_pt(CTFontGetUnderlineThickness(ctfont) as float),
+            underline_size:   au::from_frac_px(CTFontGetUnderlineThickness(ctfont) as float),
             // TODO: underline metrics are not reliable. Have to pull out of font table directly.
             // see also: https://bugs.webkit.org/show_bug.cgi?id=16768
             // see also: https://bugreports.qt-project.org/browse/QTBUG-13364
-            underline_offset: au::from_pt(CTFontGetUnderlinePosition(ctfont) as float),
-            leading:          au::from_pt(CTFontGetLeading(ctfont) as float),
-            x_height:         au::from_pt(CTFontGetXHeight(ctfont) as float),
+            underline_offset: au::from_frac_px(CTFontGetUnderlinePosition(ctfont) as float),
+            leading:          au::from_frac_px(CTFontGetLeading(ctfont) as float),
+            x_height:         au::from_frac_px(CTFontGetXHeight(ctfont) as float),
             em_size:          ascent + descent,
             ascent:           ascent,
             descent:          descent,
-            max_advance:      au::from_pt(bounding_rect.size.width as float)
+            max_advance:      au::from_frac_px(bounding_rect.size.width as float)
         };
 
-        debug!("Font metrics (@%f pt): %?", CTFontGetSize(ctfont) as float, metrics);
+        debug!("Font metrics (@%f px): %?", CTFontGetSize(ctfont) as float, metrics);
         return metrics;
     }
 }
 
-fn ctfont_from_cgfont(cgfont: CGFontRef) -> CTFontRef {
+fn ctfont_from_cgfont(cgfont: CGFontRef, size: Au) -> CTFontRef {
     assert cgfont.is_not_null();
 
-    // TODO: use actual font size here!
-    CTFontCreateWithGraphicsFont(cgfont, 21f as CGFloat, null(), null())
+    CTFontCreateWithGraphicsFont(cgfont, au::to_frac_px(size) as CGFloat, null(), null())
 }
 
-pub fn create(buf: @~[u8]) -> Result<QuartzNativeFont, ()> {
+pub fn create(buf: @~[u8], size: Au) -> Result<QuartzNativeFont, ()> {
     let fontprov = vec::as_imm_buf(*buf, |cbuf, len| {
         CGDataProviderCreateWithData(
             null(),
@@ -163,18 +164,18 @@ pub fn create(buf: @~[u8]) -> Result<QuartzNativeFont, ()> {
     let cgfont = CGFontCreateWithDataProvider(fontprov);
 
     match cgfont.is_not_null() {
-        true => Ok(QuartzNativeFont(fontprov, cgfont)),
+        true => Ok(QuartzNativeFont(fontprov, cgfont, size)),
         false => Err(())
     }
     
 }
 
 pub fn with_test_native_font(f: fn@(nf: &NativeFont)) {
-    use font::test_font_bin;
+    use font::{test_font_bin, TEST_FONT_SIZE_PX};
     use unwrap_result = result::unwrap;
 
     let buf = @test_font_bin();
-    let res = create(buf);
+    let res = create(buf, au::from_px(TEST_FONT_SIZE_PX));
     let font = unwrap_result(move res);
     f(&font);
 }
@@ -11,16 +11,18 @@ use libc::types::common::c99::int32_t;
 use libc::{c_uint, c_int, c_void, c_char};
 use ptr::{null, to_unsafe_ptr, offset};
 use std::arc;
-use text_run::TextRun;
+use text_run::{TextRun, TextSegment};
+use bidi::is_rtl;
+use servo_util::range::Range;
 use util::*;
 
 
 use cast::reinterpret_cast;
 use harfbuzz::{HB_MEMORY_MODE_READONLY,
-                  HB_DIRECTION_LTR};
+                  HB_DIRECTION_LTR, HB_DIRECTION_RTL};
 use harfbuzz::{hb_blob_t, hb_face_t, hb_font_t, hb_font_funcs_t, hb_buffer_t,
                   hb_codepoint_t, hb_bool_t, hb_glyph_position_t,
-		  hb_glyph_info_t, hb_var_int_t, hb_position_t};
+		  hb_glyph_info_t, hb_var_int_t, hb_position_t, hb_script_t};
 use harfbuzz::bindgen::{hb_blob_create, hb_blob_destroy,
                            hb_face_create, hb_face_destroy,
                            hb_font_create, hb_font_destroy,
@@ -30,6 +32,7 @@ use harfbuzz::bindgen::{hb_blob_create, hb_blob_destroy,
                            hb_buffer_get_glyph_positions,
                            hb_font_set_ppem, hb_font_set_scale,
                            hb_buffer_set_direction,
+                           hb_buffer_set_script,
                            hb_font_funcs_create, hb_font_funcs_destroy,
                            hb_font_set_funcs,
                            hb_font_funcs_set_glyph_h_advance_func,
@@ -50,14 +53,56 @@ fn fixed_to_rounded_int_hb(f: hb_position_t) -> int {
 
 /**
 Calculate the layout metrics associated with a some given text
-when rendered in a specific font.
+when rendered in a specific font. Each segment of the run is shaped
+with its own font, in the direction of its embedding level.
 */
 pub fn shape_textrun(run: &TextRun) {
     debug!("shaping text '%s'", run.text);
 
-    // TODO: harfbuzz fonts and faces should be cached on the Font object.
+    for run.segments.each |segment| {
+        shape_segment(run, segment);
+    }
+}
+
+/**
+The HarfBuzz objects that shape text in one font, at its size. A font
+owns these once it has shaped text, so they are made once per face and
+size, like the font itself.
+*/
+pub struct HarfBuzzFont {
+    priv blob: *hb_blob_t,
+    priv face: *hb_face_t,
+    priv font: *hb_font_t,
+    priv funcs: *hb_font_funcs_t,
+
+    drop {
+        hb_font_funcs_destroy(self.funcs);
+        hb_font_destroy(self.font);
+        hb_face_destroy(self.face);
+        hb_blob_destroy(self.blob);
+    }
+}
+
+/**
+Returns the HarfBuzz objects of a font, making them the first time. They
+are made once the font is boxed, since HarfBuzz calls back into the font
+through its address.
+*/
+pub fn get_harfbuzz_font(font: @Font) -> @HarfBuzzFont {
+    match font.harfbuzz_font {
+      Some(hb_font) => hb_font,
+      None => {
+        let hb_font = @HarfBuzzFont(font);
+        font.harfbuzz_font = Some(hb_font);
+        hb_font
+      }
+    }
+}
+
+fn HarfBuzzFont(font: @Font) -> HarfBuzzFont {
     // TODO: font tables should be stored in Font object and cached by FontCache (Issue #92)
-    let face_blob: *hb_blob_t = vec::as_imm_buf(*(run.font).fontbuf, |buf: *u8, len: uint| {
+    // The blob doesn't copy the font's data, which lives as long as the font.
+    let face_blob: *hb_blob_t = vec::as_imm_buf(*font.fontbuf, |buf: *u8, len: uint| {
         hb_blob_create(buf as *c_char,
                        len as c_uint,
                        HB_MEMORY_MODE_READONLY,
@@ -65,34 +110,82 @@ pub fn shape_textrun(run: &TextRun) {
                        null())
     });
 
-    let hb_face: *hb_face_t = hb_face_create(face_blob, 0 as c_uint);
+    let hb_face: *hb_face_t = hb_face_create(face_blob, font.face.index() as c_uint);
     let hb_font: *hb_font_t = hb_font_create(hb_face);
 
-    // TODO: set font size here, based on Font's size
+    let size = au::to_frac_px(font.descriptor.size);
     // Set points-per-em. if zero, performs no hinting in that direction.
-    hb_font_set_ppem(hb_font, 21 as c_uint, 21 as c_uint);
+    let ppem = float::round(size) as c_uint;
+    hb_font_set_ppem(hb_font, ppem, ppem);
     // Set scaling. Note that this takes 16.16 fixed point.
-    hb_font_set_scale(hb_font, float_to_fixed_hb(21f) as c_int, float_to_fixed_hb(21f) as c_int);
+    let scale = float_to_fixed_hb(size) as c_int;
+    hb_font_set_scale(hb_font, scale, scale);
 
     let funcs: *hb_font_funcs_t = hb_font_funcs_create();
     hb_font_funcs_set_glyph_func(funcs, glyph_func, null(), null());
     hb_font_funcs_set_glyph_h_advance_func(funcs, glyph_h_advance_func, null(), null());
 
-    unsafe {
-        let font_data: *c_void = core::ptr::addr_of(run.font) as *c_void;
-        hb_font_set_funcs(hb_font, funcs, font_data, null());
-    };
+    let font_data: *c_void = to_unsafe_ptr(&*font) as *c_void;
+    hb_font_set_funcs(hb_font, funcs, font_data, null());
+
+    HarfBuzzFont {
+        blob: face_blob,
+        face: hb_face,
+        font: hb_font,
+        funcs: funcs
+    }
+}
+
+/** Makes a HarfBuzz tag, such as a script's, from its four letters. */
+pure fn hb_tag(a: char, b: char, c: char, d: char) -> u32 {
+    ((a as u32) << 24) | ((b as u32) << 16) | ((c as u32) << 8) | (d as u32)
+}
+
+/**
+The script of the first character in a range that belongs to one of the
+right-to-left scripts, which need their script to be shaped. HarfBuzz
+guesses the script of other text.
+*/
+fn script_for_range(text: &str, range: Range) -> Option<hb_script_t> {
+    let mut i = range.begin();
+    while i < range.end() {
+        let {ch, next} = str::char_range_at(text, i);
+        let tag = match ch as uint {
+          0x0590 .. 0x05FF | 0xFB1D .. 0xFB4F => Some(hb_tag('H', 'e', 'b', 'r')),
+          0x0600 .. 0x06FF | 0x0750 .. 0x077F | 0x08A0 .. 0x08FF | 0xFB50 .. 0xFDFF
+              | 0xFE70 .. 0xFEFE => Some(hb_tag('A', 'r', 'a', 'b')),
+          0x0700 .. 0x074F => Some(hb_tag('S', 'y', 'r', 'c')),
+          0x0780 .. 0x07BF => Some(hb_tag('T', 'h', 'a', 'a')),
+          0x07C0 .. 0x07FF => Some(hb_tag('N', 'k', 'o', 'o')),
+          _ => None
+        };
+        if tag.is_some() { return tag.map(|tag| *tag as hb_script_t); }
+        i = next;
+    }
+    None
+}
+
+fn shape_segment(run: &TextRun, segment: &TextSegment) {
+    let font = segment.font, range = segment.range;
+    let hb_font = get_harfbuzz_font(font).font;
 
     let hb_buffer: *hb_buffer_t = hb_buffer_create();
-    hb_buffer_set_direction(hb_buffer, HB_DIRECTION_LTR);
+    // right-to-left glyphs come out in visual order, so their clusters go backwards
+    hb_buffer_set_direction(hb_buffer, if is_rtl(segment.level) { HB_DIRECTION_RTL }
+                                       else { HB_DIRECTION_LTR });
+    match script_for_range(run.text, range) {
+      Some(script) => hb_buffer_set_script(hb_buffer, script),
+      None => {}
+    }
 
+    // The whole text is given, as context for the segment, so that clusters are offsets in it.
     // Using as_buf because it never does a copy - we don't need the trailing null
     str::as_buf(run.text, |ctext: *u8, _l: uint| {
         hb_buffer_add_utf8(hb_buffer, 
                            ctext as *c_char,
                            run.text.len() as c_int,
-                           0 as c_uint,
-                           run.text.len() as c_int);
+                           range.begin() as c_uint,
+                           range.length() as c_int);
     });
 
     hb_shape(hb_font, hb_buffer, null(), 0 as c_uint);
@@ -106,6 +199,9 @@ pub fn shape_textrun(run: &TextRun) {
 
     assert info_buf_len == pos_buf_len;
 
+    // the glyphs of each cluster are stored at the offset of the cluster's first char
+    let mut cluster_glyphs: ~[GlyphData] = ~[];
+    let mut cluster = 0u;
     for uint::range(0u, info_buf_len as uint) |i| { unsafe {
         let hb_info: hb_glyph_info_t = *offset(info_buf, i);
         let hb_pos: hb_glyph_position_t = *offset(pos_buf, i);
@@ -117,20 +213,32 @@ pub fn shape_textrun(run: &TextRun) {
                                     au::from_frac_px(fixed_to_float_hb(y))))
         };
         // TODO: convert pos.y_advance into offset adjustment
-        // TODO: handle multiple glyphs per char, ligatures, etc.
+        // TODO: handle ligatures, etc.
         // See Issue #
-        debug!("glyph %?: index %?, advance %?, offset %?",
-               i, codepoint, advance, offset);
+        debug!("glyph %?: index %?, advance %?, offset %?, cluster %?",
+               i, codepoint, advance, offset, hb_info.cluster);
 
-        let data = GlyphData(codepoint, advance, offset, false, false, false);
-        run.glyphs.add_glyph_for_index(i, &data);
+        if hb_info.cluster as uint != cluster && cluster_glyphs.is_not_empty() {
+            add_glyphs_for_cluster(run, cluster, cluster_glyphs);
+            cluster_glyphs = ~[];
+        }
+        cluster = hb_info.cluster as uint;
+        let first = cluster_glyphs.is_empty();
+        vec::push(&mut cluster_glyphs, GlyphData(codepoint, advance, offset, false, first, false));
     } /* unsafe */ }
+    if cluster_glyphs.is_not_empty() {
+        add_glyphs_for_cluster(run, cluster, cluster_glyphs);
+    }
 
     hb_buffer_destroy(hb_buffer);
-    hb_font_funcs_destroy(funcs);
-    hb_font_destroy(hb_font);
-    hb_face_destroy(hb_face);
-    hb_blob_destroy(face_blob);
+}
+
+fn add_glyphs_for_cluster(run: &TextRun, cluster: uint, glyphs: &[GlyphData]) {
+    if glyphs.len() == 1 {
+        run.glyphs.add_glyph_for_index(cluster, &glyphs[0]);
+    } else {
+        run.glyphs.add_glyphs_for_index(cluster, glyphs);
+    }
 }
 
 extern fn glyph_func(_font: *hb_font_t,
@@ -158,3 +266,19 @@ extern fn glyph_h_advance_func(_font: *hb_font_t,
     let advance = (*font).glyph_h_advance(glyph as GlyphIndex);
     float_to_fixed_hb(advance)
 }
+
+#[test]
+fn should_make_harfbuzz_fonts_once_per_font() {
+    let flib = FontCache();
+    let font = flib.get_test_font();
+    let hb_font = get_harfbuzz_font(font);
+    assert box::ptr_eq(hb_font, get_harfbuzz_font(font));
+}
+
+#[test]
+fn should_find_scripts_of_right_to_left_text() {
+    let text = ~"ab אב";
+    let hebrew = hb_tag('H', 'e', 'b', 'r') as hb_script_t;
+    assert script_for_range(text, Range(0, text.len())) == Some(hebrew);
+    assert script_for_range(text, Range(0, 3)).is_none();
+}
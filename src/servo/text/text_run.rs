@@ -1,12 +1,14 @@
 use arc = std::arc;
 use arc::ARC;
 use au = gfx::geometry;
-use font::{RunMetrics, Font};
+use bidi::Level;
+use font::{RunMetrics, Font, FontDescriptor, FontFace};
 use font_cache::FontCache;
 use geom::point::Point2D;
 use geom::size::Size2D;
 use gfx::geometry::Au;
-use glyph::GlyphStore;
+use glyph::{GlyphStore, BreakTypeNone};
+use line_break::break_opportunities;
 use layout::context::LayoutContext;
 use libc::{c_void};
 use servo_util::color;
@@ -16,23 +18,39 @@ use servo_util::range::{Range, MutableRange};
 
 pub struct TextRun {
     text: ~str,
+    // what the text is styled with
+    descriptor: FontDescriptor,
+    // the font for the descriptor, whose metrics are the run's
     font: @Font,
+    // the font and embedding level of each part of the text, in order
+    segments: ~[TextSegment],
     priv glyphs: GlyphStore,
 }
 
+/** A part of a run's text whose glyphs all come from one font, at one embedding level. */
+pub struct TextSegment {
+    range: Range,
+    font: @Font,
+    level: Level,
+}
+
 // This is a hack until TextRuns are normally sendable, or
 // we instead use ARC<TextRun> everywhere.
 pub struct SendableTextRun {
     text: ~str,
-    font_descriptor: (),
+    font_descriptor: FontDescriptor,
+    // the face of each segment's font, and its level; the size is the descriptor's
+    font_segments: ~[(Range, FontFace, Level)],
     priv glyphs: GlyphStore,
 }
 
 pub fn serialize(_cache: @FontCache, run: &TextRun) -> ~SendableTextRun {
     ~SendableTextRun {
         text: copy run.text,
-        // TODO: actually serialize a font descriptor thingy
-        font_descriptor: (),
+        font_descriptor: copy run.descriptor,
+        font_segments: do run.segments.map |segment| {
+            (segment.range, copy segment.font.face, segment.level)
+        },
         glyphs: copy run.glyphs,
     }
 }
@@ -40,14 +58,27 @@ pub fn serialize(_cache: @FontCache, run: &TextRun) -> ~SendableTextRun {
 pub fn deserialize(cache: @FontCache, run: &SendableTextRun) -> @TextRun {
     @TextRun {
         text: copy run.text,
-        // TODO: actually deserialize a font descriptor thingy
-        font: cache.get_test_font(),
+        descriptor: copy run.font_descriptor,
+        font: cache.get_font(&run.font_descriptor),
+        segments: do run.font_segments.map |segment| {
+            let (range, ref face, level) = *segment;
+            TextSegment {
+                range: range,
+                font: cache.get_font_for_face(copy *face, &run.font_descriptor),
+                level: level
+            }
+        },
         glyphs: copy run.glyphs
     }
 }
 
 trait TextRunMethods {
     pure fn glyphs(&self) -> &self/GlyphStore;
+    fn iter_segments_for_range(&self, range: Range, f: fn&(&TextSegment) -> bool);
+    fn level_for_range(&self, range: Range) -> Level;
+    fn iter_level_runs_for_range(&self, range: Range, f: fn&(Level, Range) -> bool);
+    fn can_break_before(&self, offset: uint) -> bool;
+    fn expansion_opportunities_for_range(&self, range: Range) -> uint;
     fn iter_indivisible_pieces_for_range(&self, range: Range, f: fn&(Range) -> bool);
     // TODO: needs to take box style as argument, or move to TextBox.
     // see Gecko's IsTrimmableSpace methods for details.
@@ -61,6 +92,45 @@ trait TextRunMethods {
 impl TextRun : TextRunMethods {
     pure fn glyphs(&self) -> &self/GlyphStore { &self.glyphs }
 
+    /** Calls `f` with each segment that a range overlaps, cut down to the range. */
+    fn iter_segments_for_range(&self, range: Range, f: fn&(&TextSegment) -> bool) {
+        for self.segments.each |segment| {
+            let begin = uint::max(segment.range.begin(), range.begin());
+            let end = uint::min(segment.range.end(), range.end());
+            let part = TextSegment { range: Range(begin, end - begin), .. *segment };
+            if begin < end && !f(&part) { break; }
+        }
+    }
+
+    /** The embedding level of the start of a range. */
+    fn level_for_range(&self, range: Range) -> Level {
+        for self.segments.each |segment| {
+            if segment.range.end() > range.begin() { return segment.level; }
+        }
+        0u8
+    }
+
+    /** Calls `f` with each longest part of a range whose text is all at one level. */
+    fn iter_level_runs_for_range(&self, range: Range, f: fn&(Level, Range) -> bool) {
+        let mut current: Option<(Level, uint, uint)> = None;
+        for self.iter_segments_for_range(range) |segment| {
+            current = match current {
+              Some((level, begin, _)) if level == segment.level => {
+                Some((level, begin, segment.range.end()))
+              }
+              Some((level, begin, end)) => {
+                if !f(level, Range(begin, end - begin)) { return; }
+                Some((segment.level, segment.range.begin(), segment.range.end()))
+              }
+              None => Some((segment.level, segment.range.begin(), segment.range.end()))
+            };
+        }
+        match current {
+          Some((level, begin, end)) => { f(level, Range(begin, end - begin)); }
+          None => {}
+        }
+    }
+
     pure fn range_is_trimmable_whitespace(&self, range: Range) -> bool {
         let mut i = range.begin();
         while i < range.end() {
@@ -118,60 +188,166 @@ impl TextRun : TextRunMethods {
         }
     }
 
+    /**
+    Whether a line may break before an offset in the text. The start of
+    the run is a break opportunity, since what comes before it is in
+    another run.
+    */
+    fn can_break_before(&self, offset: uint) -> bool {
+        if offset == 0 { return true; }
+        match self.glyphs.can_break_before(offset) {
+            BreakTypeNone => false,
+            _ => true
+        }
+    }
+
+    /** The number of chars in a range that justification may add space after. */
+    fn expansion_opportunities_for_range(&self, range: Range) -> uint {
+        let mut count = 0;
+        for range.eachi |i| {
+            if self.glyphs.char_is_space(i) { count += 1; }
+        }
+        count
+    }
+
+    /**
+    Calls `f` with the pieces of a range that a line can't break inside:
+    the text between break opportunities, with any whitespace at the end
+    of it as a piece of its own, since that is trimmed at line ends.
+    */
     fn iter_indivisible_pieces_for_range(&self, range: Range, f: fn(Range) -> bool) {
         assert range.is_valid_for_string(self.text);
 
-        let clump = MutableRange(range.begin(), 0);
-        loop {
-            // find next non-whitespace byte index, then clump all whitespace before it.
-            match str::find_between(self.text, clump.begin(), range.end(), |c| !char::is_whitespace(c)) {
-                Some(nonws_char_offset) => {
-                    clump.extend_to(nonws_char_offset);
-                    if !f(clump.as_immutable()) { break }
-                    clump.reset(clump.end(), 0);
-                },
-                None => {
-                    // nothing left, flush last piece containing only whitespace
-                    if clump.end() < range.end() {
-                        clump.extend_to(range.end());
-                        f(clump.as_immutable());
-                        break;
-                    }
+        let mut piece_start = range.begin();
+        let mut in_whitespace = false;
+        let mut i = range.begin();
+        while i < range.end() {
+            let {ch, next} = str::char_range_at(self.text, i);
+            let is_whitespace = char::is_whitespace(ch);
+            let boundary = if i == range.begin() {
+                false
+            } else if self.can_break_before(i) {
+                true
+            } else if is_whitespace && !in_whitespace {
+                // whitespace that a line may break after is a piece of its own
+                let ws_end = str::find_between(self.text, i, range.end(),
+                                               |c| !char::is_whitespace(c));
+                match ws_end {
+                    Some(end) => self.can_break_before(end),
+                    None => true
                 }
+            } else {
+                false
             };
-
-            // find next whitespace byte index, then clump all non-whitespace before it.
-            match str::find_between(self.text, clump.begin(), range.end(), |c| char::is_whitespace(c)) {
-                Some(ws_char_offset) => {
-                    clump.extend_to(ws_char_offset);
-                    if !f(clump.as_immutable()) { break }
-                    clump.reset(clump.end(), 0);
-                }
-                None => {
-                    // nothing left, flush last piece containing only non-whitespaces
-                    if clump.end() < range.end() {
-                        clump.extend_to(range.end());
-                        f(clump.as_immutable());
-                        break;
-                    }
-                }
+            if boundary {
+                if !f(Range(piece_start, i - piece_start)) { return; }
+                piece_start = i;
             }
+            in_whitespace = is_whitespace;
+            i = next;
+        }
+        if piece_start < range.end() {
+            f(Range(piece_start, range.end() - piece_start));
         }
     }
 }
  
-fn TextRun(font: @Font, text: ~str) -> TextRun {
+/**
+Makes a run of text styled with a descriptor. `levels` holds the
+embedding level of each byte of the text, which the bidi algorithm
+resolves over the whole paragraph that the run is part of.
+*/
+fn TextRun(cache: @FontCache, desc: &FontDescriptor, text: ~str, levels: &[Level]) -> TextRun {
+    assert levels.len() == text.len();
+    let font = cache.get_font(desc);
+    let segments = segment_text(cache, desc, font, text, levels);
     let glyph_store = GlyphStore(text.len());
     let run = TextRun {
         text: move text,
+        descriptor: copy *desc,
         font: font,
+        segments: move segments,
         glyphs: move glyph_store,
     };
 
     shape_textrun(&run);
+    // shaping sets the glyph entries, so newlines, spaces and line breaks are stored after it
+    let mut i = 0;
+    while i < run.text.len() {
+        let {ch, next} = str::char_range_at(run.text, i);
+        match ch {
+            '\n' => run.glyphs.add_newline_for_index(i),
+            // spaces are the expansion opportunities that justification widens
+            ' ' | '\u00a0' => run.glyphs.set_char_is_space(i),
+            _ => {}
+        }
+        i = next;
+    }
+    for break_opportunities(run.text).each |opportunity| {
+        let (offset, kind) = *opportunity;
+        run.glyphs.set_can_break_before(offset, kind);
+    }
     return move run;
 }
 
+/**
+Splits text into the parts that each font draws at each embedding
+level. A character goes to the descriptor's own font if that has a
+glyph for it, or else to the font of the character before it, a
+fallback font found earlier in the text, or a new fallback font, in
+that order. Whitespace and control characters stay in the font they
+are in.
+*/
+fn segment_text(cache: @FontCache, desc: &FontDescriptor, primary: @Font,
+                text: &str, levels: &[Level]) -> ~[TextSegment] {
+    fn has_glyph(font: @Font, c: char) -> bool {
+        font.glyph_index(c).is_some()
+    }
+
+    let mut segments = ~[];
+    let mut fallbacks = ~[];
+    let mut start = 0u, current = primary;
+    let mut current_level = if levels.is_empty() { 0u8 } else { levels[0] };
+    let mut i = 0u;
+    while i < text.len() {
+        let {ch, next} = str::char_range_at(text, i);
+        let font = if char::is_whitespace(ch) || (ch as uint) < 0x20u {
+            current
+        } else if has_glyph(primary, ch) {
+            primary
+        } else if has_glyph(current, ch) {
+            current
+        } else {
+            match fallbacks.find(|font| has_glyph(*font, ch)) {
+                Some(font) => font,
+                None => match cache.get_fallback_font(desc, ch) {
+                    Some(font) => {
+                        vec::push(&mut fallbacks, font);
+                        font
+                    }
+                    // no installed face has it, so the missing glyph is drawn
+                    None => primary
+                }
+            }
+        };
+        if !box::ptr_eq(font, current) || levels[i] != current_level {
+            if i > start {
+                vec::push(&mut segments, TextSegment { range: Range(start, i - start),
+                                                       font: current, level: current_level });
+            }
+            start = i;
+            current = font;
+            current_level = levels[i];
+        }
+        i = next;
+    }
+    if text.len() > start {
+        vec::push(&mut segments, TextSegment { range: Range(start, text.len() - start),
+                                               font: current, level: current_level });
+    }
+    move segments
+}
+
 // this test can't run until LayoutContext is removed as an argument
 // to min_width_for_range.
 /*
@@ -179,9 +355,11 @@ fn TextRun(font: @Font, text: ~str) -> TextRun {
 fn test_calc_min_break_width() {
 
     fn test_min_width_for_run(text: ~str, width: Au) {
+        use font::test_font_descriptor;
+
         let flib = FontCache();
-        let font = flib.get_test_font();
-        let run = TextRun(font, text);
+        let levels = vec::from_elem(text.len(), 0u8);
+        let run = TextRun(flib, &test_font_descriptor(), text, levels);
         run.min_width_for_range(0, text.len())
     }
 
@@ -196,9 +374,11 @@ fn test_calc_min_break_width() {
 #[ignore]
 fn test_iter_indivisible_pieces() {
     fn test_pieces(text: ~str, res: ~[~str]) {
+        use font::test_font_descriptor;
+
         let flib = FontCache();
-        let font = flib.get_test_font();
-        let run = TextRun(font, copy text);
+        let run = TextRun(flib, &test_font_descriptor(), copy text,
+                          vec::from_elem(text.len(), 0u8));
         let mut slices : ~[~str] = ~[];
         for run.iter_indivisible_pieces_for_range(Range(0, text.len())) |subrange| {
             slices.push(str::slice(text, subrange.begin(), subrange.length()));
@@ -213,3 +393,58 @@ fn test_iter_indivisible_pieces() {
     test_pieces(~"", ~[]);
 }
 
+#[test]
+fn should_keep_text_its_font_covers_in_one_segment() {
+    use font::test_font_descriptor;
+
+    let flib = FontCache();
+    let text = ~"firecracker yumyum";
+    let run = TextRun(flib, &test_font_descriptor(), copy text, vec::from_elem(text.len(), 0u8));
+    assert run.segments.len() == 1;
+    assert run.segments[0].range.begin() == 0 && run.segments[0].range.end() == run.text.len();
+    assert box::ptr_eq(run.segments[0].font, run.font);
+}
+
+#[test]
+fn should_split_segments_by_embedding_level() {
+    use bidi::resolve_levels;
+    use font::test_font_descriptor;
+
+    let flib = FontCache();
+    // two Hebrew letters of two bytes each, between Latin words
+    let text = ~"ab אב cd";
+    let run = TextRun(flib, &test_font_descriptor(), copy text, resolve_levels(text, 0u8));
+    let mut level_runs = ~[];
+    for run.iter_level_runs_for_range(Range(0, run.text.len())) |level, range| {
+        vec::push(&mut level_runs, (level, range.begin(), range.end()));
+    }
+    assert level_runs == ~[(0u8, 0u, 3u), (1u8, 3u, 7u), (0u8, 7u, 10u)];
+    assert run.level_for_range(Range(3, 4)) == 1u8;
+}
+
+#[test]
+fn should_give_preserved_newlines_no_glyphs() {
+    use font::test_font_descriptor;
+
+    let flib = FontCache();
+    let text = ~"one\ntwo";
+    let run = TextRun(flib, &test_font_descriptor(), copy text, vec::from_elem(text.len(), 0u8));
+    assert run.glyphs.char_is_newline(3);
+    assert !run.glyphs.char_is_newline(2);
+    let mut glyph_count = 0;
+    for run.glyphs.iter_glyphs_for_range(Range(3, 1)) |_i, _glyph| { glyph_count += 1; }
+    assert glyph_count == 0;
+    assert run.metrics_for_range(Range(3, 1)).advance_width == Au(0);
+}
+
+#[test]
+fn should_count_spaces_as_expansion_opportunities() {
+    use font::test_font_descriptor;
+
+    let flib = FontCache();
+    let text = ~"one two\u00a0three  four";
+    let run = TextRun(flib, &test_font_descriptor(), copy text, vec::from_elem(text.len(), 0u8));
+    assert run.expansion_opportunities_for_range(Range(0, run.text.len())) == 4;
+    assert run.expansion_opportunities_for_range(Range(0, 3)) == 0;
+    assert run.glyphs.char_is_space(7) && !run.glyphs.char_is_space(8);
+}
@@ -2,6 +2,7 @@ enum CompressionMode {
     CompressNone,
     CompressWhitespace,
     CompressWhitespaceNewline,
+    CompressWhitespaceAroundNewline,
     DiscardNewline
 }
 
@@ -11,6 +12,7 @@ impl CompressionMode : cmp::Eq {
             (CompressNone, CompressNone) => true,
             (CompressWhitespace, CompressWhitespace) => true,
             (CompressWhitespaceNewline, CompressWhitespaceNewline) => true,
+            (CompressWhitespaceAroundNewline, CompressWhitespaceAroundNewline) => true,
             (DiscardNewline, DiscardNewline) => true,
             _ => false
         }
@@ -47,9 +49,18 @@ pub fn transform_text(text: &str, mode: CompressionMode) -> ~str {
             }
         },
 
-        CompressWhitespace | CompressWhitespaceNewline => {
+        CompressWhitespace | CompressWhitespaceNewline | CompressWhitespaceAroundNewline => {
             let mut in_whitespace: bool = false;
             for str::each_char(text) |ch: char| {
+                if ch == '\n' && mode == CompressWhitespaceAroundNewline {
+                    // the newline is kept, and the spaces and tabs on either side of it removed
+                    if str::ends_with(out_str, " ") {
+                        str::pop_char(&mut out_str);
+                    }
+                    str::push_char(&mut out_str, ch);
+                    in_whitespace = true;
+                    loop;
+                }
                 // TODO: discard newlines between CJK chars
                 let mut next_in_whitespace: bool = match (ch, mode) {
                     (' ', _)  => true,
@@ -196,6 +207,32 @@ fn test_transform_compress_whitespace() {
     }
 }
 
+#[test]
+fn test_transform_compress_whitespace_around_newline() {
+    let  test_strs : ~[~str] = ~[~"  foo bar",
+                                 ~"foo bar  ",
+                                 ~"foo\n bar",
+                                 ~"foo \nbar",
+                                 ~"  foo  bar  \nbaz",
+                                 ~"foo \t\n \n\t bar",
+                                 ~"foobarbaz\n\n"];
+
+    let oracle_strs : ~[~str] = ~[~" foo bar",
+                                 ~"foo bar ",
+                                 ~"foo\nbar",
+                                 ~"foo\nbar",
+                                 ~" foo bar\nbaz",
+                                 ~"foo\n\nbar",
+                                 ~"foobarbaz\n\n"];
+
+    assert vec::same_length(test_strs, oracle_strs);
+    let mode = CompressWhitespaceAroundNewline;
+
+    for uint::range(0, test_strs.len()) |i| {
+        assert transform_text(test_strs[i], mode) == oracle_strs[i];
+    }
+}
+
 #[test]
 fn test_transform_compress_whitespace_newline() {
     let  test_strs : ~[~str] = ~[~"  foo bar",
@@ -25,15 +25,17 @@ fn make_url(str_url: ~str, current_url: Option<Url>) -> Url {
         } else {
             let current_url = current_url.get();
             #debug("make_url: current_url: %?", current_url);
-            if current_url.path.is_empty() || current_url.path.ends_with("/") {
-                current_url.scheme + "://" + current_url.host + "/" + str_url
+            let path = if str_url.starts_with("/") {
+                move str_url
+            } else if current_url.path.is_empty() {
+                ~"/" + str_url
             } else {
                 let path = str::split_char(current_url.path, '/');
                 let path = path.init();
-                let path = str::connect(path + ~[move str_url], "/");
+                str::connect(path + ~[move str_url], "/")
+            };
 
-                current_url.scheme + "://" + current_url.host + path
-            }
+            current_url.scheme + "://" + current_url.host + remove_dot_segments(path)
         }
     } else {
         move str_url
@@ -43,6 +45,25 @@ fn make_url(str_url: ~str, current_url: Option<Url>) -> Url {
     url::from_str(str_url).get()
 }
 
+/** Resolves the '.' and '..' segments of an absolute path (RFC 3986 Section 5.2.4). */
+fn remove_dot_segments(path: &str) -> ~str {
+    let parts = str::split_char(path, '/');
+    let mut segments = ~[];
+    // the first part is empty, since the path starts with '/'
+    for parts.eachi |i, part| {
+        if i == 0u { loop; }
+        let last = i == parts.len() - 1u;
+        match *part {
+          ~"." => {}
+          ~".." => { if segments.len() > 0u { vec::pop(&mut segments); } }
+          _ => { vec::push(&mut segments, copy *part); loop; }
+        }
+        // a path ending in '.' or '..' still names a directory
+        if last { vec::push(&mut segments, ~""); }
+    }
+    ~"/" + str::connect(segments, "/")
+}
+
 mod make_url_tests {
 
     #[test]
@@ -98,6 +119,17 @@ mod make_url_tests {
         assert new_url.path == ~"/snarf/crumpet.html";
     }
 
+    #[test]
+    fn should_resolve_absolute_paths_and_dot_segments() {
+        let old_url = make_url(~"http://example.com/snarf/blurf/index.html", None);
+        let new_url = make_url(~"../crumpet.html", Some(copy old_url));
+        assert new_url.path == ~"/snarf/crumpet.html";
+        let new_url = make_url(~"./../../../crumpet.html", Some(copy old_url));
+        assert new_url.path == ~"/crumpet.html";
+        let new_url = make_url(~"/muffin/./..", Some(move old_url));
+        assert new_url.path == ~"/";
+    }
+
 }
 
 type UrlMap<T: Copy> = HashMap<Url, T>;